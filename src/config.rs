@@ -1,9 +1,14 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, hash_map::DefaultHasher},
+    hash::{Hash, Hasher}
+};
 
 use floem::peniko::Color;
 use lsp_types::DiagnosticSeverity;
 use serde::{Deserialize, Serialize};
 
+use crate::lines::phantom_text::PhantomKindTag;
+
 pub const SCALE_OR_SIZE_LIMIT: f64 = 5.0;
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq)]
@@ -47,6 +52,31 @@ impl std::fmt::Display for WrapStyle {
     }
 }
 
+/// 光标移动到折叠区域内部时的行为，方向键和点击一致
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, Eq, PartialEq)]
+pub enum FoldedCursorPolicy {
+    /// Move over the folded region, to its end when moving forward and
+    /// to its start when moving backward
+    #[default]
+    Skip,
+    /// Unfold the folds hiding the cursor
+    Unfold,
+    /// Put the cursor before the fold placeholder
+    Placeholder
+}
+
+/// 重叠的背景色（搜索结果、诊断、装饰等）如何合成
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, Eq, PartialEq)]
+pub enum BackgroundBlend {
+    /// Draw the backgrounds one by one
+    #[default]
+    Stack,
+    /// Source-over compositing, later backgrounds on top
+    Over,
+    /// The average of the overlapping colors
+    Mix
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize, Default, Eq, PartialEq)]
 pub struct EditorConfig {
     pub font_family:               String,
@@ -79,7 +109,159 @@ pub struct EditorConfig {
 
     pub editor_foreground: Color,
 
-    pub syntax: HashMap<String, Color>
+    pub syntax: HashMap<String, Color>,
+
+    /// Home toggles between the first non-blank char and column 0
+    #[serde(default)]
+    pub smart_home:     bool,
+    /// End moves to the end of the visual line first if the line is
+    /// wrapped
+    #[serde(default)]
+    pub wrap_aware_end: bool,
+    /// Up/Down move by origin lines, a wrapped line is skipped as a
+    /// whole. By default they move by visual lines
+    #[serde(default)]
+    pub move_by_origin_line: bool,
+
+    #[serde(default)]
+    pub background_blend: BackgroundBlend,
+
+    /// 打开文件时，折叠范围第一次到达后自动折叠
+    #[serde(default)]
+    pub auto_fold: AutoFoldConfig,
+
+    #[serde(default)]
+    pub folded_cursor: FoldedCursorPolicy,
+
+    /// 超过该列的部分加背景色，提示过长的行
+    #[serde(default)]
+    pub long_line_column: Option<usize>,
+
+    /// 过长部分的背景色，为空时使用`inlay_hint_bg`
+    #[serde(default)]
+    pub long_line_bg: Option<Color>,
+
+    /// 高亮光标所在的列，整个视口高度
+    #[serde(default)]
+    pub cursor_column: bool,
+
+    /// 光标列的背景色，为空时使用当前行的颜色
+    #[serde(default)]
+    pub cursor_column_bg: Option<Color>,
+
+    /// 查找结果的背景色，为空时使用`inlay_hint_bg`
+    #[serde(default)]
+    pub search_match_bg: Option<Color>,
+
+    /// 局部变量所有使用处的背景色，每个变量按名称固定取其中一个，
+    /// 为空时使用`inlay_hint_bg`
+    #[serde(default)]
+    pub local_uses_bg: Vec<Color>,
+
+    /// 同一列上幻影文本的顺序，未列出的种类按默认顺序排在后面。
+    /// The default order is ime, placeholder, completion, inlay hint,
+    /// diagnostic; fold placeholders are always the last.
+    #[serde(default)]
+    pub phantom_order: Vec<PhantomKindTag>,
+
+    #[serde(default)]
+    pub enter_between_brackets: EnterBetweenBracketsConfig,
+
+    #[serde(default)]
+    pub backspace: SmartBackspaceConfig,
+
+    /// 用波浪线标出tree-sitter解析出的语法错误，不依赖语言服务器
+    #[serde(default)]
+    pub syntax_errors: bool,
+
+    #[serde(default)]
+    pub continue_comments: ContinueCommentsConfig,
+
+    /// 错误透镜的消息超出视口宽度时截断，以省略号结尾
+    #[serde(default)]
+    pub error_lens_truncate: bool,
+
+    /// 一行最多显示几个诊断的消息，其余的显示为`+N more`，0为1个
+    #[serde(default)]
+    pub error_lens_max_count: usize,
+
+    /// 只排版视口附近的行，其余的行按等宽字体估算宽高，滚动到视口时再排版
+    #[serde(default)]
+    pub lazy_layout: bool
+}
+
+/// 在行注释或文档注释中回车时，新行延续注释前缀，如`/// `，默认关闭
+#[derive(Debug, Clone, Deserialize, Serialize, Default, Eq, PartialEq)]
+pub struct ContinueCommentsConfig {
+    /// enable it for all languages, except the ones disabled in
+    /// `languages`
+    #[serde(default)]
+    pub enabled:   bool,
+    /// per language switches, keyed by the language name, e.g. "Rust"
+    #[serde(default)]
+    pub languages: HashMap<String, bool>
+}
+
+impl ContinueCommentsConfig {
+    pub fn is_enabled(&self, language: &str) -> bool {
+        self.languages.get(language).copied().unwrap_or(self.enabled)
+    }
+}
+
+/// 插入模式下退格键的行为，默认都开启
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(default)]
+pub struct SmartBackspaceConfig {
+    /// in the leading whitespace, delete to the previous indent level
+    pub indent: bool,
+    /// between a pair, like `(|)` or `"|"`, delete both halves
+    pub pairs:  bool
+}
+
+impl Default for SmartBackspaceConfig {
+    fn default() -> Self {
+        Self {
+            indent: true,
+            pairs:  true
+        }
+    }
+}
+
+/// 在成对括号之间回车，如`{|}`，闭括号另起一行，默认开启
+#[derive(Debug, Clone, Deserialize, Serialize, Default, Eq, PartialEq)]
+pub struct EnterBetweenBracketsConfig {
+    /// disable it for all languages, except the ones enabled in
+    /// `languages`
+    #[serde(default)]
+    pub disabled:  bool,
+    /// per language switches, keyed by the language name, e.g. "Python"
+    #[serde(default)]
+    pub languages: HashMap<String, bool>
+}
+
+impl EnterBetweenBracketsConfig {
+    pub fn is_enabled(&self, language: &str) -> bool {
+        self.languages.get(language).copied().unwrap_or(!self.disabled)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, Default, Eq, PartialEq)]
+pub struct AutoFoldConfig {
+    /// fold the ranges of imports
+    #[serde(default)]
+    pub imports:  bool,
+    /// fold the ranges of (doc) comments
+    #[serde(default)]
+    pub comments: bool,
+    /// fold the ranges nested deeper than the level, top level is 1
+    #[serde(default)]
+    pub level:    Option<usize>
+}
+
+impl AutoFoldConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.imports || self.comments || self.level.is_some()
+    }
 }
 
 impl EditorConfig {
@@ -101,6 +283,29 @@ impl EditorConfig {
         }
     }
 
+    pub fn search_match_bg(&self) -> Color {
+        self.search_match_bg.unwrap_or(self.inlay_hint_bg)
+    }
+
+    pub fn long_line_bg(&self) -> Color {
+        self.long_line_bg.unwrap_or(self.inlay_hint_bg)
+    }
+
+    /// The background of the uses of the local variable `name`, the
+    /// same variable always has the same color
+    pub fn local_uses_bg(&self, name: &str) -> Color {
+        if self.local_uses_bg.is_empty() {
+            return self.inlay_hint_bg;
+        }
+        let mut hasher = DefaultHasher::new();
+        name.hash(&mut hasher);
+        self.local_uses_bg[hasher.finish() as usize % self.local_uses_bg.len()]
+    }
+
+    pub fn error_lens_max_count(&self) -> usize {
+        self.error_lens_max_count.max(1)
+    }
+
     pub fn completion_lens_font_size(&self) -> usize {
         if self.completion_lens_font_size == 0 {
             self.inlay_hint_font_size()
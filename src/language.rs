@@ -1695,6 +1695,8 @@ const LANGUAGES: &[SyntaxProperties] = &[
 impl LapceLanguage {
     pub const HIGHLIGHTS_INJECTIONS_FILE_NAME: &'static str = "injections.scm";
     pub const HIGHLIGHTS_QUERIES_FILE_NAME: &'static str = "highlights.scm";
+    pub const RUNNABLES_QUERIES_FILE_NAME: &'static str = "runnables.scm";
+    pub const LOCALS_QUERIES_FILE_NAME: &'static str = "locals.scm";
 
     pub fn from_path(path: &Path) -> LapceLanguage {
         Self::from_path_raw(path).unwrap_or(LapceLanguage::PlainText)
@@ -1918,12 +1920,16 @@ fn add_bracket_pos(
     }
 }
 
+/// `open`: the (offset, row) of the unclosed brackets, `pairs`: the
+/// bracket pairs across lines
 pub(crate) fn walk_tree_bracket_ast(
     cursor: &mut TreeCursor,
     level: &mut usize,
     counter: &mut usize,
     bracket_pos: &mut HashMap<usize, Vec<LineStyle>>,
-    palette: &Vec<String>
+    palette: &Vec<String>,
+    open: &mut Vec<(usize, usize)>,
+    pairs: &mut Vec<(usize, usize)>
 ) {
     if cursor.node().kind().ends_with('(')
         || cursor.node().kind().ends_with('{')
@@ -1937,6 +1943,7 @@ pub(crate) fn walk_tree_bracket_ast(
             start_pos,
             palette.get(*level % palette.len()).unwrap().clone()
         );
+        open.push((cursor.node().end_byte() - 1, row));
         *level += 1;
     } else if cursor.node().kind().ends_with(')')
         || cursor.node().kind().ends_with('}')
@@ -1955,12 +1962,25 @@ pub(crate) fn walk_tree_bracket_ast(
                 start_pos,
                 palette.get(*level % palette.len()).unwrap().clone()
             );
+            if let Some((start, start_row)) = open.pop() {
+                if start_row != row {
+                    pairs.push((start, cursor.node().end_byte() - 1));
+                }
+            }
         }
     }
     *counter += 1;
     if cursor.goto_first_child() {
         loop {
-            walk_tree_bracket_ast(cursor, level, counter, bracket_pos, palette);
+            walk_tree_bracket_ast(
+                cursor,
+                level,
+                counter,
+                bracket_pos,
+                palette,
+                open,
+                pairs
+            );
             if !cursor.goto_next_sibling() {
                 break;
             }
@@ -0,0 +1,126 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use lapce_xi_rope::{
+    DeltaElement, Interval, Rope, RopeDelta,
+    spans::{Spans, SpansBuilder}
+};
+
+use crate::lines::buffer::rope_text::{RopeText, RopeTextRef};
+
+/// The commit of a line, provided by the embedder, e.g. `git blame`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlameInfo {
+    pub commit: String,
+    pub author: String,
+    /// commit time, seconds since the unix epoch
+    pub time:   i64
+}
+
+impl BlameInfo {
+    /// Seconds from the commit to `now`, for heat-maps
+    pub fn age(&self, now: i64) -> i64 {
+        (now - self.time).max(0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LineBlame {
+    Committed(Arc<BlameInfo>),
+    /// 本地修改过的行
+    Modified
+}
+
+/// 每行的blame信息，随编辑移动。
+///
+/// The lines touched by edits, including the new lines, are marked as
+/// [LineBlame::Modified].
+#[derive(Clone)]
+pub struct BlameLayer {
+    spans: Spans<LineBlame>
+}
+
+impl BlameLayer {
+    /// `lines[i]` is the blame of line `i`, None for uncommitted lines
+    pub fn new(text: &Rope, lines: Vec<Option<BlameInfo>>) -> Result<Self> {
+        let text = RopeTextRef::new(text);
+        let mut builder = SpansBuilder::new(text.len());
+        for (line, info) in lines.into_iter().enumerate() {
+            let blame = match info {
+                Some(info) => LineBlame::Committed(Arc::new(info)),
+                None => LineBlame::Modified
+            };
+            let start = text.offset_of_line(line)?;
+            let end = text.offset_of_line(line + 1)?;
+            if start >= end {
+                break;
+            }
+            builder.add_span(Interval::new(start, end), blame);
+        }
+        Ok(Self {
+            spans: builder.build()
+        })
+    }
+
+    /// `text` is the content after the edit
+    pub fn apply_delta(&mut self, delta: &RopeDelta, text: &Rope) -> Result<()> {
+        let text = RopeTextRef::new(text);
+        self.spans.apply_shape(delta);
+        for iv in changed_intervals(delta) {
+            let start = text.offset_of_line(text.line_of_offset(iv.start))?;
+            // 插入的文本以换行结尾时，不影响下一行
+            let end_line = text.line_of_offset(iv.end.max(iv.start + 1) - 1);
+            let end = text.offset_of_line(end_line + 1)?;
+            if start >= end {
+                continue;
+            }
+            let mut builder = SpansBuilder::new(end - start);
+            builder.add_span(Interval::new(0, end - start), LineBlame::Modified);
+            self.spans.edit(Interval::new(start, end), builder.build());
+        }
+        Ok(())
+    }
+
+    /// The blame of the line. A line without blame is treated as
+    /// modified.
+    pub fn line(&self, text: &Rope, line: usize) -> Result<LineBlame> {
+        let text = RopeTextRef::new(text);
+        let start = text.offset_of_line(line)?;
+        let end = text.offset_of_line(line + 1)?;
+        let mut blame = None;
+        for (_, x) in self.spans.iter_chunks(start..end) {
+            match x {
+                LineBlame::Modified => return Ok(LineBlame::Modified),
+                LineBlame::Committed(_) => {
+                    blame.get_or_insert_with(|| x.clone());
+                }
+            }
+        }
+        Ok(blame.unwrap_or(LineBlame::Modified))
+    }
+}
+
+/// The changed ranges in the new text, a deletion is an empty range
+fn changed_intervals(delta: &RopeDelta) -> Vec<Interval> {
+    let mut changed = Vec::new();
+    let (mut old_end, mut new_offset) = (0, 0);
+    for el in &delta.els {
+        match el {
+            DeltaElement::Copy(start, end) => {
+                if *start > old_end {
+                    changed.push(Interval::new(new_offset, new_offset));
+                }
+                new_offset += end - start;
+                old_end = *end;
+            },
+            DeltaElement::Insert(rope) => {
+                changed.push(Interval::new(new_offset, new_offset + rope.len()));
+                new_offset += rope.len();
+            }
+        }
+    }
+    if delta.base_len > old_end {
+        changed.push(Interval::new(new_offset, new_offset));
+    }
+    changed
+}
@@ -0,0 +1,11 @@
+/// A vertical guide of a bracket pair crossing a screen line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BracketGuide {
+    /// the display column of the indent of the line of the opening
+    /// bracket, tabs expanded
+    pub column: usize,
+    pub open:   usize,
+    pub close:  usize,
+    /// the innermost pair containing the cursor
+    pub active: bool
+}
@@ -30,6 +30,36 @@ pub enum DiffLines {
     Right(Range<usize>)
 }
 
+/// 比较行时忽略的差异。The ranges of [DiffLines] are always the lines of
+/// the real text, whatever the options are.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DiffOptions {
+    /// ignore the whitespaces at the end of lines
+    pub trim_trailing:     bool,
+    /// ignore all whitespace changes, like `git diff -w`
+    pub ignore_whitespace: bool,
+    pub ignore_case:       bool
+}
+
+impl DiffOptions {
+    /// The content of the line used to compare
+    pub fn normalize<'a>(&self, line: Cow<'a, str>) -> Cow<'a, str> {
+        let mut line = line;
+        if self.ignore_whitespace {
+            line = line.chars().filter(|x| !x.is_whitespace()).collect();
+        } else if self.trim_trailing {
+            let trimmed = line.trim_end();
+            if trimmed.len() != line.len() {
+                line = Cow::Owned(trimmed.to_string());
+            }
+        }
+        if self.ignore_case {
+            line = Cow::Owned(line.to_lowercase());
+        }
+        line
+    }
+}
+
 pub enum DiffExpand {
     Up(usize),
     Down(usize),
@@ -83,8 +113,32 @@ pub fn rope_diff(
     atomic_rev: Arc<AtomicU64>,
     context_lines: Option<usize>
 ) -> Option<Vec<DiffLines>> {
-    let left_lines = left_rope.lines(..).collect::<Vec<Cow<str>>>();
-    let right_lines = right_rope.lines(..).collect::<Vec<Cow<str>>>();
+    rope_diff_with_options(
+        left_rope,
+        right_rope,
+        rev,
+        atomic_rev,
+        context_lines,
+        DiffOptions::default()
+    )
+}
+
+pub fn rope_diff_with_options(
+    left_rope: Rope,
+    right_rope: Rope,
+    rev: u64,
+    atomic_rev: Arc<AtomicU64>,
+    context_lines: Option<usize>,
+    options: DiffOptions
+) -> Option<Vec<DiffLines>> {
+    let left_lines = left_rope
+        .lines(..)
+        .map(|x| options.normalize(x))
+        .collect::<Vec<Cow<str>>>();
+    let right_lines = right_rope
+        .lines(..)
+        .map(|x| options.normalize(x))
+        .collect::<Vec<Cow<str>>>();
 
     let left_count = left_lines.len();
     let right_count = right_lines.len();
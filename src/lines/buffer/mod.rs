@@ -1,22 +1,30 @@
 use std::{
     borrow::{Borrow, Cow},
+    cell::RefCell,
     cmp::Ordering,
     collections::BTreeSet,
     fmt::Display,
+    ops::Range,
+    rc::Rc,
     sync::{
         Arc,
         atomic::{self, AtomicU64}
     }
 };
 
+use anyhow::Result;
 use floem::views::editor::core::{indent::IndentStyle, mode::Mode};
 use lapce_xi_rope::{
     Delta, DeltaBuilder, DeltaElement, Interval, Rope, RopeDelta,
     multiset::Subset,
     tree::{Node, NodeInfo}
 };
+use log::error;
 
-use crate::lines::line_ending::{LineEnding, LineEndingDetermination};
+use crate::lines::{
+    encoding::{Utf16ColumnCache, offset_utf16_to_utf8, offset_utf8_to_utf16},
+    line_ending::{LineEnding, LineEndingDetermination}
+};
 
 pub mod diff;
 pub mod rope_text;
@@ -72,6 +80,32 @@ pub struct InvalLines {
     pub old_text:    Rope
 }
 
+/// 一次缓冲区修改的结构化描述，包括编辑、撤销、重做和重新加载
+#[derive(Debug, Clone)]
+pub struct EditEvent {
+    pub delta:          RopeDelta,
+    pub old_rev:        u64,
+    pub new_rev:        u64,
+    pub edit_type:      EditType,
+    /// the lines touched by the edit, in the new text
+    pub affected_lines: Range<usize>
+}
+
+/// 编辑应用前校验的结果
+#[derive(Debug, Clone)]
+pub enum EditVerdict {
+    Accept,
+    /// apply this delta instead, it must have the same base as the
+    /// proposed one and normalized line endings
+    Adjust(RopeDelta),
+    /// veto the edit with the reason
+    Reject(String)
+}
+
+/// Checks an edit before it's applied: `(text, proposed delta, edit
+/// type)`
+pub type EditValidator = Rc<dyn Fn(&Rope, &RopeDelta, EditType) -> EditVerdict>;
+
 #[derive(Clone)]
 pub struct Buffer {
     rev_counter:     u64,
@@ -89,9 +123,23 @@ pub struct Buffer {
     tombstones:         Rope,
     this_edit_type:     EditType,
     last_edit_type:     EditType,
+    /// 为真时编辑合并到上一个撤销组，如重复执行的命令
+    join_undo_group:    bool,
+    /// 最后一次编辑只改动了空白字符或换行
+    whitespace_only:    bool,
+    /// 未取走的修改事件，None时不记录
+    edit_events:        Option<Vec<EditEvent>>,
+    /// 编辑前的校验(id, 校验)，不包括撤销和重做
+    validators:         Vec<(usize, EditValidator)>,
+    next_validator_id:  usize,
+    /// 最后一次被拒绝的原因，下一次事务外的编辑时清除
+    rejection:          Option<String>,
+    /// 事务中一次编辑被拒绝后，事务中之后的编辑都被拒绝
+    in_transaction:     bool,
 
     indent_style: IndentStyle,
-    line_ending:  LineEnding
+    line_ending:  LineEnding,
+    utf16_cache:  RefCell<Utf16ColumnCache>
 }
 
 impl Display for Buffer {
@@ -141,8 +189,16 @@ impl Buffer {
 
             this_edit_type: EditType::Other,
             last_edit_type: EditType::Other,
+            join_undo_group: false,
+            whitespace_only: false,
+            edit_events: None,
+            validators: Vec::new(),
+            next_validator_id: 0,
+            rejection: None,
+            in_transaction: false,
             indent_style: IndentStyle::DEFAULT_INDENT,
-            line_ending
+            line_ending,
+            utf16_cache: Default::default()
         }
     }
 
@@ -160,6 +216,86 @@ impl Buffer {
         self.is_equivalent_revision(self.pristine_rev_id, self.rev())
     }
 
+    /// Whether the last edit, undo or redo changed only whitespace and
+    /// line endings, e.g. a reformat
+    pub fn is_last_edit_whitespace_only(&self) -> bool {
+        self.whitespace_only
+    }
+
+    /// Start or stop recording an [EditEvent] for every mutation
+    pub fn set_record_edit_events(&mut self, record: bool) {
+        if record != self.edit_events.is_some() {
+            self.edit_events = record.then(Vec::new);
+        }
+    }
+
+    /// The recorded events since the last call, in order
+    pub fn take_edit_events(&mut self) -> Vec<EditEvent> {
+        self.edit_events.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    /// Check the edits before they are applied, in the order of
+    /// registration. Undo and redo are not checked. Returns the id to
+    /// remove it.
+    pub fn add_validator(&mut self, validator: EditValidator) -> usize {
+        let id = self.next_validator_id;
+        self.next_validator_id += 1;
+        self.validators.push((id, validator));
+        id
+    }
+
+    pub fn remove_validator(&mut self, id: usize) -> bool {
+        let len = self.validators.len();
+        self.validators.retain(|(x, _)| *x != id);
+        len != self.validators.len()
+    }
+
+    /// The reason of the last rejected edit. A rejected edit leaves the
+    /// text unchanged and [Self::edit] returns None.
+    pub fn take_rejection(&mut self) -> Option<String> {
+        self.rejection.take()
+    }
+
+    /// Group the edits of one command, e.g. an edit and the auto-closed
+    /// pair after it. Once an edit is rejected, the following edits of
+    /// the transaction are rejected too.
+    pub fn start_transaction(&mut self) {
+        self.rejection = None;
+        self.in_transaction = true;
+    }
+
+    /// Returns the reason if an edit of the transaction is rejected
+    pub fn end_transaction(&mut self) -> Option<String> {
+        self.in_transaction = false;
+        self.rejection.take()
+    }
+
+    /// Run the validators on `delta`, Err is the reason of the rejection
+    fn validate(&self, delta: RopeDelta) -> Result<RopeDelta, String> {
+        if let Some(reason) = &self.rejection {
+            return Err(reason.clone());
+        }
+        let mut delta = delta;
+        for (_, validator) in &self.validators {
+            match validator(&self.text, &delta, self.this_edit_type) {
+                EditVerdict::Accept => {},
+                EditVerdict::Adjust(adjusted) => {
+                    if adjusted.base_len != self.text.len() {
+                        error!(
+                            "adjusted delta base_len {} != {}",
+                            adjusted.base_len,
+                            self.text.len()
+                        );
+                        return Err("invalid adjusted edit".to_string());
+                    }
+                    delta = adjusted;
+                },
+                EditVerdict::Reject(reason) => return Err(reason)
+            }
+        }
+        Ok(delta)
+    }
+
     pub fn set_cursor_before(&mut self, cursor: CursorMode) {
         if let Some(rev) = self.revs.last_mut() {
             rev.cursor_before = Some(cursor);
@@ -172,6 +308,24 @@ impl Buffer {
         }
     }
 
+    /// The delta from the text of `base_rev` to the current text, None
+    /// if the revision is unknown
+    pub fn delta_rev_head(&self, base_rev: u64) -> Option<RopeDelta> {
+        let rev_index = self.find_rev(base_rev)?;
+        let prev_from_union = self.deletes_from_cur_union_for_index(rev_index);
+        let old_tombstones = shuffle_tombstones(
+            &self.text,
+            &self.tombstones,
+            &self.deletes_from_union,
+            &prev_from_union
+        );
+        Some(Delta::synthesize(
+            &old_tombstones,
+            &prev_from_union,
+            &self.deletes_from_union
+        ))
+    }
+
     fn is_equivalent_revision(&self, base_rev: u64, other_rev: u64) -> bool {
         let base_subset = self
             .find_rev(base_rev)
@@ -208,6 +362,7 @@ impl Buffer {
                 self.mk_new_rev(0, delta.clone());
             self.apply_edit(
                 &delta,
+                EditType::Other,
                 new_rev,
                 new_text,
                 new_tombstones,
@@ -228,10 +383,22 @@ impl Buffer {
 
         let content = self.line_ending.normalize_limited(&content);
 
+        // 只替换变化的部分，使变化之外的诊断、提示等保持原位
         let len = self.text.len();
-        let delta = Delta::simple_edit(Interval::new(0, len), content, len);
+        let (prefix, suffix) = {
+            let old = self.text.slice_to_cow(..);
+            let new = content.slice_to_cow(..);
+            common_affix(&old, &new)
+        };
+        let new_len = content.len();
+        let delta = Delta::simple_edit(
+            Interval::new(prefix, len - suffix),
+            content.slice(prefix..new_len - suffix),
+            len
+        );
         self.this_edit_type = EditType::Other;
-        let (text, delta, inval_lines) = self.add_delta(delta);
+        // 重新加载的是磁盘上的内容，不经过编辑校验
+        let (text, delta, inval_lines) = self.commit_delta(delta);
         if set_pristine {
             self.set_pristine();
         }
@@ -265,13 +432,24 @@ impl Buffer {
         self.last_edit_type = EditType::Other;
     }
 
+    /// Merge the following edits into the last undo group, until it's
+    /// set to false
+    pub fn set_join_undo_group(&mut self, join: bool) {
+        self.join_undo_group = join;
+    }
+
+    pub fn join_undo_group(&self) -> bool {
+        self.join_undo_group
+    }
+
     /// Apply edits, normalizes line endings before applying.
-    /// Returns `(Text before delta, delta, invalidated lines)`
+    /// Returns `(Text before delta, delta, invalidated lines)`, None if
+    /// it's rejected, see [Self::take_rejection]
     pub fn edit<'a, I, E, S>(
         &mut self,
         edits: I,
         edit_type: EditType
-    ) -> (Rope, RopeDelta, InvalLines)
+    ) -> Option<(Rope, RopeDelta, InvalLines)>
     where
         I: IntoIterator<Item = E>,
         E: Borrow<(S, &'a str)>,
@@ -318,23 +496,46 @@ impl Buffer {
             return None;
         };
         self.this_edit_type = EditType::NormalizeLineEndings;
-        Some(self.add_delta(delta))
+        self.add_delta(delta)
     }
 
     // TODO: don't clone the delta and return it, if the caller needs
     // it then they can clone it
     /// Note: the delta's line-endings should be normalized.
-    fn add_delta(&mut self, delta: RopeDelta) -> (Rope, RopeDelta, InvalLines) {
-        let text = self.text.clone();
+    fn add_delta(
+        &mut self,
+        delta: RopeDelta
+    ) -> Option<(Rope, RopeDelta, InvalLines)> {
+        if !self.in_transaction {
+            self.rejection = None;
+        }
+        match self.validate(delta) {
+            Ok(delta) => Some(self.commit_delta(delta)),
+            Err(reason) => {
+                self.rejection = Some(reason);
+                None
+            }
+        }
+    }
 
+    /// Apply `delta` without the validators, e.g. reloading the file
+    fn commit_delta(
+        &mut self,
+        delta: RopeDelta
+    ) -> (Rope, RopeDelta, InvalLines) {
+        let text = self.text.clone();
         let undo_group = self.calculate_undo_group();
-        self.last_edit_type = self.this_edit_type;
+        // 镜像编辑不影响后续输入的合并
+        if self.this_edit_type != EditType::Linked {
+            self.last_edit_type = self.this_edit_type;
+        }
 
         let (new_rev, new_text, new_tombstones, new_deletes_from_union) =
             self.mk_new_rev(undo_group, delta.clone());
 
         let inval_lines = self.apply_edit(
             &delta,
+            self.this_edit_type,
             new_rev,
             new_text,
             new_tombstones,
@@ -347,6 +548,7 @@ impl Buffer {
     fn apply_edit(
         &mut self,
         delta: &RopeDelta,
+        edit_type: EditType,
         new_rev: Revision,
         new_text: Rope,
         new_tombstones: Rope,
@@ -357,6 +559,9 @@ impl Buffer {
         let (iv, newlen) = delta.summary();
         let old_logical_end_line = self.text.line_of_offset(iv.end) + 1;
         let old_text = self.text.clone();
+        self.whitespace_only = is_whitespace_only_delta(&old_text, delta);
+        let old_rev = self.rev();
+        let new_rev_num = new_rev.num;
 
         self.revs.push(new_rev);
         self.text = new_text;
@@ -367,6 +572,21 @@ impl Buffer {
         let new_logical_end_line = self.text.line_of_offset(iv.start + newlen) + 1;
         let old_hard_count = old_logical_end_line - logical_start_line;
         let new_hard_count = new_logical_end_line - logical_start_line;
+        self.utf16_cache.get_mut().invalidate(
+            logical_start_line,
+            old_hard_count,
+            new_hard_count
+        );
+        if let Some(events) = self.edit_events.as_mut() {
+            events.push(EditEvent {
+                delta: delta.clone(),
+                old_rev,
+                new_rev: new_rev_num,
+                edit_type,
+                affected_lines: logical_start_line
+                    ..logical_start_line + new_hard_count
+            });
+        }
 
         InvalLines {
             start_line: logical_start_line,
@@ -378,8 +598,8 @@ impl Buffer {
 
     fn calculate_undo_group(&mut self) -> usize {
         let has_undos = !self.live_undos.is_empty();
-        let is_unbroken_group =
-            !self.this_edit_type.breaks_undo_group(self.last_edit_type);
+        let is_unbroken_group = self.join_undo_group
+            || !self.this_edit_type.breaks_undo_group(self.last_edit_type);
 
         if has_undos && is_unbroken_group {
             *self.live_undos.last().unwrap()
@@ -646,8 +866,10 @@ impl Buffer {
         let cursor_before = new_rev.cursor_before.clone();
         let cursor_after = new_rev.cursor_after.clone();
 
+        // do_undo和do_redo已设置编辑类型
         let inval_lines = self.apply_edit(
             &delta,
+            self.last_edit_type,
             new_rev,
             new_text,
             new_tombstones,
@@ -712,6 +934,94 @@ impl RopeText for Buffer {
     fn text(&self) -> &Rope {
         &self.text
     }
+
+    fn utf16_col_of_line_col(&self, line: usize, col: usize) -> Result<usize> {
+        let cached = self
+            .utf16_cache
+            .borrow_mut()
+            .utf16_col(line, col, || Ok(self.line_content(line)?.to_string()))?;
+        match cached {
+            Some(utf16_col) => Ok(utf16_col),
+            // 超出行尾时与不缓存的换算一致
+            None => {
+                let line_offset = self.offset_of_line(line)?;
+                Ok(offset_utf8_to_utf16(self.char_indices_iter(line_offset..), col))
+            }
+        }
+    }
+
+    fn line_col_of_utf16_col(&self, line: usize, utf16_col: usize) -> Result<usize> {
+        let cached = self
+            .utf16_cache
+            .borrow_mut()
+            .utf8_col(line, utf16_col, || Ok(self.line_content(line)?.to_string()))?;
+        match cached {
+            Some(col) => Ok(col),
+            None => {
+                let line_offset = self.offset_of_line(line)?;
+                Ok(offset_utf16_to_utf8(
+                    self.char_indices_iter(line_offset..),
+                    utf16_col
+                ))
+            }
+        }
+    }
+}
+
+/// The length of common prefix and common suffix(not overlapped with
+/// the prefix), in bytes and on char boundaries
+fn common_affix(old: &str, new: &str) -> (usize, usize) {
+    let mut prefix = old
+        .bytes()
+        .zip(new.bytes())
+        .take_while(|(x, y)| x == y)
+        .count();
+    while !old.is_char_boundary(prefix) || !new.is_char_boundary(prefix) {
+        prefix -= 1;
+    }
+    let max_suffix = old.len().min(new.len()) - prefix;
+    let mut suffix = old
+        .bytes()
+        .rev()
+        .zip(new.bytes().rev())
+        .take(max_suffix)
+        .take_while(|(x, y)| x == y)
+        .count();
+    while !old.is_char_boundary(old.len() - suffix)
+        || !new.is_char_boundary(new.len() - suffix)
+    {
+        suffix -= 1;
+    }
+    (prefix, suffix)
+}
+
+/// Whether the inserted text and the deleted text of the delta are all
+/// whitespace. Only the changed ranges are visited.
+pub fn is_whitespace_only_delta(old: &Rope, delta: &RopeDelta) -> bool {
+    let is_whitespace = |text: &Rope, range: Interval| {
+        text.iter_chunks(range)
+            .all(|chunk| chunk.chars().all(char::is_whitespace))
+    };
+    let mut old_end = 0;
+    for el in &delta.els {
+        match el {
+            DeltaElement::Copy(start, end) => {
+                if *start > old_end
+                    && !is_whitespace(old, Interval::new(old_end, *start))
+                {
+                    return false;
+                }
+                old_end = *end;
+            },
+            DeltaElement::Insert(rope) => {
+                if !is_whitespace(rope, Interval::new(0, rope.len())) {
+                    return false;
+                }
+            }
+        }
+    }
+    delta.base_len <= old_end
+        || is_whitespace(old, Interval::new(old_end, delta.base_len))
 }
 
 fn shuffle_tombstones(
@@ -4,7 +4,12 @@ use anyhow::Result;
 use floem::views::editor::core::mode::Mode;
 use lapce_xi_rope::{Cursor, Rope, interval::IntervalBounds, rope::ChunkIter};
 
-use crate::lines::{paragraph::ParagraphCursor, word::WordCursor};
+use crate::lines::{
+    encoding::{offset_utf8_to_utf16, offset_utf16_to_utf8},
+    paragraph::ParagraphCursor,
+    sentence::{paragraph_end_line, paragraph_start_line, visit_sentence_starts},
+    word::WordCursor
+};
 
 pub trait RopeText {
     fn text(&self) -> &Rope;
@@ -17,6 +22,21 @@ pub trait RopeText {
         self.len() == 0
     }
 
+    /// utf8 col of the line -> utf16 col(LSP character)
+    fn utf16_col_of_line_col(&self, line: usize, col: usize) -> Result<usize> {
+        let line_offset = self.offset_of_line(line)?;
+        Ok(offset_utf8_to_utf16(self.char_indices_iter(line_offset..), col))
+    }
+
+    /// utf16 col(LSP character) of the line -> utf8 col
+    fn line_col_of_utf16_col(&self, line: usize, utf16_col: usize) -> Result<usize> {
+        let line_offset = self.offset_of_line(line)?;
+        Ok(offset_utf16_to_utf8(
+            self.char_indices_iter(line_offset..),
+            utf16_col
+        ))
+    }
+
     /// The last line of the held rope
     fn last_line(&self) -> usize {
         self.line_of_offset(self.len())
@@ -352,6 +372,110 @@ pub trait RopeText {
         self.find_nth_paragraph(offset, count, |cursor| cursor.prev_boundary())
     }
 
+    /// The lines of the paragraph(or the blank lines) containing
+    /// `offset`, like `ip`/`ap` of vim. The following blank lines are
+    /// included if `around`, or the previous ones at the end of text.
+    fn select_paragraph(
+        &self,
+        offset: usize,
+        around: bool
+    ) -> Result<(usize, usize)> {
+        let line = self.line_of_offset(offset);
+        let mut start_line = paragraph_start_line(self, line)?;
+        let mut end_line = paragraph_end_line(self, line)?;
+        if around {
+            if end_line < self.last_line() {
+                end_line = paragraph_end_line(self, end_line + 1)?;
+            } else if start_line > 0 {
+                start_line = paragraph_start_line(self, start_line - 1)?;
+            }
+        }
+        Ok((
+            self.offset_of_line(start_line)?,
+            self.offset_of_line(end_line + 1)?
+        ))
+    }
+
+    /// The start of the `count`th sentence after `offset`, like `)` of
+    /// vim. The end of text if there are not enough sentences.
+    fn move_n_sentences_forward(&self, offset: usize, count: usize) -> Result<usize> {
+        if count == 0 {
+            return Ok(offset);
+        }
+        let start_line = paragraph_start_line(self, self.line_of_offset(offset))?;
+        let mut count = count;
+        let mut new_offset = self.len();
+        visit_sentence_starts(self, start_line, self.last_line(), |start| {
+            if start > offset {
+                count -= 1;
+                if count == 0 {
+                    new_offset = start;
+                    return false;
+                }
+            }
+            true
+        })?;
+        Ok(new_offset)
+    }
+
+    /// The start of the `count`th sentence before `offset`, like `(` of
+    /// vim
+    fn move_n_sentences_backward(
+        &self,
+        offset: usize,
+        count: usize
+    ) -> Result<usize> {
+        if count == 0 {
+            return Ok(offset);
+        }
+        let line = self.line_of_offset(offset);
+        let mut start_line = paragraph_start_line(self, line)?;
+        loop {
+            let mut starts = Vec::new();
+            visit_sentence_starts(self, start_line, line, |start| {
+                if start < offset {
+                    starts.push(start);
+                }
+                start < offset
+            })?;
+            if starts.len() >= count {
+                return Ok(starts[starts.len() - count]);
+            }
+            if start_line == 0 {
+                return Ok(0);
+            }
+            // 不够时从上一段落开始查找
+            start_line = paragraph_start_line(self, start_line - 1)?;
+        }
+    }
+
+    /// The sentence containing `offset`, like `is`/`as` of vim. The
+    /// whitespace after the sentence is included if `around`.
+    fn select_sentence(
+        &self,
+        offset: usize,
+        around: bool
+    ) -> Result<(usize, usize)> {
+        let line = self.line_of_offset(offset);
+        let start_line = paragraph_start_line(self, line)?;
+        let end_line = paragraph_end_line(self, line)?;
+        let mut start = self.offset_of_line(start_line)?;
+        let mut end = self.offset_of_line(end_line + 1)?;
+        visit_sentence_starts(self, start_line, end_line, |x| {
+            if x <= offset {
+                start = x;
+                true
+            } else {
+                end = x;
+                false
+            }
+        })?;
+        if !around {
+            end = start + self.slice_to_cow(start..end).trim_end().len();
+        }
+        Ok((start, end))
+    }
+
     /// Find the nth (`count`) word starting at `offset` in either
     /// direction depending on `find_next`.
     ///
@@ -408,6 +532,21 @@ pub trait RopeText {
         self.find_nth_word(offset, count, |cursor| cursor.prev_boundary(mode))
     }
 
+    fn move_n_subwords_forward(&self, offset: usize, count: usize) -> usize {
+        self.find_nth_word(offset, count, |cursor| cursor.next_subword_boundary())
+    }
+
+    fn move_n_subwords_backward(
+        &self,
+        offset: usize,
+        count: usize,
+        mode: Mode
+    ) -> usize {
+        self.find_nth_word(offset, count, |cursor| {
+            cursor.prev_subword_boundary(mode)
+        })
+    }
+
     fn move_word_backward_deletion(&self, offset: usize) -> usize {
         self.find_nth_word(offset, 1, |cursor| cursor.prev_deletion_boundary())
     }
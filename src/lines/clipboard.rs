@@ -0,0 +1,115 @@
+use floem::views::editor::core::{
+    mode::VisualMode,
+    register::{Clipboard, RegisterData}
+};
+
+/// The metadata of the text copied to the clipboard by the editor.
+///
+/// The system clipboard only keeps text, so the type is kept by the
+/// document and matched by content when pasting.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CopyMeta {
+    pub content: String,
+    /// charwise, linewise or blockwise
+    pub mode:    VisualMode,
+    /// Some if `mode` is blockwise
+    pub block:   Option<BlockShape>
+}
+
+impl CopyMeta {
+    pub fn new(data: &RegisterData) -> Self {
+        let block = match data.mode {
+            VisualMode::Blockwise => Some(BlockShape::new(&data.content)),
+            VisualMode::Normal | VisualMode::Linewise => None
+        };
+        Self {
+            content: data.content.clone(),
+            mode: data.mode,
+            block
+        }
+    }
+}
+
+/// The shape of a block selection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockShape {
+    /// the count of lines
+    pub height: usize,
+    /// the chars of the longest line
+    pub width:  usize
+}
+
+impl BlockShape {
+    pub fn new(content: &str) -> Self {
+        let lines = content.strip_suffix('\n').unwrap_or(content).split('\n');
+        let (height, width) = lines.fold((0, 0), |(height, width), line| {
+            let line = line.strip_suffix('\r').unwrap_or(line);
+            (height + 1, width.max(line.chars().count()))
+        });
+        Self { height, width }
+    }
+}
+
+/// What is copied from a range with folded ranges or phantom texts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CopyMode {
+    /// The text of buffer, folded ranges are copied in full
+    #[default]
+    Underlying,
+    /// The visible text, folded ranges are replaced by the placeholders
+    /// like `{...}`
+    Visible,
+    /// Same as `Visible`, and inlay hints are materialized
+    WithInlayHints
+}
+
+/// A clipboard which also owns the primary selection(X11/Wayland),
+/// which is pasted by middle click.
+///
+/// Platforms without primary selection can keep the default
+/// implementation, which ignores it.
+pub trait PrimaryClipboard: Clipboard {
+    fn get_primary_string(&mut self) -> Option<String> {
+        None
+    }
+
+    fn put_primary_string(&mut self, _s: impl AsRef<str>) {}
+}
+
+/// Wrap a clipboard and keep the primary selection in memory, used when
+/// the platform clipboard has no primary selection, e.g. the
+/// `SystemClipboard` of floem.
+#[derive(Default)]
+pub struct LocalPrimaryClipboard<C: Clipboard> {
+    clipboard: C,
+    primary:   Option<String>
+}
+
+impl<C: Clipboard> LocalPrimaryClipboard<C> {
+    pub fn new(clipboard: C) -> Self {
+        Self {
+            clipboard,
+            primary: None
+        }
+    }
+}
+
+impl<C: Clipboard> Clipboard for LocalPrimaryClipboard<C> {
+    fn get_string(&mut self) -> Option<String> {
+        self.clipboard.get_string()
+    }
+
+    fn put_string(&mut self, s: impl AsRef<str>) {
+        self.clipboard.put_string(s)
+    }
+}
+
+impl<C: Clipboard> PrimaryClipboard for LocalPrimaryClipboard<C> {
+    fn get_primary_string(&mut self) -> Option<String> {
+        self.primary.clone()
+    }
+
+    fn put_primary_string(&mut self, s: impl AsRef<str>) {
+        self.primary = Some(s.as_ref().to_string());
+    }
+}
@@ -1,7 +1,12 @@
-use anyhow::Result;
-use floem::views::editor::core::{
-    mode::{Mode, MotionMode, VisualMode},
-    register::RegisterData
+use std::collections::HashMap;
+
+use anyhow::{Result, bail};
+use floem::{
+    kurbo::Rect,
+    views::editor::core::{
+        mode::{Mode, MotionMode, VisualMode},
+        register::RegisterData
+    }
 };
 use lapce_xi_rope::{RopeDelta, Transformer};
 use log::error;
@@ -9,6 +14,7 @@ use serde::{Deserialize, Serialize};
 
 use crate::lines::{
     buffer::{Buffer, rope_text::RopeText},
+    line::VisualLine,
     selection::{InsertDrift, SelRegion, Selection}
 };
 
@@ -20,15 +26,60 @@ pub enum ColPosition {
     Col(f64)
 }
 
+/// 光标的形状
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum CaretStyle {
+    /// 竖线
+    #[default]
+    Bar,
+    /// 覆盖光标下的字符
+    Block,
+    /// 光标下字符的下划线
+    Underline
+}
+
+/// 一个光标的渲染信息，见 `DocLines::caret_infos`
+#[derive(Clone, Debug, PartialEq)]
+pub struct CaretInfo {
+    pub offset:      usize,
+    /// (start, end) of the selection, `end` is the caret
+    pub region:      (usize, usize),
+    pub visual_line: VisualLine,
+    /// zero width, one line height, in viewport. None if the caret is
+    /// not on screen
+    pub rect:        Option<Rect>,
+    /// x of the caret in the visual line
+    pub x:           f64,
+    /// preferred horizontal position kept by vertical moves
+    pub horiz:       Option<ColPosition>,
+    pub style:       CaretStyle,
+    /// The rect to paint in viewport: the bar, the block covering the
+    /// grapheme under caret, or the underline of it. None if the caret
+    /// is not on screen
+    pub shape_rect:  Option<Rect>
+}
+
+impl CaretInfo {
+    pub fn is_visible(&self) -> bool {
+        self.rect.is_some()
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Cursor {
     mode:                   CursorMode,
     pub horiz:              Option<ColPosition>,
     pub motion_mode:        Option<MotionMode>,
     pub history_selections: Vec<Selection>,
+    /// Selections undone by [`Cursor::undo_selection`]
+    #[serde(default)]
+    pub future_selections:  Vec<Selection>,
     pub affinity:           CursorAffinity
 }
 
+/// Max count of selections kept in `history_selections`
+const MAX_HISTORY_SELECTIONS: usize = 128;
+
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum CursorMode {
     Normal(usize),
@@ -187,6 +238,7 @@ impl Cursor {
             horiz,
             motion_mode,
             history_selections: Vec::new(),
+            future_selections: Vec::new(),
             // It should appear before any inlay hints at the very
             // first position
             affinity: CursorAffinity::Backward
@@ -226,6 +278,42 @@ impl Cursor {
         self.mode.regions_iter()
     }
 
+    /// Block in normal and visual mode like vim, underline while
+    /// waiting for the motion of an operator
+    pub fn caret_style(&self) -> CaretStyle {
+        match &self.mode {
+            CursorMode::Insert(_) => CaretStyle::Bar,
+            CursorMode::Normal(_) if self.motion_mode.is_some() => {
+                CaretStyle::Underline
+            },
+            CursorMode::Normal(_) | CursorMode::Visual { .. } => CaretStyle::Block
+        }
+    }
+
+    /// All the carets, `end` of region is the caret. The horizontal
+    /// position of region is used before the one of cursor.
+    pub fn caret_regions(&self) -> Vec<SelRegion> {
+        match &self.mode {
+            CursorMode::Insert(selection) => selection
+                .regions()
+                .iter()
+                .map(|region| SelRegion {
+                    horiz: region.horiz.or(self.horiz),
+                    ..*region
+                })
+                .collect(),
+            _ => self
+                .mode
+                .regions_iter()
+                .map(|(start, end)| SelRegion {
+                    start,
+                    end,
+                    horiz: self.horiz
+                })
+                .collect()
+        }
+    }
+
     pub fn is_normal(&self) -> bool {
         matches!(&self.mode, CursorMode::Normal(_))
     }
@@ -248,11 +336,51 @@ impl Cursor {
 
     pub fn set_mode(&mut self, mode: CursorMode) {
         if let CursorMode::Insert(selection) = &self.mode {
-            self.history_selections.push(selection.clone());
+            if self.mode != mode
+                && self.history_selections.last() != Some(selection)
+            {
+                self.history_selections.push(selection.clone());
+                if self.history_selections.len() > MAX_HISTORY_SELECTIONS {
+                    self.history_selections.remove(0);
+                }
+                self.future_selections.clear();
+            }
         }
         self.mode = mode;
     }
 
+    /// Restore the previous selection of insert mode. Return false if
+    /// there is no history.
+    pub fn undo_selection(&mut self) -> bool {
+        let CursorMode::Insert(current) = &self.mode else {
+            return false;
+        };
+        while let Some(selection) = self.history_selections.pop() {
+            if selection != *current {
+                self.future_selections.push(current.clone());
+                self.mode = CursorMode::Insert(selection);
+                self.horiz = None;
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Redo the selection undone by [`Cursor::undo_selection`]. Return
+    /// false if there is nothing to redo.
+    pub fn redo_selection(&mut self) -> bool {
+        let CursorMode::Insert(current) = &self.mode else {
+            return false;
+        };
+        let Some(selection) = self.future_selections.pop() else {
+            return false;
+        };
+        self.history_selections.push(current.clone());
+        self.mode = CursorMode::Insert(selection);
+        self.horiz = None;
+        true
+    }
+
     pub fn set_insert(&mut self, selection: Selection) {
         self.set_mode(CursorMode::Insert(selection));
     }
@@ -351,6 +479,13 @@ impl Cursor {
                 self.mode = CursorMode::Insert(selection);
             }
         }
+        for selection in self
+            .history_selections
+            .iter_mut()
+            .chain(self.future_selections.iter_mut())
+        {
+            *selection = selection.apply_delta(delta, true, InsertDrift::Default);
+        }
         self.horiz = None;
     }
 
@@ -620,6 +755,63 @@ impl Cursor {
     }
 }
 
+/// Mark of the position where insert mode was last stopped(`^` in vim)
+pub const MARK_LAST_INSERT: char = '^';
+/// Mark of the position of the last change(`.` in vim)
+pub const MARK_LAST_CHANGE: char = '.';
+
+/// Vim-style marks of a document: `a`–`z` set by user, plus
+/// [`MARK_LAST_INSERT`] and [`MARK_LAST_CHANGE`] set by edits.
+///
+/// The offsets are anchors, which are transformed by every delta of
+/// the buffer.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Marks {
+    marks: HashMap<char, usize>
+}
+
+impl Marks {
+    pub fn is_valid_mark(mark: char) -> bool {
+        mark.is_ascii_lowercase() || mark == MARK_LAST_INSERT || mark == MARK_LAST_CHANGE
+    }
+
+    pub fn set(&mut self, mark: char, offset: usize) -> Result<()> {
+        if !Self::is_valid_mark(mark) {
+            bail!("invalid mark {mark:?}");
+        }
+        self.marks.insert(mark, offset);
+        Ok(())
+    }
+
+    pub fn get(&self, mark: char) -> Option<usize> {
+        self.marks.get(&mark).copied()
+    }
+
+    pub fn remove(&mut self, mark: char) -> Option<usize> {
+        self.marks.remove(&mark)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (char, usize)> + '_ {
+        self.marks.iter().map(|(mark, offset)| (*mark, *offset))
+    }
+
+    pub fn clear(&mut self) {
+        self.marks.clear();
+    }
+
+    pub fn apply_delta(&mut self, delta: &RopeDelta) {
+        let mut transformer = Transformer::new(delta);
+        for offset in self.marks.values_mut() {
+            // 在标记处插入文本时，标记保持在插入文本之前
+            *offset = transformer.transform(*offset, false);
+        }
+        let (iv, new_len) = delta.summary();
+        if !iv.is_empty() || new_len > 0 {
+            self.marks.insert(MARK_LAST_CHANGE, iv.start() + new_len);
+        }
+    }
+}
+
 pub fn get_first_selection_after(
     cursor: &Cursor,
     buffer: &Buffer,
@@ -0,0 +1,70 @@
+use anyhow::Result;
+use lapce_xi_rope::{Rope, RopeDelta, Transformer};
+
+use crate::lines::buffer::rope_text::{RopeText, RopeTextRef};
+
+/// 调试暂停时行尾显示的变量值，如`a = 1`，锚定在行首，随编辑移动。
+///
+/// The values of a deleted line are dropped.
+#[derive(Debug, Clone, Default)]
+pub struct InlineValues {
+    /// (行首偏移, 值)，按偏移排序
+    anchors: Vec<(usize, String)>
+}
+
+impl InlineValues {
+    /// `values` are `(line, text)`, the texts of the same line are
+    /// shown in order
+    pub fn new(text: &Rope, values: Vec<(usize, String)>) -> Result<Self> {
+        let text = RopeTextRef::new(text);
+        let last_line = text.last_line();
+        let mut anchors: Vec<(usize, String)> = values
+            .into_iter()
+            .filter(|(line, _)| *line <= last_line)
+            .map(|(line, x)| Ok((text.offset_of_line(line)?, x)))
+            .collect::<Result<_>>()?;
+        anchors.sort_by_key(|(offset, _)| *offset);
+        Ok(Self { anchors })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.anchors.is_empty()
+    }
+
+    /// `text` is the content after the edit
+    pub fn apply_delta(&mut self, delta: &RopeDelta, text: &Rope) {
+        let mut transformer = Transformer::new(delta);
+        let mut anchors: Vec<(usize, String)> =
+            Vec::with_capacity(self.anchors.len());
+        let mut last: Option<(usize, usize)> = None;
+        for (offset, x) in self.anchors.drain(..) {
+            let new_offset = transformer.transform(offset, true);
+            let line = text.line_of_offset(new_offset);
+            // 删除整行后与下一行重合，丢弃被删除行的值
+            if let Some((last_offset, last_line)) = last {
+                if last_line == line && last_offset != offset {
+                    anchors.retain(|(x, _)| text.line_of_offset(*x) != line);
+                }
+            }
+            last = Some((offset, line));
+            anchors.push((new_offset, x));
+        }
+        self.anchors = anchors;
+    }
+
+    /// The values of the line joined, None if there is no value
+    pub fn line(&self, text: &Rope, line: usize) -> Result<Option<String>> {
+        let text = RopeTextRef::new(text);
+        let start = text.offset_of_line(line)?;
+        let end = text.offset_of_line(line + 1)?;
+        let values: Vec<&str> = self
+            .anchors
+            .iter()
+            .filter(|(offset, _)| {
+                *offset >= start && (*offset < end || end == text.len())
+            })
+            .map(|(_, x)| x.as_str())
+            .collect();
+        Ok((!values.is_empty()).then(|| values.join(", ")))
+    }
+}
@@ -0,0 +1,154 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use floem::peniko::Color;
+use lapce_xi_rope::{
+    Interval, RopeDelta, Transformer,
+    spans::{Spans, SpansBuilder}
+};
+
+/// 装饰的样式，如搜索结果的背景色、lint的波浪线。The fg color is
+/// drawn over the syntax and semantic colors.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Decoration {
+    pub fg_color:   Option<Color>,
+    pub bg_color:   Option<Color>,
+    pub under_line: Option<Color>,
+    pub wave_line:  Option<Color>
+}
+
+/// Named decoration layers, the spans of each layer are moved by
+/// edits like diagnostics.
+#[derive(Clone, Default)]
+pub struct Decorations {
+    layers: BTreeMap<String, Spans<Decoration>>
+}
+
+impl Decorations {
+    pub fn get(&self, key: &str) -> Option<&Spans<Decoration>> {
+        self.layers.get(key)
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.layers.keys().map(|x| x.as_str())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.layers.is_empty()
+    }
+
+    /// Replace the whole layer
+    pub fn set(
+        &mut self,
+        key: impl Into<String>,
+        len: usize,
+        spans: impl IntoIterator<Item = (Interval, Decoration)>
+    ) {
+        let spans = Self::build(0, len, spans);
+        self.layers.insert(key.into(), spans);
+    }
+
+    /// Replace the whole layer by spans over the whole text
+    pub fn set_spans(&mut self, key: impl Into<String>, spans: Spans<Decoration>) {
+        self.layers.insert(key.into(), spans);
+    }
+
+    /// Replace the decorations of the layer in `range` only, spans out
+    /// of `range` are clipped. The layer is created if it does not
+    /// exist.
+    pub fn update(
+        &mut self,
+        key: &str,
+        len: usize,
+        range: Interval,
+        spans: impl IntoIterator<Item = (Interval, Decoration)>
+    ) {
+        let range = Interval::new(range.start.min(len), range.end.min(len));
+        let new = Self::build(range.start, range.size(), spans);
+        let layer = self
+            .layers
+            .entry(key.to_string())
+            .or_insert_with(|| SpansBuilder::new(len).build());
+        layer.edit(range, new);
+    }
+
+    pub fn remove(&mut self, key: &str) -> bool {
+        self.layers.remove(key).is_some()
+    }
+
+    pub fn clear(&mut self) {
+        self.layers.clear();
+    }
+
+    pub fn apply_delta(&mut self, delta: &RopeDelta) {
+        for spans in self.layers.values_mut() {
+            spans.apply_shape(delta);
+        }
+    }
+
+    /// All decorations intersect with `range`, in the order of layer
+    /// key
+    pub fn iter_range(
+        &self,
+        range: Interval
+    ) -> impl Iterator<Item = (Interval, &Decoration)> + '_ {
+        self.layers
+            .values()
+            .flat_map(move |spans| spans.iter_chunks(range))
+    }
+
+    /// `spans` are in absolute offsets, build the spans of
+    /// `[start, start + len)`
+    fn build(
+        start: usize,
+        len: usize,
+        spans: impl IntoIterator<Item = (Interval, Decoration)>
+    ) -> Spans<Decoration> {
+        let mut builder = SpansBuilder::new(len);
+        let mut spans: Vec<(Interval, Decoration)> = spans
+            .into_iter()
+            .filter_map(|(iv, decoration)| {
+                let iv_start = iv.start.max(start) - start;
+                let iv_end = iv.end.min(start + len).saturating_sub(start);
+                (iv_start < iv_end)
+                    .then_some((Interval::new(iv_start, iv_end), decoration))
+            })
+            .collect();
+        spans.sort_by_key(|(iv, _)| iv.start);
+        for (iv, decoration) in spans {
+            builder.add_span(iv, decoration);
+        }
+        builder.build()
+    }
+}
+
+/// 临时高亮，如快速修复将修改的代码。The range is moved by edits
+/// and the flash expires after `remaining`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Flash {
+    pub range:      Interval,
+    pub decoration: Decoration,
+    pub remaining:  Duration
+}
+
+impl Flash {
+    /// Move the ranges, the flashes whose range is deleted are removed
+    pub fn apply_delta(flashes: &mut Vec<Flash>, delta: &RopeDelta) {
+        let mut transformer = Transformer::new(delta);
+        flashes.retain_mut(|flash| {
+            let start = transformer.transform(flash.range.start, true);
+            let end = transformer.transform(flash.range.end, false);
+            flash.range = Interval::new(start, end.max(start));
+            !flash.range.is_empty()
+        });
+    }
+
+    /// Decrease the time left, return true if some flashes expire
+    pub fn tick(flashes: &mut Vec<Flash>, elapsed: Duration) -> bool {
+        let len = flashes.len();
+        flashes.retain_mut(|flash| {
+            flash.remaining = flash.remaining.saturating_sub(elapsed);
+            !flash.remaining.is_zero()
+        });
+        flashes.len() != len
+    }
+}
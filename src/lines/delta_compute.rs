@@ -123,6 +123,34 @@ pub fn resolve_delta_rs(
     resolve_line_delta(rope, delta_compute)
 }
 
+/// 连续的多个编辑(如多光标、自动闭合括号、联动编辑)合并计算：
+/// 所有编辑都未改动的前缀和后缀直接复制，其余的行重新计算。
+/// `rope` is the text before the first delta.
+pub fn resolve_deltas_rs<'a>(
+    rope: &Rope,
+    deltas: impl IntoIterator<Item = &'a RopeDelta>
+) -> Result<OriginLinesDelta> {
+    let mut prefix = usize::MAX;
+    let mut suffix = usize::MAX;
+    let mut new_len = rope.len();
+    for delta in deltas {
+        let (iv, inserted) = delta.summary();
+        prefix = prefix.min(iv.start);
+        suffix = suffix.min(delta.base_len - iv.end);
+        new_len = delta.base_len - iv.size() + inserted;
+    }
+    if prefix == usize::MAX {
+        return Ok(OriginLinesDelta::default());
+    }
+    let offset_delta = OffsetDelta {
+        copy_start: Interval::new(0, prefix),
+        internal_len: new_len - prefix - suffix,
+        copy_end: Interval::new(rope.len() - suffix, rope.len()),
+    };
+    debug!("{offset_delta:?}");
+    resolve_line_delta(rope, offset_delta)
+}
+
 pub fn resolve_line_delta(
     rope: &Rope,
     offset_delta_compute: OffsetDelta,
@@ -191,6 +219,35 @@ pub fn resolve_line_delta(
     })
 }
 
+/// 只重新计算指定行（内容未变，如幽灵文本变化），其余行直接复制
+pub fn resolve_line_delta_of_line(
+    rope: &Rope,
+    line: usize,
+) -> Result<OriginLinesDelta> {
+    resolve_line_delta_of_lines(rope, line, line)
+}
+
+/// 只重新计算[start_line, end_line]的行
+pub fn resolve_line_delta_of_lines(
+    rope: &Rope,
+    start_line: usize,
+    end_line: usize,
+) -> Result<OriginLinesDelta> {
+    let last_line = rope.line_of_offset(rope.len());
+    if end_line + 1 >= last_line {
+        // 末尾的行由resolve_line_delta整体处理，这里直接全量计算
+        return Ok(OriginLinesDelta::default());
+    }
+    let start = rope.offset_of_line(start_line)?;
+    let end = rope.offset_of_line(end_line + 1)?;
+    let offset_delta = OffsetDelta {
+        copy_start: Interval::new(0, start),
+        internal_len: end - start,
+        copy_end: Interval::new(end, rope.len()),
+    };
+    resolve_line_delta(rope, offset_delta)
+}
+
 /// return (line, offset_line, recompute)
 fn resolve_line_complete_by_start_offset(
     rope: &Rope,
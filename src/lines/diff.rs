@@ -1,7 +1,329 @@
-use crate::lines::buffer::diff::DiffLines;
+use std::sync::{Arc, atomic::AtomicU64};
+
+use anyhow::{Result, bail};
+use floem::{
+    kurbo::{BezPath, Point},
+    views::editor::view::{DiffSection, DiffSectionKind}
+};
+use lapce_xi_rope::{Interval, Rope};
+
+use crate::lines::{
+    buffer::diff::{DiffLines, DiffOptions, rope_diff, rope_diff_with_options},
+    screen_lines::ScreenLines
+};
 
 #[derive(Clone)]
 pub struct DiffInfo {
     pub is_right: bool,
-    pub changes:  Vec<DiffLines>
+    pub changes:  Vec<DiffLines>,
+    /// the options `changes` are computed with
+    pub options:  DiffOptions
+}
+
+impl DiffInfo {
+    /// Diff again if the options are changed. Return true if `changes`
+    /// is updated.
+    pub fn set_options(
+        &mut self,
+        options: DiffOptions,
+        left: &Rope,
+        right: &Rope,
+        context_lines: Option<usize>
+    ) -> bool {
+        if self.options == options {
+            return false;
+        }
+        let Some(changes) = rope_diff_with_options(
+            left.clone(),
+            right.clone(),
+            0,
+            Arc::new(AtomicU64::new(0)),
+            context_lines,
+            options
+        ) else {
+            return false;
+        };
+        self.changes = changes;
+        self.options = options;
+        true
+    }
+}
+
+/// 不修改缓冲区，预览一组编辑的结果，如重构前的确认
+#[derive(Clone)]
+pub struct EditPreview {
+    /// the text after the edits
+    pub text: Rope,
+    /// the diff of the edited text, for the right pane
+    pub diff: DiffInfo
+}
+
+/// Apply `edits`, ranges of `text`, to a copy of `text`. The edits must
+/// not overlap; inserts at the same offset keep their order.
+pub fn preview_edits(
+    text: &Rope,
+    edits: Vec<(Interval, String)>,
+    context_lines: Option<usize>
+) -> Result<EditPreview> {
+    let new_text = apply_edits(text, edits)?;
+    Ok(diff_preview(text, new_text, context_lines))
+}
+
+/// 把一组不重叠的编辑应用到`text`的副本
+pub fn apply_edits(
+    text: &Rope,
+    mut edits: Vec<(Interval, String)>
+) -> Result<Rope> {
+    edits.sort_by_key(|(iv, _)| (iv.start, iv.end));
+    for (prev, next) in edits.iter().zip(edits.iter().skip(1)) {
+        if prev.0.end > next.0.start {
+            bail!("overlapping edits {:?} {:?}", prev.0, next.0);
+        }
+    }
+    let mut new_text = text.clone();
+    for (iv, content) in edits.into_iter().rev() {
+        if iv.end > new_text.len() {
+            bail!("edit {iv:?} out of text {}", new_text.len());
+        }
+        new_text.edit(iv, content);
+    }
+    Ok(new_text)
+}
+
+/// Diff `new_text` against `text` for the right pane of a preview.
+pub fn diff_preview(
+    text: &Rope,
+    new_text: Rope,
+    context_lines: Option<usize>
+) -> EditPreview {
+    let changes = rope_diff(
+        text.clone(),
+        new_text.clone(),
+        0,
+        Arc::new(AtomicU64::new(0)),
+        context_lines
+    )
+    .unwrap_or_default();
+    EditPreview {
+        text: new_text,
+        diff: DiffInfo {
+            is_right: true,
+            changes,
+            options: DiffOptions::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffRibbonKind {
+    /// only lines of the right pane
+    Added,
+    /// only lines of the left pane
+    Removed,
+    Changed
+}
+
+/// 并排diff视图中，连接左右两侧对应变更块的色带
+///
+/// The y values are relative to the window of each pane. An empty side
+/// has the same top and bottom, at the line where the lines are
+/// inserted or deleted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DiffRibbon {
+    pub kind:         DiffRibbonKind,
+    pub left_top:     f64,
+    pub left_bottom:  f64,
+    pub right_top:    f64,
+    pub right_bottom: f64
+}
+
+impl DiffRibbon {
+    /// The control points of the top and bottom cubic curves, from the
+    /// left edge `x0` to the right edge `x1` of the gap between the panes
+    pub fn control_points(&self, x0: f64, x1: f64) -> [[Point; 4]; 2] {
+        let mid = (x0 + x1) / 2.0;
+        let curve = |left: f64, right: f64| {
+            [
+                Point::new(x0, left),
+                Point::new(mid, left),
+                Point::new(mid, right),
+                Point::new(x1, right)
+            ]
+        };
+        [
+            curve(self.left_top, self.right_top),
+            curve(self.left_bottom, self.right_bottom)
+        ]
+    }
+
+    /// The closed path of the ribbon
+    pub fn path(&self, x0: f64, x1: f64) -> BezPath {
+        let [top, bottom] = self.control_points(x0, x1);
+        let mut path = BezPath::new();
+        path.move_to(top[0]);
+        path.curve_to(top[1], top[2], top[3]);
+        path.line_to(bottom[3]);
+        path.curve_to(bottom[2], bottom[1], bottom[0]);
+        path.close_path();
+        path
+    }
+}
+
+/// The ribbons of the changed hunks. Adjacent `Left` and `Right`
+/// changes form one hunk. Hunks out of both windows are skipped.
+pub fn diff_ribbons(
+    changes: &[DiffLines],
+    left: &ScreenLines,
+    right: &ScreenLines
+) -> Vec<DiffRibbon> {
+    let mut ribbons = Vec::new();
+    // 当前变更块在左右两侧的起始行与行数
+    let (mut left_line, mut right_line) = (0, 0);
+    let (mut left_len, mut right_len) = (0, 0);
+    let mut push = |left_line: usize,
+                    left_len: usize,
+                    right_line: usize,
+                    right_len: usize| {
+        if left_len == 0 && right_len == 0 {
+            return;
+        }
+        let (Some(left_top), Some(right_top)) =
+            (left.origin_line_top(left_line), right.origin_line_top(right_line))
+        else {
+            return;
+        };
+        let (Some(left_bottom), Some(right_bottom)) = (
+            left.origin_line_top(left_line + left_len),
+            right.origin_line_top(right_line + right_len)
+        ) else {
+            return;
+        };
+        let out_of_window = |top: f64, bottom: f64, screen: &ScreenLines| {
+            bottom < 0.0 || top > screen.base.height()
+        };
+        if out_of_window(left_top, left_bottom, left)
+            && out_of_window(right_top, right_bottom, right)
+        {
+            return;
+        }
+        let kind = match (left_len, right_len) {
+            (0, _) => DiffRibbonKind::Added,
+            (_, 0) => DiffRibbonKind::Removed,
+            _ => DiffRibbonKind::Changed
+        };
+        ribbons.push(DiffRibbon {
+            kind,
+            left_top,
+            left_bottom,
+            right_top,
+            right_bottom
+        });
+    };
+    for change in changes {
+        match change {
+            DiffLines::Left(range) => {
+                if left_len == 0 {
+                    left_line = range.start;
+                }
+                left_len += range.len();
+            },
+            DiffLines::Right(range) => {
+                if right_len == 0 {
+                    right_line = range.start;
+                }
+                right_len += range.len();
+            },
+            DiffLines::Both(info) => {
+                push(left_line, left_len, right_line, right_len);
+                (left_len, right_len) = (0, 0);
+                left_line = info.left.end;
+                right_line = info.right.end;
+            }
+        }
+    }
+    push(left_line, left_len, right_line, right_len);
+    ribbons
+}
+
+/// The sections of one side of a diff view, in visual lines. Each hunk
+/// is padded by `NoCode` to the visual lines of the other side, so the
+/// two sides keep aligned when lines are wrapped differently. `wraps`
+/// returns the visual lines of an origin line of the left/right side.
+pub fn diff_sections(
+    changes: &[DiffLines],
+    is_right: bool,
+    left_wraps: impl Fn(usize) -> usize,
+    right_wraps: impl Fn(usize) -> usize
+) -> Vec<DiffSection> {
+    let wraps = |right: bool, line: usize| {
+        if right { right_wraps(line) } else { left_wraps(line) }
+    };
+    let own_wraps = |line: usize| wraps(is_right, line);
+    let other_wraps = |line: usize| wraps(!is_right, line);
+    let mut sections = Vec::new();
+    let mut y_idx = 0;
+    let mut push = |y_idx: &mut usize, height: usize, kind: DiffSectionKind| {
+        if height > 0 {
+            sections.push(DiffSection {
+                y_idx: *y_idx,
+                height,
+                kind
+            });
+            *y_idx += height;
+        }
+    };
+    // 当前变更块在本侧与另一侧的视觉行数
+    let (mut own, mut other) = (0, 0);
+    for change in changes {
+        let (range, own_side) = match change {
+            DiffLines::Left(range) => (range, !is_right),
+            DiffLines::Right(range) => (range, is_right),
+            DiffLines::Both(info) => {
+                let padding = other.saturating_sub(own);
+                push(&mut y_idx, padding, DiffSectionKind::NoCode);
+                (own, other) = (0, 0);
+                let (own_range, other_range) = if is_right {
+                    (&info.right, &info.left)
+                } else {
+                    (&info.left, &info.right)
+                };
+                for (index, (own_line, other_line)) in
+                    own_range.clone().zip(other_range.clone()).enumerate()
+                {
+                    // 被折叠的行只占一行
+                    if let Some(skip) = &info.skip {
+                        if skip.contains(&index) {
+                            if skip.start == index {
+                                y_idx += 1;
+                            }
+                            continue;
+                        }
+                    }
+                    let (own_height, other_height) =
+                        (own_wraps(own_line), other_wraps(other_line));
+                    y_idx += own_height;
+                    push(
+                        &mut y_idx,
+                        other_height.saturating_sub(own_height),
+                        DiffSectionKind::NoCode
+                    );
+                }
+                continue;
+            }
+        };
+        if own_side {
+            let height = range.clone().map(own_wraps).sum();
+            let kind = if is_right {
+                DiffSectionKind::Added
+            } else {
+                DiffSectionKind::Removed
+            };
+            push(&mut y_idx, height, kind);
+            own += height;
+        } else {
+            other += range.clone().map(other_wraps).sum::<usize>();
+        }
+    }
+    push(&mut y_idx, other.saturating_sub(own), DiffSectionKind::NoCode);
+    sections
 }
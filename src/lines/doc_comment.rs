@@ -0,0 +1,97 @@
+use std::ops::Range;
+
+/// A doc comment skeleton replacing an empty doc comment line, see
+/// [crate::lines::DocLines::doc_comment_template]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DocCommentTemplate {
+    /// the doc comment line, without the line ending
+    pub range: Range<usize>,
+    pub text:  String,
+    /// the offset in `text` to put the cursor, at the summary
+    pub caret: usize
+}
+
+/// 函数签名的(参数名, 是否有返回值)
+pub type Signature = (Vec<String>, bool);
+
+const FUNCTION_KEYWORDS: &[&str] = &["fn", "def", "function", "func"];
+
+/// The signature of a function declared on one line, like
+/// `pub fn a(x: u8) -> u8 {`, without a syntax tree. `self` is skipped.
+pub fn signature_of_line(line: &str) -> Option<Signature> {
+    let open = line.find('(')?;
+    let is_function = line[..open]
+        .split(|c: char| !c.is_alphanumeric() && c != '_')
+        .any(|word| FUNCTION_KEYWORDS.contains(&word));
+    if !is_function {
+        return None;
+    }
+    let mut params = Vec::new();
+    let mut depth = 0usize;
+    let mut param_start = open + 1;
+    let mut close = None;
+    let mut prev = '(';
+    for (index, c) in line[open..].char_indices() {
+        let index = open + index;
+        match c {
+            '(' | '[' | '{' | '<' => depth += 1,
+            // `->`不是括号
+            '>' if prev == '-' => {},
+            ')' | ']' | '}' | '>' => {
+                depth = depth.saturating_sub(1);
+                if depth == 0 {
+                    params.push(&line[param_start..index]);
+                    close = Some(index);
+                    break;
+                }
+            },
+            ',' if depth == 1 => {
+                params.push(&line[param_start..index]);
+                param_start = index + 1;
+            },
+            _ => {}
+        }
+        prev = c;
+    }
+    let rest = line[close? + 1..].trim();
+    let has_return = rest.contains("->")
+        || (rest.starts_with(':') && !rest[1..].trim().is_empty());
+    let params = params.into_iter().filter_map(param_name).collect();
+    Some((params, has_return))
+}
+
+/// `x: u8` -> `x`, `int x` -> `x`, `x=1` -> `x`
+fn param_name(param: &str) -> Option<String> {
+    let param = param.split(['=', ':']).next()?.trim();
+    let name = param
+        .rsplit(|c: char| !c.is_alphanumeric() && c != '_')
+        .find(|x| !x.is_empty())?;
+    (name != "self" && name != "mut").then(|| name.to_string())
+}
+
+/// The doc comment lines with the parameters and return sections,
+/// like rustdoc. `leader` is the doc comment token, like `///`.
+pub fn template(
+    indent: &str,
+    leader: &str,
+    line_ending: &str,
+    (params, has_return): &Signature
+) -> (String, usize) {
+    let mut lines = vec![format!("{indent}{leader} ")];
+    let caret = lines[0].len();
+    if !params.is_empty() {
+        lines.push(format!("{indent}{leader}"));
+        lines.push(format!("{indent}{leader} # Arguments"));
+        lines.push(format!("{indent}{leader}"));
+        lines.extend(
+            params
+                .iter()
+                .map(|x| format!("{indent}{leader} * `{x}` -"))
+        );
+    }
+    if *has_return {
+        lines.push(format!("{indent}{leader}"));
+        lines.push(format!("{indent}{leader} # Returns"));
+    }
+    (lines.join(line_ending), caret)
+}
@@ -12,16 +12,142 @@ use floem::views::editor::core::{
     word::{CharClassification, get_char_property}
 };
 use itertools::Itertools;
-use lapce_xi_rope::{DeltaElement, Rope, RopeDelta};
+use lapce_xi_rope::{DeltaElement, Rope, RopeDelta, Transformer};
 use log::error;
 
-use crate::lines::{
-    buffer::{Buffer, InvalLines, rope_text::RopeText},
-    cursor::{Cursor, CursorMode, get_first_selection_after},
-    indent::{create_edit, create_outdent},
-    selection::{InsertDrift, SelRegion, Selection}
+use crate::{
+    config::SmartBackspaceConfig,
+    lines::{
+        buffer::{Buffer, InvalLines, rope_text::RopeText},
+        clipboard::CopyMeta,
+        cursor::{Cursor, CursorMode, get_first_selection_after},
+        indent::{create_edit, create_outdent},
+        search::Search,
+        selection::{InsertDrift, SelRegion, Selection}
+    }
 };
 
+/// The end of the closing half after `offset`, if `[start, offset)`
+/// deleted by backspace is the opening half of a pair, like `(|)`. The
+/// blanks between the halves are deleted too.
+fn pair_end_after(
+    buffer: &Buffer,
+    start: usize,
+    offset: usize
+) -> Result<Option<usize>> {
+    let delete_str = buffer.slice_to_cow(start..offset);
+    let matching_char = match delete_str.as_ref() {
+        "\"" | "'" | "`" => delete_str.chars().next(),
+        s if str_is_pair_left(s) => str_matching_pair(s),
+        _ => None
+    };
+    let Some(c) = matching_char else {
+        return Ok(None);
+    };
+    let line_end = buffer.line_end_offset(buffer.line_of_offset(offset), true)?;
+    let content = buffer.slice_to_cow(offset..line_end);
+    if !content.trim_start().starts_with(c) {
+        return Ok(None);
+    }
+    Ok(content.find(c).map(|index| offset + index + c.len_utf8()))
+}
+
+/// The leader to continue on the new line when Enter is pressed in a
+/// comment, without the indent, like `/// ` or ` * `. `line_prefix` is
+/// the text of the line before the cursor, only leading comments are
+/// continued. `in_block_comment` tells if a line starting with `*` is
+/// inside a `/* */` block, rather than e.g. a dereference.
+fn comment_leader(
+    line_prefix: &str,
+    comment_token: &str,
+    in_block_comment: impl FnOnce() -> bool
+) -> Option<String> {
+    let text = line_prefix.trim_start();
+    if !comment_token.is_empty() && text.starts_with(comment_token) {
+        // 文档注释的标记更长，如`///`、`//!`
+        let marker_len = text
+            .find(|c: char| !comment_token.contains(c) && c != '!')
+            .unwrap_or(text.len());
+        let rest = &text[marker_len..];
+        let gap = &rest[..rest.len() - rest.trim_start().len()];
+        let gap = if gap.is_empty() { " " } else { gap };
+        return Some(format!("{}{gap}", &text[..marker_len]));
+    }
+    // C风格的块注释
+    if comment_token == "//" && !text.contains("*/") {
+        if text.starts_with("/*") {
+            return Some(" * ".to_string());
+        } else if text.starts_with('*') && in_block_comment() {
+            return Some("* ".to_string());
+        }
+    }
+    None
+}
+
+/// The lines above `line` are the middle of a block comment: lines
+/// starting with `*`, up to an unclosed `/*`
+fn in_block_comment(buffer: &Buffer, line: usize) -> bool {
+    for line in (0..line).rev() {
+        let Ok(content) = buffer.line_content(line) else {
+            return false;
+        };
+        let content = content.trim();
+        if content.contains("*/") {
+            return false;
+        } else if content.starts_with("/*") {
+            return true;
+        } else if !content.starts_with('*') {
+            return false;
+        }
+    }
+    false
+}
+
+/// A selection of whole lines, surrounded by delimiters on their own
+/// lines
+struct SurroundLines {
+    indent:                String,
+    line_ending:           &'static str,
+    /// the selection ends with a line ending
+    ends_with_line_ending: bool
+}
+
+impl SurroundLines {
+    fn delimiters(&self, opening: char, closing: char) -> (String, String) {
+        let Self {
+            indent,
+            line_ending,
+            ..
+        } = self;
+        let closing = if self.ends_with_line_ending {
+            format!("{indent}{closing}{line_ending}")
+        } else {
+            format!("{line_ending}{indent}{closing}")
+        };
+        (format!("{indent}{opening}{line_ending}"), closing)
+    }
+}
+
+/// Some if the region covers whole lines, from a line start to a line
+/// start or the end of buffer. The indent is of the first line.
+fn surround_lines(buffer: &Buffer, region: &SelRegion) -> Option<SurroundLines> {
+    let (start, end) = (region.min(), region.max());
+    let start_line = buffer.line_of_offset(start);
+    let end_line = buffer.line_of_offset(end);
+    if start_line == end_line || buffer.offset_of_line(start_line).ok()? != start {
+        return None;
+    }
+    let ends_with_line_ending = buffer.offset_of_line(end_line).ok()? == end;
+    if !ends_with_line_ending && end != buffer.len() {
+        return None;
+    }
+    Some(SurroundLines {
+        indent: buffer.indent_on_line(start_line).ok()?,
+        line_ending: buffer.line_ending().get_chars(),
+        ends_with_line_ending
+    })
+}
+
 fn format_start_end(
     buffer: &Buffer,
     range: Range<usize>,
@@ -70,6 +196,10 @@ pub enum EditType {
     NormalizeLineEndings,
     Undo,
     Redo,
+    /// 联动范围的镜像编辑，合并到上一次编辑的撤销组
+    Linked,
+    /// 查找替换，全部替换也只有一个撤销步骤
+    Replace,
     Other
 }
 
@@ -77,17 +207,28 @@ impl EditType {
     /// Checks whether a new undo group should be created between two
     /// edits.
     pub fn breaks_undo_group(self, previous: EditType) -> bool {
+        if self == EditType::Linked {
+            return false;
+        }
         !((self == EditType::InsertChars || self == EditType::Delete)
             && self == previous)
     }
 }
 
+#[derive(Clone, Copy)]
 pub struct EditConf<'a> {
-    pub comment_token: &'a str,
-    pub modal:         bool,
-    pub smart_tab:     bool,
-    pub keep_indent:   bool,
-    pub auto_indent:   bool
+    pub comment_token:     &'a str,
+    pub modal:             bool,
+    pub smart_tab:         bool,
+    pub keep_indent:       bool,
+    pub auto_indent:       bool,
+    /// Enter between a pair of brackets puts the closing bracket on
+    /// its own line
+    pub split_brackets:    bool,
+    pub backspace:         SmartBackspaceConfig,
+    /// Enter in a line comment or doc comment continues the comment
+    /// leader, like `/// `
+    pub continue_comments: bool
 }
 
 pub struct Action {}
@@ -104,12 +245,14 @@ impl Action {
         let mut deltas = Vec::new();
         if let CursorMode::Insert(selection) = cursor.mode() {
             if s.chars().count() != 1 {
-                let (text, delta, inval_lines) =
-                    buffer.edit([(selection, s)], EditType::InsertChars);
-                let selection =
-                    selection.apply_delta(&delta, true, InsertDrift::Default);
-                deltas.push((text, delta, inval_lines));
-                cursor.set_mode(CursorMode::Insert(selection));
+                if let Some((text, delta, inval_lines)) =
+                    buffer.edit([(selection, s)], EditType::InsertChars)
+                {
+                    let selection =
+                        selection.apply_delta(&delta, true, InsertDrift::Default);
+                    deltas.push((text, delta, inval_lines));
+                    cursor.set_mode(CursorMode::Insert(selection));
+                }
             } else {
                 let c = s.chars().next().unwrap();
                 let matching_pair_type = matching_pair_direction(c);
@@ -146,16 +289,22 @@ impl Action {
                             || c == '\''
                             || c == '`')
                     {
-                        edits.push((
-                            Selection::region(region.min(), region.min()),
-                            c.to_string()
-                        ));
-                        edits_after.push((idx, match c {
+                        let closing = match c {
                             '"' => '"',
                             '\'' => '\'',
                             '`' => '`',
                             _ => matching_char(c).unwrap()
-                        }));
+                        };
+                        let (opening, closing) =
+                            match surround_lines(buffer, region) {
+                                Some(lines) => lines.delimiters(c, closing),
+                                None => (c.to_string(), closing.to_string())
+                            };
+                        edits.push((
+                            Selection::region(region.min(), region.min()),
+                            opening
+                        ));
+                        edits_after.push((idx, closing));
                         continue;
                     }
 
@@ -258,7 +407,7 @@ impl Action {
                                     '`' => '`',
                                     _ => matching_char(c).unwrap()
                                 };
-                                edits_after.push((idx, insert_after));
+                                edits_after.push((idx, insert_after.to_string()));
                             }
                         };
                     }
@@ -275,8 +424,11 @@ impl Action {
                     .map(|(selection, content)| (selection, content.as_str()))
                     .collect::<Vec<_>>();
 
-                let (text, delta, inval_lines) =
-                    buffer.edit(&edits, EditType::InsertChars);
+                let Some((text, delta, inval_lines)) =
+                    buffer.edit(&edits, EditType::InsertChars)
+                else {
+                    return deltas;
+                };
 
                 buffer.set_cursor_before(CursorMode::Insert(selection.clone()));
 
@@ -292,10 +444,7 @@ impl Action {
                     .iter()
                     .map(|(idx, content)| {
                         let region = &selection.regions()[*idx];
-                        (
-                            Selection::region(region.max(), region.max()),
-                            content.to_string()
-                        )
+                        (Selection::region(region.max(), region.max()), content)
                     })
                     .collect::<Vec<_>>();
 
@@ -305,37 +454,17 @@ impl Action {
                     .collect::<Vec<_>>();
 
                 if !edits_after.is_empty() {
-                    let (text, delta, inval_lines) =
-                        buffer.edit(&edits_after, EditType::InsertChars);
-                    deltas.push((text, delta, inval_lines));
-                }
-
-                // Adjust selection according to previous late edits
-                let mut adjustment = 0;
-                for region in selection.regions_mut().iter_mut().sorted_by(
-                    |region_a, region_b| region_a.start.cmp(&region_b.start)
-                ) {
-                    let new_region = SelRegion::new(
-                        region.start + adjustment,
-                        region.end + adjustment,
-                        None
-                    );
-
-                    if let Some(inserted) =
-                        edits_after.iter().find_map(|(selection, str)| {
-                            if selection.last_inserted().map(|r| r.start)
-                                == Some(region.start)
-                            {
-                                Some(str)
-                            } else {
-                                None
-                            }
-                        })
+                    if let Some((text, delta, inval_lines)) =
+                        buffer.edit(&edits_after, EditType::InsertChars)
                     {
-                        adjustment += inserted.len();
+                        // 光标和选区停在后插入的闭合字符之前
+                        selection = selection.apply_delta(
+                            &delta,
+                            false,
+                            InsertDrift::Default
+                        );
+                        deltas.push((text, delta, inval_lines));
                     }
-
-                    *region = new_region;
                 }
 
                 cursor.set_mode(CursorMode::Insert(selection));
@@ -372,16 +501,21 @@ impl Action {
         }
     }
 
+    /// `split_brackets`: Enter between a pair of brackets, like `{|}`,
+    /// also puts the closing bracket on its own line, in one edit.
+    /// `comment_token`: continue the comment leader of the line, None to
+    /// disable it.
     fn insert_new_line(
         buffer: &mut Buffer,
         cursor: &mut Cursor,
         selection: Selection,
         keep_indent: bool,
-        auto_indent: bool
+        auto_indent: bool,
+        split_brackets: bool,
+        comment_token: Option<&str>
     ) -> Vec<(Rope, RopeDelta, InvalLines)> {
+        // (选区, 插入内容, 光标在插入内容中的位置)
         let mut edits = Vec::with_capacity(selection.regions().len());
-        let mut extra_edits = Vec::new();
-        let mut shift = 0i32;
         let line_ending = buffer.line_ending().get_chars();
         for region in selection.regions() {
             let offset = region.max();
@@ -402,10 +536,18 @@ impl Action {
             let second_half = buffer.slice_to_cow(offset..line_end);
             let second_half_trim = second_half.trim();
 
+            let leader = comment_token.and_then(|token| {
+                comment_leader(&first_half, token, || {
+                    in_block_comment(buffer, line)
+                })
+            });
             // TODO: this could be done with 1 string
-            let new_line_content = {
+            let mut new_line_content = {
                 let indent_storage;
-                let indent = if auto_indent && has_unmatched_pair(&first_half) {
+                let indent = if let Some(leader) = &leader {
+                    indent_storage = format!("{line_indent}{leader}");
+                    &indent_storage
+                } else if auto_indent && has_unmatched_pair(&first_half) {
                     indent_storage =
                         format!("{}{}", line_indent, buffer.indent_unit());
                     &indent_storage
@@ -420,69 +562,64 @@ impl Action {
                 };
                 format!("{line_ending}{indent}")
             };
+            let caret = new_line_content.len();
+
+            let between_brackets = first_half
+                .chars()
+                .rev()
+                .find(|&c| c != ' ')
+                .filter(|c| matching_pair_direction(*c) == Some(true))
+                .and_then(matching_char)
+                .is_some_and(|c| second_half_trim.starts_with(c));
+            if split_brackets && between_brackets && leader.is_none() {
+                new_line_content.push_str(line_ending);
+                new_line_content.push_str(&line_indent);
+            }
 
             let selection = Selection::region(region.min(), region.max());
-
-            shift -= (region.max() - region.min()) as i32;
-            shift += new_line_content.len() as i32;
-
-            edits.push((selection, new_line_content));
-
-            if let Some(c) = first_half.chars().rev().find(|&c| c != ' ') {
-                if let Some(true) = matching_pair_direction(c) {
-                    if let Some(c) = matching_char(c) {
-                        if second_half_trim.starts_with(c) {
-                            let selection = Selection::caret(
-                                (region.max() as i32 + shift) as usize
-                            );
-                            let content = format!("{line_ending}{line_indent}",);
-                            extra_edits.push((selection, content));
-                        }
-                    }
-                }
-            }
+            edits.push((selection, new_line_content, caret));
         }
 
-        let edits = edits
-            .iter()
-            .map(|(selection, s)| (selection, s.as_str()))
-            .collect::<Vec<_>>();
-        let (text, delta, inval_lines) =
-            buffer.edit(&edits, EditType::InsertNewline);
-        let mut selection =
-            selection.apply_delta(&delta, true, InsertDrift::Default);
-
-        let mut deltas = vec![(text, delta, inval_lines)];
-
-        if !extra_edits.is_empty() {
-            let edits = extra_edits
+        let Some((text, delta, inval_lines)) = buffer.edit(
+            &edits
                 .iter()
-                .map(|(selection, s)| (selection, s.as_str()))
-                .collect::<Vec<_>>();
-            let (text, delta, inval_lines) =
-                buffer.edit(&edits, EditType::InsertNewline);
-            selection = selection.apply_delta(&delta, false, InsertDrift::Default);
-            deltas.push((text, delta, inval_lines));
+                .map(|(selection, s, _)| (selection, s.as_str()))
+                .collect::<Vec<_>>(),
+            EditType::InsertNewline
+        ) else {
+            return vec![];
+        };
+        let mut selection = Selection::new();
+        let mut shift = 0i32;
+        for (region, content, caret) in &edits {
+            let start = (region.min_offset() as i32 + shift) as usize;
+            selection.add_region(SelRegion::caret(start + caret));
+            shift += content.len() as i32
+                - (region.max_offset() - region.min_offset()) as i32;
         }
 
         cursor.set_mode(CursorMode::Insert(selection));
 
-        deltas
+        vec![(text, delta, inval_lines)]
     }
 
+    /// `count` extends vertical motions by lines, like `3dd`. The range of
+    /// other motions should include the count already.
     pub fn execute_motion_mode(
         cursor: &mut Cursor,
         buffer: &mut Buffer,
         motion_mode: MotionMode,
         range: Range<usize>,
         is_vertical: bool,
+        count: usize,
         register: &mut Register
     ) -> Vec<(Rope, RopeDelta, InvalLines)> {
+        let count = count.max(1);
         let mut deltas = Vec::new();
         match motion_mode {
             MotionMode::Delete { .. } => {
                 let Ok(range) =
-                    format_start_end(buffer, range.clone(), is_vertical, false, 1)
+                    format_start_end(buffer, range.clone(), is_vertical, false, count)
                 else {
                     error!("{range:?}");
                     return vec![];
@@ -496,14 +633,16 @@ impl Action {
                     }
                 });
                 let selection = Selection::region(range.start, range.end);
-                let (text, delta, inval_lines) =
-                    buffer.edit([(&selection, "")], EditType::MotionDelete);
-                cursor.apply_delta(&delta);
-                deltas.push((text, delta, inval_lines));
+                if let Some((text, delta, inval_lines)) =
+                    buffer.edit([(&selection, "")], EditType::MotionDelete)
+                {
+                    cursor.apply_delta(&delta);
+                    deltas.push((text, delta, inval_lines));
+                }
             },
             MotionMode::Yank { .. } => {
                 let Ok(range) =
-                    format_start_end(buffer, range.clone(), is_vertical, false, 1)
+                    format_start_end(buffer, range.clone(), is_vertical, false, count)
                 else {
                     error!("{range:?}");
                     return vec![];
@@ -519,23 +658,19 @@ impl Action {
             },
             MotionMode::Indent => {
                 let selection = Selection::region(range.start, range.end);
-                let Ok((text, delta, inval_lines)) =
-                    Self::do_indent(buffer, &selection)
-                else {
+                let Ok(rs) = Self::do_indent(buffer, &selection) else {
                     error!("{selection:?}");
                     return vec![];
                 };
-                deltas.push((text, delta, inval_lines));
+                deltas.extend(rs);
             },
             MotionMode::Outdent => {
                 let selection = Selection::region(range.start, range.end);
-                let Ok((text, delta, inval_lines)) =
-                    Self::do_outdent(buffer, &selection)
-                else {
+                let Ok(rs) = Self::do_outdent(buffer, &selection) else {
                     error!("{selection:?}");
                     return vec![];
                 };
-                deltas.push((text, delta, inval_lines));
+                deltas.extend(rs);
             }
         }
         deltas
@@ -553,7 +688,7 @@ impl Action {
         selection: &Selection,
         content: &str,
         mode: VisualMode
-    ) -> (Rope, RopeDelta, InvalLines) {
+    ) -> Option<(Rope, RopeDelta, InvalLines)> {
         if selection.len() > 1 {
             let line_ends: Vec<_> =
                 content.match_indices('\n').map(|(idx, _)| idx).collect();
@@ -636,6 +771,94 @@ impl Action {
         }
     }
 
+    /// Where a block is pasted: after the cursor in normal mode, at the
+    /// caret in insert mode. None for visual mode or several regions,
+    /// which are pasted line by line.
+    fn block_paste_offset(
+        cursor: &Cursor,
+        buffer: &Buffer
+    ) -> Result<Option<usize>> {
+        Ok(match cursor.mode() {
+            CursorMode::Normal(offset) => {
+                let line_end = buffer.offset_line_end(*offset, true)?;
+                Some(buffer.next_grapheme_offset(*offset, 1, line_end))
+            },
+            CursorMode::Insert(selection) => match selection.regions() {
+                [region] if region.is_caret() => Some(region.start),
+                _ => None
+            },
+            CursorMode::Visual { .. } => None
+        })
+    }
+
+    /// Paste `content` as a rectangle, one line of it for a line of
+    /// buffer starting at the column of `offset`
+    fn do_block_paste(
+        cursor: &mut Cursor,
+        buffer: &mut Buffer,
+        offset: usize,
+        content: &str
+    ) -> Result<Vec<(Rope, RopeDelta, InvalLines)>> {
+        let line = buffer.line_of_offset(offset);
+        let line_start = buffer.offset_of_line(line)?;
+        let col = buffer.slice_to_cow(line_start..offset).chars().count();
+        let content = content.strip_suffix('\n').unwrap_or(content);
+        let segments: Vec<&str> = content
+            .split('\n')
+            .map(|x| x.strip_suffix('\r').unwrap_or(x))
+            .collect();
+
+        let last_line = buffer.last_line();
+        let mut edits: Vec<(usize, String)> = Vec::with_capacity(segments.len());
+        let mut tail = String::new();
+        for (index, segment) in segments.iter().enumerate() {
+            let target = line + index;
+            if target > last_line {
+                tail.push('\n');
+                tail.push_str(&" ".repeat(col));
+                tail.push_str(segment);
+                continue;
+            }
+            let start = buffer.offset_of_line(target)?;
+            let end = buffer.line_end_offset(target, true)?;
+            let line_content = buffer.slice_to_cow(start..end);
+            let (offset, pad) = match line_content.char_indices().nth(col) {
+                Some((index, _)) => (start + index, 0),
+                None => (end, col - line_content.chars().count())
+            };
+            edits.push((offset, " ".repeat(pad) + segment));
+        }
+        if !tail.is_empty() {
+            // 插入的位置相同时合并，避免两次插入的顺序不确定
+            match edits.last_mut() {
+                Some((offset, text)) if *offset == buffer.len() => {
+                    text.push_str(&tail)
+                },
+                _ => edits.push((buffer.len(), tail))
+            }
+        }
+        let edits: Vec<(Selection, &str)> = edits
+            .iter()
+            .map(|(offset, text)| (Selection::caret(*offset), text.as_str()))
+            .collect();
+        let Some((text, delta, inval_lines)) = buffer.edit(&edits, EditType::Paste)
+        else {
+            return Ok(vec![]);
+        };
+        // 首行不会补空格，粘贴的起点不变
+        match cursor.mode() {
+            CursorMode::Insert(_) => {
+                let offset =
+                    offset + segments.first().map(|x| x.len()).unwrap_or(0);
+                cursor.set_mode(CursorMode::Insert(Selection::caret(offset)));
+            },
+            CursorMode::Normal(_) | CursorMode::Visual { .. } => {
+                cursor.set_mode(CursorMode::Normal(offset));
+            }
+        }
+        Ok(vec![(text, delta, inval_lines)])
+    }
+
     pub fn do_paste(
         cursor: &mut Cursor,
         buffer: &mut Buffer,
@@ -655,6 +878,11 @@ impl Action {
         buffer: &mut Buffer,
         data: &RegisterData
     ) -> Result<Vec<(Rope, RopeDelta, InvalLines)>> {
+        if matches!(data.mode, VisualMode::Blockwise) {
+            if let Some(offset) = Self::block_paste_offset(cursor, buffer)? {
+                return Self::do_block_paste(cursor, buffer, offset, &data.content);
+            }
+        }
         let mut deltas = Vec::new();
         match data.mode {
             VisualMode::Normal => {
@@ -669,12 +897,14 @@ impl Action {
                     },
                 };
                 let after = cursor.is_insert() || !data.content.contains('\n');
-                let (text, delta, inval_lines) = Self::compute_paste_edit(
+                let Some((text, delta, inval_lines)) = Self::compute_paste_edit(
                     buffer,
                     &selection,
                     &data.content,
                     data.mode
-                );
+                ) else {
+                    return Ok(deltas);
+                };
                 let selection =
                     selection.apply_delta(&delta, after, InsertDrift::Default);
                 deltas.push((text, delta, inval_lines));
@@ -697,11 +927,27 @@ impl Action {
                 }
             },
             VisualMode::Linewise | VisualMode::Blockwise => {
+                let mut content = data.content.clone();
+                if !content.ends_with('\n') {
+                    content.push('\n');
+                }
+                // 最后一行没有换行符时，在其后新起一行
+                let mut newline_before = false;
                 let (selection, content) = match cursor.mode() {
                     CursorMode::Normal(offset) => {
                         let line = buffer.line_of_offset(*offset);
                         let offset = buffer.offset_of_line(line + 1)?;
-                        (Selection::caret(offset), data.content.clone())
+                        if offset == buffer.len()
+                            && buffer.line_end_offset(line, true)? == offset
+                        {
+                            newline_before = true;
+                            content.pop();
+                            if content.ends_with('\r') {
+                                content.pop();
+                            }
+                            content.insert(0, '\n');
+                        }
+                        (Selection::caret(offset), content)
                     },
                     CursorMode::Insert(selection) => {
                         let mut selection = selection.clone();
@@ -713,20 +959,22 @@ impl Action {
                                 region.end = start;
                             }
                         }
-                        (selection, data.content.clone())
+                        (selection, content)
                     },
                     CursorMode::Visual { mode, .. } => {
                         let selection = cursor.edit_selection(buffer)?;
                         let data = match mode {
-                            VisualMode::Linewise => data.content.clone(),
-                            _ => "\n".to_string() + &data.content
+                            VisualMode::Linewise => content,
+                            _ => "\n".to_string() + &content
                         };
                         (selection, data)
                     }
                 };
-                let (text, delta, inval_lines) = Self::compute_paste_edit(
+                let Some((text, delta, inval_lines)) = Self::compute_paste_edit(
                     buffer, &selection, &content, data.mode
-                );
+                ) else {
+                    return Ok(deltas);
+                };
                 let selection = selection.apply_delta(
                     &delta,
                     cursor.is_insert(),
@@ -736,7 +984,7 @@ impl Action {
                 match cursor.mode() {
                     CursorMode::Normal(_) | CursorMode::Visual { .. } => {
                         let offset = selection.min_offset();
-                        let offset = if cursor.is_visual() {
+                        let offset = if cursor.is_visual() || newline_before {
                             offset + 1
                         } else {
                             offset
@@ -758,7 +1006,7 @@ impl Action {
     fn do_indent(
         buffer: &mut Buffer,
         selection: &Selection
-    ) -> Result<(Rope, RopeDelta, InvalLines)> {
+    ) -> Result<Option<(Rope, RopeDelta, InvalLines)>> {
         let indent = buffer.indent_unit();
         let mut edits = Vec::new();
 
@@ -791,7 +1039,7 @@ impl Action {
     fn do_outdent(
         buffer: &mut Buffer,
         selection: &Selection
-    ) -> Result<(Rope, RopeDelta, InvalLines)> {
+    ) -> Result<Option<(Rope, RopeDelta, InvalLines)>> {
         let indent = buffer.indent_unit();
         let mut edits = Vec::new();
 
@@ -867,29 +1115,92 @@ impl Action {
             .map(|(sel, content)| (sel, content.as_str()))
             .collect::<Vec<_>>();
 
-        let (text, delta, inval_lines) = buffer.edit(&edits, EditType::InsertChars);
+        let Some((text, delta, inval_lines)) =
+            buffer.edit(&edits, EditType::InsertChars)
+        else {
+            return vec![];
+        };
 
         *selection = selection.apply_delta(&delta, true, InsertDrift::Default);
 
         vec![(text, delta, inval_lines)]
     }
 
+    /// Record the text deleted by `selection` in modal editing, typed by
+    /// the visual mode
+    fn register_delete(
+        cursor: &Cursor,
+        buffer: &Buffer,
+        selection: &Selection,
+        register: &mut Register
+    ) -> Result<()> {
+        let data = match cursor.mode() {
+            CursorMode::Visual { .. } => cursor.yank(buffer)?,
+            CursorMode::Normal(_) => RegisterData {
+                content: buffer
+                    .slice_to_cow(selection.min_offset()..selection.max_offset())
+                    .to_string(),
+                mode:    VisualMode::Normal
+            },
+            CursorMode::Insert(_) => return Ok(())
+        };
+        if !data.content.is_empty() {
+            register.add(RegisterKind::Delete, data);
+        }
+        Ok(())
+    }
+
+    /// Execute `cmd` `count` times, the edits are in one undo group.
+    /// Stop when a repetition changes nothing. `copied` is the type of
+    /// the text copied to the clipboard, see [CopyMeta].
     #[allow(clippy::too_many_arguments)]
     pub fn do_edit<T: Clipboard>(
         cursor: &mut Cursor,
         buffer: &mut Buffer,
         cmd: &EditCommand,
+        count: usize,
         clipboard: &mut T,
         register: &mut Register,
+        copied: &mut Option<CopyMeta>,
         conf: EditConf
     ) -> Vec<(Rope, RopeDelta, InvalLines)> {
-        match Self::_do_edit(cursor, buffer, cmd, clipboard, register, conf) {
-            Ok(rs) => rs,
-            Err(err) => {
-                error!("{err:?}");
-                vec![]
+        let count = count.max(1);
+        let backward = matches!(cmd, EditCommand::DeleteBackward);
+        let join_register = count > 1
+            && !cursor.is_insert()
+            && (backward || matches!(cmd, EditCommand::DeleteForward));
+        // 外层要求合并时，如`.`重复
+        let join = buffer.join_undo_group();
+        let mut deleted = Vec::new();
+        let mut deltas = Vec::new();
+        for index in 0..count {
+            buffer.set_join_undo_group(join || index > 0);
+            match Self::_do_edit(
+                cursor, buffer, cmd, clipboard, register, copied, conf
+            ) {
+                Ok(rs) if rs.is_empty() && index > 0 => break,
+                Ok(rs) => deltas.extend(rs),
+                Err(err) => {
+                    error!("{err:?}");
+                    break;
+                }
+            }
+            if join_register {
+                deleted.push(register.unnamed.content.clone());
+            }
+        }
+        buffer.set_join_undo_group(join);
+        if deleted.len() > 1 {
+            // `3x`的寄存器内容为删除的所有字符，按原文的顺序
+            if backward {
+                deleted.reverse();
             }
+            register.add(RegisterKind::Delete, RegisterData {
+                content: deleted.concat(),
+                mode:    register.unnamed.mode
+            });
         }
+        deltas
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -899,12 +1210,16 @@ impl Action {
         cmd: &EditCommand,
         clipboard: &mut T,
         register: &mut Register,
+        copied: &mut Option<CopyMeta>,
         EditConf {
             comment_token,
             modal,
             smart_tab,
             keep_indent,
-            auto_indent
+            auto_indent,
+            split_brackets,
+            backspace,
+            continue_comments
         }: EditConf
     ) -> Result<Vec<(Rope, RopeDelta, InvalLines)>> {
         use EditCommand::*;
@@ -923,7 +1238,7 @@ impl Action {
                             let end = buffer.offset_of_line(end_line + 1)?;
                             let content =
                                 buffer.slice_to_cow(start..end).to_string();
-                            let (text, delta, inval_lines) = buffer.edit(
+                            let Some((text, delta, inval_lines)) = buffer.edit(
                                 [
                                     (&Selection::region(start, end), ""),
                                     (
@@ -934,7 +1249,9 @@ impl Action {
                                     )
                                 ],
                                 EditType::MoveLine
-                            );
+                            ) else {
+                                break;
+                            };
                             deltas.push((text, delta, inval_lines));
                             region.start -= previous_line_len;
                             region.end -= previous_line_len;
@@ -959,7 +1276,7 @@ impl Action {
                             let end = buffer.offset_of_line(end_line + 1)?;
                             let content =
                                 buffer.slice_to_cow(start..end).to_string();
-                            let (text, delta, inval_lines) = buffer.edit(
+                            let Some((text, delta, inval_lines)) = buffer.edit(
                                 [
                                     (
                                         &Selection::caret(
@@ -970,7 +1287,9 @@ impl Action {
                                     (&Selection::region(start, end), "")
                                 ],
                                 EditType::MoveLine
-                            );
+                            ) else {
+                                break;
+                            };
                             deltas.push((text, delta, inval_lines));
                             region.start += next_line_len;
                             region.end += next_line_len;
@@ -986,14 +1305,18 @@ impl Action {
                     cursor,
                     Selection::caret(offset),
                     keep_indent,
-                    auto_indent
+                    auto_indent,
+                    split_brackets,
+                    continue_comments.then_some(comment_token)
                 ),
                 CursorMode::Insert(selection) => Self::insert_new_line(
                     buffer,
                     cursor,
                     selection,
                     keep_indent,
-                    auto_indent
+                    auto_indent,
+                    split_brackets,
+                    continue_comments.then_some(comment_token)
                 ),
                 CursorMode::Visual {
                     start: _,
@@ -1028,18 +1351,20 @@ impl Action {
                             }
                         }
 
-                        let (text, delta, inval_lines) =
-                            buffer.edit(&edits, EditType::InsertChars);
-                        let selection = selection.apply_delta(
-                            &delta,
-                            true,
-                            InsertDrift::Default
-                        );
-                        deltas.push((text, delta, inval_lines));
-                        cursor.set_mode(CursorMode::Insert(selection));
-                    } else {
-                        let (text, delta, inval_lines) =
-                            buffer.edit([(&selection, "\t")], EditType::InsertChars);
+                        if let Some((text, delta, inval_lines)) =
+                            buffer.edit(&edits, EditType::InsertChars)
+                        {
+                            let selection = selection.apply_delta(
+                                &delta,
+                                true,
+                                InsertDrift::Default
+                            );
+                            deltas.push((text, delta, inval_lines));
+                            cursor.set_mode(CursorMode::Insert(selection));
+                        }
+                    } else if let Some((text, delta, inval_lines)) =
+                        buffer.edit([(&selection, "\t")], EditType::InsertChars)
+                    {
                         let selection = selection.apply_delta(
                             &delta,
                             true,
@@ -1053,8 +1378,11 @@ impl Action {
             },
             IndentLine => {
                 let selection = cursor.edit_selection(buffer)?;
-                let (text, delta, inval_lines) =
-                    Self::do_indent(buffer, &selection)?;
+                let Some((text, delta, inval_lines)) =
+                    Self::do_indent(buffer, &selection)?
+                else {
+                    return Ok(vec![]);
+                };
                 cursor.apply_delta(&delta);
                 vec![(text, delta, inval_lines)]
             },
@@ -1064,18 +1392,24 @@ impl Action {
                 if line < buffer.last_line() {
                     let start = buffer.line_end_offset(line, true)?;
                     let end = buffer.first_non_blank_character_on_line(line + 1)?;
-                    vec![buffer.edit(
-                        [(&Selection::region(start, end), " ")],
-                        EditType::Other
-                    )]
+                    buffer
+                        .edit(
+                            [(&Selection::region(start, end), " ")],
+                            EditType::Other
+                        )
+                        .into_iter()
+                        .collect()
                 } else {
                     vec![]
                 }
             },
             OutdentLine => {
                 let selection = cursor.edit_selection(buffer)?;
-                let (text, delta, inval_lines) =
-                    Self::do_outdent(buffer, &selection)?;
+                let Some((text, delta, inval_lines)) =
+                    Self::do_outdent(buffer, &selection)?
+                else {
+                    return Ok(vec![]);
+                };
                 cursor.apply_delta(&delta);
                 vec![(text, delta, inval_lines)]
             },
@@ -1122,7 +1456,7 @@ impl Action {
                     }
                 }
 
-                let (text, delta, inval_lines) = if had_comment {
+                let rs = if had_comment {
                     let mut selection = Selection::new();
                     for (line, indent, len) in lines.iter() {
                         let start = buffer.offset_of_line(*line)? + indent;
@@ -1144,6 +1478,9 @@ impl Action {
                         EditType::ToggleComment
                     )
                 };
+                let Some((text, delta, inval_lines)) = rs else {
+                    return Ok(vec![]);
+                };
                 cursor.apply_delta(&delta);
                 vec![(text, delta, inval_lines)]
             },
@@ -1186,6 +1523,7 @@ impl Action {
                     error!("fail");
                     return Ok(vec![]);
                 };
+                *copied = Some(CopyMeta::new(&data));
                 clipboard.put_string(data.content);
 
                 match cursor.mode() {
@@ -1205,6 +1543,7 @@ impl Action {
             },
             ClipboardCut => {
                 let data = cursor.yank(buffer)?;
+                *copied = Some(CopyMeta::new(&data));
                 clipboard.put_string(data.content);
 
                 let selection = if let CursorMode::Insert(mut selection) =
@@ -1224,8 +1563,11 @@ impl Action {
                     cursor.edit_selection(buffer)?
                 };
 
-                let (text, delta, inval_lines) =
-                    buffer.edit([(&selection, "")], EditType::Cut);
+                let Some((text, delta, inval_lines)) =
+                    buffer.edit([(&selection, "")], EditType::Cut)
+                else {
+                    return Ok(vec![]);
+                };
                 let selection =
                     selection.apply_delta(&delta, true, InsertDrift::Default);
                 cursor.update_selection(buffer, selection);
@@ -1233,10 +1575,11 @@ impl Action {
             },
             ClipboardPaste => {
                 if let Some(s) = clipboard.get_string() {
-                    let mode = if s.ends_with('\n') {
-                        VisualMode::Linewise
-                    } else {
-                        VisualMode::Normal
+                    let meta = copied.as_ref().filter(|x| x.content == s);
+                    let mode = match meta {
+                        Some(meta) => meta.mode,
+                        None if s.ends_with('\n') => VisualMode::Linewise,
+                        None => VisualMode::Normal
                     };
                     let data = RegisterData { content: s, mode };
                     Self::do_paste(cursor, buffer, &data)
@@ -1285,7 +1628,9 @@ impl Action {
                     cursor,
                     Selection::caret(offset),
                     keep_indent,
-                    auto_indent
+                    auto_indent,
+                    split_brackets,
+                    None
                 );
                 if line == 0 {
                     cursor.set_mode(CursorMode::Insert(Selection::caret(offset)));
@@ -1300,7 +1645,9 @@ impl Action {
                     cursor,
                     Selection::caret(offset),
                     keep_indent,
-                    auto_indent
+                    auto_indent,
+                    split_brackets,
+                    None
                 )
             },
             DeleteBackward => {
@@ -1321,85 +1668,45 @@ impl Action {
                         let indent = buffer.indent_unit();
                         let mut new_selection = Selection::new();
                         for region in selection.regions() {
-                            let new_region = if region.is_caret() {
-                                if indent.starts_with('\t') {
-                                    let new_end = buffer.move_left(
-                                        region.end,
-                                        Mode::Insert,
-                                        1
-                                    )?;
-                                    SelRegion::new(region.start, new_end, None)
-                                } else {
-                                    let line = buffer.line_of_offset(region.start);
-                                    let nonblank = buffer
-                                        .first_non_blank_character_on_line(line)?;
-                                    let (_, col) =
-                                        buffer.offset_to_line_col(region.start)?;
-                                    let count =
-                                        if region.start <= nonblank && col > 0 {
-                                            let r = col % indent.len();
-                                            if r == 0 { indent.len() } else { r }
-                                        } else {
-                                            1
-                                        };
-                                    let new_end = buffer.move_left(
-                                        region.end,
-                                        Mode::Insert,
-                                        count
-                                    )?;
-                                    SelRegion::new(region.start, new_end, None)
-                                }
+                            if !region.is_caret() {
+                                new_selection.add_region(*region);
+                                continue;
+                            }
+                            let line = buffer.line_of_offset(region.start);
+                            let nonblank =
+                                buffer.first_non_blank_character_on_line(line)?;
+                            let (_, col) = buffer.offset_to_line_col(region.start)?;
+                            // 在行首空白中删除到上一个缩进级别
+                            let count = if backspace.indent
+                                && !indent.starts_with('\t')
+                                && region.start <= nonblank
+                                && col > 0
+                            {
+                                let r = col % indent.len();
+                                if r == 0 { indent.len() } else { r }
                             } else {
-                                *region
+                                1
                             };
-                            new_selection.add_region(new_region);
-                        }
-
-                        let mut selection = new_selection;
-                        if selection.regions().len() == 1 {
-                            let delete_str = buffer
-                                .slice_to_cow(
-                                    selection.min_offset()..selection.max_offset()
-                                )
-                                .to_string();
-                            if str_is_pair_left(&delete_str)
-                                || delete_str == "\""
-                                || delete_str == "'"
-                                || delete_str == "`"
-                            {
-                                let matching_char = match delete_str.as_str() {
-                                    "\"" => Some('"'),
-                                    "'" => Some('\''),
-                                    "`" => Some('`'),
-                                    _ => str_matching_pair(&delete_str)
-                                };
-                                if let Some(c) = matching_char {
-                                    let offset = selection.max_offset();
-                                    let line = buffer.line_of_offset(offset);
-                                    let line_end =
-                                        buffer.line_end_offset(line, true)?;
-                                    let content = buffer
-                                        .slice_to_cow(offset..line_end)
-                                        .to_string();
-                                    if content.trim().starts_with(&c.to_string()) {
-                                        let index = content
-                                            .match_indices(c)
-                                            .next()
-                                            .unwrap()
-                                            .0;
-                                        selection = Selection::region(
-                                            selection.min_offset(),
-                                            offset + index + 1
-                                        );
-                                    }
-                                }
-                            }
+                            let new_end =
+                                buffer.move_left(region.end, Mode::Insert, count)?;
+                            let end = if backspace.pairs {
+                                pair_end_after(buffer, new_end, region.start)?
+                                    .unwrap_or(region.start)
+                            } else {
+                                region.start
+                            };
+                            new_selection
+                                .add_region(SelRegion::new(end, new_end, None));
                         }
-                        (selection, edit_type)
+                        (new_selection, edit_type)
                     }
                 };
-                let (text, delta, inval_lines) =
-                    buffer.edit([(&selection, "")], edit_type);
+                Self::register_delete(cursor, buffer, &selection, register)?;
+                let Some((text, delta, inval_lines)) =
+                    buffer.edit([(&selection, "")], edit_type)
+                else {
+                    return Ok(vec![]);
+                };
                 let selection =
                     selection.apply_delta(&delta, true, InsertDrift::Default);
                 cursor.update_selection(buffer, selection);
@@ -1437,8 +1744,12 @@ impl Action {
                         (new_selection, edit_type)
                     }
                 };
-                let (text, delta, inval_lines) =
-                    buffer.edit([(&selection, "")], edit_type);
+                Self::register_delete(cursor, buffer, &selection, register)?;
+                let Some((text, delta, inval_lines)) =
+                    buffer.edit([(&selection, "")], edit_type)
+                else {
+                    return Ok(vec![]);
+                };
                 let selection =
                     selection.apply_delta(&delta, true, InsertDrift::Default);
                 cursor.update_selection(buffer, selection);
@@ -1454,8 +1765,11 @@ impl Action {
                     1
                 )?;
                 let selection = Selection::region(range.start, range.end);
-                let (text, delta, inval_lines) =
-                    buffer.edit([(&selection, "")], EditType::Delete);
+                let Some((text, delta, inval_lines)) =
+                    buffer.edit([(&selection, "")], EditType::Delete)
+                else {
+                    return Ok(vec![]);
+                };
                 let selection =
                     selection.apply_delta(&delta, true, InsertDrift::Default);
                 cursor.set_mode(CursorMode::Insert(selection));
@@ -1480,8 +1794,11 @@ impl Action {
                         new_selection
                     }
                 };
-                let (text, delta, inval_lines) =
-                    buffer.edit([(&selection, "")], EditType::DeleteWord);
+                let Some((text, delta, inval_lines)) =
+                    buffer.edit([(&selection, "")], EditType::DeleteWord)
+                else {
+                    return Ok(vec![]);
+                };
                 let selection =
                     selection.apply_delta(&delta, true, InsertDrift::Default);
                 cursor.update_selection(buffer, selection);
@@ -1505,8 +1822,11 @@ impl Action {
                         new_selection
                     }
                 };
-                let (text, delta, inval_lines) =
-                    buffer.edit([(&selection, "")], EditType::DeleteWord);
+                let Some((text, delta, inval_lines)) =
+                    buffer.edit([(&selection, "")], EditType::DeleteWord)
+                else {
+                    return Ok(vec![]);
+                };
                 let selection =
                     selection.apply_delta(&delta, true, InsertDrift::Default);
                 cursor.update_selection(buffer, selection);
@@ -1531,8 +1851,11 @@ impl Action {
                         new_selection
                     }
                 };
-                let (text, delta, inval_lines) = buffer
-                    .edit([(&selection, "")], EditType::DeleteToBeginningOfLine);
+                let Some((text, delta, inval_lines)) = buffer
+                    .edit([(&selection, "")], EditType::DeleteToBeginningOfLine)
+                else {
+                    return Ok(vec![]);
+                };
                 let selection =
                     selection.apply_delta(&delta, true, InsertDrift::Default);
                 cursor.update_selection(buffer, selection);
@@ -1557,8 +1880,11 @@ impl Action {
                         selection
                     }
                 };
-                let (text, delta, inval_lines) =
-                    buffer.edit([(&selection, "")], EditType::DeleteToEndOfLine);
+                let Some((text, delta, inval_lines)) =
+                    buffer.edit([(&selection, "")], EditType::DeleteToEndOfLine)
+                else {
+                    return Ok(vec![]);
+                };
                 let selection =
                     selection.apply_delta(&delta, true, InsertDrift::Default);
                 cursor.update_selection(buffer, selection);
@@ -1566,8 +1892,11 @@ impl Action {
             },
             DeleteForwardAndInsert => {
                 let selection = cursor.edit_selection(buffer)?;
-                let (text, delta, inval_lines) =
-                    buffer.edit([(&selection, "")], EditType::Delete);
+                let Some((text, delta, inval_lines)) =
+                    buffer.edit([(&selection, "")], EditType::Delete)
+                else {
+                    return Ok(vec![]);
+                };
                 let selection =
                     selection.apply_delta(&delta, true, InsertDrift::Default);
                 cursor.set_mode(CursorMode::Insert(selection));
@@ -1586,8 +1915,11 @@ impl Action {
 
                     new_selection
                 };
-                let (text, delta, inval_lines) =
-                    buffer.edit([(&selection, "")], EditType::DeleteWord);
+                let Some((text, delta, inval_lines)) =
+                    buffer.edit([(&selection, "")], EditType::DeleteWord)
+                else {
+                    return Ok(vec![]);
+                };
                 let selection =
                     selection.apply_delta(&delta, true, InsertDrift::Default);
                 cursor.set_mode(CursorMode::Insert(selection));
@@ -1603,8 +1935,11 @@ impl Action {
                     1
                 )?;
                 let selection = Selection::region(range.start, range.end - 1); // -1 because we want to keep the line itself
-                let (text, delta, inval_lines) =
-                    buffer.edit([(&selection, "")], EditType::Delete);
+                let Some((text, delta, inval_lines)) =
+                    buffer.edit([(&selection, "")], EditType::Delete)
+                else {
+                    return Ok(vec![]);
+                };
                 let selection =
                     selection.apply_delta(&delta, true, InsertDrift::Default);
                 cursor.set_mode(CursorMode::Insert(selection));
@@ -1621,8 +1956,11 @@ impl Action {
                     SelRegion::new(cursor_offset, end_of_line_offset, None);
                 selection.add_region(new_region);
 
-                let (text, delta, inval_lines) =
-                    buffer.edit([(&selection, "")], EditType::Delete);
+                let Some((text, delta, inval_lines)) =
+                    buffer.edit([(&selection, "")], EditType::Delete)
+                else {
+                    return Ok(vec![]);
+                };
                 let selection =
                     selection.apply_delta(&delta, true, InsertDrift::Default);
                 cursor.set_mode(CursorMode::Insert(selection));
@@ -1761,6 +2099,75 @@ impl Action {
             }
         })
     }
+
+    /// Replace the first match at or after the start of the primary
+    /// selection, wrapping around, then select the next match
+    pub fn replace_next(
+        cursor: &mut Cursor,
+        buffer: &mut Buffer,
+        search: &Search,
+        replacement: &str
+    ) -> Vec<(Rope, RopeDelta, InvalLines)> {
+        let offset = match cursor.mode() {
+            CursorMode::Insert(selection) => selection
+                .first()
+                .map(|x| x.min())
+                .unwrap_or_else(|| cursor.offset()),
+            _ => cursor.offset()
+        };
+        let Some(found) = search.next_match(offset) else {
+            return vec![];
+        };
+        let text = search.replacement(buffer.text(), found, replacement);
+        let selection = Selection::region(found.start, found.end);
+        let Some((rope, delta, inval_lines)) =
+            buffer.edit(&[(selection, text.as_str())], EditType::Replace)
+        else {
+            return vec![];
+        };
+        // 下一个匹配是替换前的位置，经过delta转换
+        let mut transformer = Transformer::new(&delta);
+        let selection = match search.next_match(found.end) {
+            Some(next) if next != found => Selection::region(
+                transformer.transform(next.start, true),
+                transformer.transform(next.end, true)
+            ),
+            _ => Selection::caret(transformer.transform(found.end, true))
+        };
+        cursor.update_selection(buffer, selection);
+        vec![(rope, delta, inval_lines)]
+    }
+
+    /// Replace all matches in one edit, which is one undo step
+    pub fn replace_all(
+        cursor: &mut Cursor,
+        buffer: &mut Buffer,
+        search: &Search,
+        replacement: &str
+    ) -> Vec<(Rope, RopeDelta, InvalLines)> {
+        let texts: Vec<(Selection, String)> = search
+            .matches()
+            .iter()
+            .map(|x| {
+                let text = search.replacement(buffer.text(), *x, replacement);
+                (Selection::region(x.start, x.end), text)
+            })
+            .collect();
+        if texts.is_empty() {
+            return vec![];
+        }
+        let edits: Vec<(Selection, &str)> = texts
+            .iter()
+            .map(|(selection, text)| (selection.clone(), text.as_str()))
+            .collect();
+        let Some((rope, delta, inval_lines)) =
+            buffer.edit(&edits, EditType::Replace)
+        else {
+            return vec![];
+        };
+        cursor.apply_delta(&delta);
+        vec![(rope, delta, inval_lines)]
+    }
 }
 
 fn apply_undo_redo(
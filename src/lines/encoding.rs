@@ -1,3 +1,5 @@
+use anyhow::Result;
+
 /// Convert a utf8 offset into a utf16 offset, if possible  
 /// `text` is what the offsets are into
 pub fn offset_utf8_to_utf16(
@@ -94,3 +96,150 @@ pub fn offset_utf16_to_utf8(
         0
     }
 }
+
+/// A non-ascii char of a line, used by [`Utf16ColumnCache`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct NonAsciiChar {
+    utf8_col:  usize,
+    utf16_col: usize,
+    utf8_len:  usize,
+    utf16_len: usize
+}
+
+#[derive(Clone, Debug, Default)]
+struct CachedLine {
+    chars:     Vec<NonAsciiChar>,
+    /// the length of the line, with the line ending
+    utf8_len:  usize,
+    utf16_len: usize
+}
+
+impl CachedLine {
+    fn new(content: &str) -> Self {
+        let mut utf16_col = 0;
+        let mut chars = Vec::new();
+        for (utf8_col, ch) in content.char_indices() {
+            let utf16_len = ch.len_utf16();
+            if !ch.is_ascii() {
+                chars.push(NonAsciiChar {
+                    utf8_col,
+                    utf16_col,
+                    utf8_len: ch.len_utf8(),
+                    utf16_len
+                });
+            }
+            utf16_col += utf16_len;
+        }
+        Self {
+            chars,
+            utf8_len: content.len(),
+            utf16_len: utf16_col
+        }
+    }
+}
+
+/// Per-line cache of utf16 columns, so converting many LSP
+/// positions(diagnostics, inlay hints, semantic tokens) on one line
+/// doesn't iterate the line for every position.
+///
+/// Only the non-ascii chars of a line are recorded. Lines are filled
+/// lazily and invalidated by [`Utf16ColumnCache::invalidate`] with the
+/// changed lines of an edit. The cols are converted like
+/// [offset_utf8_to_utf16] and [offset_utf16_to_utf8], a col past the
+/// end of the line is not converted by the cache.
+#[derive(Clone, Debug, Default)]
+pub struct Utf16ColumnCache {
+    lines: Vec<Option<CachedLine>>
+}
+
+impl Utf16ColumnCache {
+    /// Lines [start_line, start_line + inval_count) are replaced by
+    /// `new_count` lines
+    pub fn invalidate(&mut self, start_line: usize, inval_count: usize, new_count: usize) {
+        if start_line >= self.lines.len() {
+            return;
+        }
+        let end = (start_line + inval_count).min(self.lines.len());
+        self.lines
+            .splice(start_line..end, std::iter::repeat(None).take(new_count));
+    }
+
+    pub fn clear(&mut self) {
+        self.lines.clear();
+    }
+
+    fn line(
+        &mut self,
+        line: usize,
+        content: impl FnOnce() -> Result<String>
+    ) -> Result<&CachedLine> {
+        if self.lines.len() <= line {
+            self.lines.resize(line + 1, None);
+        }
+        let cached = &mut self.lines[line];
+        if cached.is_none() {
+            *cached = Some(CachedLine::new(&content()?));
+        }
+        Ok(cached.get_or_insert_with(CachedLine::default))
+    }
+
+    /// utf8 col of the line -> utf16 col. A col in the middle of a char
+    /// is converted to the end of the char. None if the col is past the
+    /// end of the line. `content` is called only if the line is not
+    /// cached.
+    pub fn utf16_col(
+        &mut self,
+        line: usize,
+        utf8_col: usize,
+        content: impl FnOnce() -> Result<String>
+    ) -> Result<Option<usize>> {
+        let cached = self.line(line, content)?;
+        if utf8_col > cached.utf8_len {
+            return Ok(None);
+        }
+        let chars = &cached.chars;
+        let index = chars.partition_point(|x| x.utf8_col <= utf8_col);
+        let utf16_col = match index.checked_sub(1).and_then(|index| chars.get(index))
+        {
+            None => utf8_col,
+            Some(ch) if utf8_col == ch.utf8_col => ch.utf16_col,
+            Some(ch) if utf8_col < ch.utf8_col + ch.utf8_len => {
+                ch.utf16_col + ch.utf16_len
+            },
+            Some(ch) => {
+                ch.utf16_col + ch.utf16_len + (utf8_col - ch.utf8_col - ch.utf8_len)
+            }
+        };
+        Ok(Some(utf16_col))
+    }
+
+    /// utf16 col of the line -> utf8 col. A col in the middle of a char
+    /// is converted to the end of the char. None if the col is past the
+    /// end of the line. `content` is called only if the line is not
+    /// cached.
+    pub fn utf8_col(
+        &mut self,
+        line: usize,
+        utf16_col: usize,
+        content: impl FnOnce() -> Result<String>
+    ) -> Result<Option<usize>> {
+        let cached = self.line(line, content)?;
+        if utf16_col > cached.utf16_len {
+            return Ok(None);
+        }
+        let chars = &cached.chars;
+        let index = chars.partition_point(|x| x.utf16_col <= utf16_col);
+        let utf8_col = match index.checked_sub(1).and_then(|index| chars.get(index))
+        {
+            None => utf16_col,
+            Some(ch) if utf16_col == ch.utf16_col => ch.utf8_col,
+            Some(ch) if utf16_col < ch.utf16_col + ch.utf16_len => {
+                ch.utf8_col + ch.utf8_len
+            },
+            Some(ch) => {
+                ch.utf8_col + ch.utf8_len + (utf16_col - ch.utf16_col - ch.utf16_len)
+            }
+        };
+        Ok(Some(utf8_col))
+    }
+}
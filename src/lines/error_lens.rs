@@ -0,0 +1,359 @@
+use std::{collections::HashMap, ops::Range};
+
+use floem::peniko::Color;
+use lsp_types::{Diagnostic, DiagnosticSeverity};
+
+pub const ELLIPSIS: &str = "…";
+
+/// 同一行的多个诊断之间的分隔
+pub const SEPARATOR: &str = "  ·  ";
+
+/// The error lens of a screen line, the messages of the diagnostics
+/// ending on the line from the most severe, shown after the end of the
+/// line
+#[derive(Debug, Clone, PartialEq)]
+pub struct ErrorLens {
+    /// the origin line of the diagnostic
+    pub line:      usize,
+    /// the x of the text, after the end of the last visual line
+    pub x:         f64,
+    /// the y(in viewport) of the top of the first line
+    pub y:         f64,
+    pub severity:  DiagnosticSeverity,
+    pub color:     Color,
+    /// 未展开时只有一行(可能被截断)，展开后为完整消息的每一行。
+    /// 展开的消息不占用高度，覆盖在下方的行之上
+    pub lines:     Vec<ErrorLensLine>,
+    /// the count of the diagnostics ending on the line, the ones not
+    /// shown are counted by a `+N more` suffix
+    pub count:     usize,
+    /// the line is cut to the viewport width with an ellipsis
+    pub truncated: bool,
+    pub expanded:  bool,
+    /// the width of the widest line, the lens is hit within it
+    pub width:     f64
+}
+
+/// The text of an error lens and its measured width
+#[derive(Debug, Clone, PartialEq)]
+pub struct ErrorLensText {
+    pub lines:     Vec<ErrorLensLine>,
+    pub truncated: bool,
+    pub width:     f64
+}
+
+/// 缓存每行错误透镜的文本及宽度，文档版本或样式变化时失效
+#[derive(Clone, Default)]
+pub struct ErrorLensCache {
+    /// (buffer rev, style rev)
+    rev:   (u64, u64),
+    lines: HashMap<usize, CachedErrorLens>
+}
+
+#[derive(Clone)]
+struct CachedErrorLens {
+    diagnostics: Vec<Diagnostic>,
+    expanded:    bool,
+    max_width:   f64,
+    text:        Option<ErrorLensText>
+}
+
+impl ErrorLensCache {
+    /// The text of the line built by `build`, reused if the
+    /// diagnostics, the expansion and the available width are the same
+    pub fn get_or_build(
+        &mut self,
+        rev: (u64, u64),
+        line: usize,
+        diagnostics: &[Diagnostic],
+        expanded: bool,
+        max_width: f64,
+        build: impl FnOnce() -> Option<ErrorLensText>
+    ) -> Option<ErrorLensText> {
+        if self.rev != rev {
+            self.lines.clear();
+            self.rev = rev;
+        }
+        if let Some(cached) = self.lines.get(&line) {
+            if cached.expanded == expanded
+                && cached.max_width == max_width
+                && cached.diagnostics == diagnostics
+            {
+                return cached.text.clone();
+            }
+        }
+        let text = build();
+        self.lines.insert(line, CachedErrorLens {
+            diagnostics: diagnostics.to_vec(),
+            expanded,
+            max_width,
+            text: text.clone()
+        });
+        text
+    }
+}
+
+/// A line of the error lens without markdown
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ErrorLensLine {
+    pub text: String,
+    /// the code spans in `text`, e.g. drawn in the editor font
+    pub code: Vec<Range<usize>>
+}
+
+impl ErrorLensLine {
+    fn push_code(&mut self, code: &str) {
+        let start = self.text.len();
+        self.text.push_str(code);
+        self.code.push(start..self.text.len());
+    }
+}
+
+/// The lines of the message without markdown: emphasis, links,
+/// headings and quotes are stripped, whitespaces are collapsed, and
+/// code spans and fenced code are kept as code. Empty lines are
+/// dropped.
+pub fn format_message(message: &str) -> Vec<ErrorLensLine> {
+    let mut lines = Vec::new();
+    let mut in_fence = false;
+    for line in message.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_fence = !in_fence;
+            continue;
+        }
+        let line = if in_fence {
+            let mut code = ErrorLensLine::default();
+            code.push_code(line.trim_end());
+            code
+        } else {
+            format_line(trimmed)
+        };
+        if !line.text.is_empty() {
+            lines.push(line);
+        }
+    }
+    lines
+}
+
+/// The formatted message in one line, the lines are joined by a space
+pub fn single_line(message: &str) -> ErrorLensLine {
+    join(format_message(message), " ")
+}
+
+/// Join the lines by the separator, the code spans are moved
+pub fn join(
+    lines: impl IntoIterator<Item = ErrorLensLine>,
+    separator: &str
+) -> ErrorLensLine {
+    let mut joined = ErrorLensLine::default();
+    for line in lines {
+        if !joined.text.is_empty() {
+            joined.text.push_str(separator);
+        }
+        // 代码块的行保留了缩进
+        let text = line.text.trim_start();
+        let indent = line.text.len() - text.len();
+        let offset = joined.text.len();
+        joined.text.push_str(text);
+        joined.code.extend(line.code.into_iter().map(|x| {
+            x.start.saturating_sub(indent) + offset
+                ..x.end.saturating_sub(indent) + offset
+        }));
+    }
+    joined
+}
+
+/// The suffix of the hidden diagnostics, empty if there is none
+pub fn more(hidden: usize) -> String {
+    if hidden == 0 {
+        String::new()
+    } else {
+        format!(" +{hidden} more")
+    }
+}
+
+fn format_line(line: &str) -> ErrorLensLine {
+    let line = line.trim_start_matches(['>', ' ']);
+    let heading = line.trim_start_matches('#');
+    let line = if heading.len() < line.len() && heading.starts_with(' ') {
+        heading.trim_start()
+    } else {
+        line
+    };
+    let chars: Vec<char> = line.chars().collect();
+    let mut formatted = ErrorLensLine::default();
+    // 跳过的字符(闭合的强调符号、链接的地址)，(起始, 结束)
+    let mut skips: Vec<(usize, usize)> = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        if let Some(index) = skips.iter().position(|(start, _)| *start == i) {
+            i = skips.swap_remove(index).1;
+            continue;
+        }
+        let c = chars[i];
+        match c {
+            '\\' if chars.get(i + 1).is_some_and(char::is_ascii_punctuation) => {
+                formatted.text.push(chars[i + 1]);
+                i += 2;
+            },
+            '`' => {
+                let ticks = run_len(&chars, i);
+                match find_run(&chars, i + ticks, '`', ticks) {
+                    Some(close) => {
+                        let code: String =
+                            chars[i + ticks..close].iter().collect();
+                        formatted.push_code(code.trim());
+                        i = close + ticks;
+                    },
+                    None => {
+                        formatted.text.extend(&chars[i..i + ticks]);
+                        i += ticks;
+                    }
+                }
+            },
+            // 图片只保留描述
+            '!' if chars.get(i + 1) == Some(&'[')
+                && link_end(&chars, i + 1).is_some() =>
+            {
+                i += 1;
+            },
+            '[' => {
+                if let Some((label_end, end)) = link_end(&chars, i) {
+                    skips.push((label_end, end));
+                } else {
+                    formatted.text.push(c);
+                }
+                i += 1;
+            },
+            '*' | '_' | '~' => {
+                let len = run_len(&chars, i);
+                let close = (c != '~' || len == 2)
+                    .then(|| emphasis_close(&chars, i, len))
+                    .flatten();
+                match close {
+                    Some(close) => skips.push((close, close + len)),
+                    None => formatted.text.extend(&chars[i..i + len])
+                }
+                i += len;
+            },
+            c if c.is_whitespace() => {
+                if !formatted.text.is_empty() && !formatted.text.ends_with(' ') {
+                    formatted.text.push(' ');
+                }
+                i += 1;
+            },
+            _ => {
+                formatted.text.push(c);
+                i += 1;
+            }
+        }
+    }
+    let code_end = formatted.code.last().map(|x| x.end).unwrap_or_default();
+    if formatted.text.ends_with(' ') && formatted.text.len() > code_end {
+        formatted.text.pop();
+    }
+    formatted
+}
+
+/// The length of the run of the same char at `index`
+fn run_len(chars: &[char], index: usize) -> usize {
+    chars[index..].iter().take_while(|x| **x == chars[index]).count()
+}
+
+/// The start of the next run of exactly `len` `c` from `from`
+fn find_run(chars: &[char], from: usize, c: char, len: usize) -> Option<usize> {
+    let mut i = from;
+    while i < chars.len() {
+        if chars[i] == c {
+            let run = run_len(chars, i);
+            if run == len {
+                return Some(i);
+            }
+            i += run;
+        } else {
+            i += 1;
+        }
+    }
+    None
+}
+
+/// The start of the delimiter run closing the emphasis opened at
+/// `open`, like markdown, `snake_case` and `a * b` are not emphasis
+fn emphasis_close(chars: &[char], open: usize, len: usize) -> Option<usize> {
+    let prev = open.checked_sub(1).map(|x| chars[x]);
+    let next = chars.get(open + len)?;
+    if next.is_whitespace() || prev.is_some_and(char::is_alphanumeric) {
+        return None;
+    }
+    let mut i = open + len;
+    while i < chars.len() {
+        match chars[i] {
+            '`' => return None,
+            c if c == chars[open] => {
+                let run = run_len(chars, i);
+                let next = chars.get(i + run);
+                if run == len
+                    && !chars[i - 1].is_whitespace()
+                    && !next.is_some_and(|x| x.is_alphanumeric())
+                {
+                    return Some(i);
+                }
+                i += run;
+            },
+            _ => i += 1
+        }
+    }
+    None
+}
+
+/// (the index of `]`, the index after `)`) of the link `[label](url)`
+/// at `open`
+fn link_end(chars: &[char], open: usize) -> Option<(usize, usize)> {
+    let label_end = open + chars[open..].iter().position(|x| *x == ']')?;
+    if chars[open + 1..label_end].contains(&'[')
+        || chars.get(label_end + 1) != Some(&'(')
+    {
+        return None;
+    }
+    let url_end = label_end + chars[label_end..].iter().position(|x| *x == ')')?;
+    Some((label_end, url_end + 1))
+}
+
+/// Cut the line to `max_width` and append an ellipsis, None if the
+/// line fits. `glyphs` are the (byte end, right x) of the glyphs in
+/// order. The code spans are clipped.
+pub fn truncate(
+    line: &ErrorLensLine,
+    glyphs: &[(usize, f64)],
+    max_width: f64,
+    ellipsis_width: f64
+) -> Option<ErrorLensLine> {
+    let text = &line.text;
+    match glyphs.last() {
+        Some((_, width)) if *width > max_width => {},
+        _ => return None
+    }
+    let limit = max_width - ellipsis_width;
+    let end = glyphs
+        .iter()
+        .take_while(|(_, x)| *x <= limit)
+        .last()
+        .map(|(end, _)| (*end).min(text.len()))
+        .unwrap_or_default();
+    let end = (0..=end)
+        .rev()
+        .find(|x| text.is_char_boundary(*x))
+        .unwrap_or_default();
+    let kept = text[..end].trim_end();
+    let code = line
+        .code
+        .iter()
+        .map(|x| x.start..x.end.min(kept.len()))
+        .filter(|x| x.start < x.end)
+        .collect();
+    Some(ErrorLensLine {
+        text: format!("{kept}{ELLIPSIS}"),
+        code
+    })
+}
@@ -1,22 +1,74 @@
-use std::cmp::Ordering;
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::BTreeMap
+};
 
 use anyhow::Result;
-use floem::peniko::Color;
+use floem::{kurbo::Rect, peniko::Color};
 use im::HashMap;
 use lapce_xi_rope::Rope;
 use log::error;
-use lsp_types::Position;
+use lsp_types::{DocumentSymbol, Position, SymbolKind};
 use serde::{Deserialize, Serialize};
 
 use super::phantom_text::{PhantomText, PhantomTextKind};
-use crate::lines::{
-    buffer::{Buffer, rope_text::RopeText},
-    screen_lines::ScreenLines
+use crate::{
+    config::AutoFoldConfig,
+    lines::{
+        buffer::{Buffer, rope_text::RopeText},
+        screen_lines::ScreenLines
+    }
 };
 
 #[derive(Default, Clone, Serialize, Deserialize)]
 pub struct FoldingRanges(pub Vec<FoldingRange>);
 
+/// 大纲中的一项，如函数、类
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct OutlineItem {
+    pub start_line:   u32,
+    pub end_line:     u32,
+    /// contains other items, e.g. an impl or a class with methods
+    pub is_container: bool
+}
+
+impl OutlineItem {
+    /// The items of functions and types in the document symbols, in
+    /// the order of start line
+    pub fn from_symbols(symbols: &[DocumentSymbol]) -> Vec<Self> {
+        fn is_outline(symbol: &DocumentSymbol) -> bool {
+            matches!(
+                symbol.kind,
+                SymbolKind::FUNCTION
+                    | SymbolKind::METHOD
+                    | SymbolKind::CONSTRUCTOR
+                    | SymbolKind::CLASS
+                    | SymbolKind::STRUCT
+                    | SymbolKind::INTERFACE
+                    | SymbolKind::ENUM
+                    | SymbolKind::MODULE
+                    | SymbolKind::NAMESPACE
+                    | SymbolKind::OBJECT
+            )
+        }
+        fn push(symbols: &[DocumentSymbol], items: &mut Vec<OutlineItem>) {
+            for symbol in symbols.iter().filter(|x| is_outline(x)) {
+                let children = symbol.children.as_deref().unwrap_or_default();
+                items.push(OutlineItem {
+                    start_line:   symbol.range.start.line,
+                    end_line:     symbol.range.end.line,
+                    is_container: children.iter().any(is_outline)
+                });
+                push(children, items);
+            }
+        }
+        let mut items = Vec::new();
+        push(symbols, &mut items);
+        items.sort_by_key(|x| x.start_line);
+        items
+    }
+}
+
 #[derive(Default, Clone)]
 pub struct FoldedRanges(pub Vec<FoldedRange>);
 
@@ -79,10 +131,54 @@ impl FoldingRanges {
         }
         if let Some(range) = last_range {
             range.status = FoldingRangeStatus::Fold;
+            range.source = FoldingSource::User;
         }
         Ok(())
     }
 
+    /// The outermost folded range `(start, end)` hiding `offset`. The
+    /// start and the end of a folded range are visible, around the
+    /// placeholder.
+    pub fn folded_range_hiding(
+        &self,
+        offset: usize,
+        rope: &Rope
+    ) -> Result<Option<(usize, usize)>> {
+        for range in self.get_folded_range().0 {
+            let start = offset_of_position(rope, range.start)?;
+            let end = offset_of_position(rope, range.end)?;
+            if start < offset && offset < end {
+                return Ok(Some((start, end)));
+            } else if offset <= start {
+                break;
+            }
+        }
+        Ok(None)
+    }
+
+    /// Unfold all the folded ranges hiding `offset`, return their start
+    /// positions
+    pub fn unfold_offset(
+        &mut self,
+        offset: usize,
+        rope: &Rope
+    ) -> Result<Vec<Position>> {
+        let mut unfolded = Vec::new();
+        for item in self.0.iter_mut() {
+            if !item.status.is_folded() {
+                continue;
+            }
+            let start = offset_of_position(rope, item.start)?;
+            let end = offset_of_position(rope, item.end)?;
+            if start < offset && offset < end {
+                item.status = FoldingRangeStatus::Unfold;
+                item.source = FoldingSource::User;
+                unfolded.push(item.start);
+            }
+        }
+        Ok(unfolded)
+    }
+
     pub fn to_display_items(&self, lines: &ScreenLines) -> Vec<FoldingDisplayItem> {
         let mut folded = HashMap::new();
         let mut unfold_start: HashMap<u32, FoldingDisplayItem> = HashMap::new();
@@ -154,18 +250,143 @@ impl FoldingRanges {
         items
     }
 
+    /// The folding items keyed by origin line, with the rect of the
+    /// item in a gutter lane of `lane_width`
+    pub fn to_lane_items(
+        &self,
+        lines: &ScreenLines,
+        lane_width: f64
+    ) -> BTreeMap<usize, FoldingLaneItem> {
+        self.to_display_items(lines)
+            .into_iter()
+            .map(|item| {
+                (
+                    item.position.line as usize,
+                    FoldingLaneItem::new(item, lane_width, lines.line_height)
+                )
+            })
+            .collect()
+    }
+
+    /// Replace the ranges by the new ones from lsp. The status of the
+    /// same range is kept, and the locked ranges are kept even if
+    /// they are not in the new ones.
     pub fn update_ranges(&mut self, mut new: Vec<FoldingRange>) {
-        let folded_range = self.get_folded_range();
-        new.iter_mut().for_each(|x| folded_range.update_status(x));
+        let old = std::mem::take(&mut self.0);
+        for range in old {
+            if let Some(x) = new
+                .iter_mut()
+                .find(|x| x.start == range.start && x.end == range.end)
+            {
+                x.status = range.status;
+                x.source = range.source;
+                x.locked = range.locked;
+            } else if range.locked {
+                new.push(range);
+            }
+        }
+        new.sort_by(|x, y| x.start.cmp(&y.start));
         self.0 = new;
     }
 
+    /// Lock the range starting at `start`, return false if not found
+    pub fn set_locked(&mut self, start: Position, locked: bool) -> bool {
+        self.0
+            .iter_mut()
+            .find(|x| x.start == start)
+            .map(|x| x.locked = locked)
+            .is_some()
+    }
+
+    /// Move the ranges by the changed lines, the ranges intersect with
+    /// the change are removed.
+    pub fn apply_line_delta(
+        &mut self,
+        start_line: usize,
+        inval_count: usize,
+        new_count: usize
+    ) {
+        if inval_count == new_count {
+            return;
+        }
+        let start = start_line as u32;
+        let old_end = (start_line + inval_count) as u32;
+        // 变化之后的行都大于inval_count，不会溢出
+        let shift = |line: &mut u32| {
+            *line = (*line as usize + new_count - inval_count) as u32;
+        };
+        self.0.retain_mut(|x| {
+            if x.end.line < start {
+                true
+            } else if x.start.line >= old_end {
+                shift(&mut x.start.line);
+                shift(&mut x.end.line);
+                true
+            } else if x.start.line < start && x.end.line >= old_end {
+                shift(&mut x.end.line);
+                true
+            } else {
+                false
+            }
+        });
+    }
+
+    /// Fold the ranges by configuration, used when the folding ranges
+    /// first arrive
+    pub fn auto_fold(&mut self, config: &AutoFoldConfig) {
+        // 嵌套的范围的结束行
+        let mut ends: Vec<u32> = Vec::new();
+        for item in self.0.iter_mut() {
+            while ends.last().is_some_and(|end| *end < item.start.line) {
+                ends.pop();
+            }
+            ends.push(item.end.line);
+            let level = ends.len();
+            let fold = match item.kind {
+                Some(FoldingRangeKind::Imports) => config.imports,
+                Some(FoldingRangeKind::Comment) => config.comments,
+                _ => false
+            } || config.level.is_some_and(|x| level > x);
+            if fold {
+                item.status = FoldingRangeStatus::Fold;
+                item.source = FoldingSource::Auto;
+            }
+        }
+    }
+
+    /// Fold the bodies of the outline items and unfold the containers,
+    /// so that only the signatures are visible. The body of an item is
+    /// the range inside it reaching its last line.
+    pub fn fold_to_outline(&mut self, items: &[OutlineItem]) {
+        for item in items {
+            let Some(range) = self
+                .0
+                .iter_mut()
+                .filter(|x| {
+                    x.start.line >= item.start_line
+                        && x.start.line < x.end.line
+                        && x.end.line <= item.end_line
+                })
+                .max_by_key(|x| (x.end.line, Reverse(x.start.line)))
+            else {
+                continue;
+            };
+            range.status = if item.is_container {
+                FoldingRangeStatus::Unfold
+            } else {
+                FoldingRangeStatus::Fold
+            };
+            range.source = FoldingSource::User;
+        }
+    }
+
     pub fn update_folding_item(&mut self, item: FoldingDisplayItem) {
         match item.ty {
             FoldingDisplayType::UnfoldStart | FoldingDisplayType::Folded => {
                 self.0.iter_mut().find_map(|range| {
                     if range.start == item.position {
                         range.status.click();
+                        range.source = FoldingSource::User;
                         Some(())
                     } else {
                         None
@@ -176,6 +397,7 @@ impl FoldingRanges {
                 self.0.iter_mut().find_map(|range| {
                     if range.end == item.position {
                         range.status.click();
+                        range.source = FoldingSource::User;
                         Some(())
                     } else {
                         None
@@ -189,6 +411,7 @@ impl FoldingRanges {
         self.0.iter_mut().find_map(|range| {
             if range.start == position {
                 range.status.click();
+                range.source = FoldingSource::User;
                 Some(())
             } else {
                 None
@@ -276,6 +499,10 @@ impl FoldedRanges {
     }
 }
 
+fn offset_of_position(rope: &Rope, position: Position) -> Result<usize> {
+    Ok(rope.offset_of_line(position.line as usize)? + position.character as usize)
+}
+
 fn get_offset(buffer: &Buffer, positon: Position) -> Result<usize> {
     Ok(buffer.offset_of_line(positon.line as usize)? + positon.character as usize)
 }
@@ -376,7 +603,26 @@ pub struct FoldingRange {
     pub start:          Position,
     pub end:            Position,
     pub status:         FoldingRangeStatus,
-    pub collapsed_text: Option<String>
+    pub collapsed_text: Option<String>,
+    #[serde(default)]
+    pub kind:           Option<FoldingRangeKind>,
+    /// who folded/unfolded the range last time
+    #[serde(default)]
+    pub source:         FoldingSource,
+    /// 锁定的范围在lsp更新折叠范围时保留原状态，不会被删除
+    #[serde(default)]
+    pub locked:         bool
+}
+
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum FoldingSource {
+    /// provided by lsp, not touched yet
+    #[default]
+    Lsp,
+    /// folded/unfolded by user
+    User,
+    /// folded by `AutoFoldConfig`
+    Auto
 }
 
 impl FoldingRange {
@@ -387,6 +633,7 @@ impl FoldingRange {
             end_line,
             end_character,
             collapsed_text,
+            kind,
             ..
         } = value;
         let status = FoldingRangeStatus::Unfold;
@@ -400,7 +647,10 @@ impl FoldingRange {
                 character: end_character.unwrap_or_default()
             },
             status,
-            collapsed_text
+            collapsed_text,
+            kind: kind.map(FoldingRangeKind::from),
+            source: FoldingSource::Lsp,
+            locked: false
         }
     }
 }
@@ -442,6 +692,50 @@ pub struct FoldingDisplayItem {
     pub ty:       FoldingDisplayType
 }
 
+impl FoldingDisplayItem {
+    pub fn contains_y(&self, y: f64, line_height: f64) -> bool {
+        let y0 = self.y as f64;
+        y0 <= y && y < y0 + line_height
+    }
+}
+
+/// 折叠图标在任意宽度的gutter中的位置
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FoldingLaneItem {
+    pub item: FoldingDisplayItem,
+    pub rect: Rect
+}
+
+impl FoldingLaneItem {
+    pub fn new(
+        item: FoldingDisplayItem,
+        lane_width: f64,
+        line_height: f64
+    ) -> Self {
+        let y = item.y as f64;
+        Self {
+            item,
+            rect: Rect::new(0.0, y, lane_width, y + line_height)
+        }
+    }
+
+    pub fn is_folded(&self) -> bool {
+        self.item.ty == FoldingDisplayType::Folded
+    }
+}
+
+/// Hit-test the folding items by the y of screen lines
+pub fn folding_item_at_y(
+    items: &[FoldingDisplayItem],
+    y: f64,
+    line_height: f64
+) -> Option<FoldingDisplayItem> {
+    items
+        .iter()
+        .find(|item| item.contains_y(y, line_height))
+        .copied()
+}
+
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub enum FoldingDisplayType {
     UnfoldStart,
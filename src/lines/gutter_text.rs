@@ -0,0 +1,71 @@
+use anyhow::Result;
+use floem::peniko::Color;
+use lapce_xi_rope::{Rope, RopeDelta, Transformer};
+
+use crate::lines::buffer::rope_text::{RopeText, RopeTextRef};
+
+/// A short text shown in the gutter of a line, provided by the
+/// embedder, e.g. coverage, profiling or log timestamps
+#[derive(Debug, Clone, PartialEq)]
+pub struct GutterText {
+    pub text:  String,
+    pub color: Option<Color>
+}
+
+/// 每行的gutter文本，锚定在行首，随编辑移动。
+///
+/// The text of a deleted line is dropped, the new lines have no text.
+#[derive(Debug, Clone, Default)]
+pub struct GutterTextLayer {
+    /// (行首偏移, 文本)，按偏移排序，每行最多一个
+    anchors: Vec<(usize, GutterText)>
+}
+
+impl GutterTextLayer {
+    pub fn new(text: &Rope, lines: Vec<(usize, GutterText)>) -> Result<Self> {
+        let text = RopeTextRef::new(text);
+        let last_line = text.last_line();
+        let mut anchors: Vec<(usize, GutterText)> = lines
+            .into_iter()
+            .filter(|(line, _)| *line <= last_line)
+            .map(|(line, x)| Ok((text.offset_of_line(line)?, x)))
+            .collect::<Result<_>>()?;
+        anchors.sort_by_key(|(offset, _)| *offset);
+        anchors.dedup_by_key(|(offset, _)| *offset);
+        Ok(Self { anchors })
+    }
+
+    /// `text` is the content after the edit
+    pub fn apply_delta(&mut self, delta: &RopeDelta, text: &Rope) {
+        let mut transformer = Transformer::new(delta);
+        let mut anchors: Vec<(usize, GutterText)> =
+            Vec::with_capacity(self.anchors.len());
+        for (offset, x) in self.anchors.drain(..) {
+            // 在行首插入的文本在锚点之前
+            let offset = transformer.transform(offset, true);
+            let line = text.line_of_offset(offset);
+            // 删除整行后与下一行重合，保留下一行的文本
+            if let Some(last) = anchors.last_mut() {
+                if text.line_of_offset(last.0) == line {
+                    *last = (offset, x);
+                    continue;
+                }
+            }
+            anchors.push((offset, x));
+        }
+        self.anchors = anchors;
+    }
+
+    pub fn line(&self, text: &Rope, line: usize) -> Result<Option<&GutterText>> {
+        let text = RopeTextRef::new(text);
+        let start = text.offset_of_line(line)?;
+        let end = text.offset_of_line(line + 1)?;
+        let index = self.anchors.partition_point(|(offset, _)| *offset < start);
+        Ok(self
+            .anchors
+            .get(index)
+            // 最后一行的锚点可能在文本末尾
+            .filter(|(offset, _)| *offset < end || end == text.len())
+            .map(|(_, x)| x))
+    }
+}
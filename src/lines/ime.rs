@@ -0,0 +1,133 @@
+use floem::peniko::Color;
+use smallvec::SmallVec;
+
+use crate::lines::phantom_text::{PhantomText, PhantomTextKind};
+
+/// 换行显示为该字符，预编辑文本不会打断布局
+pub const PREEDIT_NEWLINE: char = '↵';
+
+/// 输入法分句的状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PreeditClause {
+    /// 未转换的输入
+    #[default]
+    Unconverted,
+    /// 已转换
+    Converted,
+    /// 正在转换（候选窗口对应的分句）
+    Target
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PreeditSegment {
+    pub text:   String,
+    pub clause: PreeditClause
+}
+
+impl PreeditSegment {
+    pub fn new(text: impl Into<String>, clause: PreeditClause) -> Self {
+        Self {
+            text: text.into(),
+            clause
+        }
+    }
+}
+
+/// The colors of clauses
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PreeditStyle {
+    pub under_line:     Color,
+    pub converted_line: Color,
+    pub target_bg:      Color
+}
+
+/// Preedit of input methods with several clauses, which may contain
+/// newlines
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ImePreedit {
+    /// buffer offset where the preedit is inserted
+    pub offset:   usize,
+    pub segments: Vec<PreeditSegment>,
+    /// The caret in the preedit text, byte offset of the joined text
+    /// of segments
+    pub cursor:   Option<usize>
+}
+
+impl ImePreedit {
+    pub fn new(offset: usize, segments: Vec<PreeditSegment>) -> Self {
+        Self {
+            offset,
+            segments,
+            cursor: None
+        }
+    }
+
+    pub fn with_cursor(mut self, cursor: usize) -> Self {
+        self.cursor = Some(cursor);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.segments.iter().all(|x| x.text.is_empty())
+    }
+
+    /// The joined text of segments
+    pub fn text(&self) -> String {
+        self.segments.iter().map(|x| x.text.as_str()).collect()
+    }
+
+    /// The length of displayed text, see [display_text]
+    pub fn display_len(&self) -> usize {
+        self.segments.iter().map(|x| display_text(&x.text).len()).sum()
+    }
+
+    /// The offset of caret in the displayed text
+    pub fn display_cursor(&self) -> Option<usize> {
+        let cursor = self.cursor?;
+        let text = self.text();
+        let cursor = cursor.min(text.len());
+        Some(display_text(text.get(..cursor)?).len())
+    }
+
+    /// One phantom text for every segment, in the order of segments
+    pub fn phantom_texts(
+        &self,
+        line: usize,
+        col: usize,
+        style: &PreeditStyle
+    ) -> SmallVec<[PhantomText; 2]> {
+        self.segments
+            .iter()
+            .filter(|x| !x.text.is_empty())
+            .map(|segment| {
+                let (under_line, bg) = match segment.clause {
+                    PreeditClause::Unconverted => (Some(style.under_line), None),
+                    PreeditClause::Converted => (Some(style.converted_line), None),
+                    PreeditClause::Target => {
+                        (Some(style.under_line), Some(style.target_bg))
+                    },
+                };
+                PhantomText {
+                    kind: PhantomTextKind::Ime,
+                    line,
+                    text: display_text(&segment.text),
+                    affinity: None,
+                    final_col: col,
+                    merge_col: col,
+                    font_size: None,
+                    fg: None,
+                    bg,
+                    under_line,
+                    col
+                }
+            })
+            .collect()
+    }
+}
+
+/// Newlines are displayed as [PREEDIT_NEWLINE]
+pub fn display_text(text: &str) -> String {
+    text.replace("\r\n", "\n")
+        .replace('\r', "\n")
+        .replace('\n', &PREEDIT_NEWLINE.to_string())
+}
@@ -1,12 +1,18 @@
 use cosmic_text::{
-    Affinity, BufferLine, Cursor, FontSystem, LayoutCursor, LayoutGlyph, LayoutLine,
-    LineEnding, Metrics, Scroll, ShapeBuffer, Shaping, Wrap
+    Affinity, BufferLine, CacheKeyFlags, Cursor, FontSystem, LayoutCursor,
+    LayoutGlyph, LayoutLine, LineEnding, Metrics, Scroll, ShapeBuffer, Shaping, Wrap,
+    fontdb
 };
 use floem::{
     kurbo::{Point, Size},
     peniko::Color,
     text::{AttrsList, FONT_SYSTEM, HitPoint, HitPosition, LayoutRun}
 };
+use std::{
+    collections::HashMap,
+    sync::{LazyLock, Mutex}
+};
+
 use unicode_segmentation::UnicodeSegmentation;
 use crate::lines::delta_compute::Offset;
 
@@ -184,7 +190,11 @@ pub struct TextLayout {
     monospace_width: Option<f32>,
     tab_width:       u16,
     /// Scratch buffer for shaping and laying out.
-    scratch:         ShapeBuffer
+    scratch:         ShapeBuffer,
+    /// 使用等宽度量时的布局，不经过shape
+    monospace:       Option<(MonospaceMetrics, Vec<LayoutLine>)>,
+    /// 懒排版时视口外的行，按等宽度量估算，未经字体整形
+    estimated:       bool
 }
 
 impl Clone for TextLayout {
@@ -201,6 +211,8 @@ impl Clone for TextLayout {
             monospace_width: self.monospace_width,
             tab_width:       self.tab_width,
             scratch:         ShapeBuffer::default(),
+            monospace:       self.monospace.clone(),
+            estimated:       self.estimated
         }
     }
 }
@@ -217,8 +229,43 @@ impl TextLayout {
         attrs_list: AttrsList,
         font_system: &mut FontSystem
     ) -> Self {
+        let mut text_layout = Self::unshaped(line, text, attrs_list);
+        text_layout.shape_until_scroll(font_system, false);
+        text_layout
+    }
+
+    /// Layout by the monospace metrics instead of the font system, the
+    /// x of columns and the wrapping do not depend on the installed
+    /// fonts
+    pub fn new_monospace<T: Into<String>>(
+        line: usize,
+        text: T,
+        attrs_list: AttrsList,
+        metrics: MonospaceMetrics
+    ) -> Self {
+        let mut text_layout = Self::unshaped(line, text, attrs_list);
+        text_layout.layout_monospace(metrics);
+        text_layout
+    }
+
+    /// Layout by the monospace metrics without shaping, the width and
+    /// the height of wrapped lines are estimated. See
+    /// [TextLayout::is_estimated]
+    pub fn new_estimated<T: Into<String>>(
+        line: usize,
+        text: T,
+        attrs_list: AttrsList,
+        metrics: MonospaceMetrics
+    ) -> Self {
+        let mut text_layout = Self::unshaped(line, text, attrs_list);
+        text_layout.layout_monospace(metrics);
+        text_layout.estimated = true;
+        text_layout
+    }
+
+    fn unshaped<T: Into<String>>(line: usize, text: T, attrs_list: AttrsList) -> Self {
         let ending = LineEnding::None;
-        let mut text_layout = Self {
+        Self {
             line,
             buffer: BufferLine::new(text, ending, attrs_list.0, Shaping::Advanced),
             width_opt: None,
@@ -229,17 +276,36 @@ impl TextLayout {
             wrap: Wrap::WordOrGlyph,
             monospace_width: None,
             tab_width: 8,
-            scratch: Default::default()
-        };
+            scratch: Default::default(),
+            monospace: None,
+            estimated: false
+        }
+    }
 
-        text_layout.shape_until_scroll(font_system, false);
-        text_layout
+    fn layout_monospace(&mut self, metrics: MonospaceMetrics) {
+        let lines = metrics.layout(
+            self.buffer.text(),
+            self.buffer.attrs_list(),
+            None,
+            self.wrap,
+            self.tab_width
+        );
+        self.monospace = Some((metrics, lines));
+    }
+
+    /// The layout is estimated instead of shaped by the fonts, it's
+    /// shaped when scrolled into the viewport
+    pub fn is_estimated(&self) -> bool {
+        self.estimated
     }
 
     pub fn line_layout_with_font_system(
         &mut self,
         font_system: &mut FontSystem
     ) -> &[LayoutLine] {
+        if let Some((_, lines)) = &self.monospace {
+            return lines;
+        }
         self.buffer.layout(
             font_system,
             self.metrics.font_size,
@@ -251,6 +317,9 @@ impl TextLayout {
     }
 
     pub fn line_layout(&self) -> &[LayoutLine] {
+        if let Some((_, lines)) = &self.monospace {
+            return lines;
+        }
         self.buffer.layout_opt().as_ref().expect("layout_opt empty")
     }
 
@@ -348,6 +417,10 @@ impl TextLayout {
         }
     }
 
+    pub fn tab_width(&self) -> usize {
+        self.tab_width as usize
+    }
+
     pub fn set_tab_width(&mut self, tab_width: usize) {
         let mut font_system = FONT_SYSTEM.lock();
         if tab_width == 0 {
@@ -356,6 +429,10 @@ impl TextLayout {
         let tab_width = tab_width as u16;
         if tab_width != self.tab_width {
             self.tab_width = tab_width;
+            if self.monospace.is_some() {
+                self.relayout(&mut font_system);
+                return;
+            }
             // Shaping must be reset when tab width is changed
             if self.buffer.shape_opt().is_some() && self.buffer.text().contains('\t')
             {
@@ -422,6 +499,17 @@ impl TextLayout {
     }
 
     fn relayout(&mut self, font_system: &mut FontSystem) {
+        if let Some((metrics, lines)) = &mut self.monospace {
+            *lines = metrics.layout(
+                self.buffer.text(),
+                self.buffer.attrs_list(),
+                self.width_opt,
+                self.wrap,
+                self.tab_width
+            );
+            self.redraw = true;
+            return;
+        }
         let line = &mut self.buffer;
         if line.shape_opt().is_some() {
             line.reset_layout();
@@ -687,6 +775,317 @@ impl TextLayout {
     }
 }
 
+/// 进程内共享的shape缓存，各文档、视图中相同内容、相同样式的行
+/// （import、license头、重复代码等）只shape一次
+static SHAPING_CACHE: LazyLock<Mutex<ShapingCache>> =
+    LazyLock::new(|| Mutex::new(ShapingCache::default()));
+
+const DEFAULT_SHAPING_CACHE_CAPACITY: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ShapingCacheStats {
+    pub hits:    u64,
+    pub misses:  u64,
+    pub entries: usize
+}
+
+impl ShapingCacheStats {
+    pub fn hit_rate(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            0.0
+        } else {
+            self.hits as f64 / total as f64
+        }
+    }
+}
+
+type ShapingCacheEntry = (AttrsList, Option<MonospaceMetrics>, TextLayout);
+
+/// Shaped text layouts keyed by (content, attrs, monospace metrics)
+#[derive(Clone)]
+pub struct ShapingCache {
+    layouts:  HashMap<String, Vec<ShapingCacheEntry>>,
+    entries:  usize,
+    capacity: usize,
+    hits:     u64,
+    misses:   u64
+}
+
+impl ShapingCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            layouts: HashMap::new(),
+            entries: 0,
+            capacity,
+            hits: 0,
+            misses: 0
+        }
+    }
+
+    pub fn get_or_shape(
+        &mut self,
+        line: usize,
+        text: &str,
+        attrs_list: AttrsList,
+        metrics: Option<MonospaceMetrics>,
+        font_system: &mut FontSystem
+    ) -> TextLayout {
+        if let Some((_, _, layout)) = self.layouts.get(text).and_then(|x| {
+            x.iter().find(|(attrs, layout_metrics, _)| {
+                attrs.0 == attrs_list.0 && *layout_metrics == metrics
+            })
+        }) {
+            self.hits += 1;
+            let mut layout = layout.clone();
+            layout.line = line;
+            return layout;
+        }
+        self.misses += 1;
+        let layout = match metrics {
+            Some(metrics) => {
+                TextLayout::new_monospace(line, text, attrs_list.clone(), metrics)
+            },
+            None => TextLayout::new_with_font_system(
+                line,
+                text,
+                attrs_list.clone(),
+                font_system
+            )
+        };
+        if self.capacity == 0 {
+            return layout;
+        }
+        if self.entries >= self.capacity {
+            // 简单处理：满了就整体清空
+            self.layouts.clear();
+            self.entries = 0;
+        }
+        self.layouts
+            .entry(text.to_string())
+            .or_default()
+            .push((attrs_list, metrics, layout.clone()));
+        self.entries += 1;
+        layout
+    }
+
+    pub fn stats(&self) -> ShapingCacheStats {
+        ShapingCacheStats {
+            hits:    self.hits,
+            misses:  self.misses,
+            entries: self.entries
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.layouts.clear();
+        self.entries = 0;
+        self.hits = 0;
+        self.misses = 0;
+    }
+
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        if self.entries > capacity {
+            self.layouts.clear();
+            self.entries = 0;
+        }
+    }
+}
+
+impl Default for ShapingCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_SHAPING_CACHE_CAPACITY)
+    }
+}
+
+/// Shape the text by the shared cache, or layout by the monospace
+/// metrics if set
+pub fn shape_with_cache(
+    line: usize,
+    text: &str,
+    attrs_list: AttrsList,
+    metrics: Option<MonospaceMetrics>,
+    font_system: &mut FontSystem
+) -> TextLayout {
+    match SHAPING_CACHE.lock() {
+        Ok(mut cache) => {
+            cache.get_or_shape(line, text, attrs_list, metrics, font_system)
+        },
+        Err(_) => match metrics {
+            Some(metrics) => {
+                TextLayout::new_monospace(line, text, attrs_list, metrics)
+            },
+            None => {
+                TextLayout::new_with_font_system(line, text, attrs_list, font_system)
+            },
+        }
+    }
+}
+
+/// The statistics of all the documents
+pub fn shaping_cache_stats() -> ShapingCacheStats {
+    SHAPING_CACHE
+        .lock()
+        .map(|cache| cache.stats())
+        .unwrap_or_default()
+}
+
+pub fn clear_shaping_cache() {
+    if let Ok(mut cache) = SHAPING_CACHE.lock() {
+        cache.clear();
+    }
+}
+
+/// 0 disables the cache
+pub fn set_shaping_cache_capacity(capacity: usize) {
+    if let Ok(mut cache) = SHAPING_CACHE.lock() {
+        cache.set_capacity(capacity);
+    }
+}
+
+/// 等宽的字体度量，布局不依赖系统字体，列到x的换算、换行在各平台一致。
+/// 用于测试及无界面的服务端
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MonospaceMetrics {
+    pub font_size:   f32,
+    pub line_height: f32,
+    /// 半角字符的宽度，全角字符为两倍
+    pub char_width:  f32,
+    pub ascent:      f32,
+    pub descent:     f32
+}
+
+impl MonospaceMetrics {
+    pub fn new(font_size: f32, line_height: f32) -> Self {
+        Self {
+            font_size,
+            line_height,
+            char_width: (font_size * 0.6).round(),
+            ascent: (font_size * 0.8).round(),
+            descent: (font_size * 0.2).round()
+        }
+    }
+
+    pub fn with_char_width(mut self, char_width: f32) -> Self {
+        self.char_width = char_width;
+        self
+    }
+
+    /// 0 for control chars, 2 for wide chars(CJK, emoji...)
+    pub fn columns(c: char) -> usize {
+        if c.is_control() {
+            return 0;
+        }
+        match c as u32 {
+            0x1100..=0x115F
+            | 0x2E80..=0xA4CF
+            | 0xAC00..=0xD7A3
+            | 0xF900..=0xFAFF
+            | 0xFE30..=0xFE4F
+            | 0xFF00..=0xFF60
+            | 0xFFE0..=0xFFE6
+            | 0x1F300..=0x1F64F
+            | 0x1F900..=0x1F9FF
+            | 0x20000..=0x3FFFD => 2,
+            _ => 1
+        }
+    }
+
+    /// Layout the text like cosmic-text, one glyph for every char
+    pub fn layout(
+        &self,
+        text: &str,
+        attrs_list: &cosmic_text::AttrsList,
+        width_opt: Option<f32>,
+        wrap: Wrap,
+        tab_width: u16
+    ) -> Vec<LayoutLine> {
+        let width = match wrap {
+            Wrap::None => f32::INFINITY,
+            _ => width_opt.unwrap_or(f32::INFINITY)
+        };
+        let tab = self.char_width * tab_width.max(1) as f32;
+        let mut lines = Vec::new();
+        let mut glyphs: Vec<LayoutGlyph> = Vec::new();
+        let mut x = 0.0;
+        // 最后一个空白之后的glyph，优先在这里换行
+        let mut break_at: Option<usize> = None;
+        for (start, c) in text.char_indices() {
+            let w = if c == '\t' {
+                tab - x % tab
+            } else {
+                self.char_width * Self::columns(c) as f32
+            };
+            if x + w > width && !glyphs.is_empty() && !c.is_whitespace() {
+                let index = match (wrap, break_at) {
+                    (Wrap::Glyph, _) | (Wrap::WordOrGlyph, None) => {
+                        Some(glyphs.len())
+                    },
+                    (_, Some(index)) => Some(index),
+                    (_, None) => None
+                };
+                if let Some(index) = index.filter(|x| *x > 0) {
+                    let mut rest = glyphs.split_off(index);
+                    lines.push(self.layout_line(glyphs));
+                    let offset = rest.first().map(|x| x.x).unwrap_or_default();
+                    for glyph in rest.iter_mut() {
+                        glyph.x -= offset;
+                    }
+                    x = rest.last().map(|x| x.x + x.w).unwrap_or_default();
+                    glyphs = rest;
+                    break_at = None;
+                }
+            }
+            glyphs.push(self.glyph(start, start + c.len_utf8(), x, w, attrs_list));
+            x += w;
+            if c.is_whitespace() {
+                break_at = Some(glyphs.len());
+            }
+        }
+        lines.push(self.layout_line(glyphs));
+        lines
+    }
+
+    fn layout_line(&self, glyphs: Vec<LayoutGlyph>) -> LayoutLine {
+        LayoutLine {
+            w: glyphs.last().map(|x| x.x + x.w).unwrap_or_default(),
+            max_ascent: self.ascent,
+            max_descent: self.descent,
+            line_height_opt: Some(self.line_height),
+            glyphs
+        }
+    }
+
+    fn glyph(
+        &self,
+        start: usize,
+        end: usize,
+        x: f32,
+        w: f32,
+        attrs_list: &cosmic_text::AttrsList
+    ) -> LayoutGlyph {
+        let attrs = attrs_list.get_span(start);
+        LayoutGlyph {
+            start,
+            end,
+            font_size: self.font_size,
+            line_height_opt: Some(self.line_height),
+            font_id: fontdb::ID::dummy(),
+            glyph_id: 0,
+            x,
+            y: 0.0,
+            w,
+            level: unicode_bidi::Level::ltr(),
+            x_offset: 0.0,
+            y_offset: 0.0,
+            color_opt: attrs.color_opt,
+            metadata: attrs.metadata,
+            cache_key_flags: CacheKeyFlags::empty()
+        }
+    }
+}
+
 /// An iterator of visible text lines, see [`LayoutRun`]
 #[derive(Debug)]
 pub struct LayoutRunIter<'b> {
@@ -717,8 +1116,13 @@ impl<'b> Iterator for LayoutRunIter<'b> {
             return None;
         }
         let line = &self.text_layout.buffer;
-        let layout = line.layout_opt().as_ref()?;
-        let shape = line.shape_opt().as_ref()?;
+        let (layout, rtl) = match &self.text_layout.monospace {
+            Some((_, lines)) => (lines.as_slice(), false),
+            None => (
+                line.layout_opt().as_ref()?.as_slice(),
+                line.shape_opt().as_ref()?.rtl
+            )
+        };
         assert_eq!(self.line_i, 0);
         while let Some(layout_line) = layout.get(self.layout_i) {
             self.layout_i += 1;
@@ -743,7 +1147,7 @@ impl<'b> Iterator for LayoutRunIter<'b> {
             return Some(LayoutRun {
                 line_i: self.line_i,
                 text: line.text(),
-                rtl: shape.rtl,
+                rtl,
                 glyphs: &layout_line.glyphs,
                 max_ascent: layout_line.max_ascent,
                 max_descent: layout_line.max_descent,
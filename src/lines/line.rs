@@ -243,6 +243,15 @@ impl Debug for VisualLine {
 }
 
 impl VisualLine {
+    /// 复制未变化的视觉行，行号和偏移随编辑移动
+    pub fn adjust(&self, offset: Offset, line_offset: Offset) -> Self {
+        let mut obj = self.clone();
+        offset.adjust(&mut obj.origin_interval.start);
+        offset.adjust(&mut obj.origin_interval.end);
+        line_offset.adjust(&mut obj.origin_line);
+        obj
+    }
+
     pub fn cmp_y(&self, other: &Self) -> Ordering {
         let rs = self.origin_folded_line.cmp(&other.origin_folded_line);
         match rs {
@@ -6,9 +6,13 @@ use crate::lines::{DocLines};
 use crate::lines::buffer::rope_text::RopeText;
 use crate::lines::delta_compute::{CopyDelta, Offset, OriginLinesDelta};
 use crate::lines::line::{OriginFoldedLine, OriginLine, VisualLine};
+use crate::lines::metrics::Phase;
 use anyhow::Result;
 use log::{error, debug};
 
+/// (旧的折叠行序号, 偏移变化, 行号变化)，None为重新计算的折叠行
+pub type CopiedFoldedLine = Option<(usize, Offset, Offset)>;
+
 impl DocLines {
     pub fn update_lines_new(
         &mut self,
@@ -16,98 +20,188 @@ impl DocLines {
     ) -> Result<()> {
         debug!("update_lines_new");
         self.clear();
-        self.visual_lines.clear();
+        self.hint_toggled_layout = None;
         self.line_height = self.config.line_height;
+        self.layout_window = self.lazy_layout_window();
 
+        let phantom_timer = self.time_phase(Phase::Phantom);
         let all_origin_lines =
             self.init_all_origin_line_new(&mut lines_delta)?;
+        drop(phantom_timer);
         check_origin_lines(&all_origin_lines, self.buffer().len());
-        let all_origin_folded_lines = self.init_all_origin_folded_line_new(&lines_delta, &all_origin_lines)?;
-        {
-            let mut visual_line_index = 0;
-            // while let Some(line) = origin_line_iter.next() {
-            for line in all_origin_folded_lines.iter() {
-                // duration += time.elapsed().unwrap();
-                let text_layout = &line.text_layout;
-                let origin_line_start = text_layout.phantom_text.line;
-                let origin_line_end = text_layout.phantom_text.last_line;
-                let origin_folded_line_index = line.line_index;
-
-                let origin_interval = Interval {
-                    start: self.buffer().offset_of_line(origin_line_start)?,
-                    end: self.buffer().offset_of_line(origin_line_end + 1)?,
-                };
+        let _layout_timer = self.time_phase(Phase::Layout);
+        let (all_origin_folded_lines, copied) = self.init_all_origin_folded_line_new(&lines_delta, &all_origin_lines)?;
+        let old_visual_lines = std::mem::take(&mut self.visual_lines);
+        self.visual_lines = self.init_visual_lines(&all_origin_folded_lines, &copied, &old_visual_lines)?;
 
-                let mut visual_offset_start = 0;
-                let mut visual_offset_end;
+        self.origin_lines = all_origin_lines;
+        self.origin_folded_lines = all_origin_folded_lines;
+        self.on_update_lines();
+        Ok(())
+    }
 
-                for (origin_folded_line_sub_index, layout) in
-                    text_layout.text.line_layout().iter().enumerate()
-                {
-                    if layout.glyphs.is_empty() {
-                        self.visual_lines.push(VisualLine {
-                            line_index: visual_line_index,
-                            origin_interval: Interval::new(
-                                origin_interval.end,
-                                origin_interval.end,
-                            ),
-                            visual_interval: Interval::new(
-                                visual_offset_start,
-                                visual_offset_start,
-                            ),
-                            origin_line: origin_line_start,
-                            origin_folded_line: origin_folded_line_index,
-                            origin_folded_line_sub_index: 0,
-                            /* text_layout:
-                                                                                        * text_layout.
-                                                                                        * clone(), */
-                        });
-                        continue;
+    /// 由折叠行生成视觉行，复制的折叠行的视觉行从`old_visual_lines`直接复制
+    fn init_visual_lines(
+        &self,
+        all_origin_folded_lines: &[OriginFoldedLine],
+        copied: &[CopiedFoldedLine],
+        old_visual_lines: &[VisualLine],
+    ) -> Result<Vec<VisualLine>> {
+        let mut visual_lines = Vec::with_capacity(old_visual_lines.len());
+        let mut visual_line_index = 0;
+        // while let Some(line) = origin_line_iter.next() {
+        for (line, copied) in all_origin_folded_lines.iter().zip(copied) {
+            // 复制的折叠行的视觉行也直接复制
+            if let Some((old_index, offset, line_offset)) = copied {
+                let start = old_visual_lines
+                    .partition_point(|x| x.origin_folded_line < *old_index);
+                let end = old_visual_lines
+                    .partition_point(|x| x.origin_folded_line <= *old_index);
+                if start < end {
+                    let base = old_visual_lines[start].line_index;
+                    for old in &old_visual_lines[start..end] {
+                        let mut visual_line = old.adjust(*offset, *line_offset);
+                        visual_line.line_index = visual_line_index + old.line_index - base;
+                        visual_line.origin_folded_line = line.line_index;
+                        visual_lines.push(visual_line);
                     }
-                    visual_offset_end =
-                        visual_offset_start + layout.glyphs.len() - 1;
-                    let offset_info = text_layout
-                        .phantom_text
-                        .cursor_position_of_final_col(visual_offset_start);
-                    let origin_interval_start =
-                        self.buffer().offset_of_line(offset_info.0)? + offset_info.1;
-                    let offset_info = text_layout
-                        .phantom_text
-                        .cursor_position_of_final_col(visual_offset_end);
+                    // 空的视觉行不占序号
+                    visual_line_index += old_visual_lines[start..end]
+                        .iter()
+                        .filter(|x| !x.visual_interval.is_empty())
+                        .count();
+                    continue;
+                }
+            }
+            // duration += time.elapsed().unwrap();
+            let text_layout = &line.text_layout;
+            let origin_line_start = text_layout.phantom_text.line;
+            let origin_line_end = text_layout.phantom_text.last_line;
+            let origin_folded_line_index = line.line_index;
+
+            let origin_interval = Interval {
+                start: self.buffer().offset_of_line(origin_line_start)?,
+                end: self.buffer().offset_of_line(origin_line_end + 1)?,
+            };
 
-                    let origin_interval_end =
-                        self.buffer().offset_of_line(offset_info.0)? + offset_info.1;
-                    let origin_interval = Interval {
-                        start: origin_interval_start,
-                        end: origin_interval_end + 1,
-                    };
+            let mut visual_offset_start = 0;
+            let mut visual_offset_end;
 
-                    self.visual_lines.push(VisualLine {
+            for (origin_folded_line_sub_index, layout) in
+                text_layout.text.line_layout().iter().enumerate()
+            {
+                if layout.glyphs.is_empty() {
+                    visual_lines.push(VisualLine {
                         line_index: visual_line_index,
-                        origin_interval,
-                        origin_line: origin_line_start,
-                        origin_folded_line: origin_folded_line_index,
-                        origin_folded_line_sub_index,
-                        // text_layout: text_layout.clone(),
+                        origin_interval: Interval::new(
+                            origin_interval.end,
+                            origin_interval.end,
+                        ),
                         visual_interval: Interval::new(
                             visual_offset_start,
-                            visual_offset_end + 1,
+                            visual_offset_start,
                         ),
+                        origin_line: origin_line_start,
+                        origin_folded_line: origin_folded_line_index,
+                        origin_folded_line_sub_index: 0,
+                        /* text_layout:
+                                                                                    * text_layout.
+                                                                                    * clone(), */
                     });
-
-                    visual_offset_start = visual_offset_end;
-                    visual_line_index += 1;
+                    continue;
                 }
+                visual_offset_end =
+                    visual_offset_start + layout.glyphs.len() - 1;
+                let offset_info = text_layout
+                    .phantom_text
+                    .cursor_position_of_final_col(visual_offset_start);
+                let origin_interval_start =
+                    self.buffer().offset_of_line(offset_info.0)? + offset_info.1;
+                let offset_info = text_layout
+                    .phantom_text
+                    .cursor_position_of_final_col(visual_offset_end);
+
+                let origin_interval_end =
+                    self.buffer().offset_of_line(offset_info.0)? + offset_info.1;
+                let origin_interval = Interval {
+                    start: origin_interval_start,
+                    end: origin_interval_end + 1,
+                };
+
+                visual_lines.push(VisualLine {
+                    line_index: visual_line_index,
+                    origin_interval,
+                    origin_line: origin_line_start,
+                    origin_folded_line: origin_folded_line_index,
+                    origin_folded_line_sub_index,
+                    // text_layout: text_layout.clone(),
+                    visual_interval: Interval::new(
+                        visual_offset_start,
+                        visual_offset_end + 1,
+                    ),
+                });
+
+                visual_offset_start = visual_offset_end;
+                visual_line_index += 1;
             }
         }
+        Ok(visual_lines)
+    }
 
-        self.origin_lines = all_origin_lines;
-        self.origin_folded_lines = all_origin_folded_lines;
+    /// 只重新计算指定的行，行的内容未变(如幽灵文本的显隐)，其余的行直接复用
+    pub fn update_lines_of(&mut self, lines: &[usize]) -> Result<()> {
+        debug!("update_lines_of {lines:?}");
+        self.clear();
+        self.hint_toggled_layout = None;
+        self.line_height = self.config.line_height;
+
+        let phantom_timer = self.time_phase(Phase::Phantom);
+        let origin_lines = lines
+            .iter()
+            .filter(|x| **x < self.origin_lines.len())
+            .map(|x| self.init_origin_line(*x))
+            .collect::<Result<Vec<_>>>()?;
+        for line in origin_lines {
+            let index = line.line_index;
+            self.origin_lines[index] = line;
+        }
+        drop(phantom_timer);
+        let _layout_timer = self.time_phase(Phase::Layout);
+        let mut folded_indexes: Vec<usize> = lines
+            .iter()
+            .map(|x| self.origin_folded_lines.partition_point(|folded| folded.origin_line_end < *x))
+            .filter(|x| *x < self.origin_folded_lines.len())
+            .collect();
+        folded_indexes.sort_unstable();
+        folded_indexes.dedup();
+        let family =
+            Cow::Owned(FamilyOwned::parse_list(&self.config.font_family).collect());
+        let attrs = self.init_attrs_with_color(&family);
+        let folded_lines = folded_indexes
+            .iter()
+            .map(|x| {
+                let start = self.origin_folded_lines[*x].origin_line_start;
+                self.init_folded_line(start, &self.origin_lines, attrs, *x)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        for folded_line in folded_lines {
+            let index = folded_line.line_index;
+            self.origin_folded_lines[index] = folded_line;
+        }
+        let copied: Vec<CopiedFoldedLine> = (0..self.origin_folded_lines.len())
+            .map(|x| {
+                folded_indexes
+                    .binary_search(&x)
+                    .is_err()
+                    .then_some((x, Offset::None, Offset::None))
+            })
+            .collect();
+        let old_visual_lines = std::mem::take(&mut self.visual_lines);
+        self.visual_lines = self.init_visual_lines(&self.origin_folded_lines, &copied, &old_visual_lines)?;
         self.on_update_lines();
         Ok(())
     }
 
-
     pub fn init_all_origin_line_new(
         &self,
         lines_delta: &mut OriginLinesDelta,
@@ -184,49 +278,39 @@ impl DocLines {
             .font_size(font_size as f32)
             .line_height(LineHeightValue::Px(self.line_height as f32))
     }
+    /// 复制的折叠行同时返回旧的折叠行序号与偏移，用于复制视觉行
     pub fn init_all_origin_folded_line_new(
         &mut self,
         lines_delta: &OriginLinesDelta, all_origin_lines: &[OriginLine],
-    ) -> Result<Vec<OriginFoldedLine>> {
+    ) -> Result<(Vec<OriginFoldedLine>, Vec<CopiedFoldedLine>)> {
         let family =
             Cow::Owned(FamilyOwned::parse_list(&self.config.font_family).collect());
         let attrs = self.init_attrs_with_color(&family);
         let mut origin_folded_lines = Vec::with_capacity(self.buffer().num_lines());
+        let mut copied = Vec::with_capacity(self.buffer().num_lines());
         let mut x = 0;
         let last_line = self.buffer().last_line();
-        if let CopyDelta::Copy {
-            offset, line_offset, copy_line, ..
-        } = lines_delta.copy_line_start {
-            let last_line = line_offset.adjust_new(copy_line.end);
-            let origin_folded_line = self.compute_copy_origin_folded_line(copy_line, offset, line_offset);
-            while x < last_line {
-                let line = if let Some((folded_line, offset, line_offset)) = origin_folded_line.get(&x) {
-                    folded_line.adjust(*offset, *line_offset, origin_folded_lines.len())
-                } else {
-                    self.init_folded_line(x, all_origin_lines, attrs, origin_folded_lines.len())?
-                };
-                x = line.origin_line_end + 1;
-                origin_folded_lines.push(line);
+        // 编辑范围前后未变化的折叠行直接复制
+        let mut copies = HashMap::new();
+        for copy_delta in [lines_delta.copy_line_start, lines_delta.copy_line_end] {
+            if let CopyDelta::Copy {
+                offset, line_offset, copy_line, ..
+            } = copy_delta {
+                copies.extend(self.compute_copy_origin_folded_line(copy_line, offset, line_offset));
             }
         }
-        let origin_folded_line = if let CopyDelta::Copy {
-            offset, line_offset, copy_line, ..
-        } = lines_delta.copy_line_start {
-            self.compute_copy_origin_folded_line(copy_line, offset, line_offset)
-        } else {
-            HashMap::new()
-        };
-
         while x <= last_line {
-            let line = if let Some((folded_line, offset, line_offset)) = origin_folded_line.get(&x) {
+            let line = if let Some((folded_line, offset, line_offset)) = copies.get(&x) {
+                copied.push(Some((folded_line.line_index, *offset, *line_offset)));
                 folded_line.adjust(*offset, *line_offset, origin_folded_lines.len())
             } else {
+                copied.push(None);
                 self.init_folded_line(x, all_origin_lines, attrs, origin_folded_lines.len())?
             };
             x = line.origin_line_end + 1;
             origin_folded_lines.push(line);
         }
-        Ok(origin_folded_lines)
+        Ok((origin_folded_lines, copied))
     }
 
     fn init_folded_line(&self, current_origin_line: usize, all_origin_lines: &[OriginLine], attrs: Attrs, origin_folded_line_index: usize) -> Result<OriginFoldedLine> {
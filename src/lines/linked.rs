@@ -0,0 +1,151 @@
+use anyhow::Result;
+use lapce_xi_rope::{Interval, Rope, RopeDelta, Transformer};
+use regex::Regex;
+
+use crate::lines::selection::{SelRegion, Selection};
+
+/// 联动编辑的范围，如片段的占位符、lsp的linkedEditingRange。
+///
+/// The ranges of a group mirror each other: an edit inside one range is
+/// copied to the others. Edits at the boundary of a range extend it.
+#[derive(Clone, Debug, Default)]
+pub struct LinkedRanges {
+    groups:       Vec<Vec<Interval>>,
+    /// the active group, like the current placeholder of snippet
+    current:      usize,
+    /// lsp `wordPattern`, the content of ranges must match it
+    word_pattern: Option<Regex>
+}
+
+impl LinkedRanges {
+    pub fn new(groups: Vec<Vec<Interval>>) -> Self {
+        let groups = groups
+            .into_iter()
+            .map(|mut x| {
+                x.sort_by_key(|x| x.start);
+                x
+            })
+            .filter(|x| !x.is_empty())
+            .collect();
+        Self {
+            groups,
+            current: 0,
+            word_pattern: None
+        }
+    }
+
+    /// The pattern must match the whole content of a range
+    pub fn with_word_pattern(mut self, pattern: &str) -> Result<Self> {
+        self.word_pattern = Some(Regex::new(&format!("^(?:{pattern})$"))?);
+        Ok(self)
+    }
+
+    pub fn groups(&self) -> &[Vec<Interval>] {
+        &self.groups
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    pub fn current_group(&self) -> Option<&[Interval]> {
+        self.groups.get(self.current).map(|x| x.as_slice())
+    }
+
+    /// Move to the next group, return false if it's the last one
+    pub fn next(&mut self) -> bool {
+        if self.current + 1 < self.groups.len() {
+            self.current += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn prev(&mut self) -> bool {
+        if self.current > 0 {
+            self.current -= 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Select all ranges of the current group, one region for a range
+    pub fn current_selection(&self) -> Option<Selection> {
+        let group = self.current_group()?;
+        let mut selection = Selection::new();
+        for range in group {
+            selection.add_region(SelRegion::new(range.start, range.end, None));
+        }
+        Some(selection)
+    }
+
+    /// The group and the index of the range containing `[start, end]`
+    pub fn find(&self, start: usize, end: usize) -> Option<(usize, usize)> {
+        self.groups.iter().enumerate().find_map(|(group, ranges)| {
+            ranges
+                .iter()
+                .position(|x| x.start <= start && end <= x.end)
+                .map(|index| (group, index))
+        })
+    }
+
+    /// Move the ranges by `delta`. Return the range containing the
+    /// edit, None if the edit is out of all ranges.
+    pub fn apply_delta(&mut self, delta: &RopeDelta) -> Option<(usize, usize)> {
+        let (iv, _) = delta.summary();
+        let edited = self.find(iv.start, iv.end);
+        let mut transformer = Transformer::new(delta);
+        for ranges in self.groups.iter_mut() {
+            for range in ranges.iter_mut() {
+                let start = transformer.transform(range.start, false);
+                let end = transformer.transform(range.end, true);
+                *range = Interval::new(start, end.max(start));
+            }
+        }
+        edited
+    }
+
+    /// The edits to make the other ranges of the group same as the range
+    /// of `index`. `text` is the content after edit. None if the content
+    /// does not match the word pattern any more.
+    pub fn mirror_edits(
+        &self,
+        (group, index): (usize, usize),
+        text: &Rope
+    ) -> Option<Vec<(Selection, String)>> {
+        let ranges = self.groups.get(group)?;
+        let source = ranges.get(index)?;
+        let content = text.slice_to_cow(source.start..source.end);
+        if let Some(pattern) = &self.word_pattern {
+            if !pattern.is_match(&content) {
+                return None;
+            }
+        }
+        let edits = ranges
+            .iter()
+            .enumerate()
+            .filter(|(i, range)| {
+                *i != index && text.slice_to_cow(range.start..range.end) != content
+            })
+            .map(|(_, range)| {
+                (Selection::region(range.start, range.end), content.to_string())
+            })
+            .collect();
+        Some(edits)
+    }
+}
+
+/// 重命名会话：在联动范围中编辑，提交或取消
+#[derive(Clone, Debug)]
+pub struct RenameSession {
+    /// the name before renaming, the content of the ranges
+    pub original: String,
+    /// the edits after the first one join its undo group
+    pub edited:   bool
+}
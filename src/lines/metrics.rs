@@ -0,0 +1,47 @@
+use std::{
+    rc::Rc,
+    time::{Duration, Instant}
+};
+
+/// 编辑后重新计算的阶段，用于统计输入延迟
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Phase {
+    /// phantom texts and styles of the origin lines
+    Phantom,
+    /// text layouts of the folded lines and the visual lines
+    Layout,
+    /// diffs computed by the lines, e.g. edit previews
+    Diff,
+    /// the screen lines of the viewport
+    ScreenLines
+}
+
+/// Receives the time of each phase, provided by the embedder, e.g. to
+/// report the p95 typing latency. Called on the ui thread, keep it
+/// cheap.
+pub trait MetricsSink {
+    fn record(&self, phase: Phase, elapsed: Duration);
+}
+
+/// Records the time from its creation to its drop
+pub(crate) struct PhaseTimer {
+    sink:  Rc<dyn MetricsSink>,
+    phase: Phase,
+    start: Instant
+}
+
+impl PhaseTimer {
+    pub fn start(sink: Rc<dyn MetricsSink>, phase: Phase) -> Self {
+        Self {
+            sink,
+            phase,
+            start: Instant::now()
+        }
+    }
+}
+
+impl Drop for PhaseTimer {
+    fn drop(&mut self) {
+        self.sink.record(self.phase, self.start.elapsed());
+    }
+}
@@ -1,6 +1,10 @@
 use std::{
-    ops::{AddAssign, Range},
-    sync::{Arc, atomic, atomic::AtomicUsize}
+    cell::RefCell,
+    collections::BTreeMap,
+    ops::{AddAssign, ControlFlow, Range},
+    rc::Rc,
+    sync::{Arc, atomic, atomic::AtomicUsize},
+    time::Duration
 };
 use std::borrow::Cow;
 use std::fmt::{Debug, Formatter};
@@ -19,8 +23,8 @@ use floem::{
         core::{
             command::EditCommand,
             indent::IndentStyle,
-            mode::{Mode, MotionMode},
-            register::Register
+            mode::{Mode, MotionMode, VisualMode},
+            register::{Register, RegisterData}
         },
         text::{PreeditData, SystemClipboard, WrapMethod}
     }
@@ -28,60 +32,175 @@ use floem::{
 use floem::text::FamilyOwned;
 use itertools::Itertools;
 use lapce_xi_rope::{Interval, Rope, RopeDelta, Transformer, spans::{Spans, SpansBuilder}};
-use layout::{TextLayout, TextLayoutLine};
+use layout::{MonospaceMetrics, TextLayout, TextLayoutLine};
 use line::{OriginFoldedLine, VisualLine};
 use log::{debug, error, info, warn};
-use lsp_types::{DiagnosticSeverity, InlayHint, InlayHintLabel, Location, Position};
+use lsp_types::{
+    Command, Diagnostic, DiagnosticRelatedInformation, DiagnosticSeverity,
+    DocumentDiagnosticReportKind, DocumentSymbol, InlayHint, InlayHintLabel,
+    InlayHintLabelPartTooltip, InlayHintTooltip, Location, Position, TextEdit, Url
+};
 use phantom_text::{
-    PhantomText, PhantomTextKind, PhantomTextLine, PhantomTextMultiLine
+    PhantomKindTag, PhantomText, PhantomTextKind, PhantomTextLine,
+    PhantomTextMultiLine
 };
 use signal::Signals;
 use smallvec::SmallVec;
-use style::NewLineStyle;
+use thiserror::Error;
+use style::{AttrsListCache, NewLineStyle, StyleRun};
 
 use crate::{
     DiagnosticData, EditorViewKind,
-    config::EditorConfig,
+    config::{EditorConfig, FoldedCursorPolicy},
     hit_position_aff,
+    language::LapceLanguage,
     lines::{
         action::UpdateFolding,
-        buffer::{Buffer, InvalLines, rope_text::RopeText},
-        cursor::{ColPosition, Cursor, CursorAffinity, CursorMode},
+        blame::{BlameInfo, BlameLayer, LineBlame},
+        bracket_guide::BracketGuide,
+        buffer::{
+            Buffer, EditEvent, EditValidator, InvalLines, rope_text::RopeText
+        },
+        clipboard::{CopyMeta, CopyMode, PrimaryClipboard},
+        cursor::{
+            CaretInfo, CaretStyle, ColPosition, Cursor, CursorAffinity,
+            CursorMode, MARK_LAST_INSERT, Marks
+        },
+        debug::InlineValues,
+        decoration::{Decoration, Decorations, Flash},
+        diff::EditPreview,
+        doc_comment::DocCommentTemplate,
         edit::{Action, EditConf, EditType},
-        encoding::{offset_utf8_to_utf16, offset_utf16_to_utf8},
-        fold::{FoldingDisplayItem, FoldingRanges},
+        error_lens::{ErrorLens, ErrorLensCache, ErrorLensLine, ErrorLensText},
+        fold::{
+            FoldingDisplayItem, FoldingLaneItem, FoldingRangeStatus, FoldingRanges,
+            FoldingSource, OutlineItem
+        },
+        gutter_text::{GutterText, GutterTextLayer},
+        ime::{ImePreedit, PreeditStyle},
         line::OriginLine,
         line_ending::LineEnding,
+        linked::{LinkedRanges, RenameSession},
+        metrics::{MetricsSink, Phase, PhaseTimer},
+        occurrence::{OccurrenceCount, Occurrences},
+        peek::PeekViews,
+        phantom_provider::{PhantomProvider, PhantomProviders},
         phantom_text::Text,
-        screen_lines::ScreenLines,
-        selection::Selection,
-        word::{CharClassification, WordCursor, get_char_property}
+        repeat::{LastChange, RepeatStep, offset_by_chars},
+        result_block::{BlockHeights, ResultBlock, ResultBlocks, ResultLine},
+        screen_lines::{
+            GutterDiagnostic, ScreenLineText, ScreenLines, ScreenTextSegment,
+            VisualLineInfo
+        },
+        search::{Search, SearchQuery},
+        selection::{SelRegion, Selection},
+        word::{CharClassification, WordCursor, WordMotion, get_char_property}
     },
-    syntax::{BracketParser, Syntax, edit::SyntaxEdit}
+    syntax::{
+        BracketParser, BracketResult, BracketTask, Syntax, SyntaxCapture,
+        SyntaxTask, SyntaxToken,
+        edit::{SyntaxEdit, SyntaxEdits},
+        runnable::RunAnchor
+    }
+};
+use crate::lines::delta_compute::{
+    OffsetDelta, OriginLinesDelta, resolve_delta_rs, resolve_deltas_rs,
+    resolve_line_delta, resolve_line_delta_of_line, resolve_line_delta_of_lines
 };
-use crate::lines::delta_compute::{OriginLinesDelta, resolve_delta_rs};
 
 pub mod action;
+pub mod blame;
+pub mod bracket_guide;
 pub mod buffer;
+pub mod clipboard;
 pub mod cursor;
+pub mod debug;
+pub mod decoration;
 pub mod diff;
+pub mod doc_comment;
 pub mod edit;
 pub mod encoding;
+pub mod error_lens;
 pub mod fold;
+pub mod gutter_text;
+pub mod ime;
 pub mod indent;
 pub mod layout;
 pub mod line;
 pub mod line_ending;
+pub mod linked;
+pub mod metrics;
+pub mod occurrence;
 pub mod paragraph;
+pub mod peek;
+pub mod phantom_provider;
 pub mod phantom_text;
+pub mod repeat;
+pub mod result_block;
 pub mod screen_lines;
+pub mod search;
 pub mod selection;
+pub mod sentence;
 mod signal;
 mod style;
+#[cfg(feature = "testing")]
+pub mod testing;
 pub mod util;
 pub mod word;
 pub mod delta_compute;
 
+/// 联动范围的装饰层
+pub const LINKED_DECORATION_KEY: &str = "linked";
+/// 诊断相关位置的次要高亮层
+pub const DIAGNOSTIC_RELATED_DECORATION_KEY: &str = "diagnostic_related";
+/// 临时高亮的装饰层
+pub const FLASH_DECORATION_KEY: &str = "flash";
+/// 调试器当前执行行的装饰层
+pub const DEBUG_LINE_DECORATION_KEY: &str = "debug_line";
+/// 调试器调用栈上层帧所在行的装饰层
+pub const DEBUG_FRAME_DECORATION_KEY: &str = "debug_frame";
+/// tree-sitter语法错误的装饰层
+pub const SYNTAX_ERROR_DECORATION_KEY: &str = "syntax_error";
+/// 局部变量所有使用处的装饰层
+pub const LOCAL_USES_DECORATION_KEY: &str = "local_uses";
+/// The decoration layer of the matches of [DocLines::set_search]
+pub const SEARCH_DECORATION_KEY: &str = "search";
+
+/// 原始行的度量
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LineMetrics {
+    /// chars without the line ending
+    pub chars:   usize,
+    /// display columns, tabs are expanded to the tab stops
+    pub columns: usize,
+    /// the width in pixels of the laid out folded line containing the
+    /// line, phantom texts included
+    pub width:   f64
+}
+
+/// 只读文档拒绝修改文本的操作。Check it by
+/// `err.downcast_ref::<ReadOnlyError>()`.
+#[derive(Debug, Error, Clone, Copy, PartialEq, Eq)]
+#[error("the document is read-only")]
+pub struct ReadOnlyError;
+
+/// 编辑被校验拒绝，见[DocLines::add_edit_validator]。Returned only if
+/// the text is unchanged, see [DocLines::take_edit_rejection].
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+#[error("the edit is rejected: {reason}")]
+pub struct EditRejectedError {
+    pub reason: String
+}
+
+/// 拖拽选择自动滚动时，每秒滚动的基础行数
+const AUTO_SCROLL_BASE_LINES_PER_SECOND: f64 = 8.0;
+
+/// 错误透镜与行尾之间的间距
+const ERROR_LENS_GAP: f64 = 24.0;
+
+/// 懒排版时，视口上下额外排版的行数
+const LAZY_LAYOUT_MARGIN_LINES: usize = 100;
+
 // /// Minimum width that we'll allow the view to be wrapped at.
 // const MIN_WRAPPED_WIDTH: f32 = 100.0;
 
@@ -150,6 +269,25 @@ impl DocLinesManager {
         batch(|| self.lines.try_update(f))
     }
 
+    /// Apply many mutations with signal triggering deferred until the
+    /// end, then emit one consolidated update.
+    pub fn with_signals_suspended<O>(
+        &self,
+        f: impl FnOnce(&mut DocLines) -> O
+    ) -> Option<O> {
+        // not remove `batch`!
+        batch(|| {
+            self.lines.try_update(|lines| {
+                lines.signals.suspend();
+                let rs = f(lines);
+                if lines.signals.resume() {
+                    lines.trigger_signals();
+                }
+                rs
+            })
+        })
+    }
+
     pub fn lines_of_origin_offset(
         &self,
         origin_offset: usize
@@ -164,6 +302,14 @@ impl DocLinesManager {
     }
 }
 
+/// 一套计算好的布局，切换内嵌提示时复用
+#[derive(Clone)]
+struct LinesLayout {
+    origin_lines:        Vec<OriginLine>,
+    origin_folded_lines: Vec<OriginFoldedLine>,
+    visual_lines:        Vec<VisualLine>
+}
+
 #[derive(Clone)]
 pub struct DocLines {
     // pub origin_lines: Vec<OriginLine>,
@@ -188,6 +334,76 @@ pub struct DocLines {
     /// This will be displayed even on views that are not focused.
     /// (line, col)
     pub inline_completion: Option<(String, usize, usize)>,
+    /// All inline completion candidates. `inline_completion` is the
+    /// one at `inline_completion_index`.
+    inline_completions:      Vec<String>,
+    inline_completion_index: usize,
+    marks:                   Marks,
+    /// 命名的装饰层，如搜索结果、lint
+    decorations:             Decorations,
+    /// 查找栏的匹配计数
+    occurrences:             Option<Occurrences>,
+    /// 查找，匹配项随编辑更新并高亮
+    search:                  Option<Search>,
+    /// 临时高亮，显示在`FLASH_DECORATION_KEY`层
+    flashes:                 Vec<Flash>,
+    /// 只读，如查看器、diff
+    read_only:               bool,
+    /// 跟随末尾模式，如日志查看
+    follow_tail:             bool,
+    /// 视口在底部时跟随，手动滚动离开底部后停止
+    following_tail:          bool,
+    /// 是否已按配置自动折叠过
+    auto_folded:             bool,
+    /// 多分句的输入法预编辑，优先于 `preedit`
+    ime_preedit:             Option<ImePreedit>,
+    /// 片段占位符、linkedEditingRange
+    linked_ranges:           Option<LinkedRanges>,
+    /// 本次编辑所在的联动范围，等待镜像到同组的其他范围
+    linked_edited:           Option<(usize, usize)>,
+    /// 进行中的重命名，使用`linked_ranges`
+    rename_session:          Option<RenameSession>,
+    /// 最后一次修改，用于`.`重复
+    last_change:             LastChange,
+    /// 每行的提交信息，由调用方提供
+    blame:                   Option<BlameLayer>,
+    /// 每行的gutter文本，由调用方提供
+    gutter_texts:            Option<GutterTextLayer>,
+    /// 调试暂停时行尾的变量值，继续执行后清除
+    inline_values:           InlineValues,
+    /// 行下方的结果块，如REPL的输出
+    result_blocks:           ResultBlocks,
+    /// 结果块占用的高度，可视行的y包括其上的块
+    block_heights:           BlockHeights,
+    /// 固定行范围的次级视图，如peek definition
+    peek_views:              PeekViews,
+    /// 语法树上次解析后的编辑(缓冲区版本, 编辑)，用于增量解析
+    syntax_edits:            SyntaxEdits,
+    /// 运行时隐藏的幻影文本种类
+    disabled_phantom_kinds:  Vec<PhantomKindTag>,
+    /// Ctrl+D由光标扩展为单词后的选区，选区不变时按整词查找下一个
+    whole_word_selection:    Option<Selection>,
+    /// 展开完整消息的错误透镜所在的原始行，编辑后收起
+    expanded_error_lens:     Option<usize>,
+    /// 懒排版时排版的原始行范围[start, end]，其余的行为估算的布局
+    layout_window:           Option<(usize, usize)>,
+    /// 懒排版时额外要求排版的原始行，见ensure_lines_laid_out
+    requested_layout:        Option<(usize, usize)>,
+    /// 拉取诊断(textDocument/diagnostic)最后一次结果的result_id
+    diagnostic_result_id:    Option<String>,
+    /// 各阶段耗时的接收者，由调用方提供
+    metrics:                 Option<Rc<dyn MetricsSink>>,
+    /// 悬停的可点击内嵌提示部分(提示位置, 部分序号)
+    hovered_hint_part:       Option<(Position, usize)>,
+    /// 上一次操作中部分编辑被拒绝的原因，之前的编辑已应用
+    edit_rejection:          Option<String>,
+    /// 内嵌提示另一开关状态的布局，重新布局或编辑后失效
+    hint_toggled_layout:     Option<LinesLayout>,
+    /// 缓冲区修改的订阅者(id, 回调)
+    edit_listeners:          Vec<(usize, Rc<dyn Fn(&EditEvent)>)>,
+    next_edit_listener_id:   usize,
+    /// 外部注册的幻影文本提供者
+    phantom_providers:       PhantomProviders,
     pub preedit:           PreeditData,
     // tree-sitter
     pub syntax:            Syntax,
@@ -204,6 +420,14 @@ pub struct DocLines {
     pub kind:              RwSignal<EditorViewKind>,
     pub(crate) signals:    Signals,
     style_from_lsp:        bool,
+    /// 字体、颜色等基础样式的版本，变化时AttrsList缓存失效
+    style_rev:             u64,
+    attrs_list_cache:      RefCell<AttrsListCache>,
+    error_lens_cache:      RefCell<ErrorLensCache>,
+    /// 编辑器复制到剪贴板的文本的类型(按列、按行...)，粘贴时按内容匹配
+    copied:                Option<CopyMeta>,
+    /// 设置时按等宽度量布局，不经过字体整形，用于测试及无界面的服务端
+    monospace_metrics:     Option<MonospaceMetrics>,
     // folding_items: Vec<FoldingDisplayItem>,
     pub line_height:       usize // pub screen_lines: ScreenLines,
 }
@@ -250,6 +474,42 @@ impl DocLines {
             diagnostics,
             completion_lens: None,
             inline_completion: None,
+            inline_completions: vec![],
+            inline_completion_index: 0,
+            marks: Marks::default(),
+            decorations: Decorations::default(),
+            occurrences: None,
+            search: None,
+            flashes: Vec::new(),
+            read_only: false,
+            follow_tail: false,
+            following_tail: false,
+            auto_folded: false,
+            ime_preedit: None,
+            linked_ranges: None,
+            linked_edited: None,
+            rename_session: None,
+            last_change: LastChange::default(),
+            blame: None,
+            gutter_texts: None,
+            inline_values: InlineValues::default(),
+            result_blocks: ResultBlocks::default(),
+            block_heights: BlockHeights::default(),
+            peek_views: PeekViews::default(),
+            syntax_edits: SyntaxEdits::default(),
+            disabled_phantom_kinds: Vec::new(),
+            whole_word_selection: None,
+            expanded_error_lens: None,
+            layout_window: None,
+            requested_layout: None,
+            diagnostic_result_id: None,
+            metrics: None,
+            hovered_hint_part: None,
+            edit_rejection: None,
+            hint_toggled_layout: None,
+            edit_listeners: Vec::new(),
+            next_edit_listener_id: 0,
+            phantom_providers: PhantomProviders::default(),
             preedit: PreeditData::new(cx),
             syntax,
             semantic_styles: None,
@@ -257,9 +517,15 @@ impl DocLines {
             // line_styles: Default::default(),
             kind,
             style_from_lsp: false,
+            style_rev: 0,
+            attrs_list_cache: Default::default(),
+            error_lens_cache: Default::default(),
+            copied: None,
+            monospace_metrics: None,
             // folding_items: Default::default(),
             line_height: 0
         };
+        lines.update_parser()?;
         lines.update_lines_new(OriginLinesDelta::default())?;
         Ok(lines)
     }
@@ -283,6 +549,10 @@ impl DocLines {
     }
 
     fn update_parser(&mut self) -> Result<()> {
+        if self.parser.background {
+            // 由bracket_task在后台计算
+            return Ok(());
+        }
         let buffer = self.signals.buffer.val(); // 提前保存，结束不可变借用
         let styles_exist = self.syntax.styles.is_some(); // 提前判断，不再借用 self.syntax
 
@@ -836,6 +1106,136 @@ impl DocLines {
         self._text_layout_of_visual_line(line).ok_or(anyhow!("text layout empty)"))
     }
 
+    /// The text of `[start, end)` for copy operations
+    pub fn copy_text(
+        &self,
+        start: usize,
+        end: usize,
+        mode: CopyMode
+    ) -> Result<String> {
+        let buffer = self.buffer();
+        let end = end.min(buffer.len());
+        if start >= end {
+            return Ok(String::new());
+        }
+        if mode == CopyMode::Underlying {
+            return Ok(buffer.slice_to_cow(start..end).to_string());
+        }
+        let start_line = buffer.line_of_offset(start);
+        let end_line = buffer.line_of_offset(end);
+        let mut rs = String::new();
+        for folded_line in self.origin_folded_lines.iter() {
+            if folded_line.origin_line_end < start_line {
+                continue;
+            } else if folded_line.origin_line_start > end_line {
+                break;
+            }
+            for text in folded_line.text_layout.phantom_text.text.iter() {
+                match text {
+                    Text::Phantom { text } => {
+                        let materialized = match text.kind {
+                            PhantomTextKind::LineFoldedRang { .. } => true,
+                            PhantomTextKind::InlayHint => {
+                                mode == CopyMode::WithInlayHints
+                            },
+                            _ => false
+                        };
+                        if !materialized {
+                            continue;
+                        }
+                        let offset = buffer.offset_of_line(text.line)? + text.col;
+                        if start <= offset && offset < end {
+                            rs.push_str(&text.text);
+                        }
+                    },
+                    Text::OriginText { text } => {
+                        let offset_of_line = buffer.offset_of_line(text.line)?;
+                        let origin_start =
+                            (offset_of_line + text.col.start).max(start);
+                        let origin_end = (offset_of_line + text.col.end).min(end);
+                        if origin_start < origin_end {
+                            rs.push_str(
+                                &buffer.slice_to_cow(origin_start..origin_end)
+                            );
+                        }
+                    },
+                    Text::EmptyLine { .. } => {}
+                }
+            }
+        }
+        Ok(rs)
+    }
+
+    /// The rendered text of the visible lines, real text merged with
+    /// phantom text
+    pub fn screen_line_texts(&self) -> impl Iterator<Item = ScreenLineText> + '_ {
+        self.screen_lines()
+            .visual_lines
+            .iter()
+            .filter_map(|info| match self.screen_line_text(info) {
+                Ok(text) => Some(text),
+                Err(err) => {
+                    error!("{err:?}");
+                    None
+                }
+            })
+    }
+
+    fn screen_line_text(&self, info: &VisualLineInfo) -> Result<ScreenLineText> {
+        let visual_line = &info.visual_line;
+        let layout_line = self.text_layout_of_visual_line(visual_line.line_index)?;
+        let final_text = layout_line.text.line().text();
+        // 视觉行在最终文本的范围
+        let range = layout_line
+            .text
+            .line_layout()
+            .get(visual_line.origin_folded_line_sub_index)
+            .and_then(|x| Some(x.glyphs.first()?.start..x.glyphs.last()?.end))
+            .unwrap_or(0..0);
+        let mut segments = Vec::new();
+        for text in layout_line.phantom_text.text.iter() {
+            match text {
+                Text::Phantom { text } => {
+                    let start = text.final_col.max(range.start);
+                    let end = (text.final_col + text.text.len()).min(range.end);
+                    if start < end {
+                        segments.push(ScreenTextSegment::Phantom {
+                            kind: text.kind.clone(),
+                            text: final_text
+                                .get(start..end)
+                                .unwrap_or_default()
+                                .to_string()
+                        });
+                    }
+                },
+                Text::OriginText { text } => {
+                    let start = text.final_col.start.max(range.start);
+                    let end = text.final_col.end.min(range.end);
+                    if start >= end {
+                        continue;
+                    }
+                    let content = self.buffer().line_content(text.line)?;
+                    let col_start = text.col.start + start - text.final_col.start;
+                    let col_end =
+                        (text.col.start + end - text.final_col.start).min(content.len());
+                    let origin = content
+                        .get(col_start..col_end)
+                        .unwrap_or_default()
+                        .trim_end_matches(['\r', '\n']);
+                    if !origin.is_empty() {
+                        segments.push(ScreenTextSegment::Origin(origin.to_string()));
+                    }
+                },
+                Text::EmptyLine { .. } => {}
+            }
+        }
+        Ok(ScreenLineText {
+            visual_line: visual_line.clone(),
+            visual_line_y: info.visual_line_y,
+            segments
+        })
+    }
+
     // 原始行的第一个视觉行。原始行可能会有多个视觉行
     pub fn start_visual_line_of_origin_line(
         &self,
@@ -874,6 +1274,48 @@ impl DocLines {
         bail!("folded_line_of_origin_line origin_line={origin_line}")
     }
 
+    /// The length and width of the origin line, e.g. for style-guide
+    /// checks
+    pub fn line_metrics(&self, line: usize) -> Result<LineMetrics> {
+        let content = self.buffer().line_content(line)?;
+        let content = content.trim_end_matches(['\r', '\n']);
+        let folded_line = self.folded_line_of_origin_line(line)?;
+        let tab_width = folded_line.text_layout.text.tab_width();
+        Ok(LineMetrics {
+            chars:   content.chars().count(),
+            columns: util::display_columns(content, tab_width),
+            width:   folded_line.text_layout.text.size().width
+        })
+    }
+
+    /// The lines longer than `config.long_line_column` columns
+    pub fn long_lines(&self) -> Result<Vec<usize>> {
+        let Some(column) = self.config.long_line_column else {
+            return Ok(Vec::new());
+        };
+        let mut lines = Vec::new();
+        for line in 0..=self.buffer().last_line() {
+            if self.line_metrics(line)?.columns > column {
+                lines.push(line);
+            }
+        }
+        Ok(lines)
+    }
+
+    /// The visual lines of the origin line, e.g. to align the sides of
+    /// diff views. 0 if the line is folded into the previous line.
+    pub fn visual_line_count_of_origin_line(
+        &self,
+        origin_line: usize
+    ) -> Result<usize> {
+        let folded_line = self.folded_line_of_origin_line(origin_line)?;
+        Ok(if folded_line.origin_line_start == origin_line {
+            folded_line.text_layout.line_count()
+        } else {
+            0
+        })
+    }
+
     pub fn folded_line_of_visual_line(
         &self,
         vl: &VisualLine
@@ -904,8 +1346,37 @@ impl DocLines {
         )
     }
 
-    pub fn last_visual_line(&self) -> &VisualLine {
-        &self.visual_lines[self.visual_lines.len() - 1]
+    /// None only before the first layout
+    pub fn last_visual_line(&self) -> Option<&VisualLine> {
+        self.visual_lines.last()
+    }
+
+    /// 原始行的行顶在整个文档的y值（不是窗口的y偏移）。
+    /// 被折叠的行返回所在折叠行的y值
+    pub fn y_of_origin_line(&self, origin_line: usize) -> Result<f64> {
+        let visual_line = self.start_visual_line_of_origin_line(origin_line)?;
+        Ok(self.y_of_visual_line(visual_line))
+    }
+
+    /// 可视行的行顶在整个文档的y值，包括其上的结果块
+    pub fn y_of_visual_line(&self, visual_line: &VisualLine) -> f64 {
+        visual_line.line_index as f64 * self.line_height as f64
+            + self.block_heights.above(visual_line.line_index)
+    }
+
+    /// 整个文档的y值所在的原始行。若y位于折叠行，返回折叠行的首个原始行；
+    /// 超出文档时返回最后一行
+    pub fn origin_line_of_y(&self, y: f64) -> Result<usize> {
+        if self.line_height == 0 {
+            bail!("origin_line_of_y line_height is zero");
+        }
+        let index = self.visual_line_index_of_y(y);
+        let visual_line = self
+            .visual_lines
+            .get(index)
+            .or_else(|| self.visual_lines.last())
+            .ok_or(anyhow!("origin_line_of_y visual lines is empty"))?;
+        Ok(visual_line.origin_line)
     }
 
     pub fn buffer_offset_of_click(
@@ -913,12 +1384,17 @@ impl DocLines {
         _mode: &CursorMode,
         point: Point
     ) -> Result<(usize, bool)> {
-        let info = self.signals.screen_lines.val().visual_line_of_y(point.y);
-        // info.visual_line.origin_line
-        let text_layout =
-            self.text_layout_of_visual_line(info.visual_line.line_index)?;
+        let screen_lines = self.signals.screen_lines.val();
+        // 视口超出文档时点击最后一行
+        let visual_line = match screen_lines.visual_line_of_y(point.y) {
+            Some(info) => &info.visual_line,
+            None => self
+                .last_visual_line()
+                .ok_or(anyhow!("buffer_offset_of_click visual lines is empty"))?
+        };
+        let text_layout = self.text_layout_of_visual_line(visual_line.line_index)?;
         let y = text_layout
-            .get_layout_y(info.visual_line.origin_folded_line_sub_index)
+            .get_layout_y(visual_line.origin_folded_line_sub_index)
             .unwrap_or(0.0);
         let hit_point = text_layout.text.hit_point(Point::new(point.x, y as f64));
         // let index = if hit_point.index {
@@ -934,61 +1410,220 @@ impl DocLines {
         Ok((offset_of_buffer, hit_point.is_inside))
     }
 
+    /// 整个文档坐标（不依赖screen lines）的点所对应的buffer偏移
+    pub fn buffer_offset_of_point(&self, point: Point) -> Result<usize> {
+        let index = self.visual_line_index_of_y(point.y);
+        let visual_line = self
+            .visual_lines
+            .get(index)
+            .or_else(|| self.visual_lines.last())
+            .ok_or(anyhow!("buffer_offset_of_point visual lines is empty"))?;
+        let text_layout = self.text_layout_of_visual_line(visual_line.line_index)?;
+        let y = text_layout
+            .get_layout_y(visual_line.origin_folded_line_sub_index)
+            .unwrap_or(0.0);
+        let hit_point = text_layout.text.hit_point(Point::new(point.x, y as f64));
+        let (origin_line, origin_col, _offset_of_line) = text_layout
+            .phantom_text
+            .cursor_position_of_final_col(hit_point.index);
+        self.buffer().offset_of_line_col(origin_line, origin_col)
+    }
+
+    /// 拖拽选择时指针超出窗口上下边缘的自动滚动。
+    ///
+    /// `point` is the pointer position in the document, `elapsed` is
+    /// the time since the last call. The farther the pointer is outside
+    /// the viewport, the faster it scrolls. Return (scroll delta of y,
+    /// offset of the new selection end). The delta is zero when the
+    /// pointer is inside the viewport.
+    pub fn drag_selection_auto_scroll(
+        &self,
+        point: Point,
+        elapsed: Duration
+    ) -> Result<(f64, usize)> {
+        let viewport = self.viewport();
+        let line_height = self.line_height.max(1) as f64;
+        let distance = if point.y < viewport.y0 {
+            point.y - viewport.y0
+        } else if point.y > viewport.y1 {
+            point.y - viewport.y1
+        } else {
+            0.0
+        };
+        // 每秒滚动的行数随距离增加
+        let lines_per_second =
+            AUTO_SCROLL_BASE_LINES_PER_SECOND * (1.0 + distance.abs() / line_height);
+        let delta = if distance == 0.0 {
+            0.0
+        } else {
+            distance.signum() * lines_per_second * line_height * elapsed.as_secs_f64()
+        };
+        let max_y = (self.content_height() - viewport.height()).max(0.0);
+        let delta =
+            (viewport.y0 + delta).clamp(0.0, max_y.max(viewport.y0)) - viewport.y0;
+
+        let y = point
+            .y
+            .clamp(viewport.y0 + delta, viewport.y1 + delta - 1.0)
+            .max(0.0);
+        let offset = self.buffer_offset_of_point(Point::new(point.x, y))?;
+        Ok((delta, offset))
+    }
+
+    /// The selection of clicking `point`: caret on single click, word
+    /// on double click and the whole line(with line ending) on triple
+    /// click.
+    pub fn selection_for_click(
+        &self,
+        point: Point,
+        click_count: usize
+    ) -> Result<Selection> {
+        let (offset, _) =
+            self.buffer_offset_of_click(&CursorMode::Normal(0), point)?;
+        Ok(match click_count {
+            0 | 1 => Selection::caret(offset),
+            2 => {
+                let (start, end) = WordCursor::new(self.buffer().text(), offset)
+                    .select_same_class();
+                Selection::region(start, end)
+            },
+            _ => {
+                let line = self.buffer().line_of_offset(offset);
+                let start = self.buffer().offset_of_line(line)?;
+                let end = self.buffer().offset_of_line(line + 1)?;
+                Selection::region(start, end)
+            }
+        })
+    }
+
     pub fn result_of_left_click(&mut self, point: Point) -> Result<ClickResult> {
-        let info = self.screen_lines().visual_line_of_y(point.y);
+        if let Some(line) = self.error_lens_at_point(point) {
+            if let Some(diagnostic) =
+                self.error_lens_diagnostics(line).into_iter().next()
+            {
+                self.toggle_error_lens(line);
+                return Ok(ClickResult::MatchDiagnostic(diagnostic));
+            }
+        }
+        let Some((line, phantom, phantom_offset)) = self.phantom_at_point(point)?
+        else {
+            return Ok(ClickResult::NoHint);
+        };
+        if let PhantomTextKind::InlayHint = phantom.kind {
+            if let Some(location) = self
+                .inlay_hint_part_of_phantom(line, phantom, phantom_offset)
+                .and_then(|(hint, index)| InlayHintPartHover::new(hint, index))
+                .and_then(|x| x.location)
+            {
+                return Ok(ClickResult::MatchHint(location));
+            }
+        } else if let PhantomTextKind::LineFoldedRang { start_position, .. } =
+            phantom.kind
+        {
+            self.update_folding_ranges(start_position.into())?;
+            return Ok(ClickResult::MatchFolded);
+        }
+        Ok(ClickResult::MatchWithoutLocation)
+    }
+
+    /// The label part of the inlay hint under the mouse, with its
+    /// tooltip. A clickable part, with a location or a command, is
+    /// underlined while hovered.
+    pub fn hover_inlay_hint(
+        &mut self,
+        point: Point
+    ) -> Result<Option<InlayHintPartHover>> {
+        let hover = self
+            .phantom_at_point(point)?
+            .filter(|(_, phantom, _)| phantom.kind == PhantomTextKind::InlayHint)
+            .and_then(|(line, phantom, phantom_offset)| {
+                self.inlay_hint_part_of_phantom(line, phantom, phantom_offset)
+            })
+            .and_then(|(hint, index)| InlayHintPartHover::new(hint, index));
+        let hovered = hover
+            .as_ref()
+            .filter(|x| x.is_clickable())
+            .map(|x| (x.position, x.part));
+        if hovered != self.hovered_hint_part {
+            // 只重新排版前后悬停的提示所在的行
+            let mut lines: Vec<usize> = [self.hovered_hint_part, hovered]
+                .into_iter()
+                .flatten()
+                .map(|(position, _)| position.line as usize)
+                .collect();
+            lines.dedup();
+            self.hovered_hint_part = hovered;
+            self.update_lines_of(&lines)?;
+            self.on_update_lines();
+            self.update_screen_lines();
+            self.update_folding_display_items();
+            self.trigger_signals();
+        }
+        Ok(hover)
+    }
+
+    fn is_inlay_hint_part_hovered(&self, hint: &InlayHint, part: usize) -> bool {
+        self.hovered_hint_part == Some((hint.position, part))
+    }
 
+    /// The phantom text under the point, and the offset of the point in
+    /// it
+    fn phantom_at_point(
+        &self,
+        point: Point
+    ) -> Result<Option<(&PhantomTextMultiLine, &PhantomText, usize)>> {
+        let Some(info) = self.screen_lines().visual_line_of_y(point.y) else {
+            return Ok(None);
+        };
         let text_layout =
             self.text_layout_of_visual_line(info.visual_line.line_index)?;
         let y = text_layout
             .get_layout_y(info.visual_line.origin_folded_line_sub_index)
             .unwrap_or(0.0);
         let hit_point = text_layout.text.hit_point(Point::new(point.x, y as f64));
-        Ok(
-            if let Text::Phantom { text: phantom } =
-                text_layout.phantom_text.text_of_final_col(hit_point.index)
-            {
-                let phantom_offset = hit_point.index - phantom.final_col;
-                if let PhantomTextKind::InlayHint = phantom.kind {
-                    let line = phantom.line as u32;
-                    let index = phantom.col as u32;
-                    if let Some(hints) = &self.inlay_hints {
-                        if let Some(location) = hints.iter().find_map(|(_, hint)| {
-                            if hint.position.line == line
-                                && hint.position.character == index
-                            {
-                                if let InlayHintLabel::LabelParts(parts) =
-                                    &hint.label
-                                {
-                                    let mut start = 0;
-                                    for part in parts {
-                                        let end = start + part.value.len();
-                                        if start <= phantom_offset
-                                            && phantom_offset < end
-                                        {
-                                            return part.location.clone();
-                                        }
-                                        start = end;
-                                    }
-                                }
-                            }
-                            None
-                        }) {
-                            return Ok(ClickResult::MatchHint(location));
-                        }
-                    }
-                } else if let PhantomTextKind::LineFoldedRang {
-                    start_position,
-                    ..
-                } = phantom.kind
-                {
-                    self.update_folding_ranges(start_position.into())?;
-                    return Ok(ClickResult::MatchFolded);
-                }
-                ClickResult::MatchWithoutLocation
-            } else {
-                ClickResult::NoHint
-            }
-        )
+        let line = &text_layout.phantom_text;
+        Ok(match line.text_of_final_col(hit_point.index) {
+            Text::Phantom { text } => {
+                Some((line, text, hit_point.index - text.final_col))
+            },
+            _ => None
+        })
+    }
+
+    /// The inlay hint of the phantom text, and the index of the label
+    /// part at `phantom_offset`
+    fn inlay_hint_part_of_phantom(
+        &self,
+        line: &PhantomTextMultiLine,
+        phantom: &PhantomText,
+        phantom_offset: usize
+    ) -> Option<(&InlayHint, usize)> {
+        let (_, hint) = self.inlay_hints.as_ref()?.iter().find(|(_, hint)| {
+            hint.position.line == phantom.line as u32
+                && hint.position.character == phantom.col as u32
+        })?;
+        // 同一提示前面的部分
+        let offset = line
+            .iter_phantom_text()
+            .filter(|x| {
+                x.kind == PhantomTextKind::InlayHint
+                    && x.line == phantom.line
+                    && x.col == phantom.col
+                    && x.final_col < phantom.final_col
+            })
+            .map(|x| x.text.len())
+            .sum::<usize>()
+            + phantom_offset;
+        let mut start = 0;
+        util::inlay_hint_label_parts(hint)
+            .iter()
+            .position(|x| {
+                let end = start + x.len();
+                let found = start <= offset && offset < end;
+                start = end;
+                found
+            })
+            .map(|index| (hint, index))
     }
 
     /// 原始位移字符所在的行信息（折叠行、原始行、视觉行）
@@ -1081,8 +1716,8 @@ impl DocLines {
         mut line_offset: usize,
         _affinity: CursorAffinity
     ) -> (VisualLine, usize, bool) {
-        let next_visual_line = &self.visual_lines
-            [visual_line_index.min(self.visual_lines.len() - 2) + 1];
+        let last = self.visual_lines.len().saturating_sub(1);
+        let next_visual_line = &self.visual_lines[(visual_line_index + 1).min(last)];
         let mut last_char = 0;
         for (index, layout) in self.origin_folded_lines
             [next_visual_line.origin_folded_line]
@@ -1146,15 +1781,14 @@ impl DocLines {
         ))
     }
 
+    /// The visual lines of `[start, end]` in the document, empty if
+    /// `start` is past the end
     pub fn visual_lines(&self, start: usize, end: usize) -> Vec<VisualLine> {
-        let start = start.min(self.visual_lines.len() - 1);
-        let end = end.min(self.visual_lines.len() - 1);
-
-        let mut vline_infos = Vec::with_capacity(end - start + 1);
-        for index in start..=end {
-            vline_infos.push(self.visual_lines[index].clone());
-        }
-        vline_infos
+        let end = end.saturating_add(1).min(self.visual_lines.len());
+        self.visual_lines
+            .get(start..end)
+            .map(|x| x.to_vec())
+            .unwrap_or_default()
     }
 
     fn phantom_text(&self, line: usize) -> Result<PhantomTextLine> {
@@ -1195,7 +1829,7 @@ impl DocLines {
                     && interval.start < end_offset
                     && !folded_ranges.contain_position(hint.position)
             })
-            .filter_map(|(interval, inlay_hint)| {
+            .flat_map(|(interval, inlay_hint)| {
                 let (col, affinity) = {
                     let mut cursor =
                         lapce_xi_rope::Cursor::new(buffer.text(), interval.start);
@@ -1237,46 +1871,37 @@ impl DocLines {
                         Ok(rs) => rs,
                         Err(err) => {
                             error!("{err:?}");
-                            return None;
+                            return Vec::new();
                         }
                     };
                     (col, affinity)
                 };
-                let mut text = match &inlay_hint.label {
-                    InlayHintLabel::String(label) => label.to_string(),
-                    InlayHintLabel::LabelParts(parts) => {
-                        parts.iter().map(|p| &p.value).join("")
-                    },
-                };
-                match (text.starts_with(':'), text.ends_with(':')) {
-                    (true, true) => {
-                        text.push(' ');
-                    },
-                    (true, false) => {
-                        text.push(' ');
-                    },
-                    (false, true) => {
-                        text = format!(" {} ", text);
-                    },
-                    (false, false) => {
-                        text = format!(" {}", text);
-                    }
-                }
-                Some(PhantomText {
-                    kind: PhantomTextKind::InlayHint,
-                    col,
-                    text,
-                    affinity,
-                    fg: Some(self.config.inlay_hint_fg),
-                    // font_family:
-                    // Some(self.config.inlay_hint_font_family()),
-                    font_size: Some(self.config.inlay_hint_font_size()),
-                    bg: Some(self.config.inlay_hint_bg),
-                    under_line: None,
-                    final_col: col,
-                    line,
-                    merge_col: col
-                })
+                // 每个标签部分是单独的幻影文本，样式各自独立
+                util::inlay_hint_label_parts(inlay_hint)
+                    .into_iter()
+                    .enumerate()
+                    .filter(|(_, text)| !text.is_empty())
+                    .map(|(index, text)| {
+                        let under_line = self
+                            .is_inlay_hint_part_hovered(inlay_hint, index)
+                            .then_some(self.config.inlay_hint_fg);
+                        PhantomText {
+                            kind: PhantomTextKind::InlayHint,
+                            col,
+                            text,
+                            affinity,
+                            fg: Some(self.config.inlay_hint_fg),
+                            // font_family:
+                            // Some(self.config.inlay_hint_font_family()),
+                            font_size: Some(self.config.inlay_hint_font_size()),
+                            bg: Some(self.config.inlay_hint_bg),
+                            under_line,
+                            final_col: col,
+                            line,
+                            merge_col: col
+                        }
+                    })
+                    .collect::<Vec<_>>()
             });
         // You're quite unlikely to have more than six hints on a
         // single line this later has the diagnostics added
@@ -1423,7 +2048,19 @@ impl DocLines {
         }
 
         // todo filter by folded?
-        if let Some(preedit) = util::preedit_phantom(
+        if let Some(ime_preedit) = &self.ime_preedit {
+            match buffer.offset_to_line_col(ime_preedit.offset) {
+                Ok((ime_line, col)) if ime_line == line => {
+                    text.extend(ime_preedit.phantom_texts(
+                        line,
+                        col,
+                        &self.preedit_style()
+                    ));
+                },
+                Ok(_) => {},
+                Err(err) => error!("{err:?}")
+            }
+        } else if let Some(preedit) = util::preedit_phantom(
             &self.preedit,
             buffer,
             Some(self.config.editor_foreground),
@@ -1432,6 +2069,53 @@ impl DocLines {
             text.push(preedit)
         }
 
+        if let Some(values) = self.inline_values.line(buffer.text(), line)? {
+            let col = buffer.line_end_offset(line, true)? - start_offset;
+            if !folded_ranges.contain_position(Position {
+                line:      line as u32,
+                character: col as u32
+            }) {
+                text.push(PhantomText {
+                    kind: PhantomTextKind::InlayHint,
+                    col,
+                    text: format!("  {values}"),
+                    affinity: Some(CursorAffinity::Backward),
+                    fg: Some(self.config.inlay_hint_fg),
+                    font_size: Some(self.config.inlay_hint_font_size()),
+                    bg: None,
+                    under_line: None,
+                    final_col: col,
+                    line,
+                    merge_col: col
+                });
+            }
+        }
+
+        text.extend(
+            self.phantom_providers
+                .phantom_texts(buffer, line)
+                .into_iter()
+                .filter(|x| {
+                    !folded_ranges.contain_position(Position {
+                        line:      line as u32,
+                        character: x.col as u32
+                    })
+                })
+        );
+
+        let mut hidden_kinds = SmallVec::new();
+        if !self.disabled_phantom_kinds.is_empty() {
+            text.retain(|x| match x.kind.tag() {
+                Some(tag) if self.disabled_phantom_kinds.contains(&tag) => {
+                    if !hidden_kinds.contains(&tag) {
+                        hidden_kinds.push(tag);
+                    }
+                    false
+                },
+                _ => true
+            });
+        }
+
         let fg = self.config.inlay_hint_fg;
         let font_size = self.config.inlay_hint_font_size();
         let bg = self.config.inlay_hint_bg;
@@ -1443,8 +2127,10 @@ impl DocLines {
             line,
             origin_text_len,
             start_offset,
-            text
-        ))
+            text,
+            &self.config.phantom_order
+        )
+        .with_hidden_kinds(hidden_kinds))
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -1468,7 +2154,6 @@ impl DocLines {
         let mut phantom_text =
             PhantomTextMultiLine::new(origin_line.phantom.clone());
 
-        let mut attrs_list = AttrsList::new(attrs);
         let mut font_system = FONT_SYSTEM.lock();
         let mut semantic_styles = origin_line.semantic_styles(0);
         let mut diagnostic_styles = origin_line.diagnostic_styles(0);
@@ -1492,24 +2177,40 @@ impl DocLines {
         }
 
         let phantom_color = self.editor_style.phantom_color();
-        phantom_text.add_phantom_style(
-            &mut attrs_list,
-            attrs.font_size(attrs.font_size - 1.0),
-            phantom_color
-        );
-        let final_line_content = phantom_text.final_line_content(&line_content);
-        self.apply_semantic_styles_2(
+        let mut style_runs =
+            phantom_text.phantom_style_runs(attrs.font_size - 1.0, phantom_color);
+        style_runs.extend(self.semantic_style_runs(
             &phantom_text,
             &semantic_styles,
-            &mut attrs_list,
-            attrs
-        );
-        let mut text_layout = TextLayout::new_with_font_system(
+            attrs.font_size
+        ));
+        style_runs
+            .extend(self.decoration_style_runs(&phantom_text, attrs.font_size));
+        let attrs_list = self.attrs_list_cache.borrow_mut().get_or_build(
+            self.style_rev,
             line,
-            &final_line_content,
-            attrs_list,
-            &mut font_system
+            style_runs,
+            attrs
         );
+        let final_line_content = phantom_text.final_line_content(&line_content);
+        let mut text_layout = if self
+            .layout_window
+            .is_some_and(|(start, end)| line < start || line > end)
+        {
+            let metrics = MonospaceMetrics::new(
+                self.config.font_size as f32,
+                self.config.line_height as f32
+            );
+            TextLayout::new_estimated(line, final_line_content, attrs_list, metrics)
+        } else {
+            layout::shape_with_cache(
+                line,
+                &final_line_content,
+                attrs_list,
+                self.monospace_metrics,
+                &mut font_system
+            )
+        };
         drop(font_system);
         match self.editor_style.wrap_method() {
             WrapMethod::None => {},
@@ -1535,6 +2236,12 @@ impl DocLines {
         // 下划线？背景色？
         util::apply_layout_styles(&mut layout_line);
         self.apply_diagnostic_styles_2(&mut layout_line, &diagnostic_styles);
+        self.apply_decoration_styles(&mut layout_line);
+        self.apply_long_line_style(&mut layout_line);
+        util::blend_backgrounds(
+            &mut layout_line.extra_style,
+            self.config.background_blend
+        );
 
         Ok((layout_line, semantic_styles, diagnostic_styles))
     }
@@ -1723,10 +2430,17 @@ impl DocLines {
         let (y0, y1) = (base.y0, base.y1);
         // Get the start and end (visual) lines that are visible in
         // the viewport
-        let min_val = (y0 / line_height as f64).floor() as usize;
-        let max_val = (y1 / line_height as f64).floor() as usize;
+        let min_val = self.visual_line_index_of_y(y0);
+        let max_val = self.visual_line_index_of_y(y1);
         let vline_infos = self.visual_lines(min_val, max_val);
-        util::compute_screen_lines(view_kind, base, vline_infos, line_height, y0)
+        util::compute_screen_lines(
+            view_kind,
+            base,
+            vline_infos,
+            line_height,
+            &self.block_heights,
+            y0
+        )
     }
 
     pub fn viewport(&self) -> Rect {
@@ -1786,29 +2500,32 @@ impl DocLines {
         }
     }
 
-    fn apply_semantic_styles_2(
+    fn semantic_style_runs(
         &self,
         phantom_text: &PhantomTextMultiLine,
         semantic_styles: &[NewLineStyle],
-        attrs_list: &mut AttrsList,
-        attrs: Attrs
-    ) {
-        for NewLineStyle {
-            origin_line_offset_start,
-            len,
-            fg_color,
-            ..
-        } in semantic_styles.iter()
-        {
-            // for (start, end, color) in styles.into_iter() {
-            let (Some(start), Some(end)) = (
-                phantom_text.col_at(*origin_line_offset_start),
-                phantom_text.col_at(*origin_line_offset_start + *len)
-            ) else {
-                continue;
-            };
-            attrs_list.add_span(start..end, attrs.color(*fg_color));
-        }
+        font_size: f32
+    ) -> Vec<StyleRun> {
+        semantic_styles
+            .iter()
+            .filter_map(
+                |NewLineStyle {
+                     origin_line_offset_start,
+                     len,
+                     fg_color,
+                     ..
+                 }| {
+                    let start = phantom_text.col_at(*origin_line_offset_start)?;
+                    let end =
+                        phantom_text.col_at(*origin_line_offset_start + *len)?;
+                    Some(StyleRun {
+                        range: start..end,
+                        fg_color: *fg_color,
+                        font_size
+                    })
+                }
+            )
+            .collect()
     }
 
     fn apply_diagnostic_styles_2(
@@ -1852,22 +2569,130 @@ impl DocLines {
         }
     }
 
-    // fn apply_diagnostic_styles(
-    //     &self,
-    //     layout_line: &mut TextLayoutLine,
-    //     line_styles: Vec<(usize, usize, Color)>,
-    //     // _max_severity: Option<DiagnosticSeverity>,
-    // ) {
-    //     let layout = &layout_line.text;
-    //     let phantom_text = &layout_line.phantom_text;
-    //
-    //     // 暂不考虑
-    //     for (start, end, color) in line_styles {
-    //         // warn!("line={} start={start}, end={end},
-    // color={color:?}", phantom_text.line);         //
-    // col_at(end)可以为空，因为end是不包含的         let
-    // (Some(start), Some(end)) = (phantom_text.col_at(start),
-    // phantom_text.col_at(end.max(1) - 1)) else {
+    /// The background past `config.long_line_column`. Only the first
+    /// line of a folded line is checked.
+    fn apply_long_line_style(&self, layout_line: &mut TextLayoutLine) {
+        let Some(column) = self.config.long_line_column else {
+            return;
+        };
+        let phantom_text = &layout_line.phantom_text;
+        let line = phantom_text.line;
+        let Ok(content) = self.buffer().line_content(line) else {
+            return;
+        };
+        let content = content.trim_end_matches(['\r', '\n']);
+        let tab_width = layout_line.text.tab_width();
+        let Some(start) = util::offset_of_display_column(content, column, tab_width)
+        else {
+            return;
+        };
+        let end = content.len();
+        if start >= end {
+            return;
+        }
+        // col_at(end)可以为空，因为end是不包含的
+        let (Some(start), Some(end)) =
+            (phantom_text.col_at(start), phantom_text.col_at(end - 1))
+        else {
+            return;
+        };
+        let styles = util::extra_styles_for_range(
+            &layout_line.text,
+            start,
+            end + 1,
+            Some(self.config.long_line_bg()),
+            None,
+            None
+        );
+        layout_line.extra_style.extend(styles);
+    }
+
+    /// The fg colors of the decorations, after the semantic styles so
+    /// they are drawn over them
+    fn decoration_style_runs(
+        &self,
+        phantom_text: &PhantomTextMultiLine,
+        font_size: f32
+    ) -> Vec<StyleRun> {
+        if self.decorations.is_empty() {
+            return Vec::new();
+        }
+        let line_start = phantom_text.offset_of_line;
+        let range = Interval::new(
+            line_start,
+            line_start + phantom_text.origin_text_len
+        );
+        self.decorations
+            .iter_range(range)
+            .filter_map(|(iv, decoration)| {
+                let fg_color = decoration.fg_color?;
+                let start = iv.start.max(line_start) - line_start;
+                let end = iv.end.min(range.end) - line_start;
+                if start >= end {
+                    return None;
+                }
+                // col_at(end)可以为空，因为end是不包含的
+                let start = phantom_text.col_at(start)?;
+                let end = phantom_text.col_at(end - 1)? + 1;
+                Some(StyleRun {
+                    range: start..end,
+                    fg_color,
+                    font_size
+                })
+            })
+            .collect()
+    }
+
+    fn apply_decoration_styles(&self, layout_line: &mut TextLayoutLine) {
+        if self.decorations.is_empty() {
+            return;
+        }
+        let phantom_text = &layout_line.phantom_text;
+        let line_start = phantom_text.offset_of_line;
+        let range = Interval::new(
+            line_start,
+            line_start + phantom_text.origin_text_len
+        );
+        for (iv, decoration) in self.decorations.iter_range(range) {
+            let start = iv.start.max(line_start) - line_start;
+            let end = iv.end.min(range.end) - line_start;
+            if start >= end {
+                continue;
+            }
+            // col_at(end)可以为空，因为end是不包含的
+            let (Some(start), Some(end)) =
+                (phantom_text.col_at(start), phantom_text.col_at(end - 1))
+            else {
+                continue;
+            };
+            let styles = util::extra_styles_for_range(
+                &layout_line.text,
+                start,
+                end + 1,
+                decoration.bg_color,
+                decoration.under_line,
+                decoration.wave_line
+            );
+            layout_line.extra_style.extend(styles);
+        }
+    }
+
+    // fn apply_diagnostic_styles(
+    //     &self,
+    //     layout_line: &mut TextLayoutLine,
+    //     line_styles: Vec<(usize, usize, Color)>,
+    //     // _max_severity: Option<DiagnosticSeverity>,
+    // ) {
+    //     let layout = &layout_line.text;
+    //     let phantom_text = &layout_line.phantom_text;
+    //
+    //     // 暂不考虑
+    //     for (start, end, color) in line_styles {
+    //         // warn!("line={} start={start}, end={end},
+    // color={color:?}", phantom_text.line);         //
+    // col_at(end)可以为空，因为end是不包含的         let
+    // (Some(start), Some(end)) = (phantom_text.col_at(start),
+    // phantom_text.col_at(end.max(1) - 1)) else {
     // warn!("line={} start={start}, end={end}, color={color:?} col_at
     // empty", phantom_text.line);             continue;
     //         };
@@ -2023,6 +2848,23 @@ impl DocLines {
         }
     }
 
+    /// Folding items keyed by origin line for a gutter lane of
+    /// `lane_width`
+    pub fn folding_lane_items(
+        &self,
+        lane_width: f64
+    ) -> BTreeMap<usize, FoldingLaneItem> {
+        self.folding_ranges.to_lane_items(self.screen_lines(), lane_width)
+    }
+
+    pub fn folding_item_at_y(&self, y: f64) -> Option<FoldingDisplayItem> {
+        fold::folding_item_at_y(
+            self.signals.folding_items.val(),
+            y,
+            self.line_height as f64
+        )
+    }
+
     fn update_folding_display_items(&mut self) {
         let display_items =
             self.folding_ranges.to_display_items(self.screen_lines());
@@ -2031,51 +2873,219 @@ impl DocLines {
             .update_if_not_equal(display_items);
     }
 
+    /// Up. Move by visual lines, or by origin lines if
+    /// `move_by_origin_line` is enabled
     pub fn move_up(
         &self,
         offset: usize,
         affinity: CursorAffinity,
         horiz: Option<ColPosition>,
-        _mode: Mode,
-        _count: usize
+        mode: Mode,
+        count: usize
     ) -> Result<(usize, ColPosition, CursorAffinity)> {
-        let (visual_line, line_offset, ..) =
+        if self.config.move_by_origin_line {
+            self.move_up_origin_line(offset, affinity, horiz, mode, count)
+        } else {
+            self.move_up_visual_line(offset, affinity, horiz, mode, count)
+        }
+    }
+
+    /// Down. Move by visual lines, or by origin lines if
+    /// `move_by_origin_line` is enabled
+    pub fn move_down(
+        &self,
+        offset: usize,
+        affinity: CursorAffinity,
+        horiz: Option<ColPosition>,
+        mode: Mode,
+        count: usize
+    ) -> Result<(usize, ColPosition, CursorAffinity)> {
+        if self.config.move_by_origin_line {
+            self.move_down_origin_line(offset, affinity, horiz, mode, count)
+        } else {
+            self.move_down_visual_line(offset, affinity, horiz, mode, count)
+        }
+    }
+
+    /// Up by `count` visual lines, a wrapped line takes several moves
+    pub fn move_up_visual_line(
+        &self,
+        offset: usize,
+        affinity: CursorAffinity,
+        horiz: Option<ColPosition>,
+        mode: Mode,
+        count: usize
+    ) -> Result<(usize, ColPosition, CursorAffinity)> {
+        let (mut visual_line, mut line_offset, ..) =
             self.visual_line_of_offset(offset, affinity)?;
-        let (previous_visual_line, line_offset, ..) =
-            self.previous_visual_line(visual_line.line_index, line_offset, affinity)?;
         let horiz = horiz.unwrap_or_else(|| {
-            ColPosition::Col(
-                match self.line_point_of_visual_line_col(
+            self.horiz_of_visual_line(&visual_line, line_offset, affinity)
+        });
+        let mut offset_of_buffer = offset;
+        let mut affinity = affinity;
+        for _ in 0..count.max(1) {
+            let (previous_visual_line, previous_line_offset, ..) = self
+                .previous_visual_line(
                     visual_line.line_index,
                     line_offset,
-                    affinity,
-                    false
-                ) {
-                    Ok(point) => {point.x}
-                    Err(err) => {
-                        error!("{:?}", err);
-                        0.0
-                    }
-                }
-            )
+                    affinity
+                )?;
+            offset_of_buffer = self.rvline_horiz_col(
+                &horiz,
+                mode != Mode::Normal,
+                &previous_visual_line
+            )?;
+            // TODO: this should maybe be doing `new_offset ==
+            // info.interval.start`?
+            affinity = if previous_line_offset == 0 {
+                CursorAffinity::Forward
+            } else {
+                CursorAffinity::Backward
+            };
+            visual_line = previous_visual_line;
+            line_offset = previous_line_offset;
+        }
+        Ok((offset_of_buffer, horiz, affinity))
+    }
+
+    /// Down by `count` visual lines, a wrapped line takes several moves
+    pub fn move_down_visual_line(
+        &self,
+        offset: usize,
+        affinity: CursorAffinity,
+        horiz: Option<ColPosition>,
+        mode: Mode,
+        count: usize
+    ) -> Result<(usize, ColPosition, CursorAffinity)> {
+        let (mut visual_line, mut line_offset, ..) =
+            self.visual_line_of_offset(offset, affinity)?;
+        let horiz = horiz.unwrap_or_else(|| {
+            self.horiz_of_visual_line(&visual_line, line_offset, affinity)
         });
+        let mut offset_of_buffer = offset;
+        let mut affinity = affinity;
+        for _ in 0..count.max(1) {
+            let (next_visual_line, next_line_offset, ..) =
+                self.next_visual_line(visual_line.line_index, line_offset, affinity);
+            offset_of_buffer = self.rvline_horiz_col(
+                &horiz,
+                mode != Mode::Normal,
+                &next_visual_line
+            )?;
+            affinity = if next_line_offset == 0 {
+                CursorAffinity::Forward
+            } else {
+                CursorAffinity::Backward
+            };
+            visual_line = next_visual_line;
+            line_offset = next_line_offset;
+        }
+        Ok((offset_of_buffer, horiz, affinity))
+    }
 
-        let offset_of_buffer = self.rvline_horiz_col(
-            &horiz,
-            _mode != Mode::Normal,
-            &previous_visual_line
-        )?;
+    /// Up by `count` origin lines. A folded line counts as one line, and
+    /// the caret lands on the first visual line of a wrapped line. The
+    /// goal column is shared with [Self::move_up_visual_line].
+    pub fn move_up_origin_line(
+        &self,
+        offset: usize,
+        affinity: CursorAffinity,
+        horiz: Option<ColPosition>,
+        mode: Mode,
+        count: usize
+    ) -> Result<(usize, ColPosition, CursorAffinity)> {
+        let (visual_line, line_offset, ..) =
+            self.visual_line_of_offset(offset, affinity)?;
+        let folded_line =
+            visual_line.origin_folded_line.saturating_sub(count.max(1));
+        self.move_to_folded_line(
+            &visual_line,
+            line_offset,
+            affinity,
+            horiz,
+            mode,
+            folded_line
+        )
+    }
 
-        // TODO: this should maybe be doing `new_offset ==
-        // info.interval.start`?
-        let affinity = if line_offset == 0 {
-            CursorAffinity::Forward
-        } else {
+    /// Down by `count` origin lines, see [Self::move_up_origin_line]
+    pub fn move_down_origin_line(
+        &self,
+        offset: usize,
+        affinity: CursorAffinity,
+        horiz: Option<ColPosition>,
+        mode: Mode,
+        count: usize
+    ) -> Result<(usize, ColPosition, CursorAffinity)> {
+        let (visual_line, line_offset, ..) =
+            self.visual_line_of_offset(offset, affinity)?;
+        let folded_line = (visual_line.origin_folded_line + count.max(1))
+            .min(self.origin_folded_lines.len().saturating_sub(1));
+        self.move_to_folded_line(
+            &visual_line,
+            line_offset,
+            affinity,
+            horiz,
+            mode,
+            folded_line
+        )
+    }
+
+    /// 移动到折叠行的第一个视觉行，保持目标列
+    fn move_to_folded_line(
+        &self,
+        visual_line: &VisualLine,
+        line_offset: usize,
+        affinity: CursorAffinity,
+        horiz: Option<ColPosition>,
+        mode: Mode,
+        folded_line: usize
+    ) -> Result<(usize, ColPosition, CursorAffinity)> {
+        let horiz = horiz.unwrap_or_else(|| {
+            self.horiz_of_visual_line(visual_line, line_offset, affinity)
+        });
+        let target =
+            self.visual_line_of_folded_line_and_sub_index(folded_line, 0)?;
+        let offset_of_buffer =
+            self.rvline_horiz_col(&horiz, mode != Mode::Normal, target)?;
+        let wrapped = self
+            .visual_lines
+            .get(target.line_index + 1)
+            .is_some_and(|x| x.origin_folded_line == folded_line);
+        // 换行处的光标显示在第一个视觉行的末尾
+        let at_end = offset_of_buffer == target.origin_interval.end;
+        let affinity = if wrapped && at_end {
             CursorAffinity::Backward
+        } else {
+            CursorAffinity::Forward
         };
         Ok((offset_of_buffer, horiz, affinity))
     }
 
+    /// The goal column of vertical moves: the x of the caret in its
+    /// visual line
+    fn horiz_of_visual_line(
+        &self,
+        visual_line: &VisualLine,
+        line_offset: usize,
+        affinity: CursorAffinity
+    ) -> ColPosition {
+        ColPosition::Col(
+            match self.line_point_of_visual_line_col(
+                visual_line.line_index,
+                line_offset,
+                affinity,
+                false
+            ) {
+                Ok(point) => point.x,
+                Err(err) => {
+                    error!("{:?}", err);
+                    0.0
+                }
+            }
+        )
+    }
+
     pub fn end_of_line(
         &self,
         affinity: &mut CursorAffinity,
@@ -2116,46 +3126,6 @@ impl DocLines {
         Ok((new_offset, ColPosition::End))
     }
 
-    pub fn move_down(
-        &self,
-        offset: usize,
-        affinity: CursorAffinity,
-        horiz: Option<ColPosition>,
-        _mode: Mode,
-        _count: usize
-    ) -> Result<(usize, ColPosition, CursorAffinity)> {
-        let (visual_line, line_offset, ..) =
-            self.visual_line_of_offset(offset, affinity)?;
-        let (next_visual_line, next_line_offset, ..) =
-            self.next_visual_line(visual_line.line_index, line_offset, affinity);
-        let horiz = horiz.unwrap_or_else(|| {
-            ColPosition::Col(
-                match self.line_point_of_visual_line_col(
-                    visual_line.line_index,
-                    line_offset,
-                    affinity,
-                    false
-                ) {
-                    Ok(point) => {point.x}
-                    Err(err) => {
-                        error!("{:?}", err);
-                        0.0
-                    }
-                }
-            )
-        });
-        let offset_of_buffer =
-            self.rvline_horiz_col(&horiz, _mode != Mode::Normal, &next_visual_line)?;
-        let affinity = if next_line_offset == 0 {
-            CursorAffinity::Forward
-        } else {
-            CursorAffinity::Backward
-        };
-        warn!("offset_of_buffer={offset_of_buffer} horiz={horiz:?}");
-
-        Ok((offset_of_buffer, horiz, affinity))
-    }
-
     fn rvline_horiz_col(
         &self,
         horiz: &ColPosition,
@@ -2203,20 +3173,71 @@ impl DocLines {
     }
 
     fn update_screen_lines(&mut self) {
+        let _timer = self.time_phase(Phase::ScreenLines);
         let screen_lines = self._compute_screen_lines(*self.signals.viewport.val());
+        let gutter_diagnostics = self.compute_gutter_diagnostics(&screen_lines);
+        self.signals
+            .gutter_diagnostics
+            .update_if_not_equal(gutter_diagnostics);
         self.signals.screen_lines.update_force(screen_lines);
     }
 
+    /// The most severe diagnostic and the count of diagnostics of each
+    /// folded line on the screen, keyed by its first visual line
+    fn compute_gutter_diagnostics(
+        &self,
+        screen_lines: &ScreenLines
+    ) -> Vec<GutterDiagnostic> {
+        let Ok((start, end)) = screen_lines.offset_interval() else {
+            return Vec::new();
+        };
+        let buffer = self.buffer();
+        // (原始行, 严重程度)
+        let diags: Vec<(usize, DiagnosticSeverity)> =
+            self.diagnostics.diagnostics_span.with_untracked(|diags| {
+                diags
+                    .iter_chunks(start..end)
+                    .filter(|(iv, _)| iv.start >= start)
+                    .filter_map(|(iv, diag)| {
+                        Some((buffer.line_of_offset(iv.start), diag.severity?))
+                    })
+                    .collect()
+            });
+        if diags.is_empty() {
+            return Vec::new();
+        }
+        screen_lines
+            .visual_lines
+            .iter()
+            .filter(|x| x.visual_line.origin_folded_line_sub_index == 0)
+            .filter_map(|info| {
+                let folded_line = self
+                    .origin_folded_lines
+                    .get(info.visual_line.origin_folded_line)?;
+                let lines =
+                    folded_line.origin_line_start..=folded_line.origin_line_end;
+                let in_line = diags.iter().filter(|x| lines.contains(&x.0));
+                let count = in_line.clone().count();
+                let severity = in_line.map(|x| x.1).min()?;
+                Some(GutterDiagnostic {
+                    origin_line: info.visual_line.origin_line,
+                    y: info.visual_line_y,
+                    severity,
+                    count
+                })
+            })
+            .collect()
+    }
+
     fn _compute_change_lines(
         &self,
         deltas: &[(Rope, RopeDelta, InvalLines)]
     ) -> Result<OriginLinesDelta> {
-        if deltas.len() == 1 {
-            if let Some(delta) = deltas.first() {
-                return resolve_delta_rs(&delta.0, &delta.1);
-            }
+        match deltas {
+            [] => Ok(OriginLinesDelta::default()),
+            [delta] => resolve_delta_rs(&delta.0, &delta.1),
+            _ => resolve_deltas_rs(&deltas[0].0, deltas.iter().map(|x| &x.1))
         }
-        Ok(OriginLinesDelta::default())
     }
 
 
@@ -2275,62 +3296,271 @@ impl ComputeLines {
             }
         })
     }
-    pub fn line_point_of_visual_line_col(
+
+    /// Home. Toggle between the first non-blank char and column 0 of the
+    /// line if `smart_home` is enabled, otherwise column 0.
+    pub fn smart_home(
         &self,
-        visual_line: usize,
-        col: usize,
-        affinity: CursorAffinity,
-        _force_affinity: bool
-    ) -> Result<Point> {
-        self._line_point_of_visual_line_col(visual_line, col, affinity, _force_affinity).ok_or(anyhow!("visual_line={visual_line} col={col} is empty"))
+        affinity: &mut CursorAffinity,
+        offset: usize
+    ) -> Result<(usize, ColPosition)> {
+        let line = self.buffer().line_of_offset(offset);
+        let line_start = self.buffer().offset_of_line(line)?;
+        *affinity = CursorAffinity::Forward;
+        if !self.config.smart_home {
+            return Ok((line_start, ColPosition::Start));
+        }
+        let non_blank = self.buffer().first_non_blank_character_on_line(line)?;
+        Ok(if offset == non_blank && non_blank != line_start {
+            (line_start, ColPosition::Start)
+        } else {
+            (non_blank, ColPosition::FirstNonBlank)
+        })
     }
-    pub fn _line_point_of_visual_line_col(
+
+    /// End. Move to the end of the visual line first if the line is
+    /// wrapped and `wrap_aware_end` is enabled, then the end of the
+    /// line.
+    pub fn smart_end(
         &self,
-        visual_line: usize,
-        col: usize,
-        affinity: CursorAffinity,
-        _force_affinity: bool
-    ) -> Option<Point> {
-        let text_layout = &self.origin_folded_lines
-            .get(self.visual_lines.get(visual_line)?.origin_folded_line)?
-            .text_layout;
-        Some(hit_position_aff(
-            &text_layout.text,
-            col,
-            affinity == CursorAffinity::Backward
-        )
-        .point)
+        affinity: &mut CursorAffinity,
+        offset: usize,
+        mode: Mode
+    ) -> Result<(usize, ColPosition)> {
+        if self.config.wrap_aware_end {
+            let (vl, ..) = self.visual_line_of_offset(offset, *affinity)?;
+            let wrapped = self
+                .visual_lines
+                .get(vl.line_index + 1)
+                .is_some_and(|x| x.origin_folded_line == vl.origin_folded_line);
+            let end = vl.origin_interval.end;
+            let at_end = offset == end && *affinity == CursorAffinity::Backward;
+            if wrapped && !at_end {
+                // 换行处的光标显示在上一视觉行的末尾
+                *affinity = CursorAffinity::Backward;
+                return Ok((end, ColPosition::End));
+            }
+        }
+        self.end_of_line(affinity, offset, mode)
     }
 
-    #[allow(clippy::type_complexity)]
-    /// return (visual line of offset, offset of visual line, offset
-    /// of folded line, is last char, viewport position of cursor,
-    /// line_height, origin position of cursor)
-    ///
-    /// last_char should be check in future
-    pub fn cursor_position_of_buffer_offset(
+    /// Move by `count` words. The caret lands at the start of a word after
+    /// the phantom texts(like inlay hints) before the word, and at the
+    /// end of a word when inserting before the phantom texts after it,
+    /// e.g. `a|: i32`.
+    pub fn move_by_word(
         &self,
+        affinity: &mut CursorAffinity,
         offset: usize,
-        affinity: CursorAffinity
-    ) -> Result<(
-        VisualLine,
-        usize,
-        usize,
-        bool,
-        Option<Point>,
-        f64,
-        Point,
-        usize
-    )> {
-        let (vl, offset_of_visual, offset_folded, last_char, _) =
-            self.visual_line_of_offset(offset, affinity)?;
-        let mut viewpport_point = hit_position_aff(
-            &self.text_layout_of_visual_line(vl.line_index)?.text,
-            offset_folded,
-            true
-        )
-        .point;
-        let line_height = self.screen_lines().line_height;
+        motion: WordMotion,
+        count: usize,
+        mode: Mode
+    ) -> Result<usize> {
+        let buffer = self.buffer();
+        let inserting = mode == Mode::Insert;
+        let (new_offset, prefer) = match motion {
+            WordMotion::Forward => (
+                buffer.move_n_words_forward(offset, count),
+                CursorAffinity::Forward
+            ),
+            WordMotion::Backward => (
+                buffer.move_n_words_backward(offset, count, mode),
+                CursorAffinity::Forward
+            ),
+            WordMotion::EndForward => {
+                let new_offset =
+                    buffer.move_n_wordends_forward(offset, count, inserting);
+                // 块光标位于字符上，在幽灵文本之后
+                let prefer = if inserting {
+                    CursorAffinity::Backward
+                } else {
+                    CursorAffinity::Forward
+                };
+                (new_offset, prefer)
+            },
+            WordMotion::SubwordForward => (
+                buffer.move_n_subwords_forward(offset, count),
+                CursorAffinity::Forward
+            ),
+            WordMotion::SubwordBackward => (
+                buffer.move_n_subwords_backward(offset, count, mode),
+                CursorAffinity::Forward
+            )
+        };
+        *affinity = self.affinity_of_offset(new_offset, prefer)?;
+        Ok(new_offset)
+    }
+
+    /// `w`, see [Self::move_by_word]
+    pub fn move_words_forward(
+        &self,
+        affinity: &mut CursorAffinity,
+        offset: usize,
+        count: usize
+    ) -> Result<usize> {
+        self.move_by_word(affinity, offset, WordMotion::Forward, count, Mode::Normal)
+    }
+
+    /// `b`, see [Self::move_by_word]
+    pub fn move_words_backward(
+        &self,
+        affinity: &mut CursorAffinity,
+        offset: usize,
+        count: usize,
+        mode: Mode
+    ) -> Result<usize> {
+        self.move_by_word(affinity, offset, WordMotion::Backward, count, mode)
+    }
+
+    /// `e`, see [Self::move_by_word]
+    pub fn move_word_ends_forward(
+        &self,
+        affinity: &mut CursorAffinity,
+        offset: usize,
+        count: usize,
+        inserting: bool
+    ) -> Result<usize> {
+        let mode = if inserting { Mode::Insert } else { Mode::Normal };
+        self.move_by_word(affinity, offset, WordMotion::EndForward, count, mode)
+    }
+
+    /// If there are phantom texts(not folded ranges) displayed at
+    /// `offset`
+    pub fn has_phantom_at(&self, offset: usize) -> Result<bool> {
+        Ok(!self.phantom_texts_at(offset)?.is_empty())
+    }
+
+    /// The phantom texts(not folded ranges) displayed at `offset`
+    pub fn phantom_texts_at(&self, offset: usize) -> Result<Vec<&PhantomText>> {
+        let (line, col) = self.buffer().offset_to_line_col(offset)?;
+        let folded_line = self.folded_line_of_origin_line(line)?;
+        Ok(folded_line
+            .text_layout
+            .phantom_text
+            .iter_phantom_text()
+            .filter(|x| {
+                x.line == line
+                    && x.col == col
+                    && !matches!(x.kind, PhantomTextKind::LineFoldedRang { .. })
+            })
+            .collect())
+    }
+
+    /// The affinity of a caret at `offset`. `prefer` is kept if both sides
+    /// are valid: at a soft-wrap boundary, or around inlay hints. The
+    /// caret is always before completions and error lens, and is
+    /// `Forward` elsewhere.
+    pub fn affinity_of_offset(
+        &self,
+        offset: usize,
+        prefer: CursorAffinity
+    ) -> Result<CursorAffinity> {
+        let phantoms = self.phantom_texts_at(offset)?;
+        if phantoms.iter().any(|x| {
+            matches!(
+                x.kind,
+                PhantomTextKind::Completion | PhantomTextKind::Diagnostic
+            )
+        }) {
+            return Ok(CursorAffinity::Backward);
+        }
+        if !phantoms.is_empty() || self.is_wrap_boundary(offset)? {
+            return Ok(prefer);
+        }
+        Ok(CursorAffinity::Forward)
+    }
+
+    /// If `offset` is both the end of a visual line and the start of the
+    /// next one of the same line, i.e. the line is soft wrapped there
+    pub fn is_wrap_boundary(&self, offset: usize) -> Result<bool> {
+        let (vl, ..) =
+            self.visual_line_of_offset(offset, CursorAffinity::Forward)?;
+        let same_line = |index: usize| {
+            self.visual_lines
+                .get(index)
+                .filter(|x| x.origin_folded_line == vl.origin_folded_line)
+        };
+        if offset == vl.origin_interval.start && vl.line_index > 0 {
+            if let Some(prev) = same_line(vl.line_index - 1) {
+                return Ok(prev.origin_interval.end == offset);
+            }
+        }
+        if offset == vl.origin_interval.end {
+            if let Some(next) = same_line(vl.line_index + 1) {
+                return Ok(next.origin_interval.start == offset);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Fix the affinity of the cursor after it is moved
+    /// programmatically. The block caret of normal mode is always on the
+    /// char after `offset`.
+    pub fn normalize_cursor(&self, cursor: &mut Cursor) -> Result<()> {
+        cursor.affinity = if cursor.is_normal() {
+            CursorAffinity::Forward
+        } else {
+            self.affinity_of_offset(cursor.offset(), cursor.affinity)?
+        };
+        Ok(())
+    }
+
+    pub fn line_point_of_visual_line_col(
+        &self,
+        visual_line: usize,
+        col: usize,
+        affinity: CursorAffinity,
+        _force_affinity: bool
+    ) -> Result<Point> {
+        self._line_point_of_visual_line_col(visual_line, col, affinity, _force_affinity).ok_or(anyhow!("visual_line={visual_line} col={col} is empty"))
+    }
+    pub fn _line_point_of_visual_line_col(
+        &self,
+        visual_line: usize,
+        col: usize,
+        affinity: CursorAffinity,
+        _force_affinity: bool
+    ) -> Option<Point> {
+        let text_layout = &self.origin_folded_lines
+            .get(self.visual_lines.get(visual_line)?.origin_folded_line)?
+            .text_layout;
+        Some(hit_position_aff(
+            &text_layout.text,
+            col,
+            affinity == CursorAffinity::Backward
+        )
+        .point)
+    }
+
+    #[allow(clippy::type_complexity)]
+    /// return (visual line of offset, offset of visual line, offset
+    /// of folded line, is last char, viewport position of cursor,
+    /// line_height, origin position of cursor)
+    ///
+    /// last_char should be check in future
+    pub fn cursor_position_of_buffer_offset(
+        &self,
+        offset: usize,
+        affinity: CursorAffinity
+    ) -> Result<(
+        VisualLine,
+        usize,
+        usize,
+        bool,
+        Option<Point>,
+        f64,
+        Point,
+        usize
+    )> {
+        let (vl, offset_of_visual, offset_folded, last_char, _) =
+            self.visual_line_of_offset(offset, affinity)?;
+        let mut viewpport_point = hit_position_aff(
+            &self.text_layout_of_visual_line(vl.line_index)?.text,
+            offset_folded,
+            true
+        )
+        .point;
+        let line_height = self.screen_lines().line_height;
         let screen_line = self.screen_lines().visual_line_info_of_visual_line(&vl);
 
         let point = if let Some(vlinfo) = screen_line {
@@ -2344,7 +3574,8 @@ impl ComputeLines {
             None
         };
         let mut origin_point = viewpport_point;
-        origin_point.y = vl.line_index as f64 * line_height;
+        origin_point.y = vl.line_index as f64 * line_height
+            + self.block_heights.above(vl.line_index);
 
         Ok((
             vl,
@@ -2358,6 +3589,199 @@ impl ComputeLines {
         ))
     }
 
+    /// The render info of all carets of `cursor`, one call per frame
+    pub fn caret_infos(&self, cursor: &Cursor) -> Vec<CaretInfo> {
+        let style = cursor.caret_style();
+        cursor
+            .caret_regions()
+            .into_iter()
+            .filter_map(|region| {
+                match self.caret_info(region, cursor.affinity, style) {
+                    Ok(info) => Some(info),
+                    Err(err) => {
+                        error!("{err:?}");
+                        None
+                    }
+                }
+            })
+            .collect()
+    }
+
+    fn caret_info(
+        &self,
+        region: SelRegion,
+        affinity: CursorAffinity,
+        style: CaretStyle
+    ) -> Result<CaretInfo> {
+        let offset = region.end;
+        // 块光标覆盖的是光标后的字符，不受幽灵文本的亲和性影响
+        let affinity = match style {
+            CaretStyle::Bar => affinity,
+            CaretStyle::Block | CaretStyle::Underline => CursorAffinity::Forward
+        };
+        let (visual_line, _offset_of_visual, offset_folded, ..) =
+            self.visual_line_of_offset(offset, affinity)?;
+        let text_layout = self.text_layout_of_visual_line(visual_line.line_index)?;
+        let x = hit_position_aff(
+            &text_layout.text,
+            offset_folded,
+            affinity == CursorAffinity::Backward
+        )
+        .point
+        .x;
+        let width = match style {
+            CaretStyle::Bar => 0.0,
+            CaretStyle::Block | CaretStyle::Underline => {
+                let grapheme_len = self.buffer().next_grapheme_offset(
+                    offset,
+                    1,
+                    self.buffer().len()
+                ) - offset;
+                grapheme_width(text_layout, offset_folded, grapheme_len)
+                    .unwrap_or(self.line_height as f64 / 2.0)
+            }
+        };
+        let line_height = self.screen_lines().line_height;
+        let rect = self
+            .screen_lines()
+            .visual_line_info_of_visual_line(&visual_line)
+            .map(|info| {
+                let base = self.screen_lines().base;
+                let x = x + base.x0;
+                let y = info.visual_line_y + base.y0;
+                Rect::new(x, y, x, y + line_height)
+            });
+        let shape_rect = rect.map(|rect| match style {
+            CaretStyle::Bar => rect.with_size((2.0, line_height)),
+            CaretStyle::Block => rect.with_size((width, line_height)),
+            CaretStyle::Underline => Rect::new(
+                rect.x0,
+                rect.y1 - 2.0,
+                rect.x0 + width,
+                rect.y1
+            )
+        });
+        Ok(CaretInfo {
+            offset,
+            region: (region.start, region.end),
+            visual_line,
+            rect,
+            x,
+            horiz: region.horiz,
+            style,
+            shape_rect
+        })
+    }
+
+    /// The band of the cursor column, the whole height of the viewport
+    /// at the x of the primary caret, as wide as the grapheme under it:
+    /// a tab is wide and the phantom texts before it are counted. None
+    /// if `cursor_column` is off. Call it per frame with
+    /// [DocLines::cursor_column_color].
+    pub fn cursor_column_rect(&self, cursor: &Cursor) -> Result<Option<Rect>> {
+        if !self.config.cursor_column {
+            return Ok(None);
+        }
+        let offset = cursor.offset();
+        let affinity = cursor.affinity;
+        let (visual_line, _offset_of_visual, offset_folded, ..) =
+            self.visual_line_of_offset(offset, affinity)?;
+        let text_layout = self.text_layout_of_visual_line(visual_line.line_index)?;
+        let x = hit_position_aff(
+            &text_layout.text,
+            offset_folded,
+            affinity == CursorAffinity::Backward
+        )
+        .point
+        .x;
+        let buffer = self.buffer();
+        let grapheme_len =
+            buffer.next_grapheme_offset(offset, 1, buffer.len()) - offset;
+        let width = grapheme_width(text_layout, offset_folded, grapheme_len)
+            .unwrap_or(self.line_height as f64 / 2.0);
+        let base = self.screen_lines().base;
+        let x = x + base.x0;
+        Ok(Some(Rect::new(x, base.y0, x + width, base.y1)))
+    }
+
+    /// The rect(in viewport, zero width, one line height) where a
+    /// completion or signature popup should anchor for `offset`.
+    ///
+    /// Phantom texts(inlay hints, folded ranges...) before `offset` are
+    /// taken into account. Return None if `offset` is not on screen.
+    pub fn completion_anchor_rect(
+        &self,
+        offset: usize,
+        affinity: CursorAffinity
+    ) -> Result<Option<Rect>> {
+        let (vl, _offset_of_visual, offset_folded, ..) =
+            self.visual_line_of_offset(offset, affinity)?;
+        let Some(vlinfo) = self.screen_lines().visual_line_info_of_visual_line(&vl)
+        else {
+            return Ok(None);
+        };
+        let point = hit_position_aff(
+            &self.text_layout_of_visual_line(vl.line_index)?.text,
+            offset_folded,
+            affinity == CursorAffinity::Backward
+        )
+        .point;
+        let base = self.screen_lines().base;
+        let x = point.x + base.x0;
+        let y = vlinfo.visual_line_y + base.y0;
+        Ok(Some(Rect::new(x, y, x, y + self.screen_lines().line_height)))
+    }
+
+    /// The rects(in viewport, one per visual line) of the text in
+    /// `range`, e.g. to place overlays like inline debugger values.
+    ///
+    /// Wraps, phantom texts and folds are taken into account, the text
+    /// hidden by a fold maps to its placeholder. The visual lines not on
+    /// screen are skipped.
+    pub fn rect_of_range(&self, range: Range<usize>) -> Result<Vec<Rect>> {
+        let len = self.buffer().len();
+        let start = range.start.min(len);
+        let end = range.end.clamp(start, len);
+        let (vl_start, _, col_start, ..) =
+            self.visual_line_of_offset(start, CursorAffinity::Forward)?;
+        let (vl_end, _, col_end, ..) =
+            self.visual_line_of_offset(end, CursorAffinity::Backward)?;
+        let screen_lines = self.screen_lines();
+        let base = screen_lines.base;
+        let mut rects = Vec::new();
+        for info in &screen_lines.visual_lines {
+            let vl = &info.visual_line;
+            if vl.line_index < vl_start.line_index {
+                continue;
+            } else if vl.line_index > vl_end.line_index {
+                break;
+            }
+            let layout = self.text_layout_of_visual_line(vl.line_index)?;
+            let (line_x0, line_x1) = layout
+                .get_layout_x(vl.origin_folded_line_sub_index)
+                .unwrap_or_default();
+            let x0 = if vl.line_index == vl_start.line_index {
+                hit_position_aff(&layout.text, col_start, false).point.x
+            } else {
+                line_x0 as f64
+            };
+            // 换行处的结束位置属于上一视觉行
+            let x1 = if vl.line_index == vl_end.line_index {
+                hit_position_aff(&layout.text, col_end, true).point.x
+            } else {
+                line_x1 as f64
+            };
+            let y = info.visual_line_y + base.y0;
+            rects.push(Rect::new(
+                base.x0 + x0,
+                y,
+                base.x0 + x1.max(x0),
+                y + screen_lines.line_height
+            ));
+        }
+        Ok(rects)
+    }
+
     pub fn char_rect_in_viewport(&self, offset: usize) -> Result<Vec<Rect>> {
         // let Ok((vl, _col, col_2, _, folded_line)) =
         // self.visual_line_of_offset(offset, CursorAffinity::Forward)
@@ -2461,21 +3885,18 @@ type LinesOnUpdate = DocLines;
 
 impl LinesOnUpdate {
     fn on_update_buffer(&mut self) -> Result<()> {
-        if self.syntax.styles.is_some() {
-            self.parser
-                .update_code(self.signals.buffer.val(), Some(&self.syntax))?;
-        } else {
-            self.parser.update_code(self.signals.buffer.val(), None)?;
-        }
+        self.update_parser()?;
         self.init_diagnostics_with_buffer()?;
         Ok(())
     }
 
     fn on_update_lines(&mut self) {
+        self.update_block_heights();
         self.max_width = 0.0;
         self.origin_folded_lines.iter().for_each(|x| if x.text_layout.text.size().width > self.max_width {
             self.max_width = x.text_layout.text.size().width;
         });
+        self.signals.max_width.update_if_not_equal(self.max_width);
 
         self.signals
             .last_line
@@ -2488,15 +3909,72 @@ impl LinesOnUpdate {
         // 设置字体属性
         let attrs = self.init_attrs_without_color(&family); // 等宽字体
         let attrs_list = AttrsList::new(attrs);
-        let mut font_system = FONT_SYSTEM.lock();
         // 创建文本缓冲区
-        let text_buffer = TextLayout::new_with_font_system(0, last_line.to_string(), attrs_list, &mut font_system);
+        let text_buffer = self.new_text_layout(&last_line.to_string(), attrs_list);
         (last_line, text_buffer.size().width)
     }
+
+    /// Shape the text by the font system, or layout by the monospace
+    /// metrics if set
+    fn new_text_layout(&self, text: &str, attrs_list: AttrsList) -> TextLayout {
+        match self.monospace_metrics {
+            Some(metrics) => TextLayout::new_monospace(0, text, attrs_list, metrics),
+            None => {
+                let mut font_system = FONT_SYSTEM.lock();
+                TextLayout::new_with_font_system(
+                    0,
+                    text,
+                    attrs_list,
+                    &mut font_system
+                )
+            }
+        }
+    }
+
+    /// Layout all the text by the monospace metrics instead of
+    /// `FONT_SYSTEM`, `None` restores the font system
+    pub fn set_monospace_metrics(
+        &mut self,
+        metrics: Option<MonospaceMetrics>
+    ) -> Result<()> {
+        if self.monospace_metrics == metrics {
+            return Ok(());
+        }
+        self.monospace_metrics = metrics;
+        self.update_lines_new(OriginLinesDelta::default())?;
+        self.on_update_lines();
+        self.update_screen_lines();
+        self.update_folding_display_items();
+        self.trigger_signals();
+        Ok(())
+    }
+
+    pub fn monospace_metrics(&self) -> Option<MonospaceMetrics> {
+        self.monospace_metrics
+    }
 }
 
 type PubUpdateLines = DocLines;
 
+/// The width of glyphs of `[final_col, final_col + len)`, None if
+/// there is no glyph(the end of file)
+fn grapheme_width(
+    text_layout: &TextLayoutLine,
+    final_col: usize,
+    len: usize
+) -> Option<f64> {
+    let end = final_col + len.max(1);
+    let width: f32 = text_layout
+        .text
+        .line_layout()
+        .iter()
+        .flat_map(|x| x.glyphs.iter())
+        .filter(|x| x.start >= final_col && x.start < end)
+        .map(|x| x.w)
+        .sum();
+    (width > 0.0).then_some(width as f64)
+}
+
 pub enum EditBuffer<'a> {
     Init(Rope),
     SetLineEnding(LineEnding),
@@ -2516,12 +3994,14 @@ pub enum EditBuffer<'a> {
         motion_mode: MotionMode,
         range:       Range<usize>,
         is_vertical: bool,
+        count:       usize,
         register:    &'a mut Register,
         response:    &'a mut Vec<(Rope, RopeDelta, InvalLines)>
     },
     DoEditBuffer {
         cursor:    &'a mut Cursor,
         cmd:       &'a EditCommand,
+        count:     usize,
         modal:     bool,
         register:  &'a mut Register,
         smart_tab: bool,
@@ -2532,6 +4012,18 @@ pub enum EditBuffer<'a> {
         s:        &'a str,
         response: &'a mut Vec<(Rope, RopeDelta, InvalLines)>
     },
+    DoPasteBuffer {
+        cursor:   &'a mut Cursor,
+        data:     &'a RegisterData,
+        response: &'a mut Vec<(Rope, RopeDelta, InvalLines)>
+    },
+    /// 替换查找的下一个匹配或全部匹配
+    Replace {
+        cursor:      &'a mut Cursor,
+        replacement: &'a str,
+        all:         bool,
+        response:    &'a mut Vec<(Rope, RopeDelta, InvalLines)>
+    },
     SetCursor {
         before_cursor: CursorMode,
         after_cursor:  CursorMode
@@ -2556,21 +4048,57 @@ impl Debug for EditBuffer<'_> {
             EditBuffer::Reload { content, set_pristine, .. } => {
                 write!(f, "EditBuffer::Reload set_pristine {set_pristine:?} content={content:?}")
             }
-            EditBuffer::ExecuteMotionMode { cursor, motion_mode, range, is_vertical, .. } => {
-                write!(f, "EditBuffer::ExecuteMotionMode {:?} {motion_mode:?} range={range:?}, is_vertical={is_vertical}", cursor.mode())
+            EditBuffer::ExecuteMotionMode { cursor, motion_mode, range, is_vertical, count, .. } => {
+                write!(f, "EditBuffer::ExecuteMotionMode {:?} {motion_mode:?} range={range:?}, is_vertical={is_vertical} count={count}", cursor.mode())
             }
-            EditBuffer::DoEditBuffer { cursor, cmd, modal, smart_tab, .. } => {
-                write!(f, "EditBuffer::DoEditBuffer {:?} {cmd:?} modal={modal} smart_tab={smart_tab}", cursor.mode())
+            EditBuffer::DoEditBuffer { cursor, cmd, count, modal, smart_tab, .. } => {
+                write!(f, "EditBuffer::DoEditBuffer {:?} {cmd:?} count={count} modal={modal} smart_tab={smart_tab}", cursor.mode())
             }
             EditBuffer::DoInsertBuffer { cursor, s, .. } => {
                 write!(f, "EditBuffer::DoInsertBuffer {:?} s={s:?}", cursor.mode())
             }
+            EditBuffer::DoPasteBuffer { cursor, data, .. } => {
+                write!(f, "EditBuffer::DoPasteBuffer {:?} mode={:?} content={:?}", cursor.mode(), data.mode, data.content)
+            }
+            EditBuffer::Replace { cursor, replacement, all, .. } => {
+                write!(f, "EditBuffer::Replace {:?} replacement={replacement:?} all={all}", cursor.mode())
+            }
             EditBuffer::SetCursor { before_cursor, after_cursor } => {
                 write!(f, "EditBuffer::SetCursor before_cursor {before_cursor:?} after_cursor={after_cursor:?}")
             }
         }
     }
 }
+
+impl EditBuffer<'_> {
+    /// Whether the edit changes the text, rejected by a read-only
+    /// document. Loading the content is always allowed.
+    fn is_mutating(&self) -> bool {
+        match self {
+            EditBuffer::Init(_)
+            | EditBuffer::Reload { .. }
+            | EditBuffer::SetPristine(_)
+            | EditBuffer::SetCursor { .. } => false,
+            EditBuffer::ExecuteMotionMode { motion_mode, .. } => {
+                !matches!(motion_mode, MotionMode::Yank { .. })
+            },
+            EditBuffer::DoEditBuffer { cmd, .. } => !matches!(
+                cmd,
+                EditCommand::ClipboardCopy
+                    | EditCommand::Yank
+                    | EditCommand::NormalMode
+                    | EditCommand::ToggleVisualMode
+                    | EditCommand::ToggleLinewiseVisualMode
+                    | EditCommand::ToggleBlockwiseVisualMode
+            ),
+            EditBuffer::SetLineEnding(_)
+            | EditBuffer::EditBuffer { .. }
+            | EditBuffer::DoInsertBuffer { .. }
+            | EditBuffer::DoPasteBuffer { .. }
+            | EditBuffer::Replace { .. } => true
+        }
+    }
+}
 impl PubUpdateLines {
     pub fn init_buffer(&mut self, content: Rope) -> Result<bool> {
         self.buffer_edit(EditBuffer::Init(content))
@@ -2578,6 +4106,51 @@ impl PubUpdateLines {
 
     pub fn buffer_edit(&mut self, edit: EditBuffer) -> Result<bool> {
         debug!("buffer_edit {edit:?}");
+        if self.read_only && edit.is_mutating() {
+            bail!(ReadOnlyError);
+        }
+        let rev = self.buffer().rev();
+        self.edit_rejection = None;
+        // 一次操作的多个编辑是一个事务，一个被拒绝则之后的都被拒绝
+        self.buffer_mut().start_transaction();
+        let flow = self.apply_buffer_edit(edit);
+        let rejection = self.buffer_mut().end_transaction();
+        let flow = flow?;
+        if let Some(reason) = rejection {
+            // 整个操作都被拒绝时文本未变，不需要重新排版
+            if self.buffer().rev() == rev {
+                bail!(EditRejectedError { reason });
+            }
+            self.edit_rejection = Some(reason);
+        }
+        let line_delta = match flow {
+            ControlFlow::Continue(line_delta) => line_delta,
+            ControlFlow::Break(changed) => return Ok(changed)
+        };
+        self.signals
+            .pristine
+            .update_if_not_equal(self.buffer().is_pristine());
+        self.signals
+            .buffer_rev
+            .update_if_not_equal(self.buffer().rev());
+        self.on_update_buffer()?;
+        self.update_lines_new(line_delta)?;
+        self.on_update_lines();
+        self.advance_tail_viewport();
+        self.update_screen_lines();
+        self.update_folding_display_items();
+        self.dispatch_edit_events();
+
+        self.trigger_signals();
+        Ok(true)
+    }
+
+    /// Apply the edit to the buffer, Break is the result of the edits
+    /// not changing the text, e.g. [EditBuffer::SetCursor]
+    fn apply_buffer_edit(
+        &mut self,
+        edit: EditBuffer
+    ) -> Result<ControlFlow<bool, OriginLinesDelta>> {
         let mut line_delta = OriginLinesDelta::default();
         match edit {
             EditBuffer::Init(content) => {
@@ -2585,6 +4158,23 @@ impl PubUpdateLines {
                     IndentStyle::from_str(self.syntax.language.indent_unit());
                 self.buffer_mut().init_content(content);
                 self.buffer_mut().detect_indent(|| indent);
+                self.marks.clear();
+                self.decorations.clear();
+                self.occurrences = None;
+                self.search = None;
+                self.signals.search_matches.update_if_not_equal(Vec::new());
+                self.flashes.clear();
+                self.auto_folded = false;
+                self.linked_ranges = None;
+                self.rename_session = None;
+                self.last_change = LastChange::default();
+                self.blame = None;
+                self.gutter_texts = None;
+                self.inline_values = InlineValues::default();
+                self.result_blocks.clear();
+                self.peek_views.clear();
+                self.diagnostic_result_id = None;
+                self.syntax_edits.clear();
             },
             EditBuffer::SetLineEnding(line_ending) => {
                 self.buffer_mut().set_line_ending(line_ending);
@@ -2594,21 +4184,23 @@ impl PubUpdateLines {
                 edit_type,
                 response
             } => {
-                let rs = self.buffer_mut().edit(iter, edit_type);
+                let Some(rs) = self.buffer_mut().edit(iter, edit_type) else {
+                    return Ok(ControlFlow::Break(false));
+                };
                 debug!("buffer_edit EditBuffer {:?} {:?}", rs.1, rs.2);
-                self.apply_delta(&rs.1)?;
+                self.apply_delta(&rs.0, &rs.1)?;
                 line_delta = resolve_delta_rs(&rs.0, &rs.1)?;
                 response.push(rs);
+                self.mirror_linked_edits(None, response, &mut line_delta)?;
             },
             EditBuffer::SetPristine(recv) => {
-                return if recv == self.buffer().rev() {
-                    self.buffer_mut().set_pristine();
-                    self.signals.pristine.update_if_not_equal(true);
-                    self.trigger_signals();
-                    Ok(true)
-                } else {
-                    Ok(false)
-                };
+                if recv != self.buffer().rev() {
+                    return Ok(ControlFlow::Break(false));
+                }
+                self.buffer_mut().set_pristine();
+                self.signals.pristine.update_if_not_equal(true);
+                self.trigger_signals();
+                return Ok(ControlFlow::Break(true));
             },
             EditBuffer::Reload {
                 content,
@@ -2617,7 +4209,13 @@ impl PubUpdateLines {
             } => {
                 let rs = self.buffer_mut().reload(content, set_pristine);
                 debug!("buffer_edit Reload {:?} {:?}", rs.1, rs.2);
-                self.apply_delta(&rs.1)?;
+                // 折叠范围以行为单位，按行的变化调整，等待lsp的新范围
+                self.folding_ranges.apply_line_delta(
+                    rs.2.start_line,
+                    rs.2.inval_count,
+                    rs.2.new_count
+                );
+                self.apply_delta(&rs.0, &rs.1)?;
                 // line_delta = self._compute_change_lines_one(&rs)?;
                 response.push(rs);
             },
@@ -2626,55 +4224,96 @@ impl PubUpdateLines {
                 motion_mode,
                 range,
                 is_vertical,
+                count,
                 register,
                 response
             } => {
+                let was_insert = cursor.is_insert();
+                let step =
+                    self.motion_step(&motion_mode, &range, is_vertical, count);
                 *response = Action::execute_motion_mode(
                     cursor,
                     self.buffer_mut(),
                     motion_mode,
                     range,
                     is_vertical,
+                    count,
                     register
                 );
                 for delta in &*response {
-                    self.apply_delta(&delta.1)?;
+                    self.apply_delta(&delta.0, &delta.1)?;
                 }
+                self.last_change.record(
+                    step,
+                    was_insert,
+                    cursor.is_insert(),
+                    !response.is_empty()
+                );
                 line_delta = self._compute_change_lines(&*response)?;
+                self.mirror_linked_edits(Some(cursor), response, &mut line_delta)?;
             },
             EditBuffer::DoEditBuffer {
                 cursor,
                 cmd,
+                count,
                 modal,
                 register,
                 smart_tab,
                 response
             } => {
-                let syntax = &self.syntax;
+                // 模板文件中按光标所在区域的语言，如vue的<script>
+                let language = self.syntax.language_at(cursor.offset());
                 let mut clipboard = SystemClipboard::new();
                 let old_cursor = cursor.mode().clone();
+                let was_insert = cursor.is_insert();
                 *response = Action::do_edit(
                     cursor,
                     self.signals.buffer.val_mut(),
                     cmd,
+                    count,
                     &mut clipboard,
                     register,
+                    &mut self.copied,
                     EditConf {
-                        comment_token: syntax.language.comment_token(),
+                        comment_token: language.comment_token(),
                         modal,
                         smart_tab,
                         keep_indent: true,
-                        auto_indent: true
+                        auto_indent: true,
+                        split_brackets: self
+                            .config
+                            .enter_between_brackets
+                            .is_enabled(language.name()),
+                        backspace: self.config.backspace,
+                        continue_comments: self
+                            .config
+                            .continue_comments
+                            .is_enabled(language.name())
                     }
                 );
                 if !response.is_empty() {
                     self.buffer_mut().set_cursor_before(old_cursor);
                     self.buffer_mut().set_cursor_after(cursor.mode().clone());
                     for delta in &*response {
-                        self.apply_delta(&delta.1)?;
+                        self.apply_delta(&delta.0, &delta.1)?;
                     }
                 }
+                if !matches!(cmd, EditCommand::Undo | EditCommand::Redo) {
+                    let step = RepeatStep::Edit {
+                        cmd: cmd.clone(),
+                        count,
+                        modal,
+                        smart_tab
+                    };
+                    self.last_change.record(
+                        step,
+                        was_insert,
+                        cursor.is_insert(),
+                        !response.is_empty()
+                    );
+                }
                 line_delta = self._compute_change_lines(&*response)?;
+                self.mirror_linked_edits(Some(cursor), response, &mut line_delta)?;
             },
             EditBuffer::DoInsertBuffer {
                 cursor,
@@ -2685,6 +4324,7 @@ impl PubUpdateLines {
                     self.config.auto_closing_matching_pairs;
                 let auto_surround = self.config.auto_surround;
                 let old_cursor = cursor.mode().clone();
+                let was_insert = cursor.is_insert();
                 let syntax = &self.syntax;
                 *response = Action::insert(
                     cursor,
@@ -2699,33 +4339,396 @@ impl PubUpdateLines {
                 self.buffer_mut().set_cursor_before(old_cursor);
                 self.buffer_mut().set_cursor_after(cursor.mode().clone());
                 for delta in &*response {
-                    self.apply_delta(&delta.1)?;
+                    self.apply_delta(&delta.0, &delta.1)?;
+                }
+                if !response.is_empty() {
+                    self.marks.set(MARK_LAST_INSERT, cursor.offset())?;
                 }
+                self.last_change.record(
+                    RepeatStep::Insert(s.to_string()),
+                    was_insert,
+                    cursor.is_insert(),
+                    !response.is_empty()
+                );
                 line_delta = self._compute_change_lines(&*response)?;
+                self.mirror_linked_edits(Some(cursor), response, &mut line_delta)?;
             },
-            EditBuffer::SetCursor {
-                before_cursor,
-                after_cursor
+            EditBuffer::DoPasteBuffer {
+                cursor,
+                data,
+                response
             } => {
-                self.buffer_mut().set_cursor_after(after_cursor);
-                self.buffer_mut().set_cursor_before(before_cursor);
-                return Ok(false);
+                let old_cursor = cursor.mode().clone();
+                let was_insert = cursor.is_insert();
+                *response = Action::do_paste(cursor, self.buffer_mut(), data);
+                if !response.is_empty() {
+                    self.buffer_mut().set_cursor_before(old_cursor);
+                    self.buffer_mut().set_cursor_after(cursor.mode().clone());
+                    for delta in &*response {
+                        self.apply_delta(&delta.0, &delta.1)?;
+                    }
+                }
+                self.last_change.record(
+                    RepeatStep::Paste(data.clone()),
+                    was_insert,
+                    cursor.is_insert(),
+                    !response.is_empty()
+                );
+                line_delta = self._compute_change_lines(&*response)?;
+                self.mirror_linked_edits(Some(cursor), response, &mut line_delta)?;
+            },
+            EditBuffer::Replace {
+                cursor,
+                replacement,
+                all,
+                response
+            } => {
+                let Some(search) = &self.search else {
+                    return Ok(ControlFlow::Break(false));
+                };
+                let old_cursor = cursor.mode().clone();
+                let buffer = self.signals.buffer.val_mut();
+                *response = if all {
+                    Action::replace_all(cursor, buffer, search, replacement)
+                } else {
+                    Action::replace_next(cursor, buffer, search, replacement)
+                };
+                if response.is_empty() {
+                    return Ok(ControlFlow::Break(false));
+                }
+                self.buffer_mut().set_cursor_before(old_cursor);
+                self.buffer_mut().set_cursor_after(cursor.mode().clone());
+                for delta in &*response {
+                    self.apply_delta(&delta.0, &delta.1)?;
+                }
+                line_delta = self._compute_change_lines(&*response)?;
+                // 查找替换编辑的是匹配处，不镜像到联动范围
+                self.linked_edited = None;
+            },
+            EditBuffer::SetCursor {
+                before_cursor,
+                after_cursor
+            } => {
+                self.buffer_mut().set_cursor_after(after_cursor);
+                self.buffer_mut().set_cursor_before(before_cursor);
+                return Ok(ControlFlow::Break(false));
             }
         }
-        self.signals
-            .pristine
-            .update_if_not_equal(self.buffer().is_pristine());
-        self.signals
-            .buffer_rev
-            .update_if_not_equal(self.buffer().rev());
-        self.on_update_buffer()?;
-        self.update_lines_new(line_delta)?;
+        Ok(ControlFlow::Continue(line_delta))
+    }
+
+    /// Copy the edit inside a linked range to the other ranges of the
+    /// group, in the same undo group
+    fn mirror_linked_edits(
+        &mut self,
+        cursor: Option<&mut Cursor>,
+        response: &mut Vec<(Rope, RopeDelta, InvalLines)>,
+        line_delta: &mut OriginLinesDelta
+    ) -> Result<()> {
+        let Some(edited) = self.linked_edited.take() else {
+            return Ok(());
+        };
+        let Some(linked) = &self.linked_ranges else {
+            return Ok(());
+        };
+        let Some(edits) = linked.mirror_edits(edited, self.buffer().text()) else {
+            // 不再匹配wordPattern，结束联动编辑
+            self.linked_ranges = None;
+            self.decorations.remove(LINKED_DECORATION_KEY);
+            *line_delta = OriginLinesDelta::default();
+            return Ok(());
+        };
+        if edits.is_empty() {
+            return Ok(());
+        }
+        let edits: Vec<(Selection, &str)> = edits
+            .iter()
+            .map(|(selection, text)| (selection.clone(), text.as_str()))
+            .collect();
+        let Some(rs) = self.buffer_mut().edit(&edits, EditType::Linked) else {
+            return Ok(());
+        };
+        if let Some(cursor) = cursor {
+            cursor.apply_delta(&rs.1);
+            self.buffer_mut().set_cursor_after(cursor.mode().clone());
+        }
+        self.apply_delta(&rs.0, &rs.1)?;
+        // 镜像编辑本身不再镜像
+        self.linked_edited = None;
+        response.push(rs);
+        *line_delta = self._compute_change_lines(response)?;
+        Ok(())
+    }
+
+    fn apply_delta_to_linked_ranges(&mut self, delta: &RopeDelta) {
+        let Some(linked) = self.linked_ranges.as_mut() else {
+            return;
+        };
+        let (iv, _) = delta.summary();
+        let touched = linked
+            .groups()
+            .iter()
+            .flatten()
+            .any(|x| x.start <= iv.end && iv.start <= x.end);
+        if !touched {
+            // 在联动范围外编辑，结束联动编辑
+            self.linked_ranges = None;
+            self.linked_edited = None;
+            self.decorations.remove(LINKED_DECORATION_KEY);
+            self.finish_rename_session();
+            return;
+        }
+        self.linked_edited = linked.apply_delta(delta);
+        self.set_linked_decorations();
+        if let Some(session) = self.rename_session.as_mut() {
+            if !session.edited {
+                session.edited = true;
+                self.buffer_mut().set_join_undo_group(true);
+            }
+        }
+    }
+
+    /// Start a snippet session, `placeholders` are the ranges of tab
+    /// stops in order, ranges of one tab stop mirror each other. Return
+    /// the selection of the first placeholder.
+    pub fn start_snippet_session(
+        &mut self,
+        placeholders: Vec<Vec<Interval>>
+    ) -> Result<Option<Selection>> {
+        let linked = LinkedRanges::new(placeholders);
+        if linked.is_empty() {
+            self.end_linked_editing()?;
+            return Ok(None);
+        }
+        let selection = linked.current_selection();
+        self.linked_ranges = Some(linked);
+        self.linked_edited = None;
+        self.set_linked_decorations();
+        self.update_lines_new(OriginLinesDelta::default())?;
         self.on_update_lines();
         self.update_screen_lines();
         self.update_folding_display_items();
+        self.trigger_signals();
+        Ok(selection)
+    }
 
+    /// Move to the next placeholder. The session ends after the last
+    /// one and None is returned.
+    pub fn next_snippet_placeholder(&mut self) -> Result<Option<Selection>> {
+        let Some(linked) = self.linked_ranges.as_mut() else {
+            return Ok(None);
+        };
+        if !linked.next() {
+            self.end_linked_editing()?;
+            return Ok(None);
+        }
+        let selection = linked.current_selection();
+        self.set_linked_decorations();
+        self.update_lines_new(OriginLinesDelta::default())?;
+        self.on_update_lines();
+        self.update_screen_lines();
         self.trigger_signals();
-        Ok(true)
+        Ok(selection)
+    }
+
+    pub fn prev_snippet_placeholder(&mut self) -> Result<Option<Selection>> {
+        let Some(linked) = self.linked_ranges.as_mut() else {
+            return Ok(None);
+        };
+        linked.prev();
+        let selection = linked.current_selection();
+        self.set_linked_decorations();
+        self.update_lines_new(OriginLinesDelta::default())?;
+        self.on_update_lines();
+        self.update_screen_lines();
+        self.trigger_signals();
+        Ok(selection)
+    }
+
+    /// Preview the lsp `TextEdit`s, e.g. of a workspace edit, as a diff
+    /// against the current text. The buffer is not changed.
+    pub fn preview_text_edits(
+        &self,
+        edits: &[TextEdit],
+        context_lines: Option<usize>
+    ) -> Result<EditPreview> {
+        let edits = edits
+            .iter()
+            .map(|x| {
+                Ok((
+                    Interval::new(
+                        self.buffer().offset_of_position(&x.range.start)?,
+                        self.buffer().offset_of_position(&x.range.end)?
+                    ),
+                    x.new_text.clone()
+                ))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let new_text = diff::apply_edits(self.buffer().text(), edits)?;
+        let _timer = self.time_phase(Phase::Diff);
+        Ok(diff::diff_preview(self.buffer().text(), new_text, context_lines))
+    }
+
+    /// Ranges from lsp `textDocument/linkedEditingRange`, e.g. the
+    /// paired html tags. An edit inside one range is mirrored into the
+    /// others in the same undo group; the linked editing ends when an
+    /// edit is out of the ranges. Empty `ranges` end it.
+    pub fn set_linked_ranges(
+        &mut self,
+        ranges: Vec<lsp_types::Range>,
+        word_pattern: Option<&str>
+    ) -> Result<()> {
+        if ranges.is_empty() {
+            return self.end_linked_editing();
+        }
+        let ranges = self.intervals_of_ranges(&ranges)?;
+        let mut linked = LinkedRanges::new(vec![ranges]);
+        if let Some(word_pattern) = word_pattern {
+            linked = linked.with_word_pattern(word_pattern)?;
+        }
+        self.linked_ranges = Some(linked);
+        self.linked_edited = None;
+        self.set_linked_decorations();
+        self.update_lines_new(OriginLinesDelta::default())?;
+        self.on_update_lines();
+        self.update_screen_lines();
+        self.update_folding_display_items();
+        self.trigger_signals();
+        Ok(())
+    }
+
+    fn intervals_of_ranges(
+        &self,
+        ranges: &[lsp_types::Range]
+    ) -> Result<Vec<Interval>> {
+        ranges
+            .iter()
+            .map(|x| {
+                Ok(Interval::new(
+                    self.buffer().offset_of_position(&x.start)?,
+                    self.buffer().offset_of_position(&x.end)?
+                ))
+            })
+            .collect()
+    }
+
+    pub fn linked_ranges(&self) -> Option<&LinkedRanges> {
+        self.linked_ranges.as_ref()
+    }
+
+    pub fn end_linked_editing(&mut self) -> Result<()> {
+        self.linked_edited = None;
+        self.finish_rename_session();
+        if self.linked_ranges.take().is_none() {
+            return Ok(());
+        }
+        self.remove_decorations(LINKED_DECORATION_KEY)
+    }
+
+    /// Rename the symbol in this document: typing in one of `ranges`,
+    /// all occurrences of the symbol, edits the others live. The edits
+    /// are in one undo group, until the session is committed or
+    /// cancelled. Return the selection of the ranges.
+    pub fn start_rename_session(
+        &mut self,
+        ranges: Vec<lsp_types::Range>
+    ) -> Result<Option<Selection>> {
+        self.end_linked_editing()?;
+        let ranges = self.intervals_of_ranges(&ranges)?;
+        let Some(first) = ranges.first() else {
+            return Ok(None);
+        };
+        let original =
+            self.buffer().slice_to_cow(first.start..first.end).to_string();
+        let linked = LinkedRanges::new(vec![ranges]);
+        let selection = linked.current_selection();
+        self.linked_ranges = Some(linked);
+        self.linked_edited = None;
+        self.rename_session = Some(RenameSession {
+            original,
+            edited: false
+        });
+        // 会话的第一次编辑开始新的撤销组
+        self.buffer_mut().reset_edit_type();
+        self.set_linked_decorations();
+        self.update_lines_new(OriginLinesDelta::default())?;
+        self.on_update_lines();
+        self.update_screen_lines();
+        self.update_folding_display_items();
+        self.trigger_signals();
+        Ok(selection)
+    }
+
+    pub fn rename_session(&self) -> Option<&RenameSession> {
+        self.rename_session.as_ref()
+    }
+
+    /// Keep the edits of the rename session
+    pub fn commit_rename_session(&mut self) -> Result<()> {
+        self.end_linked_editing()
+    }
+
+    /// Revert all the edits of the rename session by undoing its undo
+    /// group
+    pub fn cancel_rename_session(
+        &mut self,
+        cursor: &mut Cursor,
+        register: &mut Register
+    ) -> Result<Vec<(Rope, RopeDelta, InvalLines)>> {
+        let Some(session) = self.rename_session.take() else {
+            return Ok(vec![]);
+        };
+        if session.edited {
+            self.buffer_mut().set_join_undo_group(false);
+        }
+        self.end_linked_editing()?;
+        if !session.edited {
+            return Ok(vec![]);
+        }
+        self.do_edit_buffer(cursor, &EditCommand::Undo, 1, false, register, false)
+    }
+
+    /// The following edits are not joined into the session's undo group
+    fn finish_rename_session(&mut self) {
+        if let Some(session) = self.rename_session.take() {
+            if session.edited {
+                self.buffer_mut().set_join_undo_group(false);
+            }
+        }
+    }
+
+    /// The current group with background, others with under line
+    fn set_linked_decorations(&mut self) {
+        let Some(linked) = &self.linked_ranges else {
+            return;
+        };
+        let current = Decoration {
+            fg_color:   None,
+            bg_color:   Some(self.config.inlay_hint_bg),
+            under_line: None,
+            wave_line:  None
+        };
+        let other = Decoration {
+            fg_color:   None,
+            bg_color:   None,
+            under_line: Some(self.editor_style.phantom_color()),
+            wave_line:  None
+        };
+        let spans: Vec<(Interval, Decoration)> = linked
+            .groups()
+            .iter()
+            .enumerate()
+            .flat_map(|(index, ranges)| {
+                let decoration = if index == linked.current() {
+                    current
+                } else {
+                    other
+                };
+                ranges.iter().map(move |x| (*x, decoration))
+            })
+            .collect();
+        let len = self.buffer().len();
+        self.decorations.set(LINKED_DECORATION_KEY, len, spans);
     }
 
     pub fn set_line_ending(&mut self, line_ending: LineEnding) -> Result<()> {
@@ -2747,6 +4750,69 @@ impl PubUpdateLines {
         Ok(rs.remove(0))
     }
 
+    /// Append `text` to the end of the buffer, e.g. streaming logs or
+    /// REPL output. Unlike [Self::edit_buffer], the lines before the
+    /// last line are neither laid out nor scanned again, and cursors
+    /// and linked editing are not involved.
+    pub fn append(
+        &mut self,
+        text: &str
+    ) -> Result<Option<(Rope, RopeDelta, InvalLines)>> {
+        if self.read_only {
+            bail!(ReadOnlyError);
+        }
+        if text.is_empty() {
+            return Ok(None);
+        }
+        let old_len = self.buffer().len();
+        let old_last_line = self.buffer().last_line();
+        let Some(rs) = self
+            .buffer_mut()
+            .edit(&[(Selection::caret(old_len), text)], EditType::Other)
+        else {
+            let reason = self.buffer_mut().take_rejection().unwrap_or_default();
+            bail!(EditRejectedError { reason });
+        };
+        self.move_layers(&rs.1)?;
+        // 只重新计算原来的最后一行及之后的行
+        let line_delta = resolve_line_delta(&rs.0, OffsetDelta {
+            copy_start:   Interval::new(0, old_len),
+            internal_len: self.buffer().len() - old_len,
+            copy_end:     Interval::new(old_len, old_len)
+        })?;
+        self.signals
+            .pristine
+            .update_if_not_equal(self.buffer().is_pristine());
+        self.signals
+            .buffer_rev
+            .update_if_not_equal(self.buffer().rev());
+        self.update_parser()?;
+        self.update_lines_new(line_delta)?;
+        self.on_append_lines(old_last_line);
+        self.advance_tail_viewport();
+        self.update_screen_lines();
+        self.update_folding_display_items();
+        self.dispatch_edit_events();
+        self.trigger_signals();
+        Ok(Some(rs))
+    }
+
+    /// Like `on_update_lines`, only the lines from `start_line` are
+    /// measured
+    fn on_append_lines(&mut self, start_line: usize) {
+        let max_width = self
+            .origin_folded_lines
+            .iter()
+            .rev()
+            .take_while(|x| x.origin_line_end >= start_line)
+            .map(|x| x.text_layout.text.size().width)
+            .fold(self.max_width, f64::max);
+        self.max_width = max_width;
+        self.signals.max_width.update_if_not_equal(self.max_width);
+        let last_line = self.compute_last_width(self.buffer().last_line() + 1);
+        self.signals.last_line.update_if_not_equal(last_line);
+    }
+
     pub fn reload_buffer(
         &mut self,
         content: Rope,
@@ -2778,12 +4844,14 @@ impl PubUpdateLines {
         }
     }
 
+    /// `count` extends vertical motions by lines, like `3dd`
     pub fn execute_motion_mode(
         &mut self,
         cursor: &mut Cursor,
         motion_mode: MotionMode,
         range: Range<usize>,
         is_vertical: bool,
+        count: usize,
         register: &mut Register
     ) -> Result<Vec<(Rope, RopeDelta, InvalLines)>> {
         let mut rs = Vec::with_capacity(1);
@@ -2792,16 +4860,20 @@ impl PubUpdateLines {
             motion_mode,
             range,
             is_vertical,
+            count,
             register,
             response: &mut rs
         })?;
         Ok(rs)
     }
 
+    /// Execute `cmd` `count` times, like `5x` and `2p`, in one undo
+    /// group and one relayout
     pub fn do_edit_buffer(
         &mut self,
         cursor: &mut Cursor,
         cmd: &EditCommand,
+        count: usize,
         modal: bool,
         register: &mut Register,
         smart_tab: bool
@@ -2810,6 +4882,7 @@ impl PubUpdateLines {
         self.buffer_edit(EditBuffer::DoEditBuffer {
             cursor,
             cmd,
+            count,
             modal,
             register,
             smart_tab,
@@ -2832,208 +4905,2409 @@ impl PubUpdateLines {
         Ok(rs)
     }
 
-    pub fn clear_completion_lens(&mut self) {
-        self.completion_lens = None;
-        if let Err(err) = self.update_lines_new(OriginLinesDelta::default()) {
-            error!("{err:?}")
-        }
-        self.on_update_lines();
-        self.update_screen_lines();
-        self.update_folding_display_items();
+    /// Replace the next match of the search from the primary cursor and
+    /// select the match after it, see [Action::replace_next]. Empty if
+    /// there is no search or match.
+    pub fn replace_next(
+        &mut self,
+        cursor: &mut Cursor,
+        replacement: &str
+    ) -> Result<Vec<(Rope, RopeDelta, InvalLines)>> {
+        let mut rs = Vec::with_capacity(1);
+        self.buffer_edit(EditBuffer::Replace {
+            cursor,
+            replacement,
+            all: false,
+            response: &mut rs
+        })?;
+        Ok(rs)
     }
 
-    pub fn init_diagnostics(&mut self) -> Result<()> {
-        self.init_diagnostics_with_buffer()?;
-        self.update_lines_new(OriginLinesDelta::default())?;
-        self.on_update_lines();
-        self.update_screen_lines();
-        self.update_folding_display_items();
-        Ok(())
+    /// Replace all matches of the search as one undo step
+    pub fn replace_all(
+        &mut self,
+        cursor: &mut Cursor,
+        replacement: &str
+    ) -> Result<Vec<(Rope, RopeDelta, InvalLines)>> {
+        let mut rs = Vec::with_capacity(1);
+        self.buffer_edit(EditBuffer::Replace {
+            cursor,
+            replacement,
+            all: true,
+            response: &mut rs
+        })?;
+        Ok(rs)
     }
 
-    pub fn update_viewport_size(&mut self, viewport: Rect) -> Result<()> {
-        let viewport_size = viewport.size();
+    pub fn do_paste_buffer(
+        &mut self,
+        cursor: &mut Cursor,
+        data: &RegisterData
+    ) -> Result<Vec<(Rope, RopeDelta, InvalLines)>> {
+        let mut rs = Vec::with_capacity(1);
+        self.buffer_edit(EditBuffer::DoPasteBuffer {
+            cursor,
+            data,
+            response: &mut rs
+        })?;
+        Ok(rs)
+    }
 
-        let should_update =
-            matches!(self.editor_style.wrap_method(), WrapMethod::EditorWidth)
-                && self.viewport_size.width != viewport_size.width;
-        if should_update {
-            self.viewport_size = viewport_size;
-        }
-        if self.signals.viewport.update_if_not_equal(viewport) {
-            self.update_screen_lines();
-            self.update_folding_display_items();
+    pub fn last_change(&self) -> &LastChange {
+        &self.last_change
+    }
+
+    /// Replay the last change at `cursor`, like `.` of vim. The edits
+    /// are in one undo group.
+    pub fn repeat_last_change(
+        &mut self,
+        cursor: &mut Cursor,
+        register: &mut Register
+    ) -> Result<Vec<(Rope, RopeDelta, InvalLines)>> {
+        let last_change = self.last_change.clone();
+        let mut deltas = Vec::new();
+        let rs =
+            self.replay_steps(last_change.steps(), cursor, register, &mut deltas);
+        self.buffer_mut().set_join_undo_group(false);
+        // 重放的步骤不覆盖最后一次修改
+        self.last_change = last_change;
+        rs.map(|_| deltas)
+    }
+
+    fn replay_steps(
+        &mut self,
+        steps: &[RepeatStep],
+        cursor: &mut Cursor,
+        register: &mut Register,
+        deltas: &mut Vec<(Rope, RopeDelta, InvalLines)>
+    ) -> Result<()> {
+        for step in steps {
+            // 第一次修改之后的编辑合并到同一撤销组
+            let join = !deltas.is_empty();
+            self.buffer_mut().set_join_undo_group(join);
+            let rs = match step {
+                RepeatStep::Edit {
+                    cmd,
+                    count,
+                    modal,
+                    smart_tab
+                } => self.do_edit_buffer(
+                    cursor, cmd, *count, *modal, register, *smart_tab
+                )?,
+                RepeatStep::Motion {
+                    motion_mode,
+                    len,
+                    backward,
+                    is_vertical,
+                    count
+                } => {
+                    let start = cursor.offset();
+                    let end = if *is_vertical {
+                        let line = self.buffer().line_of_offset(start);
+                        let line = if *backward {
+                            line.saturating_sub(*len)
+                        } else {
+                            (line + len).min(self.buffer().last_line())
+                        };
+                        self.buffer().offset_of_line(line)?
+                    } else {
+                        let text = self.buffer().text();
+                        offset_by_chars(text, start, *len, *backward)
+                    };
+                    self.execute_motion_mode(
+                        cursor,
+                        motion_mode.clone(),
+                        start..end,
+                        *is_vertical,
+                        *count,
+                        register
+                    )?
+                },
+                RepeatStep::Insert(s) => self.do_insert_buffer(cursor, s)?,
+                RepeatStep::Paste(data) => self.do_paste_buffer(cursor, data)?
+            };
+            deltas.extend(rs);
         }
-        self.trigger_signals();
         Ok(())
     }
 
-    pub fn update_viewport_by_scroll(&mut self, viewport: Rect) {
-        debug!("viewport={viewport:?} self.signals.viewport={:?} {:?}", self.signals.viewport.val(), self.editor_style.wrap_method());
-        if self.signals.viewport.val().y0 == viewport.y0 && self.signals.viewport.val().y1 == viewport.y1 && !matches!(self.editor_style.wrap_method(), WrapMethod::EditorWidth) {
-            return;
+    fn motion_step(
+        &self,
+        motion_mode: &MotionMode,
+        range: &Range<usize>,
+        is_vertical: bool,
+        count: usize
+    ) -> RepeatStep {
+        let (min, max) = (range.start.min(range.end), range.start.max(range.end));
+        let len = if is_vertical {
+            self.buffer().line_of_offset(max) - self.buffer().line_of_offset(min)
+        } else {
+            self.buffer().slice_to_cow(min..max).chars().count()
+        };
+        RepeatStep::Motion {
+            motion_mode: motion_mode.clone(),
+            len,
+            backward: range.end < range.start,
+            is_vertical,
+            count
+        }
+    }
+
+    /// Paste the primary selection(middle click) at the cursor
+    pub fn paste_primary<C: PrimaryClipboard>(
+        &mut self,
+        cursor: &mut Cursor,
+        clipboard: &mut C
+    ) -> Result<Vec<(Rope, RopeDelta, InvalLines)>> {
+        let Some(content) = clipboard.get_primary_string() else {
+            return Ok(vec![]);
+        };
+        let data = RegisterData {
+            content,
+            mode: VisualMode::Normal
+        };
+        self.do_paste_buffer(cursor, &data)
+    }
+
+    /// Mirror the selected text of `cursor` to the primary selection.
+    /// Carets are ignored.
+    pub fn update_primary_selection<C: PrimaryClipboard>(
+        &self,
+        cursor: &Cursor,
+        clipboard: &mut C
+    ) -> Result<()> {
+        let content = match cursor.mode() {
+            CursorMode::Normal(_) => return Ok(()),
+            CursorMode::Visual { .. } => cursor.yank(self.buffer())?.content,
+            CursorMode::Insert(selection) => selection
+                .regions()
+                .iter()
+                .filter(|region| !region.is_caret())
+                .map(|region| self.buffer().slice_to_cow(region.min()..region.max()))
+                .join("\n")
+        };
+        if !content.is_empty() {
+            clipboard.put_primary_string(content);
+        }
+        Ok(())
+    }
+
+    /// Set the mark(`a`–`z`, or the special marks) to `offset`
+    pub fn set_mark(&mut self, mark: char, offset: usize) -> Result<()> {
+        let offset = offset.min(self.buffer().len());
+        self.marks.set(mark, offset)
+    }
+
+    pub fn mark_offset(&self, mark: char) -> Option<usize> {
+        self.marks.get(mark)
+    }
+
+    pub fn marks(&self) -> &Marks {
+        &self.marks
+    }
+
+    /// Move `cursor` to the mark. With `linewise`(`'a` in vim), move to
+    /// the first non blank character of the line of the mark. Return
+    /// false if the mark is not set.
+    pub fn goto_mark(
+        &self,
+        cursor: &mut Cursor,
+        mark: char,
+        linewise: bool
+    ) -> Result<bool> {
+        let Some(offset) = self.marks.get(mark) else {
+            return Ok(false);
+        };
+        let offset = offset.min(self.buffer().len());
+        let offset = if linewise {
+            let line = self.buffer().line_of_offset(offset);
+            self.buffer().first_non_blank_character_on_line(line)?
+        } else {
+            offset
+        };
+        cursor.set_offset(offset, false, false);
+        Ok(true)
+    }
+
+    /// Set the preedit with clauses, replacing the single run preedit
+    /// of `preedit`
+    pub fn set_ime_preedit(&mut self, preedit: ImePreedit) -> Result<()> {
+        if preedit.is_empty() {
+            return self.clear_ime_preedit();
+        }
+        self.ime_preedit = Some(preedit);
+        self.update_lines_new(OriginLinesDelta::default())?;
+        self.on_update_lines();
+        self.update_screen_lines();
+        self.update_folding_display_items();
+        self.trigger_signals();
+        Ok(())
+    }
+
+    pub fn clear_ime_preedit(&mut self) -> Result<()> {
+        if self.ime_preedit.take().is_none() {
+            return Ok(());
+        }
+        self.update_lines_new(OriginLinesDelta::default())?;
+        self.on_update_lines();
+        self.update_screen_lines();
+        self.update_folding_display_items();
+        self.trigger_signals();
+        Ok(())
+    }
+
+    pub fn ime_preedit(&self) -> Option<&ImePreedit> {
+        self.ime_preedit.as_ref()
+    }
+
+    /// The rect(in viewport, zero width, one line height) of the caret
+    /// in preedit, where the candidate window is placed. The start of
+    /// preedit is used if the caret is not set.
+    pub fn ime_cursor_rect(&self) -> Result<Option<Rect>> {
+        let Some(preedit) = &self.ime_preedit else {
+            return Ok(None);
+        };
+        let (vl, _offset_of_visual, _offset_folded, ..) =
+            self.visual_line_of_offset(preedit.offset, CursorAffinity::Forward)?;
+        let Some(vlinfo) = self.screen_lines().visual_line_info_of_visual_line(&vl)
+        else {
+            return Ok(None);
+        };
+        let text_layout = self.text_layout_of_visual_line(vl.line_index)?;
+        let start = text_layout.phantom_text.text.iter().find_map(|x| match x {
+            Text::Phantom { text } if text.kind == PhantomTextKind::Ime => {
+                Some(text.final_col)
+            },
+            _ => None
+        });
+        let Some(start) = start else {
+            return Ok(None);
+        };
+        let final_col = start + preedit.display_cursor().unwrap_or_default();
+        let hit = text_layout.text.hit_position(final_col);
+        let line_height = self.screen_lines().line_height;
+        let base = self.screen_lines().base;
+        let x = hit.point.x + base.x0;
+        let y = vlinfo.visual_line_y
+            + (hit.line as f64 - vl.origin_folded_line_sub_index as f64) * line_height
+            + base.y0;
+        Ok(Some(Rect::new(x, y, x, y + line_height)))
+    }
+
+    fn preedit_style(&self) -> PreeditStyle {
+        PreeditStyle {
+            under_line:     self.config.editor_foreground,
+            converted_line: self.editor_style.phantom_color(),
+            target_bg:      self.config.inlay_hint_bg
+        }
+    }
+
+    pub fn clear_completion_lens(&mut self) {
+        self.completion_lens = None;
+        if let Err(err) = self.update_lines_new(OriginLinesDelta::default()) {
+            error!("{err:?}")
+        }
+        self.on_update_lines();
+        self.update_screen_lines();
+        self.update_folding_display_items();
+    }
+
+    pub fn init_diagnostics(&mut self) -> Result<()> {
+        self.init_diagnostics_with_buffer()?;
+        self.update_lines_new(OriginLinesDelta::default())?;
+        self.on_update_lines();
+        self.update_screen_lines();
+        self.update_folding_display_items();
+        Ok(())
+    }
+
+    /// The `previousResultId` of the next `textDocument/diagnostic`
+    /// request
+    pub fn diagnostic_result_id(&self) -> Option<&str> {
+        self.diagnostic_result_id.as_deref()
+    }
+
+    /// Apply the report of `textDocument/diagnostic` requested at the
+    /// buffer revision `rev`. A report for an old revision is dropped,
+    /// the diagnostics have been moved by the edits since. An
+    /// unchanged report keeps the current diagnostics. Return true if
+    /// the diagnostics are replaced.
+    pub fn set_pull_diagnostics(
+        &mut self,
+        rev: u64,
+        report: DocumentDiagnosticReportKind
+    ) -> Result<bool> {
+        if self.buffer().rev() != rev {
+            return Ok(false);
+        }
+        match report {
+            DocumentDiagnosticReportKind::Full(report) => {
+                self.diagnostic_result_id = report.result_id;
+                let diagnostics: im::Vector<Diagnostic> = report.items.into();
+                self.diagnostics.diagnostics.set(diagnostics);
+                self.init_diagnostics()?;
+                self.trigger_signals();
+                Ok(true)
+            },
+            DocumentDiagnosticReportKind::Unchanged(report) => {
+                self.diagnostic_result_id = Some(report.result_id);
+                Ok(false)
+            }
+        }
+    }
+
+    /// The diagnostics covering `offset`, the most severe first
+    pub fn diagnostics_at(&self, offset: usize) -> Vec<Diagnostic> {
+        let offset = offset.min(self.buffer().len());
+        self.diagnostics.diagnostics_span.with_untracked(|diags| {
+            diags
+                .iter_chunks(offset.saturating_sub(1)..offset + 1)
+                .filter(|(iv, _)| iv.start <= offset && offset <= iv.end)
+                .map(|(_, diag)| diag.clone())
+                .sorted_by_key(|x| x.severity.unwrap_or(DiagnosticSeverity::HINT))
+                .collect()
+        })
+    }
+
+    /// The related information of the diagnostics at `offset`, the
+    /// locations may be in other files
+    pub fn related_information_at(
+        &self,
+        offset: usize
+    ) -> Vec<DiagnosticRelatedInformation> {
+        self.diagnostics_at(offset)
+            .into_iter()
+            .flat_map(|x| x.related_information.unwrap_or_default())
+            .collect()
+    }
+
+    /// Show the related information of the diagnostics at `offset`,
+    /// e.g. when the diagnostic is hovered or clicked. The related
+    /// ranges in this document(`uri`) get a secondary highlight until
+    /// [Self::clear_related_information].
+    pub fn show_related_information(
+        &mut self,
+        offset: usize,
+        uri: &Url
+    ) -> Result<Vec<DiagnosticRelatedInformation>> {
+        let mut spans = Vec::new();
+        for diag in self.diagnostics_at(offset) {
+            let color = diag
+                .severity
+                .and_then(|x| self.config.color_of_diagnostic(x))
+                .unwrap_or_else(|| self.editor_style.phantom_color());
+            let decoration = Decoration {
+                fg_color:   None,
+                bg_color:   None,
+                under_line: Some(color),
+                wave_line:  None
+            };
+            for info in diag.related_information.iter().flatten() {
+                if info.location.uri != *uri {
+                    continue;
+                }
+                let range = &info.location.range;
+                let start = self.buffer().offset_of_position(&range.start)?;
+                let end = self.buffer().offset_of_position(&range.end)?;
+                spans.push((Interval::new(start, end), decoration));
+            }
+        }
+        let related = self.related_information_at(offset);
+        if spans.is_empty() {
+            self.clear_related_information()?;
+        } else {
+            self.set_decorations(DIAGNOSTIC_RELATED_DECORATION_KEY, spans)?;
+        }
+        Ok(related)
+    }
+
+    pub fn clear_related_information(&mut self) -> Result<()> {
+        self.remove_decorations(DIAGNOSTIC_RELATED_DECORATION_KEY)
+    }
+
+    pub fn update_viewport_size(&mut self, viewport: Rect) -> Result<()> {
+        let viewport_size = viewport.size();
+
+        let should_update =
+            matches!(self.editor_style.wrap_method(), WrapMethod::EditorWidth)
+                && self.viewport_size.width != viewport_size.width;
+        if should_update {
+            self.viewport_size = viewport_size;
+        }
+        if self.signals.viewport.update_if_not_equal(viewport) {
+            self.highlight_viewport()?;
+            self.layout_viewport()?;
+            self.update_screen_lines();
+            self.update_folding_display_items();
+        }
+        self.trigger_signals();
+        Ok(())
+    }
+
+    /// Report the time of layout, phantom texts, diffs and screen lines
+    /// to `sink`, None to stop
+    pub fn set_metrics_sink(&mut self, sink: Option<Rc<dyn MetricsSink>>) {
+        self.metrics = sink;
+    }
+
+    pub(crate) fn time_phase(&self, phase: Phase) -> Option<PhaseTimer> {
+        self.metrics
+            .as_ref()
+            .map(|sink| PhaseTimer::start(sink.clone(), phase))
+    }
+
+    /// Call `listener` with an [EditEvent] for every mutation of the
+    /// buffer, including undo, redo, reload and append, after the lines
+    /// are updated. Returns the id to remove it.
+    pub fn add_edit_listener(
+        &mut self,
+        listener: impl Fn(&EditEvent) + 'static
+    ) -> usize {
+        let id = self.next_edit_listener_id;
+        self.next_edit_listener_id += 1;
+        self.edit_listeners.push((id, Rc::new(listener)));
+        self.buffer_mut().set_record_edit_events(true);
+        id
+    }
+
+    pub fn remove_edit_listener(&mut self, id: usize) -> bool {
+        let len = self.edit_listeners.len();
+        self.edit_listeners.retain(|(x, _)| *x != id);
+        if self.edit_listeners.is_empty() {
+            self.buffer_mut().set_record_edit_events(false);
+        }
+        len != self.edit_listeners.len()
+    }
+
+    fn dispatch_edit_events(&mut self) {
+        if self.edit_listeners.is_empty() {
+            return;
+        }
+        let events = self.buffer_mut().take_edit_events();
+        for event in &events {
+            for (_, listener) in &self.edit_listeners {
+                listener(event);
+            }
+        }
+    }
+
+    /// Add the phantom texts of `provider` to the lines, replacing the
+    /// provider of the same name
+    pub fn register_phantom_provider(
+        &mut self,
+        name: impl Into<String>,
+        provider: Rc<dyn PhantomProvider>
+    ) -> Result<()> {
+        self.phantom_providers.register(name.into(), provider);
+        self.relayout_phantom_providers()
+    }
+
+    pub fn remove_phantom_provider(&mut self, name: &str) -> Result<bool> {
+        if !self.phantom_providers.remove(name) {
+            return Ok(false);
+        }
+        self.relayout_phantom_providers()?;
+        Ok(true)
+    }
+
+    /// The results of the provider are out of date, e.g. the analyzer
+    /// finished. Only this provider is asked again.
+    pub fn invalidate_phantom_provider(&mut self, name: &str) -> Result<bool> {
+        if !self.phantom_providers.invalidate(name) {
+            return Ok(false);
+        }
+        self.relayout_phantom_providers()?;
+        Ok(true)
+    }
+
+    fn relayout_phantom_providers(&mut self) -> Result<()> {
+        self.update_lines_new(OriginLinesDelta::default())?;
+        self.on_update_lines();
+        self.update_screen_lines();
+        self.update_folding_display_items();
+        self.trigger_signals();
+        Ok(())
+    }
+
+    /// Check the edits before they are applied, e.g. read-only regions
+    /// or a max file size. A rejected edit leaves the text unchanged,
+    /// and the edit method returns [EditRejectedError] with the reason.
+    /// Loading or reloading the file is not checked.
+    pub fn add_edit_validator(&mut self, validator: EditValidator) -> usize {
+        self.buffer_mut().add_validator(validator)
+    }
+
+    /// The reason if the last operation is partly rejected, e.g. the
+    /// auto-closed pair after an accepted insert. The edits before the
+    /// rejected one are applied and the edit method returns Ok.
+    pub fn take_edit_rejection(&mut self) -> Option<String> {
+        self.edit_rejection.take()
+    }
+
+    pub fn remove_edit_validator(&mut self, id: usize) -> bool {
+        self.buffer_mut().remove_validator(id)
+    }
+
+    /// A read-only document rejects the edits with [ReadOnlyError],
+    /// cursor movement, selection, search and folding still work
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Follow the end of the document, like `tail -f`: when lines are
+    /// appended and the viewport is at the bottom, the viewport moves
+    /// to keep the last line visible. Scrolling away from the bottom
+    /// stops following, scrolling back resumes it.
+    pub fn set_follow_tail(&mut self, follow: bool) {
+        self.follow_tail = follow;
+        let viewport = *self.signals.viewport.val();
+        self.following_tail = follow && self.is_viewport_at_bottom(viewport);
+    }
+
+    /// Whether the viewport is moved by the following edits
+    pub fn is_following_tail(&self) -> bool {
+        self.follow_tail && self.following_tail
+    }
+
+    fn content_height(&self) -> f64 {
+        (self.visual_lines.len() * self.line_height) as f64
+            + self.block_heights.total()
+    }
+
+    fn is_viewport_at_bottom(&self, viewport: Rect) -> bool {
+        viewport.y1 + self.line_height as f64 / 2.0 >= self.content_height()
+    }
+
+    /// 跟随末尾时，移动视口使最后一行可见
+    fn advance_tail_viewport(&mut self) {
+        if !self.is_following_tail() {
+            return;
+        }
+        let viewport = *self.signals.viewport.val();
+        let y0 = (self.content_height() - viewport.height()).max(0.0);
+        if y0 > viewport.y0 {
+            let viewport = viewport.with_origin(Point::new(viewport.x0, y0));
+            self.signals.viewport.update_if_not_equal(viewport);
+        }
+    }
+
+    pub fn update_viewport_by_scroll(&mut self, viewport: Rect) {
+        if self.follow_tail {
+            self.following_tail = self.is_viewport_at_bottom(viewport);
+        }
+        debug!("viewport={viewport:?} self.signals.viewport={:?} {:?}", self.signals.viewport.val(), self.editor_style.wrap_method());
+        if self.signals.viewport.val().y0 == viewport.y0 && self.signals.viewport.val().y1 == viewport.y1 && !matches!(self.editor_style.wrap_method(), WrapMethod::EditorWidth) {
+            return;
+        }
+        if self.signals.viewport.update_if_not_equal(viewport) {
+            if let Err(err) = self.highlight_viewport() {
+                error!("{err:?}");
+            }
+            if let Err(err) = self.layout_viewport() {
+                error!("{err:?}");
+            }
+            self.update_screen_lines();
+            self.update_folding_display_items();
+            self.trigger_signals();
+        }
+    }
+
+    /// 大文件只高亮可视区域，视口移出已高亮的范围时重新高亮
+    fn highlight_viewport(&mut self) -> Result<()> {
+        if self.style_from_lsp
+            || self.syntax.styles_range.is_none()
+            || self.syntax.rev != self.buffer().rev()
+        {
+            return Ok(());
+        }
+        let viewport = self.signals.viewport.val();
+        let start_line = self.origin_line_of_y(viewport.y0)?;
+        let end_line = self.origin_line_of_y(viewport.y1)?;
+        if self.syntax.ensure_lines_highlighted(start_line, end_line)? {
+            self.update_lines_new(OriginLinesDelta::default())?;
+            self.on_update_lines();
+        }
+        Ok(())
+    }
+
+    /// 懒排版时，排版滚动到视口附近的估算布局的行。排版后行高变化，
+    /// 视口对应的行也随之变化，因此重复几次
+    fn layout_viewport(&mut self) -> Result<()> {
+        for _ in 0..3 {
+            let Some((start, end)) = self.lazy_layout_window() else {
+                break;
+            };
+            if !self.ensure_lines_laid_out(start, end)? {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// The origin lines shaped in lazy layout: the lines around the
+    /// viewport and the requested lines
+    fn lazy_layout_window(&self) -> Option<(usize, usize)> {
+        if !self.config.lazy_layout {
+            return None;
+        }
+        let viewport = self.signals.viewport.val();
+        let (start, end) = match (
+            self.origin_line_of_y(viewport.y0),
+            self.origin_line_of_y(viewport.y1)
+        ) {
+            (Ok(start), Ok(end)) => (start, end),
+            // 第一次排版，还没有视觉行
+            _ => {
+                let line_height = self.config.line_height.max(1) as f64;
+                (
+                    (viewport.y0.max(0.0) / line_height) as usize,
+                    (viewport.y1.max(0.0) / line_height) as usize
+                )
+            }
+        };
+        let start = start.saturating_sub(LAZY_LAYOUT_MARGIN_LINES);
+        let end = end + LAZY_LAYOUT_MARGIN_LINES;
+        Some(match self.requested_layout {
+            Some((x, y)) => (start.min(x), end.max(y)),
+            None => (start, end)
+        })
+    }
+
+    /// Shape the lines of `[start_line, end_line]` whose layouts are
+    /// estimated in lazy layout, e.g. before jumping to a line far from
+    /// the viewport. Returns true if any line is laid out again.
+    pub fn ensure_lines_laid_out(
+        &mut self,
+        start_line: usize,
+        end_line: usize
+    ) -> Result<bool> {
+        let mut estimated = self.origin_folded_lines.iter().filter(|x| {
+            x.origin_line_end >= start_line
+                && x.origin_line_start <= end_line
+                && x.text_layout.text.is_estimated()
+        });
+        let Some(first) = estimated.next() else {
+            return Ok(false);
+        };
+        let start = first.origin_line_start;
+        let end = estimated.next_back().unwrap_or(first).origin_line_end;
+        self.requested_layout = Some((start_line.min(start), end_line.max(end)));
+        let line_delta =
+            resolve_line_delta_of_lines(self.buffer().text(), start, end);
+        let rs = line_delta.and_then(|x| self.update_lines_new(x));
+        self.requested_layout = None;
+        rs?;
+        self.on_update_lines();
+        Ok(true)
+    }
+
+    pub fn update_config(&mut self, config: EditorConfig) -> Result<()> {
+        if self.config != config {
+            let syntax_errors = self.config.syntax_errors != config.syntax_errors;
+            self.config = config;
+            self.style_rev += 1;
+            if syntax_errors {
+                self.update_syntax_error_decorations();
+            }
+            if self.search.is_some() {
+                self.update_search_decorations();
+            }
+            self.update_lines_new(OriginLinesDelta::default())?;
+            self.on_update_lines();
+            self.update_screen_lines();
+            self.update_folding_display_items();
+            self.trigger_signals();
+        }
+        Ok(())
+    }
+
+    /// Show or hide the phantom texts of a kind at runtime, e.g. the
+    /// diagnostics lens or the completion lens. Only the lines which
+    /// have or will have the kind are laid out again. Fold placeholders
+    /// have no kind and are always shown, they stand for the hidden
+    /// text.
+    pub fn set_phantom_kind_enabled(
+        &mut self,
+        kind: PhantomKindTag,
+        enabled: bool
+    ) -> Result<()> {
+        if self.disabled_phantom_kinds.contains(&kind) != enabled {
+            return Ok(());
+        }
+        let lines: Vec<usize> = self
+            .origin_lines
+            .iter()
+            .filter(|x| {
+                if enabled {
+                    x.phantom.has_hidden_kind(kind)
+                } else {
+                    x.phantom.has_kind(kind)
+                }
+            })
+            .map(|x| x.line_index)
+            .collect();
+        if enabled {
+            self.disabled_phantom_kinds.retain(|x| *x != kind);
+        } else {
+            self.disabled_phantom_kinds.push(kind);
+        }
+        if lines.is_empty() {
+            return Ok(());
+        }
+        self.update_lines_of(&lines)?;
+        self.on_update_lines();
+        self.update_screen_lines();
+        self.update_folding_display_items();
+        self.trigger_signals();
+        Ok(())
+    }
+
+    pub fn is_phantom_kind_enabled(&self, kind: PhantomKindTag) -> bool {
+        !self.disabled_phantom_kinds.contains(&kind)
+    }
+
+    /// Show or hide all inlay hints, e.g. only while Ctrl is held. The
+    /// layout of the other state is kept, so toggling back without
+    /// edits or relayout swaps the layouts instead of computing them.
+    pub fn set_inlay_hints_visible(&mut self, visible: bool) -> Result<()> {
+        if self.config.enable_inlay_hints == visible {
+            return Ok(());
+        }
+        let current = LinesLayout {
+            origin_lines:        std::mem::take(&mut self.origin_lines),
+            origin_folded_lines: std::mem::take(&mut self.origin_folded_lines),
+            visual_lines:        std::mem::take(&mut self.visual_lines)
+        };
+        self.config.enable_inlay_hints = visible;
+        match self.hint_toggled_layout.take() {
+            Some(layout) => {
+                self.origin_lines = layout.origin_lines;
+                self.origin_folded_lines = layout.origin_folded_lines;
+                self.visual_lines = layout.visual_lines;
+            },
+            None => self.update_lines_new(OriginLinesDelta::default())?
+        }
+        self.hint_toggled_layout = Some(current);
+        self.on_update_lines();
+        self.update_screen_lines();
+        self.update_folding_display_items();
+        self.trigger_signals();
+        Ok(())
+    }
+
+    pub fn update_folding_ranges(&mut self, action: UpdateFolding) -> Result<()> {
+        match action {
+            UpdateFolding::UpdateByItem(item) => {
+                self.folding_ranges.update_folding_item(item);
+            },
+            UpdateFolding::New(ranges) => {
+                self.folding_ranges.update_ranges(ranges);
+                // 折叠范围第一次到达时，按配置自动折叠
+                if !self.auto_folded && !self.folding_ranges.0.is_empty() {
+                    self.auto_folded = true;
+                    if self.config.auto_fold.is_enabled() {
+                        self.folding_ranges.auto_fold(&self.config.auto_fold);
+                    }
+                }
+            },
+            UpdateFolding::UpdateByPhantom(position) => {
+                self.folding_ranges.update_by_phantom(position);
+            }
+            UpdateFolding::FoldCode(offset) => {
+                let rope = self.signals.buffer.val().text();
+                self.folding_ranges.fold_by_offset(offset, rope)?;
+            }
+        }
+        self.update_lines_new(OriginLinesDelta::default())?;
+        self.check_lines();
+        self.update_screen_lines();
+        self.update_folding_display_items();
+        self.trigger_signals();
+        Ok(())
+    }
+
+    /// The range and text to evaluate for `range`, the selection or the
+    /// cursor if empty, e.g. "evaluate selection" in a REPL. It expands
+    /// to the nearest complete statement or expression of the syntax
+    /// tree. Without a tree, the selection or the line of the cursor is
+    /// used, trimmed. None if it's blank.
+    pub fn evaluation_range(
+        &self,
+        range: Range<usize>
+    ) -> Result<Option<(Range<usize>, String)>> {
+        let buffer = self.buffer();
+        let len = buffer.len();
+        let range = range.start.min(len)..range.end.min(len);
+        let range = match self.syntax.evaluation_range(range.clone()) {
+            Some(range) => range,
+            None if range.is_empty() => {
+                let line = buffer.line_of_offset(range.start);
+                buffer.offset_of_line(line)?..buffer.line_end_offset(line, true)?
+            },
+            None => range
+        };
+        let text = buffer.slice_to_cow(range.clone());
+        let start = range.start + (text.len() - text.trim_start().len());
+        let end = range.end - (text.len() - text.trim_end().len());
+        if start >= end {
+            return Ok(None);
+        }
+        let text = buffer.slice_to_cow(start..end).to_string();
+        Ok(Some((start..end, text)))
+    }
+
+    /// The language governing `offset`, the injected language in
+    /// templating files like Vue or ERB, otherwise the language of the
+    /// document
+    pub fn language_at(&self, offset: usize) -> LapceLanguage {
+        self.syntax.language_at(offset.min(self.buffer().len()))
+    }
+
+    /// The language of the first non-blank char of the line
+    pub fn language_of_line(&self, line: usize) -> Result<LapceLanguage> {
+        let buffer = self.buffer();
+        let offset = buffer.first_non_blank_character_on_line(line)?;
+        Ok(self.language_at(offset))
+    }
+
+    /// The doc comment skeleton to offer when `offset` is on an empty
+    /// doc comment line, like `///`, above a function. The parameters
+    /// and return are from the syntax tree, or the signature line if
+    /// there is no tree.
+    pub fn doc_comment_template(
+        &self,
+        offset: usize
+    ) -> Result<Option<DocCommentTemplate>> {
+        let buffer = self.buffer();
+        let offset = offset.min(buffer.len());
+        let token = self.language_at(offset).comment_token();
+        let Some(last) = token.chars().last() else {
+            return Ok(None);
+        };
+        let leader = format!("{token}{last}");
+        let line = buffer.line_of_offset(offset);
+        if buffer.line_content(line)?.trim() != leader {
+            return Ok(None);
+        }
+        // 跳过属性和装饰器，找到下面的函数
+        let Some(function_line) = (line + 1..=buffer.last_line()).find(|x| {
+            buffer.line_content(*x).is_ok_and(|content| {
+                let content = content.trim_start();
+                !content.starts_with("#[") && !content.starts_with('@')
+            })
+        }) else {
+            return Ok(None);
+        };
+        let start = buffer.first_non_blank_character_on_line(function_line)?;
+        let signature = if self.syntax.rev == buffer.rev() {
+            self.syntax.function_signature_at(start)
+        } else {
+            None
+        };
+        let signature = match signature {
+            Some(signature) => signature,
+            None if self.syntax.layers.is_none() => {
+                let content = buffer.line_content(function_line)?;
+                let Some(signature) = doc_comment::signature_of_line(&content)
+                else {
+                    return Ok(None);
+                };
+                signature
+            },
+            None => return Ok(None)
+        };
+        let indent = buffer.indent_on_line(line)?;
+        let (text, caret) = doc_comment::template(
+            &indent,
+            &leader,
+            buffer.line_ending().get_chars(),
+            &signature
+        );
+        let range =
+            buffer.offset_of_line(line)?..buffer.line_end_offset(line, true)?;
+        Ok(Some(DocCommentTemplate { range, text, caret }))
+    }
+
+    /// The innermost syntax node at `offset`, its kind, highlight name
+    /// and range. None without a syntax tree.
+    pub fn syntax_token_at(&self, offset: usize) -> Option<SyntaxToken> {
+        self.syntax.token_at(offset)
+    }
+
+    /// Highlight the definition and references of the local variable at
+    /// `offset` by the locals query of the language, without lsp. The
+    /// highlight is cleared if it's not a local variable. Return the
+    /// number of uses.
+    ///
+    /// The color is picked from `local_uses_bg` by the variable name.
+    pub fn highlight_local_uses(&mut self, offset: usize) -> Result<usize> {
+        let uses = if self.syntax.rev == self.buffer().rev() {
+            self.syntax.local_uses(offset).unwrap_or_default()
+        } else {
+            Vec::new()
+        };
+        let Some(first) = uses.first() else {
+            self.remove_decorations(LOCAL_USES_DECORATION_KEY)?;
+            return Ok(0);
+        };
+        let name = self.buffer().slice_to_cow(first.clone());
+        let decoration = Decoration {
+            fg_color:   None,
+            bg_color:   Some(self.config.local_uses_bg(&name)),
+            under_line: None,
+            wave_line:  None
+        };
+        let count = uses.len();
+        self.set_decorations(
+            LOCAL_USES_DECORATION_KEY,
+            uses.into_iter()
+                .map(|x| (Interval::new(x.start, x.end), decoration))
+        )?;
+        Ok(count)
+    }
+
+    /// Run a custom tree-sitter query in `range`. The ranges are of the
+    /// last parsed text, which may lag behind the buffer while typing,
+    /// compare [Syntax::rev] with the buffer revision if it matters.
+    pub fn syntax_query(
+        &self,
+        range: Range<usize>,
+        query_src: &str
+    ) -> Result<Vec<SyntaxCapture>> {
+        self.syntax.query(range, query_src)
+    }
+
+    /// Fold every function and type body but keep the signatures
+    /// visible, by the outline nodes of the syntax tree
+    pub fn fold_to_outline(&mut self) -> Result<()> {
+        let Some(ranges) = self.syntax.outline_ranges() else {
+            return Ok(());
+        };
+        let buffer = self.buffer();
+        let items: Vec<OutlineItem> = ranges
+            .into_iter()
+            .map(|(range, is_container)| OutlineItem {
+                start_line: buffer.line_of_offset(range.start) as u32,
+                end_line: buffer.line_of_offset(range.end) as u32,
+                is_container
+            })
+            .collect();
+        self.fold_outline_items(&items)
+    }
+
+    /// [Self::fold_to_outline] by lsp `textDocument/documentSymbol`
+    pub fn fold_to_outline_by_symbols(
+        &mut self,
+        symbols: &[DocumentSymbol]
+    ) -> Result<()> {
+        self.fold_outline_items(&OutlineItem::from_symbols(symbols))
+    }
+
+    fn fold_outline_items(&mut self, items: &[OutlineItem]) -> Result<()> {
+        self.folding_ranges.fold_to_outline(items);
+        self.update_lines_new(OriginLinesDelta::default())?;
+        self.check_lines();
+        self.update_screen_lines();
+        self.update_folding_display_items();
+        self.trigger_signals();
+        Ok(())
+    }
+
+    /// Apply `config.folded_cursor` when the cursor moves from `from`
+    /// into a folded region, by motions or clicks. Return true if the
+    /// cursor is moved or folds are opened.
+    pub fn resolve_folded_cursor(
+        &mut self,
+        cursor: &mut Cursor,
+        from: usize
+    ) -> Result<bool> {
+        let offset = cursor.offset();
+        let rope = self.signals.buffer.val().text();
+        let Some((start, end)) =
+            self.folding_ranges.folded_range_hiding(offset, rope)?
+        else {
+            return Ok(false);
+        };
+        let new_offset = match self.config.folded_cursor {
+            FoldedCursorPolicy::Skip if offset >= from => end,
+            FoldedCursorPolicy::Skip | FoldedCursorPolicy::Placeholder => start,
+            FoldedCursorPolicy::Unfold => {
+                self.reveal_offset(offset)?;
+                return Ok(true);
+            }
+        };
+        let modify = cursor.is_visual()
+            || cursor.get_selection().is_some_and(|(start, end)| start != end);
+        cursor.set_offset(new_offset, modify, false);
+        Ok(true)
+    }
+
+    /// Unfold the chain of folds hiding `offset`, e.g. the target of a
+    /// search match, goto line or diagnostic jump. Return the start
+    /// positions of the opened folds, so that they can be folded again
+    /// by [Self::refold].
+    pub fn reveal_offset(&mut self, offset: usize) -> Result<Vec<Position>> {
+        let rope = self.signals.buffer.val().text();
+        let unfolded = self.folding_ranges.unfold_offset(offset, rope)?;
+        if !unfolded.is_empty() {
+            self.update_lines_new(OriginLinesDelta::default())?;
+            self.check_lines();
+            self.update_screen_lines();
+            self.update_folding_display_items();
+            self.trigger_signals();
+        }
+        Ok(unfolded)
+    }
+
+    /// Fold the ranges starting at `starts` again, e.g. the folds
+    /// opened by [Self::reveal_offset]
+    pub fn refold(&mut self, starts: &[Position]) -> Result<()> {
+        let mut changed = false;
+        for range in self.folding_ranges.0.iter_mut() {
+            if !range.status.is_folded() && starts.contains(&range.start) {
+                range.status = FoldingRangeStatus::Fold;
+                range.source = FoldingSource::User;
+                changed = true;
+            }
+        }
+        if changed {
+            self.update_lines_new(OriginLinesDelta::default())?;
+            self.check_lines();
+            self.update_screen_lines();
+            self.update_folding_display_items();
+            self.trigger_signals();
+        }
+        Ok(())
+    }
+
+    /// Move the caret of `cursor` to `offset` for navigation, the folds
+    /// hiding it are opened and returned like [Self::reveal_offset]
+    pub fn goto_offset(
+        &mut self,
+        cursor: &mut Cursor,
+        offset: usize
+    ) -> Result<Vec<Position>> {
+        let offset = offset.min(self.buffer().len());
+        let unfolded = self.reveal_offset(offset)?;
+        cursor.set_offset(offset, false, false);
+        Ok(unfolded)
+    }
+
+    /// Lock the folding range starting at `start`, so that it is kept
+    /// when lsp provides new ranges
+    pub fn set_folding_range_locked(
+        &mut self,
+        start: Position,
+        locked: bool
+    ) -> bool {
+        self.folding_ranges.set_locked(start, locked)
+    }
+
+    pub fn update_inline_completion(&mut self, delta: &RopeDelta) -> Result<()> {
+        let Some((completion, ..)) = self.inline_completion.take() else {
+            return Ok(());
+        };
+        let (line, col) = self.completion_pos;
+        let offset = self.buffer().offset_of_line_col(line, col)?;
+
+        // Shift the position by the rope delta
+        let mut transformer = Transformer::new(delta);
+
+        let new_offset = transformer.transform(offset, true);
+        let new_pos = self.buffer().offset_to_line_col(new_offset)?;
+
+        if let Some(inserted) = delta.as_simple_insert() {
+            let inserted = String::from(inserted);
+            let (iv, _) = delta.summary();
+            let candidates = if self.inline_completions.is_empty() {
+                vec![completion]
+            } else {
+                std::mem::take(&mut self.inline_completions)
+            };
+            if iv.start() == iv.end() && iv.start() == offset {
+                // 只保留以输入内容开头的候选，并去掉输入的部分
+                let current = self.inline_completion_index;
+                let mut index = None;
+                for (i, x) in candidates.iter().enumerate() {
+                    let Some(rest) = x.strip_prefix(inserted.as_str()) else {
+                        continue;
+                    };
+                    if i == current {
+                        index = Some(self.inline_completions.len());
+                    }
+                    self.inline_completions.push(rest.to_string());
+                }
+                let index = index.unwrap_or_default();
+                self.inline_completion_index = index;
+                self.inline_completion = self
+                    .inline_completions
+                    .get(index)
+                    .map(|x| (x.clone(), new_pos.0, new_pos.1));
+            } else {
+                self.inline_completion_index = 0;
+            }
+        } else {
+            self.inline_completion = Some((completion, new_pos.0, new_pos.1));
+        }
+        self.update_lines_new(OriginLinesDelta::default())?;
+        self.on_update_lines();
+        self.update_screen_lines();
+        self.update_folding_display_items();
+        self.trigger_signals();
+        Ok(())
+    }
+
+    /// `old_text` is the text before the delta
+    pub fn apply_delta(
+        &mut self,
+        old_text: &Rope,
+        delta: &RopeDelta
+    ) -> Result<()> {
+        self.record_syntax_edit(old_text, delta);
+        self.move_layers(delta)?;
+        // self.update_lines();
+        self.on_update_lines();
+        self.update_screen_lines();
+        self.update_folding_display_items();
+        self.trigger_signals();
+        Ok(())
+    }
+
+    /// Move the styles, marks, decorations, diagnostics and other
+    /// layers by the delta
+    fn move_layers(&mut self, delta: &RopeDelta) -> Result<()> {
+        self.hint_toggled_layout = None;
+        if self.style_from_lsp {
+            if let Some(styles) = &mut self.semantic_styles {
+                styles.1.apply_shape(delta);
+            }
+        } else if let Some(styles) = self.syntax.styles.as_mut() {
+            styles.apply_shape(delta);
+        }
+        self.syntax.lens.apply_delta(delta);
+        if !self.syntax.run_anchors.is_empty() {
+            RunAnchor::apply_delta(&mut self.syntax.run_anchors, delta);
+            self.signals
+                .run_anchors
+                .update_if_not_equal(self.syntax.run_anchors.clone());
+        }
+        self.marks.apply_delta(delta);
+        self.decorations.apply_delta(delta);
+        if !self.flashes.is_empty() {
+            Flash::apply_delta(&mut self.flashes, delta);
+        }
+        self.apply_delta_to_linked_ranges(delta);
+        if let Some(occurrences) = self.occurrences.as_mut() {
+            occurrences.apply_delta(delta);
+        }
+        if let Some(search) = self.search.as_mut() {
+            search.apply_delta(delta, self.signals.buffer.val().text());
+            self.update_search_decorations();
+        }
+        if let Some(blame) = self.blame.as_mut() {
+            blame.apply_delta(delta, self.signals.buffer.val().text())?;
+        }
+        if let Some(gutter_texts) = self.gutter_texts.as_mut() {
+            gutter_texts.apply_delta(delta, self.signals.buffer.val().text());
+        }
+        self.result_blocks.apply_delta(delta);
+        self.peek_views.apply_delta(delta);
+        self.expanded_error_lens = None;
+        if !self.inline_values.is_empty() {
+            self.inline_values
+                .apply_delta(delta, self.signals.buffer.val().text());
+        }
+        self.update_diagnostics(delta);
+        self.update_inlay_hints(delta);
+        self.update_completion_lens(delta)?;
+        Ok(())
+    }
+
+    /// Record the edit of the new revision for the incremental parse of
+    /// the next [Self::syntax_task]
+    fn record_syntax_edit(&mut self, old_text: &Rope, delta: &RopeDelta) {
+        // 被拒绝的编辑不产生新版本
+        if self.syntax.layers.is_none() || delta.is_identity() {
+            return;
+        }
+        let rev = self.buffer().rev();
+        match SyntaxEdit::from_delta(old_text, delta.clone()) {
+            Ok(edit) => self.syntax_edits.push(rev, edit),
+            Err(err) => {
+                // 缺少该版本的编辑，下次完整解析
+                error!("{err:?}");
+                self.syntax_edits.clear();
+            }
+        }
+    }
+
+    /// Cancel the running parse, the edits since the last parse are
+    /// recorded by [Self::buffer_edit] for the next [Self::syntax_task]
+    pub fn trigger_syntax_change(&mut self) -> Result<()> {
+        self.syntax.cancel_flag.store(1, atomic::Ordering::Relaxed);
+        self.syntax.cancel_flag = Arc::new(AtomicUsize::new(0));
+        self.update_lines_new(OriginLinesDelta::default())?;
+        self.on_update_lines();
+        self.update_screen_lines();
+        self.update_folding_display_items();
+        self.trigger_signals();
+        Ok(())
+    }
+
+    pub fn set_inline_completion(
+        &mut self,
+        inline_completion: String,
+        line: usize,
+        col: usize
+    ) -> Result<()> {
+        self.set_inline_completions(vec![inline_completion], line, col)
+    }
+
+    /// Set all candidates of inline completion, the first one is
+    /// displayed.
+    pub fn set_inline_completions(
+        &mut self,
+        inline_completions: Vec<String>,
+        line: usize,
+        col: usize
+    ) -> Result<()> {
+        self.inline_completion = inline_completions
+            .first()
+            .map(|completion| (completion.clone(), line, col));
+        self.inline_completions = inline_completions;
+        self.inline_completion_index = 0;
+        self.update_lines_new(OriginLinesDelta::default())?;
+        self.on_update_lines();
+        self.update_screen_lines();
+        self.update_folding_display_items();
+        self.trigger_signals();
+        Ok(())
+    }
+
+    /// Display the next inline completion candidate
+    pub fn next_inline_completion(&mut self) -> Result<()> {
+        let len = self.inline_completions.len();
+        if len <= 1 {
+            return Ok(());
+        }
+        self.switch_inline_completion((self.inline_completion_index + 1) % len)
+    }
+
+    /// Display the previous inline completion candidate
+    pub fn previous_inline_completion(&mut self) -> Result<()> {
+        let len = self.inline_completions.len();
+        if len <= 1 {
+            return Ok(());
+        }
+        self.switch_inline_completion(
+            (self.inline_completion_index + len - 1) % len
+        )
+    }
+
+    /// (index of the displayed candidate, count of candidates)
+    pub fn inline_completion_index(&self) -> Option<(usize, usize)> {
+        self.inline_completion.as_ref()?;
+        Some((self.inline_completion_index, self.inline_completions.len()))
+    }
+
+    fn switch_inline_completion(&mut self, index: usize) -> Result<()> {
+        let Some((_, line, col)) = self.inline_completion.take() else {
+            return Ok(());
+        };
+        let Some(completion) = self.inline_completions.get(index) else {
+            bail!("inline completion index {index} out of range");
+        };
+        self.inline_completion = Some((completion.clone(), line, col));
+        self.inline_completion_index = index;
+        // 只有补全所在的行发生变化
+        let line_delta = resolve_line_delta_of_line(self.buffer().text(), line)?;
+        self.update_lines_new(line_delta)?;
+        self.on_update_lines();
+        self.update_screen_lines();
+        self.update_folding_display_items();
+        self.trigger_signals();
+        Ok(())
+    }
+
+    pub fn clear_inline_completion(&mut self) -> Result<()> {
+        self.inline_completion = None;
+        self.inline_completions.clear();
+        self.inline_completion_index = 0;
+        self.update_lines_new(OriginLinesDelta::default())?;
+        self.on_update_lines();
+        self.update_screen_lines();
+        self.update_folding_display_items();
+        self.trigger_signals();
+        Ok(())
+    }
+
+    /// Set the syntax parsed for the buffer of `rev`. If the buffer has
+    /// changed since, the styles and anchors are moved by the edits in
+    /// between, so the highlights don't flash to plain text while
+    /// typing, and the tree is reparsed by the next [Self::syntax_task].
+    ///
+    /// False if the syntax is older than the current one, the revision
+    /// is unknown or only a part of the text is highlighted.
+    pub fn set_syntax_with_rev(
+        &mut self,
+        mut syntax: Syntax,
+        rev: u64
+    ) -> Result<bool> {
+        let buffer = self.buffer();
+        if rev < self.syntax.rev {
+            return Ok(false);
+        }
+        if buffer.rev() != rev {
+            if syntax.styles_range.is_some() {
+                return Ok(false);
+            }
+            let Some(delta) = buffer.delta_rev_head(rev) else {
+                return Ok(false);
+            };
+            if let Some(styles) = syntax.styles.as_mut() {
+                styles.apply_shape(&delta);
+            }
+            syntax.lens.apply_delta(&delta);
+            RunAnchor::apply_delta(&mut syntax.run_anchors, &delta);
+        }
+        self.set_syntax(syntax)
+    }
+
+    pub fn set_syntax(&mut self, mut syntax: Syntax) -> Result<bool> {
+        // 新的syntax还没有高亮结果时(如重新初始化)，保留当前的高亮，
+        // 避免文档闪烁
+        if syntax.styles.is_none()
+            && syntax.language == self.syntax.language
+            && self.syntax.styles.is_some()
+        {
+            syntax.styles = self.syntax.styles.take();
+            syntax.styles_range = self.syntax.styles_range.take();
+        }
+        self.syntax = syntax;
+        let rev = self.syntax.rev;
+        self.syntax_edits.retain_after(rev);
+        self.signals
+            .run_anchors
+            .update_if_not_equal(self.syntax.run_anchors.clone());
+        self.update_syntax_error_decorations();
+        if self.style_from_lsp {
+            self.trigger_signals();
+            return Ok(false);
+        }
+        self.update_parser()?;
+        if self.syntax.styles_range.is_some() && !self.visual_lines.is_empty() {
+            let viewport = self.signals.viewport.val();
+            let start_line = self.origin_line_of_y(viewport.y0)?;
+            let end_line = self.origin_line_of_y(viewport.y1)?;
+            self.syntax.ensure_lines_highlighted(start_line, end_line)?;
+        }
+
+        self.update_lines_new(OriginLinesDelta::default())?;
+        self.on_update_lines();
+        self.update_screen_lines();
+        self.update_folding_display_items();
+        self.trigger_signals();
+        Ok(true)
+    }
+
+    /// The bracket analysis to run in background, `None` if the
+    /// bracket pairs are up to date or computed synchronously.
+    pub fn bracket_task(&self) -> Option<BracketTask> {
+        let buffer = self.signals.buffer.val();
+        if !self.parser.background || self.parser.is_up_to_date(buffer) {
+            return None;
+        }
+        let syntax = self.syntax.styles.is_some().then_some(&self.syntax);
+        Some(self.parser.task(buffer, syntax))
+    }
+
+    /// The parse of the syntax to run in background, `None` if the tree
+    /// is up to date or there is no grammar. The parse is incremental if
+    /// the edits of every revision since the last parse are recorded by
+    /// [Self::buffer_edit], the result is set by [Self::set_syntax].
+    pub fn syntax_task(&self) -> Option<SyntaxTask> {
+        let buffer = self.buffer();
+        if self.syntax.layers.is_none() || self.syntax.rev == buffer.rev() {
+            return None;
+        }
+        let edits = self
+            .syntax_edits
+            .between(self.syntax.rev, buffer.rev())
+            .unwrap_or_default();
+        Some(SyntaxTask::new(
+            buffer.rev(),
+            buffer.text().clone(),
+            self.syntax.clone(),
+            edits
+        ))
+    }
+
+    /// Merge the result of `bracket_task`, return false if the buffer
+    /// has changed since the task was created.
+    pub fn set_bracket_result(&mut self, result: BracketResult) -> bool {
+        if !self.parser.merge(result, self.signals.buffer.val()) {
+            return false;
+        }
+        self.signals.bracket_rev.update_if_not_equal(self.parser.rev);
+        self.trigger_signals();
+        true
+    }
+
+    /// Replace the whole decoration layer of `key`
+    pub fn set_decorations(
+        &mut self,
+        key: &str,
+        spans: impl IntoIterator<Item = (Interval, Decoration)>
+    ) -> Result<()> {
+        let len = self.buffer().len();
+        self.decorations.set(key, len, spans);
+        self.update_lines_new(OriginLinesDelta::default())?;
+        self.on_update_lines();
+        self.update_screen_lines();
+        self.update_folding_display_items();
+        self.trigger_signals();
+        Ok(())
+    }
+
+    /// Replace the whole decoration layer of `key` by spans over the
+    /// whole buffer, e.g. built by a plugin with a `SpansBuilder`. The
+    /// spans move with the text when editing.
+    pub fn set_decoration_spans(
+        &mut self,
+        key: &str,
+        spans: Spans<Decoration>
+    ) -> Result<()> {
+        let len = self.buffer().len();
+        if spans.len() != len {
+            bail!(
+                "set_decoration_spans spans len={} buffer len={len}",
+                spans.len()
+            );
+        }
+        self.decorations.set_spans(key, spans);
+        self.update_lines_new(OriginLinesDelta::default())?;
+        self.on_update_lines();
+        self.update_screen_lines();
+        self.update_folding_display_items();
+        self.trigger_signals();
+        Ok(())
+    }
+
+    /// Only replace the decorations of layer `key` in `range`, for the
+    /// providers streaming results(search, lints)
+    pub fn update_decorations(
+        &mut self,
+        key: &str,
+        range: Interval,
+        spans: impl IntoIterator<Item = (Interval, Decoration)>
+    ) -> Result<()> {
+        let len = self.buffer().len();
+        self.decorations.update(key, len, range, spans);
+        self.update_lines_new(OriginLinesDelta::default())?;
+        self.on_update_lines();
+        self.update_screen_lines();
+        self.update_folding_display_items();
+        self.trigger_signals();
+        Ok(())
+    }
+
+    pub fn remove_decorations(&mut self, key: &str) -> Result<()> {
+        if !self.decorations.remove(key) {
+            return Ok(());
+        }
+        self.update_lines_new(OriginLinesDelta::default())?;
+        self.on_update_lines();
+        self.update_screen_lines();
+        self.update_folding_display_items();
+        self.trigger_signals();
+        Ok(())
+    }
+
+    /// The debugger stopped at line `current`, `frames` are the lines
+    /// of the ancestor frames in this document. The highlights move with
+    /// the lines when editing, until [Self::debug_resume].
+    pub fn set_debug_position(
+        &mut self,
+        current: Option<usize>,
+        frames: &[usize]
+    ) -> Result<()> {
+        let current_style = Decoration {
+            fg_color:   None,
+            bg_color:   Some(self.config.inlay_hint_bg),
+            under_line: Some(self.config.diagnostic_warn),
+            wave_line:  None
+        };
+        let frame_style = Decoration {
+            fg_color:   None,
+            bg_color:   Some(self.config.inlay_hint_bg),
+            under_line: None,
+            wave_line:  None
+        };
+        let current = self.debug_line_spans(current.into_iter(), current_style)?;
+        let frames = self.debug_line_spans(frames.iter().copied(), frame_style)?;
+        let len = self.buffer().len();
+        self.decorations.set(DEBUG_LINE_DECORATION_KEY, len, current);
+        self.decorations.set(DEBUG_FRAME_DECORATION_KEY, len, frames);
+        self.update_lines_new(OriginLinesDelta::default())?;
+        self.on_update_lines();
+        self.update_screen_lines();
+        self.update_folding_display_items();
+        self.trigger_signals();
+        Ok(())
+    }
+
+    /// The syntax errors of the current syntax as a decoration layer,
+    /// removed if disabled. The errors of an outdated syntax are skipped,
+    /// the current layer is moved by the edits instead.
+    fn update_syntax_error_decorations(&mut self) {
+        if !self.config.syntax_errors {
+            self.decorations.remove(SYNTAX_ERROR_DECORATION_KEY);
+            return;
+        }
+        let buffer = self.buffer();
+        if self.syntax.rev != buffer.rev() || self.syntax.layers.is_none() {
+            return;
+        }
+        let len = buffer.len();
+        let style = Decoration {
+            fg_color:   None,
+            bg_color:   None,
+            under_line: None,
+            wave_line:  Some(self.config.diagnostic_error)
+        };
+        let spans: Vec<(Interval, Decoration)> = self
+            .syntax
+            .errors
+            .iter()
+            .filter(|x| x.end <= len)
+            .map(|x| (Interval::new(x.start, x.end), style))
+            .collect();
+        self.decorations.set(SYNTAX_ERROR_DECORATION_KEY, len, spans);
+    }
+
+    /// The whole lines, without the line endings
+    fn debug_line_spans(
+        &self,
+        lines: impl Iterator<Item = usize>,
+        style: Decoration
+    ) -> Result<Vec<(Interval, Decoration)>> {
+        let buffer = self.buffer();
+        let mut spans = Vec::new();
+        for line in lines.filter(|x| *x <= buffer.last_line()) {
+            let start = buffer.offset_of_line(line)?;
+            let end = buffer.line_end_offset(line, true)?;
+            spans.push((Interval::new(start, end.max(start)), style));
+        }
+        Ok(spans)
+    }
+
+    /// Show `values`(`(line, text)`, e.g. `a = 1`) at the end of the
+    /// lines while stepping, replacing the previous ones
+    pub fn set_debug_inline_values(
+        &mut self,
+        values: Vec<(usize, String)>
+    ) -> Result<()> {
+        self.inline_values = InlineValues::new(self.buffer().text(), values)?;
+        self.update_lines_new(OriginLinesDelta::default())?;
+        self.on_update_lines();
+        self.update_screen_lines();
+        self.update_folding_display_items();
+        self.trigger_signals();
+        Ok(())
+    }
+
+    /// The debuggee resumed or stopped, clear the execution highlights
+    /// and the inline values
+    pub fn debug_resume(&mut self) -> Result<()> {
+        self.inline_values = InlineValues::default();
+        self.decorations.remove(DEBUG_LINE_DECORATION_KEY);
+        self.decorations.remove(DEBUG_FRAME_DECORATION_KEY);
+        self.update_lines_new(OriginLinesDelta::default())?;
+        self.on_update_lines();
+        self.update_screen_lines();
+        self.update_folding_display_items();
+        self.trigger_signals();
+        Ok(())
+    }
+
+    /// Highlight `range` for a while, e.g. the code a quick-fix will
+    /// touch when its lightbulb is hovered. The range is moved by edits.
+    /// The flash expires after `duration_hint`, counted by
+    /// [Self::tick_flashes].
+    pub fn flash_range(
+        &mut self,
+        range: Interval,
+        style: Decoration,
+        duration_hint: Duration
+    ) -> Result<()> {
+        if range.is_empty() || duration_hint.is_zero() {
+            return Ok(());
+        }
+        // 新的高亮替换重叠的
+        self.flashes.retain(|x| x.range.intersect(range).is_empty());
+        self.flashes.push(Flash {
+            range,
+            decoration: style,
+            remaining: duration_hint
+        });
+        self.set_flash_decorations()
+    }
+
+    /// `elapsed` is the time since the last call. Return true if some
+    /// flashes expire.
+    pub fn tick_flashes(&mut self, elapsed: Duration) -> Result<bool> {
+        if !Flash::tick(&mut self.flashes, elapsed) {
+            return Ok(false);
+        }
+        self.set_flash_decorations()?;
+        Ok(true)
+    }
+
+    pub fn flashes(&self) -> &[Flash] {
+        &self.flashes
+    }
+
+    fn set_flash_decorations(&mut self) -> Result<()> {
+        if self.flashes.is_empty() {
+            return self.remove_decorations(FLASH_DECORATION_KEY);
+        }
+        let spans: Vec<(Interval, Decoration)> = self
+            .flashes
+            .iter()
+            .map(|x| (x.range, x.decoration))
+            .collect();
+        self.set_decorations(FLASH_DECORATION_KEY, spans)
+    }
+
+    /// Count the matches of `occurrences` for the status of find
+    /// bar, `None` to stop counting
+    pub fn set_occurrences(&mut self, occurrences: Option<Occurrences>) {
+        self.occurrences = occurrences;
+    }
+
+    /// (index of the match containing `offset`, total count), e.g.
+    /// "3 of 17"
+    pub fn occurrence_count(&mut self, offset: usize) -> Option<OccurrenceCount> {
+        let rope = self.signals.buffer.val().text();
+        let occurrences = self.occurrences.as_mut()?;
+        occurrences.refresh(rope);
+        Some(occurrences.count(offset))
+    }
+
+    /// Search the buffer and highlight the matches, which are updated
+    /// by edits. An empty pattern or `None` stops the search. The
+    /// search is not changed if the regex is invalid. The scope of the
+    /// previous search is kept.
+    pub fn set_search(&mut self, query: Option<SearchQuery>) -> Result<()> {
+        let scope = self
+            .search
+            .as_ref()
+            .and_then(|x| x.scope())
+            .map(<[Interval]>::to_vec);
+        self.search = match query.filter(|x| !x.pattern.is_empty()) {
+            Some(query) => {
+                let mut search = Search::new(query, self.buffer().text())?;
+                if let Some(scope) = scope {
+                    search.set_scope(scope);
+                }
+                Some(search)
+            },
+            None => None
+        };
+        self.update_search_decorations();
+        self.update_lines_new(OriginLinesDelta::default())?;
+        self.on_update_lines();
+        self.update_screen_lines();
+        self.update_folding_display_items();
+        self.trigger_signals();
+        Ok(())
+    }
+
+    pub fn search(&self) -> Option<&Search> {
+        self.search.as_ref()
+    }
+
+    /// Find in selection: restrict the search to the regions of
+    /// `selection`, the matches outside are neither highlighted, gone
+    /// to nor replaced. Carets are ignored, `None` or no region
+    /// searches the whole buffer again.
+    pub fn set_search_scope(
+        &mut self,
+        selection: Option<&Selection>
+    ) -> Result<()> {
+        let Some(search) = self.search.as_mut() else {
+            return Ok(());
+        };
+        let ranges = selection
+            .into_iter()
+            .flat_map(|x| x.regions())
+            .map(|region| Interval::new(region.min(), region.max()));
+        search.set_scope(ranges);
+        self.update_search_decorations();
+        self.update_lines_new(OriginLinesDelta::default())?;
+        self.on_update_lines();
+        self.update_screen_lines();
+        self.update_folding_display_items();
+        self.trigger_signals();
+        Ok(())
+    }
+
+    /// The match to go to by "find next" from `offset`, see
+    /// [Search::next_match]
+    pub fn next_match(&self, offset: usize) -> Option<Interval> {
+        self.search.as_ref()?.next_match(offset)
+    }
+
+    /// The match to go to by "find previous" from `offset`, see
+    /// [Search::prev_match]
+    pub fn prev_match(&self, offset: usize) -> Option<Interval> {
+        self.search.as_ref()?.prev_match(offset)
+    }
+
+    /// "3 of 17" of the search
+    pub fn search_count(&self, offset: usize) -> Option<OccurrenceCount> {
+        Some(self.search.as_ref()?.count(offset))
+    }
+
+    /// Add selection to next match, like Ctrl+D of vscode. A caret is
+    /// expanded to the word around it first, otherwise the next
+    /// occurrence of the text of the last added region is added as a
+    /// new region, wrapping around and skipping the selected ones. The
+    /// occurrences are whole words while the selection is the one
+    /// expanded from a caret.
+    /// Return the range selected, None if nothing changes.
+    pub fn add_selection_to_next_match(
+        &mut self,
+        cursor: &mut Cursor
+    ) -> Option<Interval> {
+        let mut selection = match cursor.mode() {
+            CursorMode::Insert(selection) if !selection.is_empty() => {
+                selection.clone()
+            },
+            _ => Selection::caret(cursor.offset())
+        };
+        let last = *selection.last_inserted()?;
+        let text = self.buffer().text();
+        let (found, whole_word) = if last.is_caret() {
+            let (start, end) = WordCursor::new(text, last.start).word_at()?;
+            selection.replace_last_inserted_region(SelRegion::new(start, end, None));
+            (Interval::new(start, end), true)
+        } else {
+            let whole_word = self.whole_word_selection.as_ref() == Some(&selection);
+            let pattern = text.slice_to_cow(last.min()..last.max());
+            let found =
+                search::find_next(text, &pattern, last.max(), whole_word, |x| {
+                    !selection.intersects(x.start, x.end)
+                })?;
+            selection.add_region(SelRegion::new(found.start, found.end, None));
+            (found, whole_word)
+        };
+        self.whole_word_selection = whole_word.then(|| selection.clone());
+        cursor.set_insert(selection);
+        Some(found)
+    }
+
+    /// Sync the decoration layer and the signal with the matches
+    fn update_search_decorations(&mut self) {
+        let matches = self
+            .search
+            .as_ref()
+            .map(|x| x.matches().to_vec())
+            .unwrap_or_default();
+        if matches.is_empty() {
+            self.decorations.remove(SEARCH_DECORATION_KEY);
+        } else {
+            let style = Decoration {
+                bg_color: Some(self.config.search_match_bg()),
+                ..Decoration::default()
+            };
+            let len = self.buffer().len();
+            self.decorations.set(
+                SEARCH_DECORATION_KEY,
+                len,
+                matches.iter().map(|x| (*x, style))
+            );
+        }
+        self.signals.search_matches.update_if_not_equal(matches);
+    }
+
+    pub fn decorations(&self) -> &Decorations {
+        &self.decorations
+    }
+
+    /// Attach the blame of lines, `lines[i]` is for line `i`. Lines
+    /// edited later are marked as modified.
+    pub fn set_blame(&mut self, lines: Vec<Option<BlameInfo>>) -> Result<()> {
+        let layer = BlameLayer::new(self.buffer().text(), lines)?;
+        self.blame = Some(layer);
+        Ok(())
+    }
+
+    pub fn clear_blame(&mut self) {
+        self.blame = None;
+    }
+
+    /// None if the blame is not attached
+    pub fn line_blame(&self, line: usize) -> Result<Option<LineBlame>> {
+        let Some(blame) = self.blame.as_ref() else {
+            return Ok(None);
+        };
+        Ok(Some(blame.line(self.buffer().text(), line)?))
+    }
+
+    /// The blame of the screen lines, for gutter heat-maps
+    pub fn screen_line_blames(&self) -> Result<Vec<(&VisualLineInfo, LineBlame)>> {
+        let Some(blame) = self.blame.as_ref() else {
+            return Ok(Vec::new());
+        };
+        let text = self.buffer().text();
+        self.screen_lines()
+            .visual_lines
+            .iter()
+            .map(|x| Ok((x, blame.line(text, x.visual_line.origin_line)?)))
+            .collect()
+    }
+
+    /// Attach short gutter texts, `(line, text)`, e.g. coverage or
+    /// timestamps. They move with the lines when editing.
+    pub fn set_gutter_texts(
+        &mut self,
+        lines: Vec<(usize, GutterText)>
+    ) -> Result<()> {
+        let layer = GutterTextLayer::new(self.buffer().text(), lines)?;
+        self.gutter_texts = Some(layer);
+        Ok(())
+    }
+
+    pub fn clear_gutter_texts(&mut self) {
+        self.gutter_texts = None;
+    }
+
+    pub fn line_gutter_text(&self, line: usize) -> Result<Option<&GutterText>> {
+        let Some(gutter_texts) = self.gutter_texts.as_ref() else {
+            return Ok(None);
+        };
+        gutter_texts.line(self.buffer().text(), line)
+    }
+
+    /// The gutter texts of the screen lines, only on the first visual
+    /// line of a folded line
+    pub fn screen_line_gutter_texts(
+        &self
+    ) -> Result<Vec<(&VisualLineInfo, &GutterText)>> {
+        let Some(gutter_texts) = self.gutter_texts.as_ref() else {
+            return Ok(Vec::new());
+        };
+        let text = self.buffer().text();
+        let mut texts = Vec::new();
+        for x in self.screen_lines().visual_lines.iter() {
+            if x.visual_line.origin_folded_line_sub_index != 0 {
+                continue;
+            }
+            if let Some(gutter_text) =
+                gutter_texts.line(text, x.visual_line.origin_line)?
+            {
+                texts.push((x, gutter_text));
+            }
+        }
+        Ok(texts)
+    }
+
+    /// The guides of the bracket pairs crossing each screen line, for
+    /// scope connector lines. A pair crosses the lines strictly between
+    /// its brackets; the innermost pair containing `cursor` is active.
+    /// The pairs come from the bracket parser, none if it is inactive.
+    pub fn screen_bracket_guides(
+        &self,
+        cursor: usize
+    ) -> Result<Vec<(&VisualLineInfo, Vec<BracketGuide>)>> {
+        let pairs = self.multi_line_bracket_pairs();
+        let buffer = self.buffer();
+        let active = pairs
+            .iter()
+            .filter(|(open, close)| *open < cursor && cursor <= *close)
+            .max_by_key(|(open, _)| *open)
+            .copied();
+        let screen_lines = self.screen_lines();
+        let (Some(first), Some(last)) =
+            (screen_lines.visual_lines.first(), screen_lines.visual_lines.last())
+        else {
+            return Ok(Vec::new());
+        };
+        let (first_line, last_line) =
+            (first.visual_line.origin_line, last.visual_line.origin_line);
+        // (开括号行, 闭括号行, 参考线)
+        let mut guides = Vec::new();
+        for (open, close) in pairs.iter().copied() {
+            let open_line = buffer.line_of_offset(open);
+            let close_line = buffer.line_of_offset(close);
+            if open_line >= last_line {
+                break;
+            }
+            if close_line <= first_line {
+                continue;
+            }
+            let content = buffer.line_content(open_line)?;
+            let indent = content
+                .find(|c: char| c != ' ' && c != '\t')
+                .unwrap_or(content.len());
+            let tab_width = self
+                .folded_line_of_origin_line(open_line)?
+                .text_layout
+                .text
+                .tab_width();
+            guides.push((open_line, close_line, BracketGuide {
+                column: util::display_columns(&content[..indent], tab_width),
+                open,
+                close,
+                active: active == Some((open, close))
+            }));
+        }
+        Ok(screen_lines
+            .visual_lines
+            .iter()
+            .map(|info| {
+                let line = info.visual_line.origin_line;
+                let line_guides = guides
+                    .iter()
+                    .filter(|(open_line, close_line, _)| {
+                        *open_line < line && line < *close_line
+                    })
+                    .map(|(_, _, guide)| *guide)
+                    .collect();
+                (info, line_guides)
+            })
+            .collect())
+    }
+
+    /// The bracket pairs across lines found by the bracket parser, empty
+    /// if it is outdated(e.g. running in background)
+    fn multi_line_bracket_pairs(&self) -> &[(usize, usize)] {
+        if self.parser.is_up_to_date(self.buffer()) {
+            &self.parser.pairs
+        } else {
+            &[]
         }
-        if self.signals.viewport.update_if_not_equal(viewport) {
-            self.update_screen_lines();
-            self.update_folding_display_items();
-            self.trigger_signals();
+    }
+
+    /// Attach a result block below `line`, e.g. the output of a REPL
+    /// cell. It moves with the line when editing. Returns its id.
+    pub fn add_result_block(
+        &mut self,
+        line: usize,
+        lines: Vec<ResultLine>,
+        collapsible: bool
+    ) -> Result<usize> {
+        let text = self.signals.buffer.val().text();
+        let id = self.result_blocks.add(text, line, lines, collapsible)?;
+        self.on_update_result_blocks();
+        Ok(id)
+    }
+
+    pub fn remove_result_block(&mut self, id: usize) -> bool {
+        let removed = self.result_blocks.remove(id);
+        if removed {
+            self.on_update_result_blocks();
         }
+        removed
     }
 
-    pub fn update_config(&mut self, config: EditorConfig) -> Result<()> {
-        if self.config != config {
-            self.config = config;
-            self.update_lines_new(OriginLinesDelta::default())?;
-            self.on_update_lines();
-            self.update_screen_lines();
-            self.update_folding_display_items();
-            self.trigger_signals();
+    /// Collapse or expand the block, false if it's not collapsible
+    pub fn toggle_result_block(&mut self, id: usize) -> bool {
+        let toggled = self.result_blocks.toggle(id);
+        if toggled {
+            self.on_update_result_blocks();
         }
-        Ok(())
+        toggled
     }
 
-    pub fn update_folding_ranges(&mut self, action: UpdateFolding) -> Result<()> {
-        match action {
-            UpdateFolding::UpdateByItem(item) => {
-                self.folding_ranges.update_folding_item(item);
-            },
-            UpdateFolding::New(ranges) => {
-                self.folding_ranges.update_ranges(ranges);
-            },
-            UpdateFolding::UpdateByPhantom(position) => {
-                self.folding_ranges.update_by_phantom(position);
-            }
-            UpdateFolding::FoldCode(offset) => {
-                let rope = self.signals.buffer.val().text();
-                self.folding_ranges.fold_by_offset(offset, rope)?;
-            }
+    pub fn clear_result_blocks(&mut self) {
+        if self.result_blocks.is_empty() {
+            return;
         }
-        self.update_lines_new(OriginLinesDelta::default())?;
-        self.check_lines();
+        self.result_blocks.clear();
+        self.on_update_result_blocks();
+    }
+
+    /// 块的高度变化，其后的行移动
+    fn on_update_result_blocks(&mut self) {
+        self.update_block_heights();
+        self.advance_tail_viewport();
         self.update_screen_lines();
-        self.update_folding_display_items();
         self.trigger_signals();
-        Ok(())
     }
 
-    pub fn update_inline_completion(&mut self, delta: &RopeDelta) -> Result<()> {
-        let Some((completion, ..)) = self.inline_completion.take() else {
-            return Ok(());
+    fn update_block_heights(&mut self) {
+        self.block_heights = match self.compute_block_heights() {
+            Ok(heights) => heights,
+            Err(err) => {
+                error!("{err:?}");
+                BlockHeights::default()
+            }
         };
-        let (line, col) = self.completion_pos;
-        let offset = self.buffer().offset_of_line_col(line, col)?;
+    }
 
-        // Shift the position by the rope delta
-        let mut transformer = Transformer::new(delta);
+    /// The height of each block, below the last visual line of the folded
+    /// line containing its line
+    fn compute_block_heights(&self) -> Result<BlockHeights> {
+        if self.result_blocks.is_empty() {
+            return Ok(BlockHeights::default());
+        }
+        let text = self.buffer().text();
+        let line_height = self.line_height as f64;
+        let mut heights = Vec::new();
+        for (line, block) in
+            self.result_blocks
+                .blocks_of_lines(text, 0, self.buffer().last_line())?
+        {
+            let folded_line = self.folded_line_of_origin_line(line)?.line_index;
+            let end = self
+                .visual_lines
+                .partition_point(|x| x.origin_folded_line <= folded_line);
+            let Some(line_index) = end.checked_sub(1) else {
+                continue;
+            };
+            let height = block.visible_lines().len() as f64 * line_height;
+            heights.push((line_index, height));
+        }
+        Ok(BlockHeights::new(heights))
+    }
 
-        let new_offset = transformer.transform(offset, true);
-        let new_pos = self.buffer().offset_to_line_col(new_offset)?;
+    /// The visual line at the y of the document, see
+    /// [BlockHeights::line_index_of_y]
+    fn visual_line_index_of_y(&self, y: f64) -> usize {
+        self.block_heights.line_index_of_y(
+            y,
+            self.line_height.max(1) as f64,
+            self.visual_lines.len()
+        )
+    }
 
-        if delta.as_simple_insert().is_some() {
-            let (iv, new_len) = delta.summary();
-            if iv.start() == iv.end()
-                && iv.start() == offset
-                && new_len <= completion.len()
-            {
-                // Remove the # of newly inserted characters
-                // These aren't necessarily the same as the characters
-                // literally in the text, but the
-                // completion will be updated when the completion
-                // widget receives the update event,
-                // and it will fix this if needed.
-                self.inline_completion =
-                    Some((completion[new_len..].to_string(), new_pos.0, new_pos.1));
+    /// Add a peek view over the origin lines `[start_line, end_line]`,
+    /// e.g. a peek definition window. It stays over the same text when
+    /// editing. Returns its id.
+    pub fn add_peek_view(
+        &mut self,
+        start_line: usize,
+        end_line: usize
+    ) -> Result<usize> {
+        let text = self.signals.buffer.val().text();
+        self.peek_views.add(text, start_line, end_line)
+    }
+
+    pub fn remove_peek_view(&mut self, id: usize) -> bool {
+        self.peek_views.remove(id)
+    }
+
+    /// The origin lines `[start_line, end_line]` of the peek view, None
+    /// if it's removed
+    pub fn peek_view_lines(&self, id: usize) -> Option<(usize, usize)> {
+        self.peek_views.lines_of(self.buffer().text(), id)
+    }
+
+    /// The screen lines of the peek view scrolled to `viewport`, whose y
+    /// is relative to the top of the first line of the view. The visual
+    /// lines and layouts are the ones of the main view, so a fold
+    /// crossing the range is shown as a whole. With `lazy_layout`, call
+    /// [Self::ensure_lines_laid_out] for the range first.
+    pub fn peek_screen_lines(
+        &self,
+        id: usize,
+        viewport: Rect
+    ) -> Result<Option<ScreenLines>> {
+        let Some((start_line, end_line)) = self.peek_view_lines(id) else {
+            return Ok(None);
+        };
+        let first = self.folded_line_of_origin_line(start_line)?.line_index;
+        let last = self.folded_line_of_origin_line(end_line)?.line_index;
+        let start = self
+            .visual_lines
+            .partition_point(|x| x.origin_folded_line < first);
+        let end = self
+            .visual_lines
+            .partition_point(|x| x.origin_folded_line <= last);
+        if start >= end {
+            bail!("peek_screen_lines no visual line of {start_line}-{end_line}");
+        }
+        let top = self.y_of_visual_line(&self.visual_lines[start]);
+        let base = Rect::new(
+            viewport.x0,
+            viewport.y0 + top,
+            viewport.x1,
+            viewport.y1 + top
+        );
+        // 按可视行的y选择，不假设范围之前的行都等高
+        let line_height = self.line_height as f64;
+        let vline_infos: Vec<VisualLine> = self.visual_lines[start..end]
+            .iter()
+            .filter(|x| {
+                let y = self.y_of_visual_line(x);
+                y + line_height > base.y0 && y <= base.y1
+            })
+            .cloned()
+            .collect();
+        Ok(Some(util::compute_screen_lines(
+            self.kind.get_untracked(),
+            base,
+            vline_infos,
+            self.line_height,
+            &self.block_heights,
+            base.y0
+        )))
+    }
+
+    /// The result blocks below the screen lines with the y(in viewport,
+    /// the top of the block), stacked in order. A block is below the
+    /// last visual line of the folded line containing its line, and
+    /// takes the height of `visible_lines().len()` lines, the lines
+    /// below are moved down.
+    pub fn screen_result_blocks(&self) -> Result<Vec<(f64, &ResultBlock)>> {
+        if self.result_blocks.is_empty() {
+            return Ok(Vec::new());
+        }
+        let text = self.buffer().text();
+        let line_height = self.screen_lines().line_height;
+        let mut blocks = Vec::new();
+        for info in &self.screen_lines().visual_lines {
+            let vl = &info.visual_line;
+            let Some(folded_line) =
+                self.origin_folded_lines.get(vl.origin_folded_line)
+            else {
+                continue;
+            };
+            let sub_lines = folded_line.text_layout.text.line_layout().len();
+            if vl.origin_folded_line_sub_index + 1 < sub_lines {
+                continue;
+            }
+            let mut y = info.visual_line_y + line_height;
+            for (_, block) in self.result_blocks.blocks_of_lines(
+                text,
+                folded_line.origin_line_start,
+                folded_line.origin_line_end
+            )? {
+                blocks.push((y, block));
+                y += block.visible_lines().len() as f64 * line_height;
             }
-        } else {
-            self.inline_completion = Some((completion, new_pos.0, new_pos.1));
         }
-        self.update_lines_new(OriginLinesDelta::default())?;
-        self.on_update_lines();
-        self.update_screen_lines();
-        self.update_folding_display_items();
-        self.trigger_signals();
-        Ok(())
+        Ok(blocks)
     }
 
-    pub fn apply_delta(&mut self, delta: &RopeDelta) -> Result<()> {
-        if self.style_from_lsp {
-            if let Some(styles) = &mut self.semantic_styles {
-                styles.1.apply_shape(delta);
+    /// The error lens after the last visual line of the screen lines.
+    /// With `error_lens_truncate`, the message is cut to the right of
+    /// the viewport. An expanded one shows the full message as an
+    /// overlay: it takes no height, the lines below are not moved and
+    /// the view draws its lines over them.
+    pub fn screen_error_lens(&self) -> Vec<ErrorLens> {
+        if !self.config.enable_error_lens
+            || self.config.only_render_error_styling
+            || !self.is_phantom_kind_enabled(PhantomKindTag::Diagnostic)
+        {
+            return Vec::new();
+        }
+        let viewport = self.viewport();
+        let mut lens = Vec::new();
+        for info in &self.screen_lines().visual_lines {
+            let vl = &info.visual_line;
+            let Some(folded_line) =
+                self.origin_folded_lines.get(vl.origin_folded_line)
+            else {
+                continue;
+            };
+            let layout = folded_line.text_layout.text.line_layout();
+            if vl.origin_folded_line_sub_index + 1 < layout.len() {
+                continue;
             }
-        } else if let Some(styles) = self.syntax.styles.as_mut() {
-            styles.apply_shape(delta);
+            let line = folded_line.origin_line_end;
+            let diagnostics = self.error_lens_diagnostics(line);
+            let Some(first) = diagnostics.first() else {
+                continue;
+            };
+            let severity = first.severity.unwrap_or(DiagnosticSeverity::WARNING);
+            let x = layout.last().map(|x| x.w as f64).unwrap_or_default()
+                + ERROR_LENS_GAP;
+            let expanded = self.expanded_error_lens == Some(line);
+            let max_width = viewport.x1 - x;
+            let Some(text) = self.error_lens_cache.borrow_mut().get_or_build(
+                (self.buffer().rev(), self.style_rev),
+                line,
+                &diagnostics,
+                expanded,
+                max_width,
+                || self.error_lens_text(&diagnostics, expanded, max_width)
+            ) else {
+                continue;
+            };
+            lens.push(ErrorLens {
+                line,
+                x,
+                y: info.visual_line_y,
+                severity,
+                color: self.config.color_of_error_lens(severity),
+                lines: text.lines,
+                count: diagnostics.len(),
+                truncated: text.truncated,
+                expanded,
+                width: text.width
+            });
         }
-        self.syntax.lens.apply_delta(delta);
-        self.update_diagnostics(delta);
-        self.update_inlay_hints(delta);
-        self.update_completion_lens(delta)?;
-        // self.update_lines();
-        self.on_update_lines();
-        self.update_screen_lines();
-        self.update_folding_display_items();
-        self.trigger_signals();
-        Ok(())
+        lens
     }
 
-    pub fn trigger_syntax_change(
-        &mut self,
-        _edits: Option<SmallVec<[SyntaxEdit; 3]>>
-    ) -> Result<()> {
-        self.syntax.cancel_flag.store(1, atomic::Ordering::Relaxed);
-        self.syntax.cancel_flag = Arc::new(AtomicUsize::new(0));
-        self.update_lines_new(OriginLinesDelta::default())?;
-        self.on_update_lines();
-        self.update_screen_lines();
-        self.update_folding_display_items();
-        self.trigger_signals();
-        Ok(())
+    /// The text of the error lens of the diagnostics and its width,
+    /// None if there is no message
+    fn error_lens_text(
+        &self,
+        diagnostics: &[Diagnostic],
+        expanded: bool,
+        max_width: f64
+    ) -> Option<ErrorLensText> {
+        let (lines, truncated) = if expanded {
+            let lines = diagnostics
+                .iter()
+                .flat_map(|x| error_lens::format_message(&x.message))
+                .collect();
+            (lines, false)
+        } else {
+            let shown = diagnostics.len().min(self.config.error_lens_max_count());
+            let mut message = error_lens::join(
+                diagnostics[..shown]
+                    .iter()
+                    .map(|x| error_lens::single_line(&x.message)),
+                error_lens::SEPARATOR
+            );
+            if message.text.is_empty() {
+                return None;
+            }
+            let more = error_lens::more(diagnostics.len() - shown);
+            let truncated = self
+                .config
+                .error_lens_truncate
+                .then(|| self.truncate_error_lens(&message, &more, max_width))
+                .flatten();
+            let is_truncated = truncated.is_some();
+            if let Some(truncated) = truncated {
+                message = truncated;
+            }
+            message.text.push_str(&more);
+            (vec![message], is_truncated)
+        };
+        let width = lines
+            .iter()
+            .map(|x| self.error_lens_layout(&x.text).size().width)
+            .fold(0.0, f64::max);
+        Some(ErrorLensText {
+            lines,
+            truncated,
+            width
+        })
     }
 
-    pub fn set_inline_completion(
-        &mut self,
-        inline_completion: String,
-        line: usize,
-        col: usize
-    ) -> Result<()> {
-        self.inline_completion = Some((inline_completion, line, col));
-        self.update_lines_new(OriginLinesDelta::default())?;
-        self.on_update_lines();
-        self.update_screen_lines();
-        self.update_folding_display_items();
-        self.trigger_signals();
-        Ok(())
+    /// Expand the full message of the error lens of the line, or
+    /// collapse it. Only one is expanded at a time.
+    pub fn toggle_error_lens(&mut self, line: usize) {
+        if self.expanded_error_lens == Some(line) {
+            self.expanded_error_lens = None;
+        } else {
+            self.expanded_error_lens = Some(line);
+        }
     }
 
-    pub fn clear_inline_completion(&mut self) -> Result<()> {
-        self.inline_completion = None;
-        self.update_lines_new(OriginLinesDelta::default())?;
-        self.on_update_lines();
-        self.update_screen_lines();
-        self.update_folding_display_items();
-        self.trigger_signals();
-        Ok(())
+    /// The origin line of the error lens under the point. The error
+    /// lens is hit within the width of its text.
+    fn error_lens_at_point(&self, point: Point) -> Option<usize> {
+        let line_height = self.screen_lines().line_height;
+        let y = point.y - self.viewport().y0;
+        self.screen_error_lens()
+            .into_iter()
+            .find(|lens| {
+                point.x >= lens.x
+                    && point.x < lens.x + lens.width
+                    && y >= lens.y
+                    && y < lens.y + lens.lines.len() as f64 * line_height
+            })
+            .map(|lens| lens.line)
     }
 
-    pub fn set_syntax_with_rev(&mut self, syntax: Syntax, rev: u64) -> Result<bool> {
-        if self.buffer().rev() != rev {
-            return Ok(false);
-        }
-        self.set_syntax(syntax)
+    /// The diagnostics of the error lens under the point, all of them
+    /// rather than the ones shown, e.g. for a hover
+    pub fn hover_error_lens(&self, point: Point) -> Vec<Diagnostic> {
+        self.error_lens_at_point(point)
+            .map(|line| self.error_lens_diagnostics(line))
+            .unwrap_or_default()
     }
 
-    pub fn set_syntax(&mut self, syntax: Syntax) -> Result<bool> {
-        self.syntax = syntax;
-        if self.style_from_lsp {
-            return Ok(false);
+    /// The diagnostics(worse than hint) ending on the line, ordered by
+    /// severity then column. The ones with the same severity and message
+    /// are merged.
+    fn error_lens_diagnostics(&self, line: usize) -> Vec<Diagnostic> {
+        let buffer = self.buffer();
+        let (Ok(start), Ok(end)) =
+            (buffer.offset_of_line(line), buffer.offset_of_line(line + 1))
+        else {
+            return Vec::new();
+        };
+        let diagnostics: Vec<Diagnostic> =
+            self.diagnostics.diagnostics_span.with_untracked(|diags| {
+                diags
+                    .iter_chunks(start..end)
+                    .filter(|(iv, diag)| {
+                        buffer.line_of_offset(iv.end) == line
+                            && diag
+                                .severity
+                                .is_some_and(|x| x < DiagnosticSeverity::HINT)
+                    })
+                    .map(|(_, diag)| diag.clone())
+                    .sorted_by_key(|x| (x.severity, x.range.start))
+                    .collect()
+            });
+        let mut merged: Vec<Diagnostic> = Vec::with_capacity(diagnostics.len());
+        for diagnostic in diagnostics {
+            if !merged.iter().any(|x| {
+                x.severity == diagnostic.severity && x.message == diagnostic.message
+            }) {
+                merged.push(diagnostic);
+            }
         }
-        self.update_parser()?;
+        merged
+    }
 
-        self.update_lines_new(OriginLinesDelta::default())?;
-        self.on_update_lines();
-        self.update_screen_lines();
-        self.update_folding_display_items();
-        self.trigger_signals();
-        Ok(true)
+    /// The layout of the text in the font of the error lens
+    fn error_lens_layout(&self, text: &str) -> TextLayout {
+        let family =
+            Cow::Owned(FamilyOwned::parse_list(&self.config.font_family).collect());
+        let attrs = self
+            .init_attrs_without_color(&family)
+            .font_size(self.config.error_lens_font_size() as f32);
+        self.new_text_layout(text, AttrsList::new(attrs))
+    }
+
+    /// Cut the message to `max_width` by the glyphs in the error lens
+    /// font size, None if it fits. The width of `suffix` is reserved.
+    fn truncate_error_lens(
+        &self,
+        message: &ErrorLensLine,
+        suffix: &str,
+        max_width: f64
+    ) -> Option<ErrorLensLine> {
+        let text = self.error_lens_layout(&message.text);
+        let ellipsis = self.error_lens_layout(error_lens::ELLIPSIS);
+        let suffix = self.error_lens_layout(suffix);
+        let glyphs: Vec<(usize, f64)> = text
+            .line_layout()
+            .iter()
+            .flat_map(|x| x.glyphs.iter())
+            .map(|x| (x.end, (x.x + x.w) as f64))
+            .collect();
+        error_lens::truncate(
+            message,
+            &glyphs,
+            max_width - suffix.size().width,
+            ellipsis.size().width
+        )
     }
 
     pub fn set_inlay_hints(&mut self, inlay_hint: Spans<InlayHint>) -> Result<()> {
@@ -3092,6 +7366,14 @@ impl LinesEditorStyle {
         EditorStyle::current_line(&self.editor_style)
     }
 
+    /// The color of [DocLines::cursor_column_rect], the current line
+    /// color by default
+    pub fn cursor_column_color(&self) -> Option<Color> {
+        self.config
+            .cursor_column_bg
+            .or_else(|| self.current_line_color())
+    }
+
     pub fn scroll_beyond_last_line(&self) -> bool {
         EditorStyle::scroll_beyond_last_line(&self.editor_style)
     }
@@ -3124,6 +7406,7 @@ impl LinesEditorStyle {
             .show_indent_guide
             .update_if_not_equal(new_show_indent_guide);
         if updated {
+            self.style_rev += 1;
             self.update_lines_new(OriginLinesDelta::default())?;
         }
         self.trigger_signals();
@@ -3173,9 +7456,34 @@ impl LinesSignals {
         self.signals.last_line.signal()
     }
 
+    /// Width of the widest laid-out line, for sizing the horizontal
+    /// scrollbar
+    pub fn signal_max_width(&self) -> ReadSignal<f64> {
+        self.signals.max_width.signal()
+    }
+
+    pub fn signal_bracket_rev(&self) -> ReadSignal<u64> {
+        self.signals.bracket_rev.signal()
+    }
+
     pub fn signal_pristine(&self) -> ReadSignal<bool> {
         self.signals.pristine.signal()
     }
+
+    /// The diagnostic summary of the screen lines, for gutter icons
+    pub fn signal_gutter_diagnostics(&self) -> ReadSignal<Vec<GutterDiagnostic>> {
+        self.signals.gutter_diagnostics.signal()
+    }
+
+    /// The matches of the search, see [DocLines::set_search]
+    pub fn signal_search_matches(&self) -> ReadSignal<Vec<Interval>> {
+        self.signals.search_matches.signal()
+    }
+
+    /// Anchors of tests and main functions, for run/debug lenses
+    pub fn signal_run_anchors(&self) -> ReadSignal<Vec<RunAnchor>> {
+        self.signals.run_anchors.signal()
+    }
 }
 
 type LinesProperty = DocLines;
@@ -3184,6 +7492,10 @@ impl LinesProperty {
     pub fn screen_lines(&self) -> &ScreenLines {
         self.signals.screen_lines.val()
     }
+
+    pub fn gutter_diagnostics(&self) -> &[GutterDiagnostic] {
+        self.signals.gutter_diagnostics.val()
+    }
 }
 
 pub trait RopeTextPosition: RopeText {
@@ -3191,10 +7503,7 @@ pub trait RopeTextPosition: RopeText {
     /// Returns None if it is not a valid UTF16 offset
     fn offset_to_position(&self, offset: usize) -> Result<Position> {
         let (line, col) = self.offset_to_line_col(offset)?;
-        let line_offset = self.offset_of_line(line)?;
-
-        let utf16_col =
-            offset_utf8_to_utf16(self.char_indices_iter(line_offset..), col);
+        let utf16_col = self.utf16_col_of_line_col(line, col)?;
 
         Ok(Position {
             line:      line as u32,
@@ -3210,14 +7519,76 @@ pub trait RopeTextPosition: RopeText {
 
     fn position_to_line_col(&self, pos: &Position) -> Result<(usize, usize)> {
         let line = pos.line as usize;
-        let line_offset = self.offset_of_line(line)?;
+        let column = self.line_col_of_utf16_col(line, pos.character as usize)?;
+        Ok((line, column))
+    }
 
-        let column = offset_utf16_to_utf8(
-            self.char_indices_iter(line_offset..),
-            pos.character as usize
-        );
+    /// Converts many UTF8 offsets to UTF16 LSP positions.
+    ///
+    /// The offsets are sorted and every line is walked once, instead of
+    /// walking from the line start for each offset. The result is in
+    /// the order of `offsets`.
+    fn offsets_to_positions(&self, offsets: &[usize]) -> Result<Vec<Position>> {
+        let mut order: Vec<usize> = (0..offsets.len()).collect();
+        order.sort_by_key(|index| offsets[*index]);
+        let mut positions = vec![Position::default(); offsets.len()];
+        // (line, offset, utf16 col) of the last converted offset
+        let mut last: Option<(usize, usize, usize)> = None;
+        for index in order {
+            let offset = offsets[index].min(self.len());
+            let offset = self
+                .text()
+                .at_or_prev_codepoint_boundary(offset)
+                .unwrap_or(offset);
+            let line = self.line_of_offset(offset);
+            let (start, mut utf16_col) = match last {
+                Some((last_line, last_offset, last_col)) if last_line == line => {
+                    (last_offset, last_col)
+                },
+                _ => (self.offset_of_line(line)?, 0)
+            };
+            for (_, ch) in self.char_indices_iter(start..offset) {
+                utf16_col += ch.len_utf16();
+            }
+            positions[index] = Position {
+                line:      line as u32,
+                character: utf16_col as u32
+            };
+            last = Some((line, offset, utf16_col));
+        }
+        Ok(positions)
+    }
 
-        Ok((line, column))
+    /// Converts many UTF16 LSP positions to UTF8 offsets, see
+    /// [`RopeTextPosition::offsets_to_positions`].
+    fn positions_to_offsets(&self, positions: &[Position]) -> Result<Vec<usize>> {
+        let mut order: Vec<usize> = (0..positions.len()).collect();
+        order.sort_by_key(|index| positions[*index]);
+        let mut offsets = vec![0; positions.len()];
+        // (line, offset, utf16 col) of the last converted position
+        let mut last: Option<(usize, usize, usize)> = None;
+        for index in order {
+            let line = positions[index].line as usize;
+            let character = positions[index].character as usize;
+            let (start, mut utf16_col) = match last {
+                Some((last_line, last_offset, last_col)) if last_line == line => {
+                    (last_offset, last_col)
+                },
+                _ => (self.offset_of_line(line)?, 0)
+            };
+            let line_end = self.line_end_offset(line, true)?;
+            let mut offset = start;
+            for (ch_offset, ch) in self.char_indices_iter(start..line_end) {
+                if utf16_col >= character {
+                    break;
+                }
+                utf16_col += ch.len_utf16();
+                offset = start + ch_offset + ch.len_utf8();
+            }
+            offsets[index] = offset;
+            last = Some((line, offset, utf16_col));
+        }
+        Ok(offsets)
     }
 }
 
@@ -3228,5 +7599,55 @@ pub enum ClickResult {
     NoHint,
     MatchWithoutLocation,
     MatchFolded,
-    MatchHint(Location)
+    MatchHint(Location),
+    /// The error lens is clicked and expanded or collapsed
+    MatchDiagnostic(Diagnostic)
+}
+
+/// 鼠标下的内嵌提示的标签部分
+#[derive(Debug, Clone, PartialEq)]
+pub struct InlayHintPartHover {
+    pub position: Position,
+    /// the index of the label part, 0 for a string label
+    pub part:     usize,
+    /// the tooltip of the part, or of the hint for a string label
+    pub tooltip:  Option<InlayHintLabelPartTooltip>,
+    pub location: Option<Location>,
+    pub command:  Option<Command>
+}
+
+impl InlayHintPartHover {
+    fn new(hint: &InlayHint, part: usize) -> Option<Self> {
+        let position = hint.position;
+        Some(match &hint.label {
+            InlayHintLabel::String(_) => Self {
+                position,
+                part,
+                tooltip: hint.tooltip.clone().map(|x| match x {
+                    InlayHintTooltip::String(x) => {
+                        InlayHintLabelPartTooltip::String(x)
+                    },
+                    InlayHintTooltip::MarkupContent(x) => {
+                        InlayHintLabelPartTooltip::MarkupContent(x)
+                    }
+                }),
+                location: None,
+                command: None
+            },
+            InlayHintLabel::LabelParts(parts) => {
+                let label = parts.get(part)?;
+                Self {
+                    position,
+                    part,
+                    tooltip: label.tooltip.clone(),
+                    location: label.location.clone(),
+                    command: label.command.clone()
+                }
+            }
+        })
+    }
+
+    pub fn is_clickable(&self) -> bool {
+        self.location.is_some() || self.command.is_some()
+    }
 }
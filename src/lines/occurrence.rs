@@ -0,0 +1,190 @@
+use std::fmt::{Display, Formatter};
+
+use anyhow::Result;
+use lapce_xi_rope::{Interval, Rope, RopeDelta};
+use regex::{Regex, RegexBuilder};
+
+use crate::lines::buffer::rope_text::{RopeText, RopeTextRef};
+
+/// "3 of 17", `current` starts from 0
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct OccurrenceCount {
+    pub current: Option<usize>,
+    pub total:   usize
+}
+
+impl OccurrenceCount {
+    /// The total count of the ordered `matches` and the index of the
+    /// match containing `offset`
+    pub fn of(matches: &[Interval], offset: usize) -> Self {
+        let index = matches.partition_point(|x| x.end < offset);
+        let current = matches
+            .get(index)
+            .filter(|x| x.start <= offset && offset <= x.end)
+            .map(|_| index);
+        Self {
+            current,
+            total: matches.len()
+        }
+    }
+}
+
+impl Display for OccurrenceCount {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.current {
+            Some(current) => write!(f, "{} of {}", current + 1, self.total),
+            None => write!(f, "{}", self.total)
+        }
+    }
+}
+
+/// All matches of a word or pattern, updated incrementally by the
+/// deltas of buffer. Only the lines touched by edits are searched
+/// again.
+///
+/// Matches across lines are not supported.
+#[derive(Clone, Debug)]
+pub struct Occurrences {
+    regex:   Regex,
+    matches: Vec<Interval>,
+    /// changed ranges not searched yet
+    dirty:   Vec<Interval>
+}
+
+impl Occurrences {
+    /// Whole word
+    pub fn word(word: &str, rope: &Rope) -> Result<Self> {
+        Self::new(&format!(r"\b{}\b", regex::escape(word)), true, rope)
+    }
+
+    pub fn literal(text: &str, case_sensitive: bool, rope: &Rope) -> Result<Self> {
+        Self::new(&regex::escape(text), case_sensitive, rope)
+    }
+
+    pub fn regex(pattern: &str, case_sensitive: bool, rope: &Rope) -> Result<Self> {
+        Self::new(pattern, case_sensitive, rope)
+    }
+
+    fn new(pattern: &str, case_sensitive: bool, rope: &Rope) -> Result<Self> {
+        let regex = RegexBuilder::new(pattern)
+            .case_insensitive(!case_sensitive)
+            .multi_line(true)
+            .build()?;
+        let mut occurrences = Self {
+            regex,
+            matches: Vec::new(),
+            dirty: Vec::new()
+        };
+        occurrences.matches = occurrences.search(rope, 0, rope.len());
+        Ok(occurrences)
+    }
+
+    pub fn apply_delta(&mut self, delta: &RopeDelta) {
+        let (iv, new_len) = delta.summary();
+        let transform = |offset: usize| {
+            if offset < iv.start {
+                offset
+            } else if offset >= iv.end {
+                offset + new_len - iv.size()
+            } else {
+                iv.start + new_len
+            }
+        };
+        self.matches.retain_mut(|x| {
+            if x.end <= iv.start {
+                true
+            } else if x.start >= iv.end {
+                *x = Interval::new(transform(x.start), transform(x.end));
+                true
+            } else {
+                false
+            }
+        });
+        for dirty in self.dirty.iter_mut() {
+            *dirty = Interval::new(transform(dirty.start), transform(dirty.end));
+        }
+        self.dirty.push(Interval::new(iv.start, iv.start + new_len));
+    }
+
+    /// Search the changed lines again
+    pub fn refresh(&mut self, rope: &Rope) {
+        if self.dirty.is_empty() {
+            return;
+        }
+        let text = RopeTextRef::new(rope);
+        let mut dirty: Vec<Interval> = self
+            .dirty
+            .drain(..)
+            .map(|x| {
+                let start = text
+                    .offset_of_line(text.line_of_offset(x.start))
+                    .unwrap_or(0);
+                let end = text
+                    .offset_of_line(text.line_of_offset(x.end) + 1)
+                    .unwrap_or(rope.len());
+                Interval::new(start, end)
+            })
+            .collect();
+        dirty.sort_by_key(|x| x.start);
+        let mut merged: Vec<Interval> = Vec::with_capacity(dirty.len());
+        for iv in dirty {
+            match merged.last_mut() {
+                Some(last) if iv.start <= last.end => {
+                    last.end = last.end.max(iv.end);
+                },
+                _ => merged.push(iv)
+            }
+        }
+        for iv in merged {
+            self.matches.retain(|x| x.end <= iv.start || x.start >= iv.end);
+            let found = self.search(rope, iv.start, iv.end);
+            let index = self.matches.partition_point(|x| x.start < iv.start);
+            self.matches.splice(index..index, found);
+        }
+    }
+
+    fn search(&self, rope: &Rope, start: usize, end: usize) -> Vec<Interval> {
+        let content = rope.slice_to_cow(start..end);
+        self.regex
+            .find_iter(&content)
+            .filter(|x| x.start() < x.end())
+            .map(|x| Interval::new(start + x.start(), start + x.end()))
+            .collect()
+    }
+
+    pub fn matches(&self) -> &[Interval] {
+        &self.matches
+    }
+
+    /// The replacement of the match `found`, `$1` and `${name}` are
+    /// expanded to the groups of the match
+    pub fn expand(
+        &self,
+        rope: &Rope,
+        found: Interval,
+        replacement: &str
+    ) -> String {
+        let text = RopeTextRef::new(rope);
+        let line = text.line_of_offset(found.start);
+        let start = text.offset_of_line(line).unwrap_or(0);
+        let end = text.offset_of_line(line + 1).unwrap_or(rope.len());
+        // 在整行中匹配，`^`、`\b`等与查找时一致
+        let content = rope.slice_to_cow(start..end);
+        let Some(captures) = self.regex.captures_at(&content, found.start - start)
+        else {
+            return replacement.to_string();
+        };
+        let mut expanded = String::new();
+        captures.expand(replacement, &mut expanded);
+        expanded
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        !self.dirty.is_empty()
+    }
+
+    /// The total count and the index of the match containing `offset`
+    pub fn count(&self, offset: usize) -> OccurrenceCount {
+        OccurrenceCount::of(&self.matches, offset)
+    }
+}
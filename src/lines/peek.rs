@@ -0,0 +1,82 @@
+use anyhow::Result;
+use lapce_xi_rope::{Rope, RopeDelta, Transformer};
+
+use crate::lines::buffer::rope_text::{RopeText, RopeTextRef};
+
+/// A secondary view over a fixed range of origin lines, e.g. a peek
+/// definition window. It shares the layouts of the main view.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct PeekView {
+    id:    usize,
+    /// 第一行的行首偏移
+    start: usize,
+    /// 最后一行的行尾偏移(不含换行符)
+    end:   usize
+}
+
+/// The peek views, anchored at the start of the first line and the end
+/// of the last line and moved by edits. Text inserted at the start of
+/// the first line is above the view, at the end of the last line is in
+/// the view.
+#[derive(Debug, Clone, Default)]
+pub struct PeekViews {
+    views:   Vec<PeekView>,
+    next_id: usize
+}
+
+impl PeekViews {
+    pub fn is_empty(&self) -> bool {
+        self.views.is_empty()
+    }
+
+    /// Add a view over `[start_line, end_line]`, clamped to the text
+    pub fn add(
+        &mut self,
+        text: &Rope,
+        start_line: usize,
+        end_line: usize
+    ) -> Result<usize> {
+        let text = RopeTextRef::new(text);
+        let last_line = text.last_line();
+        let start_line = start_line.min(last_line);
+        let end_line = end_line.clamp(start_line, last_line);
+        let start = text.offset_of_line(start_line)?;
+        let end = line_end(&text, end_line)?;
+        let id = self.next_id;
+        self.next_id += 1;
+        self.views.push(PeekView { id, start, end });
+        Ok(id)
+    }
+
+    pub fn remove(&mut self, id: usize) -> bool {
+        let len = self.views.len();
+        self.views.retain(|x| x.id != id);
+        len != self.views.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.views.clear();
+    }
+
+    pub fn apply_delta(&mut self, delta: &RopeDelta) {
+        let mut transformer = Transformer::new(delta);
+        for view in self.views.iter_mut() {
+            view.start = transformer.transform(view.start, true);
+            view.end = transformer.transform(view.end, true).max(view.start);
+        }
+    }
+
+    /// The origin lines `[start_line, end_line]` of the view
+    pub fn lines_of(&self, text: &Rope, id: usize) -> Option<(usize, usize)> {
+        let view = self.views.iter().find(|x| x.id == id)?;
+        Some((text.line_of_offset(view.start), text.line_of_offset(view.end)))
+    }
+}
+
+/// The offset of the end of the line, before the line ending
+fn line_end(text: &RopeTextRef, line: usize) -> Result<usize> {
+    let start = text.offset_of_line(line)?;
+    let end = text.offset_of_line(line + 1)?;
+    let content = text.slice_to_cow(start..end);
+    Ok(start + content.trim_end_matches(['\r', '\n']).len())
+}
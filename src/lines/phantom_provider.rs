@@ -0,0 +1,88 @@
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use crate::lines::{buffer::Buffer, phantom_text::PhantomText};
+
+/// Produces phantom texts of a line, registered by the embedder, e.g.
+/// parameter names from a custom analyzer or inline REPL results.
+///
+/// The results are cached per line until the buffer revision changes or
+/// the provider is invalidated, see
+/// [crate::lines::DocLines::invalidate_phantom_provider].
+pub trait PhantomProvider {
+    /// The phantom texts of `line` of `buffer`. Only `kind`, `col`,
+    /// `text`, `affinity` and the styles are used, the other columns
+    /// are computed.
+    fn phantom_texts(&self, buffer: &Buffer, line: usize) -> Vec<PhantomText>;
+}
+
+#[derive(Clone)]
+struct ProviderEntry {
+    name:     String,
+    provider: Rc<dyn PhantomProvider>,
+    /// (缓冲区版本, 行 -> 幻影文本)
+    cache:    RefCell<(u64, HashMap<usize, Vec<PhantomText>>)>
+}
+
+/// 外部幻影文本的提供者，按注册顺序调用
+#[derive(Clone, Default)]
+pub struct PhantomProviders {
+    entries: Vec<ProviderEntry>
+}
+
+impl PhantomProviders {
+    /// Replace the provider of the same name
+    pub fn register(&mut self, name: String, provider: Rc<dyn PhantomProvider>) {
+        let entry = ProviderEntry {
+            name,
+            provider,
+            cache: Default::default()
+        };
+        match self.entries.iter_mut().find(|x| x.name == entry.name) {
+            Some(old) => *old = entry,
+            None => self.entries.push(entry)
+        }
+    }
+
+    pub fn remove(&mut self, name: &str) -> bool {
+        let len = self.entries.len();
+        self.entries.retain(|x| x.name != name);
+        len != self.entries.len()
+    }
+
+    /// Clear the cache of the provider, false if there is no such
+    /// provider
+    pub fn invalidate(&self, name: &str) -> bool {
+        let Some(entry) = self.entries.iter().find(|x| x.name == name) else {
+            return false;
+        };
+        entry.cache.borrow_mut().1.clear();
+        true
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The phantom texts of all providers for the line
+    pub fn phantom_texts(&self, buffer: &Buffer, line: usize) -> Vec<PhantomText> {
+        let rev = buffer.rev();
+        let mut texts = Vec::new();
+        for entry in &self.entries {
+            let mut cache = entry.cache.borrow_mut();
+            if cache.0 != rev {
+                *cache = (rev, HashMap::new());
+            }
+            let line_texts = cache
+                .1
+                .entry(line)
+                .or_insert_with(|| entry.provider.phantom_texts(buffer, line));
+            texts.extend(line_texts.iter().map(|x| PhantomText {
+                line,
+                merge_col: x.col,
+                final_col: x.col,
+                ..x.clone()
+            }));
+        }
+        texts
+    }
+}
@@ -7,10 +7,12 @@ use floem::{
 use lapce_xi_rope::Interval;
 use log::{info, warn};
 use lsp_types::Position;
+use serde::{Deserialize, Serialize};
 use smallvec::SmallVec;
 
 use crate::lines::cursor::CursorAffinity;
 use crate::lines::delta_compute::Offset;
+use crate::lines::style::StyleRun;
 
 /// `PhantomText` is for text that is not in the actual document, but
 /// should be rendered with it.
@@ -221,6 +223,33 @@ pub enum PhantomTextKind {
 }
 
 impl PhantomTextKind {
+    /// None for the fold placeholders
+    pub fn tag(&self) -> Option<PhantomKindTag> {
+        Some(match self {
+            Self::Ime => PhantomKindTag::Ime,
+            Self::Placeholder => PhantomKindTag::Placeholder,
+            Self::Completion => PhantomKindTag::Completion,
+            Self::InlayHint => PhantomKindTag::InlayHint,
+            Self::Diagnostic => PhantomKindTag::Diagnostic,
+            Self::LineFoldedRang { .. } => return None
+        })
+    }
+
+    /// The sort key of phantom texts at the same column. The kinds in
+    /// `order` come first, in its order; the others follow in the
+    /// default order. Fold placeholders are always the last, since
+    /// they hide the text after them.
+    fn priority(&self, order: &[PhantomKindTag]) -> (usize, Self) {
+        let rank = match self.tag() {
+            Some(tag) => order
+                .iter()
+                .position(|x| *x == tag)
+                .unwrap_or(order.len()),
+            None => usize::MAX
+        };
+        (rank, *self)
+    }
+
     pub fn adjust(&mut self, line_delta: Offset) {
         if let Self::LineFoldedRang {
             next_line,
@@ -235,6 +264,16 @@ impl PhantomTextKind {
     }
 }
 
+/// The kind of phantom texts without data, used by configs
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Eq, PartialEq, Hash)]
+pub enum PhantomKindTag {
+    Ime,
+    Placeholder,
+    Completion,
+    InlayHint,
+    Diagnostic
+}
+
 /// Information about the phantom text on a specific line.
 ///
 /// This has various utility functions for transforming a coordinate
@@ -251,19 +290,24 @@ pub struct PhantomTextLine {
     final_text_len:     usize,
     /// This uses a smallvec because most lines rarely have more than
     /// a couple phantom texts
-    texts:              SmallVec<[Text; 6]>
+    texts:              SmallVec<[Text; 6]>,
+    /// 被禁用而未显示的种类，重新启用时只需重新计算这些行
+    hidden_kinds:       SmallVec<[PhantomKindTag; 2]>
 }
 
 impl PhantomTextLine {
+    /// `order` decides the order of phantom texts at the same column,
+    /// see `EditorConfig::phantom_order`
     pub fn new(
         line: usize,
         origin_text_len: usize,
         offset_of_line: usize,
-        mut phantom_texts: SmallVec<[PhantomText; 6]>
+        mut phantom_texts: SmallVec<[PhantomText; 6]>,
+        order: &[PhantomKindTag]
     ) -> Self {
         phantom_texts.sort_by(|a, b| {
             if a.merge_col == b.merge_col {
-                a.kind.cmp(&b.kind)
+                a.kind.priority(order).cmp(&b.kind.priority(order))
             } else {
                 a.merge_col.cmp(&b.merge_col)
             }
@@ -341,10 +385,32 @@ impl PhantomTextLine {
             line,
             origin_text_len,
             texts,
-            offset_of_line
+            offset_of_line,
+            hidden_kinds: SmallVec::new()
         }
     }
 
+    /// The kinds of the phantom texts not shown since they are disabled
+    pub fn with_hidden_kinds(
+        mut self,
+        hidden_kinds: SmallVec<[PhantomKindTag; 2]>
+    ) -> Self {
+        self.hidden_kinds = hidden_kinds;
+        self
+    }
+
+    /// The line has a phantom text of the kind
+    pub fn has_kind(&self, tag: PhantomKindTag) -> bool {
+        self.texts.iter().any(|x| {
+            matches!(x, Text::Phantom { text } if text.kind.tag() == Some(tag))
+        })
+    }
+
+    /// The line has a phantom text of the kind which is disabled
+    pub fn has_hidden_kind(&self, tag: PhantomKindTag) -> bool {
+        self.hidden_kinds.contains(&tag)
+    }
+
     pub fn folded_line(&self) -> Option<usize> {
         if let Some(Text::Phantom { text }) = self.texts.iter().last() {
             if let PhantomTextKind::LineFoldedRang { next_line, .. } = text.kind {
@@ -468,6 +534,29 @@ impl PhantomTextMultiLine {
         });
     }
 
+    /// The same styles as `add_phantom_style`, `font_size` is the
+    /// max font size of phantom text
+    pub fn phantom_style_runs(
+        &self,
+        font_size: f32,
+        phantom_color: Color
+    ) -> Vec<StyleRun> {
+        self.text
+            .iter()
+            .filter_map(|x| match x {
+                Text::Phantom { text } if !text.text.is_empty() => Some(StyleRun {
+                    range:     text.final_col..(text.final_col + text.text.len()),
+                    fg_color:  text.fg.unwrap_or(phantom_color),
+                    font_size: text
+                        .font_size
+                        .map(|x| (x as f32).min(font_size))
+                        .unwrap_or(font_size)
+                }),
+                _ => None
+            })
+            .collect()
+    }
+
     // /// 被折叠的范围。用于计算因折叠导致的原始文本的样式变化
     // ///
     // pub fn floded_ranges(&self) -> Vec<Range<usize>> {
@@ -916,8 +1005,8 @@ mod test {
     use smallvec::SmallVec;
 
     use super::{
-        PhantomText, PhantomTextKind, PhantomTextLine, PhantomTextMultiLine, Text,
-        combine_with_text
+        PhantomKindTag, PhantomText, PhantomTextKind, PhantomTextLine,
+        PhantomTextMultiLine, Text, combine_with_text
     };
 
     // "0123456789012345678901234567890123456789
@@ -1010,7 +1099,7 @@ mod test {
                 panic!("");
             }
         }
-        PhantomTextLine::new(visual_line - 1, origin_text_len, 0, text)
+        PhantomTextLine::new(visual_line - 1, origin_text_len, 0, text, &[])
     }
     // "0         10        20        30
     // "0123456789012345678901234567890123456789
@@ -1026,14 +1115,50 @@ mod test {
             text: ": A ".to_string(),
             ..Default::default()
         });
-        PhantomTextLine::new(6, origin_text_len, 0, text)
+        PhantomTextLine::new(6, origin_text_len, 0, text, &[])
     }
 
     fn empty_data() -> PhantomTextLine {
         let text: SmallVec<[PhantomText; 6]> = SmallVec::new();
         let origin_text_len = 0;
-        PhantomTextLine::new(6, origin_text_len, 0, text)
+        PhantomTextLine::new(6, origin_text_len, 0, text, &[])
+    }
+    #[test]
+    fn test_phantom_order() {
+        let phantom = |kind, text: &str| PhantomText {
+            kind,
+            merge_col: 4,
+            line: 6,
+            col: 4,
+            text: text.to_string(),
+            ..Default::default()
+        };
+        let texts: SmallVec<[PhantomText; 6]> = SmallVec::from_iter([
+            phantom(PhantomTextKind::InlayHint, "hint"),
+            phantom(PhantomTextKind::Completion, "lens")
+        ]);
+        let kinds = |line: &PhantomTextLine| {
+            line.texts
+                .iter()
+                .filter_map(|x| match x {
+                    Text::Phantom { text } => Some(text.kind),
+                    _ => None
+                })
+                .collect::<Vec<_>>()
+        };
+        let line = PhantomTextLine::new(6, 10, 0, texts.clone(), &[]);
+        assert_eq!(kinds(&line), vec![
+            PhantomTextKind::Completion,
+            PhantomTextKind::InlayHint
+        ]);
+        let line =
+            PhantomTextLine::new(6, 10, 0, texts, &[PhantomKindTag::InlayHint]);
+        assert_eq!(kinds(&line), vec![
+            PhantomTextKind::InlayHint,
+            PhantomTextKind::Completion
+        ]);
     }
+
     #[test]
     fn test_all() {
         custom_utils::logger::logger_stdout_debug();
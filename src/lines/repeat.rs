@@ -0,0 +1,103 @@
+use floem::views::editor::core::{
+    command::EditCommand, mode::MotionMode, register::RegisterData
+};
+use lapce_xi_rope::Rope;
+
+/// A step of a change, replayed by `.`
+#[derive(Debug, Clone)]
+pub enum RepeatStep {
+    Edit {
+        cmd:       EditCommand,
+        count:     usize,
+        modal:     bool,
+        smart_tab: bool
+    },
+    /// The range starts at the cursor. `len` is the chars of the range
+    /// for horizontal motions, the lines for vertical ones.
+    Motion {
+        motion_mode: MotionMode,
+        len:         usize,
+        backward:    bool,
+        is_vertical: bool,
+        count:       usize
+    },
+    Insert(String),
+    Paste(RegisterData)
+}
+
+/// 最后一次修改缓冲的操作，用于`.`重复
+///
+/// A change starts with an edit command, a motion or a paste. The edits
+/// in the insert mode entered by it belong to the same change, until
+/// the insert mode is left.
+#[derive(Debug, Clone, Default)]
+pub struct LastChange {
+    steps: Vec<RepeatStep>,
+    /// in the insert mode entered by the change
+    open:  bool
+}
+
+impl LastChange {
+    pub fn steps(&self) -> &[RepeatStep] {
+        &self.steps
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    /// Record a step. `changed` if the buffer is changed by it.
+    pub fn record(
+        &mut self,
+        step: RepeatStep,
+        was_insert: bool,
+        is_insert: bool,
+        changed: bool
+    ) {
+        if self.open && was_insert {
+            // 离开插入模式的命令也要重放
+            if changed || !is_insert {
+                self.push(step);
+            }
+            self.open = is_insert;
+        } else if changed || (!was_insert && is_insert) {
+            self.steps = vec![step];
+            self.open = is_insert;
+        }
+    }
+
+    fn push(&mut self, step: RepeatStep) {
+        match (self.steps.last_mut(), step) {
+            (Some(RepeatStep::Insert(text)), RepeatStep::Insert(s)) => {
+                text.push_str(&s)
+            },
+            (_, step) => self.steps.push(step)
+        }
+    }
+}
+
+/// The offset `chars` chars after(or before) `offset`
+pub fn offset_by_chars(
+    text: &Rope,
+    offset: usize,
+    chars: usize,
+    backward: bool
+) -> usize {
+    let mut offset = offset;
+    for _ in 0..chars {
+        let next = if backward {
+            text.prev_codepoint_offset(offset)
+        } else {
+            text.next_codepoint_offset(offset)
+        };
+        match next {
+            Some(next) => offset = next,
+            None => break
+        }
+    }
+    offset
+}
@@ -0,0 +1,176 @@
+use anyhow::Result;
+use floem::peniko::Color;
+use lapce_xi_rope::{Rope, RopeDelta, Transformer};
+
+use crate::lines::buffer::rope_text::{RopeText, RopeTextRef};
+
+/// A line of a result block, e.g. a line of the REPL output
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResultLine {
+    pub text: String,
+    pub fg:   Option<Color>,
+    pub bg:   Option<Color>
+}
+
+/// 显示在某行下方的多行结果，如notebook单元格的输出
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResultBlock {
+    pub id:          usize,
+    pub lines:       Vec<ResultLine>,
+    pub collapsible: bool,
+    /// 折叠后只显示第一行
+    pub collapsed:   bool
+}
+
+impl ResultBlock {
+    /// The lines to show, only the first one if collapsed
+    pub fn visible_lines(&self) -> &[ResultLine] {
+        if self.collapsed {
+            &self.lines[..self.lines.len().min(1)]
+        } else {
+            &self.lines
+        }
+    }
+}
+
+/// The result blocks, anchored at the start of their lines and moved
+/// by edits. The blocks of a deleted line move to the next line.
+#[derive(Debug, Clone, Default)]
+pub struct ResultBlocks {
+    /// (行首偏移, 结果)，按偏移排序
+    anchors: Vec<(usize, ResultBlock)>,
+    next_id: usize
+}
+
+impl ResultBlocks {
+    pub fn is_empty(&self) -> bool {
+        self.anchors.is_empty()
+    }
+
+    /// Add a block below `line`, after the existing blocks of the line
+    pub fn add(
+        &mut self,
+        text: &Rope,
+        line: usize,
+        lines: Vec<ResultLine>,
+        collapsible: bool
+    ) -> Result<usize> {
+        let text = RopeTextRef::new(text);
+        let line = line.min(text.last_line());
+        let offset = text.offset_of_line(line)?;
+        let id = self.next_id;
+        self.next_id += 1;
+        let index = self.anchors.partition_point(|(x, _)| *x <= offset);
+        self.anchors.insert(index, (offset, ResultBlock {
+            id,
+            lines,
+            collapsible,
+            collapsed: false
+        }));
+        Ok(id)
+    }
+
+    pub fn remove(&mut self, id: usize) -> bool {
+        let len = self.anchors.len();
+        self.anchors.retain(|(_, x)| x.id != id);
+        len != self.anchors.len()
+    }
+
+    /// Collapse or expand the block, false if it's not collapsible
+    pub fn toggle(&mut self, id: usize) -> bool {
+        match self.anchors.iter_mut().find(|(_, x)| x.id == id) {
+            Some((_, block)) if block.collapsible => {
+                block.collapsed = !block.collapsed;
+                true
+            },
+            _ => false
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.anchors.clear();
+    }
+
+    pub fn apply_delta(&mut self, delta: &RopeDelta) {
+        let mut transformer = Transformer::new(delta);
+        for (offset, _) in self.anchors.iter_mut() {
+            // 在行首插入的文本在锚点之前
+            *offset = transformer.transform(*offset, true);
+        }
+    }
+
+    /// The blocks below the lines in `[start_line, end_line]`, in order
+    pub fn blocks_of_lines<'a>(
+        &'a self,
+        text: &'a Rope,
+        start_line: usize,
+        end_line: usize
+    ) -> Result<impl Iterator<Item = (usize, &'a ResultBlock)> + 'a> {
+        let rope_text = RopeTextRef::new(text);
+        let start = rope_text.offset_of_line(start_line)?;
+        let end = rope_text.offset_of_line(end_line + 1)?;
+        let to_end = end == text.len();
+        Ok(self
+            .anchors
+            .iter()
+            .filter(move |(x, _)| *x >= start && (*x < end || to_end))
+            .map(|(x, block)| (text.line_of_offset(*x), block)))
+    }
+}
+
+/// 结果块占用的高度，块在所在折叠行的最后一个可视行之下，其后的行下移
+#[derive(Debug, Clone, Default)]
+pub struct BlockHeights {
+    /// (可视行序号, 到该行为止所有块的累计高度)，按序号排序
+    prefix: Vec<(usize, f64)>
+}
+
+impl BlockHeights {
+    /// `heights`: (visual line index, height of a block below it),
+    /// sorted by the index
+    pub fn new(heights: impl IntoIterator<Item = (usize, f64)>) -> Self {
+        let mut prefix: Vec<(usize, f64)> = Vec::new();
+        let mut total = 0.0;
+        for (line_index, height) in heights {
+            total += height;
+            match prefix.last_mut() {
+                Some((x, sum)) if *x == line_index => *sum = total,
+                _ => prefix.push((line_index, total))
+            }
+        }
+        Self { prefix }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.prefix.is_empty()
+    }
+
+    /// The height of the blocks above the visual line
+    pub fn above(&self, line_index: usize) -> f64 {
+        let index = self.prefix.partition_point(|(x, _)| *x < line_index);
+        index
+            .checked_sub(1)
+            .map(|x| self.prefix[x].1)
+            .unwrap_or_default()
+    }
+
+    pub fn total(&self) -> f64 {
+        self.prefix.last().map(|x| x.1).unwrap_or_default()
+    }
+
+    /// The last one of the `len` visual lines whose top is not below
+    /// `y`, so a y in a block is of the line above the block.
+    pub fn line_index_of_y(&self, y: f64, line_height: f64, len: usize) -> usize {
+        // 行顶随序号递增，二分查找
+        let (mut low, mut high) = (0, len);
+        while low < high {
+            let mid = (low + high) / 2;
+            if mid as f64 * line_height + self.above(mid) <= y {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        low.saturating_sub(1)
+    }
+}
@@ -5,14 +5,15 @@ use anyhow::{Result, bail};
 use floem::{kurbo::Rect, reactive::Scope, views::editor::view::DiffSection};
 use floem::kurbo::Point;
 use log::{error, info};
+use lsp_types::DiagnosticSeverity;
 
-use crate::lines::line::VisualLine;
+use crate::lines::{line::VisualLine, phantom_text::PhantomTextKind};
 
 // TODO(minor): We have diff sections in screen lines because Lapce
 // uses them, but we don't really have support for diffs in
 // floem-editor! Is there a better design for this? Possibly we should
 // just move that out to a separate field on Lapce's editor.
-// 不允许滚到到窗口没有文本！！！因此lines等不会为空
+// 视口完全超出文档时（如滚动到末尾之后）visual_lines为空
 #[derive(Clone)]
 pub struct ScreenLines {
     pub visual_lines:  Vec<VisualLineInfo>,
@@ -56,6 +57,90 @@ impl VisualLineInfo {
     pub fn paint_point(&self) -> Point {
         Point::new(self.base.x0, self.visual_line_y + self.base.y0)
     }
+
+    /// The parity of the visual line in the whole document, for
+    /// alternating backgrounds by screen line. It does not change when
+    /// scrolling.
+    pub fn is_odd_visual_line(&self) -> bool {
+        self.visual_line.line_index % 2 == 1
+    }
+
+    /// The parity of the origin line, the same for the visual lines of a
+    /// wrapped line, for alternating backgrounds by origin line
+    pub fn is_odd_origin_line(&self) -> bool {
+        self.visual_line.origin_line % 2 == 1
+    }
+
+    /// 折叠行（原始行）的第一个视觉行，可在其上方绘制分组的分隔线
+    pub fn is_first_of_folded_line(&self) -> bool {
+        self.visual_line.origin_folded_line_sub_index == 0
+    }
+}
+
+/// 屏幕上折叠行（原始行）的诊断汇总，用于在gutter绘制图标
+#[derive(Clone, Debug, PartialEq)]
+pub struct GutterDiagnostic {
+    pub origin_line: usize,
+    /// the y of the visual line in the window
+    pub y:           f64,
+    /// the most severe one
+    pub severity:    DiagnosticSeverity,
+    pub count:       usize
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ScreenTextSegment {
+    Origin(String),
+    Phantom { kind: PhantomTextKind, text: String }
+}
+
+impl ScreenTextSegment {
+    pub fn text(&self) -> &str {
+        match self {
+            ScreenTextSegment::Origin(text)
+            | ScreenTextSegment::Phantom { text, .. } => text
+        }
+    }
+
+    /// 折叠的占位符
+    pub fn is_folded(&self) -> bool {
+        matches!(self, ScreenTextSegment::Phantom {
+            kind: PhantomTextKind::LineFoldedRang { .. },
+            ..
+        })
+    }
+}
+
+/// 视觉行最终显示的文本：原始文本与幽灵文本合并，不包含换行符
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScreenLineText {
+    pub visual_line:   VisualLine,
+    /// 视觉行在窗口的y偏移
+    pub visual_line_y: f64,
+    pub segments:      Vec<ScreenTextSegment>
+}
+
+impl ScreenLineText {
+    /// The text the user actually sees
+    pub fn text(&self) -> String {
+        self.segments.iter().map(|x| x.text()).collect()
+    }
+
+    /// Same as `text`, but the fold placeholders are wrapped by `open`
+    /// and `close`
+    pub fn marked_text(&self, open: &str, close: &str) -> String {
+        let mut rs = String::new();
+        for segment in &self.segments {
+            if segment.is_folded() {
+                rs.push_str(open);
+                rs.push_str(segment.text());
+                rs.push_str(close);
+            } else {
+                rs.push_str(segment.text());
+            }
+        }
+        rs
+    }
 }
 
 impl ScreenLines {
@@ -84,14 +169,20 @@ impl ScreenLines {
     //     Some(info.clone().with_base(self.base))
     // }
 
-    pub fn visual_line_of_y(&self, y: f64) -> &VisualLineInfo {
+    /// The visual line at the y of the document. A y above the first
+    /// line returns the first line, below the last line returns the
+    /// last line. None if the screen lines are empty, e.g. the viewport
+    /// is past the end of the document.
+    pub fn visual_line_of_y(&self, y: f64) -> Option<&VisualLineInfo> {
         let y = y - self.base.y0;
-        for vli in &self.visual_lines {
-            if vli.folded_line_y <= y && y < vli.folded_line_y + self.line_height {
-                return vli;
-            }
+        let first = self.visual_lines.first()?;
+        if y < first.visual_line_y {
+            return Some(first);
         }
-        self.visual_lines.last().unwrap()
+        self.visual_lines
+            .iter()
+            .find(|x| y < x.visual_line_y + self.line_height)
+            .or_else(|| self.visual_lines.last())
     }
 
     // pub fn vline_info(&self, rvline: RVLine) ->
@@ -273,6 +364,27 @@ impl ScreenLines {
         }
     }
 
+    /// The top y of the origin line in the window. It is estimated by
+    /// the line height if the line is out of the window. None if empty.
+    pub fn origin_line_top(&self, origin_line: usize) -> Option<f64> {
+        let first = self.visual_lines.first()?;
+        let last = self.visual_lines.last()?;
+        let first_line = first.visual_line.origin_line;
+        if origin_line < first_line {
+            let above = (first_line - origin_line) as f64;
+            return Some(first.folded_line_y - above * self.line_height);
+        }
+        if let Some(info) = self
+            .visual_lines
+            .iter()
+            .find(|x| x.visual_line.origin_line >= origin_line)
+        {
+            return Some(info.folded_line_y);
+        }
+        let below = (origin_line - last.visual_line.origin_line - 1) as f64;
+        Some(last.visual_line_y + (below + 1.0) * self.line_height)
+    }
+
     /// 获取原始行的视觉行信息。为none则说明被折叠，或者没有在窗口范围
     pub fn visual_line_info_for_origin_line(
         &self,
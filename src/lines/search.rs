@@ -0,0 +1,251 @@
+use anyhow::Result;
+use lapce_xi_rope::{Cursor, Interval, Rope, RopeDelta, Transformer};
+
+use crate::lines::occurrence::{OccurrenceCount, Occurrences};
+
+/// 查找的条件
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SearchQuery {
+    pub pattern:        String,
+    /// `pattern` is a regex, otherwise a literal
+    pub regex:          bool,
+    pub whole_word:     bool,
+    pub case_sensitive: bool
+}
+
+impl SearchQuery {
+    pub fn literal(pattern: impl Into<String>) -> Self {
+        Self {
+            pattern: pattern.into(),
+            case_sensitive: true,
+            ..Self::default()
+        }
+    }
+
+    pub fn regex_pattern(&self) -> String {
+        let pattern = if self.regex {
+            self.pattern.clone()
+        } else {
+            regex::escape(&self.pattern)
+        };
+        if self.whole_word {
+            format!(r"\b(?:{pattern})\b")
+        } else {
+            pattern
+        }
+    }
+}
+
+/// The matches of the query in the buffer, kept up to date by edits.
+/// Matches across lines are not supported, see [Occurrences].
+#[derive(Clone, Debug)]
+pub struct Search {
+    query:       SearchQuery,
+    occurrences: Occurrences,
+    /// 查找范围(如选区内查找)，按顺序且不重叠，None时查找整个文档
+    scope:       Option<Vec<Interval>>,
+    /// the matches inside the scope, only with a scope
+    scoped:      Vec<Interval>
+}
+
+impl Search {
+    pub fn new(query: SearchQuery, rope: &Rope) -> Result<Self> {
+        let occurrences =
+            Occurrences::regex(&query.regex_pattern(), query.case_sensitive, rope)?;
+        Ok(Self {
+            query,
+            occurrences,
+            scope: None,
+            scoped: Vec::new()
+        })
+    }
+
+    pub fn query(&self) -> &SearchQuery {
+        &self.query
+    }
+
+    /// Restrict the matches to the ranges, e.g. the selections. Empty
+    /// ranges are ignored, the whole buffer is searched if there is no
+    /// range left.
+    pub fn set_scope(&mut self, ranges: impl IntoIterator<Item = Interval>) {
+        let mut ranges: Vec<Interval> =
+            ranges.into_iter().filter(|x| !x.is_empty()).collect();
+        ranges.sort_by_key(|x| x.start);
+        let mut scope: Vec<Interval> = Vec::with_capacity(ranges.len());
+        for range in ranges {
+            match scope.last_mut() {
+                Some(last) if range.start <= last.end => {
+                    last.end = last.end.max(range.end);
+                },
+                _ => scope.push(range)
+            }
+        }
+        self.scope = (!scope.is_empty()).then_some(scope);
+        self.update_scoped();
+    }
+
+    /// The ranges the search is restricted to, None for the whole
+    /// buffer
+    pub fn scope(&self) -> Option<&[Interval]> {
+        self.scope.as_deref()
+    }
+
+    /// Move the matches and the scope by the delta and search the
+    /// changed lines again, `rope` is the text after the delta. Text
+    /// inserted at the edges of a range is in the scope.
+    pub fn apply_delta(&mut self, delta: &RopeDelta, rope: &Rope) {
+        self.occurrences.apply_delta(delta);
+        self.occurrences.refresh(rope);
+        let Some(scope) = self.scope.as_mut() else {
+            return;
+        };
+        // 范围内的文本被全部删除后仍然限制，没有匹配
+        let mut transformer = Transformer::new(delta);
+        for range in scope.iter_mut() {
+            let start = transformer.transform(range.start, false);
+            let end = transformer.transform(range.end, true);
+            *range = Interval::new(start, end.max(start));
+        }
+        self.update_scoped();
+    }
+
+    fn update_scoped(&mut self) {
+        let Some(scope) = &self.scope else {
+            self.scoped.clear();
+            return;
+        };
+        // 匹配与范围都按顺序，同时向后遍历
+        self.scoped.clear();
+        let mut ranges = scope.iter().peekable();
+        for found in self.occurrences.matches() {
+            while ranges.next_if(|x| x.end < found.end).is_some() {}
+            let Some(range) = ranges.peek() else {
+                break;
+            };
+            if range.start <= found.start {
+                self.scoped.push(*found);
+            }
+        }
+    }
+
+    /// The matches in order, inside the scope if any
+    pub fn matches(&self) -> &[Interval] {
+        match &self.scope {
+            Some(_) => &self.scoped,
+            None => self.occurrences.matches()
+        }
+    }
+
+    /// The text to replace the match `found` by, the groups are expanded
+    /// only for a regex query
+    pub fn replacement(
+        &self,
+        rope: &Rope,
+        found: Interval,
+        replacement: &str
+    ) -> String {
+        if self.query.regex {
+            self.occurrences.expand(rope, found, replacement)
+        } else {
+            replacement.to_string()
+        }
+    }
+
+    /// The total count and the index of the match containing `offset`
+    pub fn count(&self, offset: usize) -> OccurrenceCount {
+        OccurrenceCount::of(self.matches(), offset)
+    }
+
+    /// The first match starting at or after `offset`, wrapping around to
+    /// the first match
+    pub fn next_match(&self, offset: usize) -> Option<Interval> {
+        let matches = self.matches();
+        let index = matches.partition_point(|x| x.start < offset);
+        matches.get(index).or_else(|| matches.first()).copied()
+    }
+
+    /// The last match ending before `offset`, wrapping around to the
+    /// last match. The match ending at `offset`(e.g. selected) is
+    /// skipped.
+    pub fn prev_match(&self, offset: usize) -> Option<Interval> {
+        let matches = self.matches();
+        let index = matches.partition_point(|x| x.end < offset);
+        index
+            .checked_sub(1)
+            .and_then(|x| matches.get(x))
+            .or_else(|| matches.last())
+            .copied()
+    }
+}
+
+/// 每次查找的文本块大小，块在行首结束
+const FIND_CHUNK_SIZE: usize = 64 * 1024;
+
+/// The first occurrence of `pattern` at or after `offset` accepted by
+/// `accept`, wrapping around to the start of the text only if there is
+/// none after. Unlike [Search], occurrences across lines are found
+/// too. With `whole_word`, the occurrence is not a part of a longer
+/// word.
+pub fn find_next(
+    text: &Rope,
+    pattern: &str,
+    offset: usize,
+    whole_word: bool,
+    accept: impl Fn(Interval) -> bool
+) -> Option<Interval> {
+    if pattern.is_empty() {
+        return None;
+    }
+    let offset = offset.min(text.len());
+    let accept = |x: Interval| (!whole_word || is_whole_word(text, x)) && accept(x);
+    find_in(text, pattern, offset, text.len(), &accept)
+        .or_else(|| find_in(text, pattern, 0, offset, &accept))
+}
+
+/// The first accepted occurrence starting in `start..end`, searched
+/// chunk by chunk from `start`
+fn find_in(
+    text: &Rope,
+    pattern: &str,
+    start: usize,
+    end: usize,
+    accept: &impl Fn(Interval) -> bool
+) -> Option<Interval> {
+    let mut chunk_start = start;
+    while chunk_start < end {
+        let chunk_end =
+            next_line_start(text, chunk_start + FIND_CHUNK_SIZE).min(end);
+        // 多取一段，跨过块尾的匹配也在该块中找到
+        let slice_end = next_line_start(text, chunk_end + pattern.len());
+        let content = text.slice_to_cow(chunk_start..slice_end);
+        let found = content
+            .match_indices(pattern)
+            .map(|(x, _)| chunk_start + x)
+            .take_while(|x| *x < chunk_end)
+            .map(|x| Interval::new(x, x + pattern.len()))
+            .find(|x| accept(*x));
+        if found.is_some() {
+            return found;
+        }
+        chunk_start = chunk_end;
+    }
+    None
+}
+
+/// The start of the line after the one of `offset`, or the end of the
+/// text
+fn next_line_start(text: &Rope, offset: usize) -> usize {
+    if offset >= text.len() {
+        return text.len();
+    }
+    text.offset_of_line(text.line_of_offset(offset) + 1)
+        .unwrap_or(text.len())
+}
+
+/// The chars around the range are not word chars
+fn is_whole_word(text: &Rope, range: Interval) -> bool {
+    let is_word =
+        |x: Option<char>| x.is_some_and(|x| x.is_alphanumeric() || x == '_');
+    !is_word(Cursor::new(text, range.start).prev_codepoint())
+        && !is_word(Cursor::new(text, range.end).next_codepoint())
+}
@@ -550,6 +550,15 @@ impl Selection {
         self.regions[self.last_inserted].end
     }
 
+    /// Whether any region overlaps `[start, end)`, touching regions
+    /// are not counted
+    pub fn intersects(&self, start: usize, end: usize) -> bool {
+        self.regions[self.search(start)..]
+            .iter()
+            .take_while(|x| x.min() < end)
+            .any(|x| start < x.max())
+    }
+
     /// Replaces last inserted [`SelRegion`] of this selection with
     /// the provided one.
     pub fn replace_last_inserted_region(&mut self, region: SelRegion) {
@@ -784,7 +793,9 @@ mod test {
 
         let (_, mock_delta, _) = {
             let mut buffer = Buffer::new("");
-            buffer.edit(&[(selection.clone(), "Hello")], EditType::InsertChars)
+            buffer
+                .edit(&[(selection.clone(), "Hello")], EditType::InsertChars)
+                .unwrap()
         };
 
         assert_eq!(
@@ -0,0 +1,91 @@
+use anyhow::Result;
+
+use crate::lines::buffer::rope_text::RopeText;
+
+/// Visit the sentence starts in the lines `[start_line, end_line]` in
+/// order, until `f` returns false. `start_line` should be the first
+/// line of a paragraph or of blank lines.
+///
+/// A sentence ends at '.', '!' or '?' followed by whitespace or the end
+/// of line, closing chars like ')', ']', '"' are allowed between them.
+/// The first non-blank char of a paragraph and the first of blank
+/// lines are sentence starts too.
+pub fn visit_sentence_starts<T: RopeText + ?Sized>(
+    text: &T,
+    start_line: usize,
+    end_line: usize,
+    mut f: impl FnMut(usize) -> bool
+) -> Result<()> {
+    let mut prev_blank = None;
+    let mut terminated = false;
+    let mut pending = false;
+    for line in start_line..=end_line.min(text.last_line()) {
+        let blank = text.is_line_whitespace(line)?;
+        let line_start = text.offset_of_line(line)?;
+        if blank {
+            if prev_blank != Some(true) && !f(line_start) {
+                return Ok(());
+            }
+            terminated = false;
+            pending = false;
+        } else {
+            // 段落的第一个非空字符
+            pending |= prev_blank != Some(false);
+            for (index, c) in text.line_content(line)?.char_indices() {
+                if pending {
+                    if c.is_whitespace() {
+                        continue;
+                    }
+                    pending = false;
+                    if !f(line_start + index) {
+                        return Ok(());
+                    }
+                }
+                if matches!(c, '.' | '!' | '?') {
+                    terminated = true;
+                } else if terminated && c.is_whitespace() {
+                    terminated = false;
+                    pending = true;
+                } else if !(terminated && matches!(c, ')' | ']' | '"' | '\'')) {
+                    terminated = false;
+                }
+            }
+            // 最后一行没有换行符
+            if terminated {
+                terminated = false;
+                pending = true;
+            }
+        }
+        prev_blank = Some(blank);
+    }
+    Ok(())
+}
+
+/// The first line of the paragraph, or of the blank lines, containing
+/// `line`
+pub fn paragraph_start_line<T: RopeText + ?Sized>(
+    text: &T,
+    line: usize
+) -> Result<usize> {
+    let blank = text.is_line_whitespace(line)?;
+    let mut start = line;
+    while start > 0 && text.is_line_whitespace(start - 1)? == blank {
+        start -= 1;
+    }
+    Ok(start)
+}
+
+/// The last line of the paragraph, or of the blank lines, containing
+/// `line`
+pub fn paragraph_end_line<T: RopeText + ?Sized>(
+    text: &T,
+    line: usize
+) -> Result<usize> {
+    let blank = text.is_line_whitespace(line)?;
+    let last_line = text.last_line();
+    let mut end = line;
+    while end < last_line && text.is_line_whitespace(end + 1)? == blank {
+        end += 1;
+    }
+    Ok(end)
+}
@@ -4,22 +4,39 @@ use floem::{
     reactive::{ReadSignal, RwSignal, Scope, SignalUpdate, batch},
     views::editor::EditorStyle
 };
-
-use crate::lines::{
-    buffer::Buffer, fold::FoldingDisplayItem, screen_lines::ScreenLines
+use lapce_xi_rope::Interval;
+
+use crate::{
+    lines::{
+        buffer::Buffer,
+        fold::FoldingDisplayItem,
+        screen_lines::{GutterDiagnostic, ScreenLines}
+    },
+    syntax::runnable::RunAnchor
 };
 
 #[derive(Clone)]
 pub struct Signals {
-    pub(crate) show_indent_guide: SignalManager<(bool, Color)>,
-    pub(crate) viewport:          SignalManager<Rect>,
-    pub(crate) folding_items:     SignalManager<Vec<FoldingDisplayItem>>,
-    pub(crate) screen_lines:      SignalManager<ScreenLines>,
-    pub(crate) buffer_rev:        SignalManager<u64>,
-    pub(crate) buffer:            SignalManager<Buffer>,
-    pub(crate) pristine:          SignalManager<bool>,
+    pub(crate) show_indent_guide:  SignalManager<(bool, Color)>,
+    pub(crate) viewport:           SignalManager<Rect>,
+    pub(crate) folding_items:      SignalManager<Vec<FoldingDisplayItem>>,
+    pub(crate) screen_lines:       SignalManager<ScreenLines>,
+    pub(crate) buffer_rev:         SignalManager<u64>,
+    pub(crate) buffer:             SignalManager<Buffer>,
+    pub(crate) pristine:           SignalManager<bool>,
     // start from 1, (line num, paint width)
-    pub(crate) last_line:         SignalManager<(usize, f64)>
+    pub(crate) last_line:          SignalManager<(usize, f64)>,
+    /// width of the widest laid-out line
+    pub(crate) max_width:          SignalManager<f64>,
+    /// buffer rev of the merged bracket pairs
+    pub(crate) bracket_rev:        SignalManager<u64>,
+    /// runnable items found by tree-sitter
+    pub(crate) run_anchors:        SignalManager<Vec<RunAnchor>>,
+    pub(crate) gutter_diagnostics: SignalManager<Vec<GutterDiagnostic>>,
+    /// the matches of the search
+    pub(crate) search_matches:     SignalManager<Vec<Interval>>,
+    /// 大于0时暂停触发，恢复后一次性触发
+    suspended:                     usize
 }
 
 impl Signals {
@@ -44,6 +61,11 @@ impl Signals {
         let buffer = SignalManager::new(cx, buffer);
         let last_line = SignalManager::new(cx, last_line);
         let pristine = SignalManager::new(cx, pristine);
+        let max_width = SignalManager::new(cx, 0.0);
+        let bracket_rev = SignalManager::new(cx, 0);
+        let run_anchors = SignalManager::new(cx, Vec::new());
+        let gutter_diagnostics = SignalManager::new(cx, Vec::new());
+        let search_matches = SignalManager::new(cx, Vec::new());
         Self {
             show_indent_guide,
             viewport,
@@ -52,7 +74,13 @@ impl Signals {
             buffer_rev,
             buffer,
             last_line,
-            pristine
+            pristine,
+            max_width,
+            bracket_rev,
+            run_anchors,
+            gutter_diagnostics,
+            search_matches,
+            suspended: 0
         }
     }
 
@@ -65,7 +93,24 @@ impl Signals {
         self.buffer_rev.signal()
     }
 
+    pub fn suspend(&mut self) {
+        self.suspended += 1;
+    }
+
+    /// Return true if the signals are no longer suspended
+    pub fn resume(&mut self) -> bool {
+        self.suspended = self.suspended.saturating_sub(1);
+        self.suspended == 0
+    }
+
+    pub fn is_suspended(&self) -> bool {
+        self.suspended > 0
+    }
+
     pub fn trigger(&mut self) {
+        if self.is_suspended() {
+            return;
+        }
         batch(|| {
             self.show_indent_guide.trigger();
             self.viewport.trigger();
@@ -75,6 +120,11 @@ impl Signals {
             self.buffer.trigger();
             self.last_line.trigger();
             self.pristine.trigger();
+            self.max_width.trigger();
+            self.bracket_rev.trigger();
+            self.run_anchors.trigger();
+            self.gutter_diagnostics.trigger();
+            self.search_matches.trigger();
         });
     }
 
@@ -87,6 +137,11 @@ impl Signals {
             self.buffer_rev.trigger_force();
             self.buffer.trigger_force();
             self.last_line.trigger_force();
+            self.max_width.trigger_force();
+            self.bracket_rev.trigger_force();
+            self.run_anchors.trigger_force();
+            self.gutter_diagnostics.trigger_force();
+            self.search_matches.trigger_force();
         });
     }
 }
@@ -1,4 +1,9 @@
-use floem::peniko::Color;
+use std::{collections::HashMap, ops::Range};
+
+use floem::{
+    peniko::Color,
+    text::{Attrs, AttrsList}
+};
 use serde::{Deserialize, Serialize};
 use crate::lines::delta_compute::Offset;
 
@@ -24,3 +29,51 @@ impl NewLineStyle {
         line_offset.adjust(&mut self.origin_line);
     }
 }
+
+/// 一段文本的前景色和字号
+#[derive(Clone, Debug, PartialEq)]
+pub struct StyleRun {
+    pub range:     Range<usize>,
+    pub fg_color:  Color,
+    pub font_size: f32
+}
+
+/// 缓存每行的样式段及其AttrsList，样式段没有变化时直接复用
+#[derive(Clone, Default)]
+pub struct AttrsListCache {
+    style_rev: u64,
+    lines:     HashMap<usize, (Vec<StyleRun>, AttrsList)>
+}
+
+impl AttrsListCache {
+    pub fn get_or_build(
+        &mut self,
+        style_rev: u64,
+        line: usize,
+        runs: Vec<StyleRun>,
+        attrs: Attrs
+    ) -> AttrsList {
+        if self.style_rev != style_rev {
+            self.lines.clear();
+            self.style_rev = style_rev;
+        }
+        if let Some((cached_runs, attrs_list)) = self.lines.get(&line) {
+            if *cached_runs == runs {
+                return attrs_list.clone();
+            }
+        }
+        let mut attrs_list = AttrsList::new(attrs);
+        for run in &runs {
+            attrs_list.add_span(
+                run.range.clone(),
+                attrs.color(run.fg_color).font_size(run.font_size)
+            );
+        }
+        self.lines.insert(line, (runs, attrs_list.clone()));
+        attrs_list
+    }
+
+    pub fn clear(&mut self) {
+        self.lines.clear();
+    }
+}
@@ -0,0 +1,474 @@
+//! Deterministic constructors and assertions for layout regression tests,
+//! which run without gpu or window. Enabled by feature `testing`.
+use std::path::PathBuf;
+
+use anyhow::{Result, anyhow, bail};
+use floem::{
+    kurbo::Rect,
+    reactive::{Scope, SignalUpdate},
+    views::editor::EditorStyle
+};
+use lapce_xi_rope::{Interval, spans::SpansBuilder};
+use lsp_types::{
+    Diagnostic, DiagnosticSeverity, InlayHint, InlayHintKind, InlayHintLabel,
+    Position, Range
+};
+
+use crate::{
+    DiagnosticData, EditorViewKind,
+    config::EditorConfig,
+    language::LapceLanguage,
+    lines::{
+        DocLines,
+        buffer::{Buffer, rope_text::RopeText},
+        edit::EditType,
+        fold::{FoldingDisplayItem, FoldingDisplayType, FoldingRange},
+        layout::MonospaceMetrics,
+        phantom_text::{PhantomTextKind, Text},
+        selection::Selection
+    },
+    syntax::{BracketParser, Syntax}
+};
+
+/// 固定的字体、字号、行高，颜色与默认主题无关
+const TEST_CONFIG: &str = r##"{"font_family":"monospace","font_size":13,"line_height":23,"enable_inlay_hints":true,"inlay_hint_font_size":0,"enable_error_lens":true,"error_lens_end_of_line":true,"error_lens_multiline":false,"error_lens_font_size":0,"enable_completion_lens":false,"enable_inline_completion":true,"completion_lens_font_size":0,"only_render_error_styling":false,"auto_closing_matching_pairs":true,"auto_surround":true,"diagnostic_error":{"r":229,"g":20,"b":0,"a":255},"diagnostic_warn":{"r":233,"g":167,"b":0,"a":255},"inlay_hint_fg":{"r":108,"g":118,"b":128,"a":255},"inlay_hint_bg":{"r":245,"g":245,"b":245,"a":255},"error_lens_error_foreground":{"r":228,"g":86,"b":73,"a":255},"error_lens_warning_foreground":{"r":193,"g":132,"b":1,"a":255},"error_lens_other_foreground":{"r":160,"g":161,"b":167,"a":255},"completion_lens_foreground":{"r":160,"g":161,"b":167,"a":255},"editor_foreground":{"r":56,"g":58,"b":66,"a":255},"syntax":{}}"##;
+
+pub fn test_config() -> EditorConfig {
+    serde_json::from_str(TEST_CONFIG).expect("test config")
+}
+
+/// The monospace metrics of the test config, see [TestLines::monospace]
+pub fn test_monospace_metrics() -> MonospaceMetrics {
+    MonospaceMetrics::new(13.0, 23.0).with_char_width(8.0)
+}
+
+pub fn test_viewport() -> Rect {
+    Rect::new(0.0, 0.0, 600.0, 540.0)
+}
+
+/// lsp folding range, `character` is the column of `{` / `}`
+pub fn folding_range(
+    start_line: u32,
+    start_character: u32,
+    end_line: u32,
+    end_character: u32
+) -> FoldingRange {
+    FoldingRange::from_lsp(lsp_types::FoldingRange {
+        start_line,
+        start_character: Some(start_character),
+        end_line,
+        end_character: Some(end_character),
+        kind: None,
+        collapsed_text: None
+    })
+}
+
+/// The action to fold the range starting at `line`/`character`
+pub fn fold_at(line: u32, character: u32) -> FoldingDisplayItem {
+    FoldingDisplayItem {
+        position: Position { line, character },
+        y:        0,
+        ty:       FoldingDisplayType::UnfoldStart
+    }
+}
+
+pub fn diagnostic(
+    line: u32,
+    start_character: u32,
+    end_character: u32,
+    severity: DiagnosticSeverity,
+    message: &str
+) -> Diagnostic {
+    Diagnostic {
+        range: Range::new(
+            Position::new(line, start_character),
+            Position::new(line, end_character)
+        ),
+        severity: Some(severity),
+        message: message.to_string(),
+        ..Default::default()
+    }
+}
+
+pub fn inlay_hint(line: u32, character: u32, label: &str) -> InlayHint {
+    InlayHint {
+        position:      Position::new(line, character),
+        label:         InlayHintLabel::String(label.to_string()),
+        kind:          Some(InlayHintKind::TYPE),
+        text_edits:    None,
+        tooltip:       None,
+        padding_left:  None,
+        padding_right: None,
+        data:          None
+    }
+}
+
+/// Build a [DocLines] with fixed config and viewport
+pub struct TestLines {
+    code:          String,
+    language:      LapceLanguage,
+    viewport:      Rect,
+    config:        EditorConfig,
+    folding:       Vec<FoldingRange>,
+    folded:        Vec<FoldingDisplayItem>,
+    diagnostics:   Vec<Diagnostic>,
+    hints:         Vec<InlayHint>,
+    grammars_dir:  PathBuf,
+    queries_dir:   PathBuf,
+    monospace:     Option<MonospaceMetrics>
+}
+
+impl TestLines {
+    pub fn new(code: impl Into<String>) -> Self {
+        Self {
+            code:         code.into(),
+            language:     LapceLanguage::PlainText,
+            viewport:     test_viewport(),
+            config:       test_config(),
+            folding:      Vec::new(),
+            folded:       Vec::new(),
+            diagnostics:  Vec::new(),
+            hints:        Vec::new(),
+            grammars_dir: PathBuf::new(),
+            queries_dir:  PathBuf::new(),
+            monospace:    None
+        }
+    }
+
+    /// Syntax highlighting needs the grammars and queries
+    pub fn language(
+        mut self,
+        language: LapceLanguage,
+        grammars_dir: impl Into<PathBuf>,
+        queries_dir: impl Into<PathBuf>
+    ) -> Self {
+        self.language = language;
+        self.grammars_dir = grammars_dir.into();
+        self.queries_dir = queries_dir.into();
+        self
+    }
+
+    pub fn viewport(mut self, viewport: Rect) -> Self {
+        self.viewport = viewport;
+        self
+    }
+
+    pub fn config(mut self, f: impl FnOnce(&mut EditorConfig)) -> Self {
+        f(&mut self.config);
+        self
+    }
+
+    pub fn folding_range(mut self, range: FoldingRange) -> Self {
+        self.folding.push(range);
+        self
+    }
+
+    /// Fold the range after all the ranges are set
+    pub fn fold(mut self, item: FoldingDisplayItem) -> Self {
+        self.folded.push(item);
+        self
+    }
+
+    pub fn diagnostic(mut self, diagnostic: Diagnostic) -> Self {
+        self.diagnostics.push(diagnostic);
+        self
+    }
+
+    pub fn inlay_hint(mut self, hint: InlayHint) -> Self {
+        self.hints.push(hint);
+        self
+    }
+
+    /// Layout by the monospace metrics, so the x of columns and the
+    /// wrapping do not depend on the installed fonts
+    pub fn monospace(mut self, metrics: MonospaceMetrics) -> Self {
+        self.monospace = Some(metrics);
+        self
+    }
+
+    pub fn build(self) -> Result<DocLines> {
+        let cx = Scope::new();
+        let buffer = Buffer::new(self.code.as_str());
+        let diagnostics = DiagnosticData {
+            expanded:         cx.create_rw_signal(false),
+            diagnostics:      cx.create_rw_signal(im::Vector::new()),
+            diagnostics_span: cx.create_rw_signal(Default::default())
+        };
+        let syntax = Syntax::from_language(
+            self.language,
+            &self.grammars_dir,
+            &self.queries_dir
+        );
+        let parser = BracketParser::new(self.code, true, 30000);
+        let kind = cx.create_rw_signal(EditorViewKind::Normal);
+        let mut lines = DocLines::new(
+            cx,
+            diagnostics,
+            syntax,
+            parser,
+            self.viewport,
+            EditorStyle::default(),
+            self.config,
+            buffer,
+            kind
+        )?;
+        lines.set_monospace_metrics(self.monospace)?;
+        if !self.folding.is_empty() {
+            let mut folding = self.folding;
+            folding.sort_by_key(|x| x.start);
+            lines.update_folding_ranges(folding.into())?;
+        }
+        if !self.hints.is_empty() {
+            let mut hints = self.hints;
+            hints.sort_by(|left, right| left.position.cmp(&right.position));
+            let len = lines.buffer().len();
+            let mut builder = SpansBuilder::new(len);
+            for hint in hints {
+                let offset = lines.buffer().offset_of_position(&hint.position)?;
+                let offset = offset.min(len);
+                builder.add_span(Interval::new(offset, (offset + 1).min(len)), hint);
+            }
+            lines.set_inlay_hints(builder.build())?;
+        }
+        if !self.diagnostics.is_empty() {
+            let diags: im::Vector<Diagnostic> = self.diagnostics.into();
+            lines.diagnostics.diagnostics.update(|x| *x = diags);
+            lines.init_diagnostics()?;
+        }
+        for folded in self.folded {
+            lines.update_folding_ranges(folded.into())?;
+        }
+        Ok(lines)
+    }
+}
+
+/// (origin line, sub index of wrapped line, rendered text) of the
+/// visible lines
+pub fn visual_line_structure(lines: &DocLines) -> Vec<(usize, usize, String)> {
+    lines
+        .screen_line_texts()
+        .map(|x| {
+            (
+                x.visual_line.origin_line,
+                x.visual_line.origin_folded_line_sub_index,
+                x.text()
+            )
+        })
+        .collect()
+}
+
+/// Assert the rendered texts of the visible lines, the fold
+/// placeholders are wrapped by `[` `]`
+#[track_caller]
+pub fn assert_screen_texts(lines: &DocLines, expect: &[&str]) {
+    let texts: Vec<String> = lines
+        .screen_line_texts()
+        .map(|x| x.marked_text("[", "]"))
+        .collect();
+    assert_eq!(texts, expect);
+}
+
+/// The final col of the phantom text in the folded line of
+/// `origin_line`
+pub fn phantom_final_col(
+    lines: &DocLines,
+    origin_line: usize,
+    kind: impl Fn(&PhantomTextKind) -> bool
+) -> Result<Option<(usize, String)>> {
+    let folded_line = lines
+        .folded_line_of_origin_line(origin_line)
+        .map_err(|err| anyhow!("origin line {origin_line}: {err}"))?;
+    Ok(folded_line
+        .text_layout
+        .phantom_text
+        .text
+        .iter()
+        .find_map(|x| match x {
+            Text::Phantom { text } if kind(&text.kind) => {
+                Some((text.final_col, text.text.clone()))
+            },
+            _ => None
+        }))
+}
+
+/// Assert a phantom text of `kind` placed at `final_col`
+#[track_caller]
+pub fn assert_phantom_at(
+    lines: &DocLines,
+    origin_line: usize,
+    final_col: usize,
+    text: &str,
+    kind: impl Fn(&PhantomTextKind) -> bool
+) {
+    match phantom_final_col(lines, origin_line, kind) {
+        Ok(Some(phantom)) => {
+            assert_eq!(phantom, (final_col, text.to_string()))
+        },
+        Ok(None) => panic!("no phantom text in line {origin_line}"),
+        Err(err) => panic!("{err:?}")
+    }
+}
+
+/// Check the visual lines are continuous and every origin line is
+/// covered once
+pub fn check_visual_lines(lines: &DocLines) -> Result<()> {
+    let mut next_origin_line = 0;
+    for line in lines.origin_folded_lines.iter() {
+        if line.origin_line_start != next_origin_line {
+            bail!(
+                "folded line {} starts at {}, expect {}",
+                line.line_index,
+                line.origin_line_start,
+                next_origin_line
+            );
+        }
+        if line.origin_line_end < line.origin_line_start {
+            bail!("folded line {} is empty", line.line_index);
+        }
+        next_origin_line = line.origin_line_end + 1;
+    }
+    let last_line = lines.buffer().last_line();
+    if next_origin_line != last_line + 1 {
+        bail!("origin lines end at {next_origin_line}, expect {}", last_line + 1);
+    }
+    Ok(())
+}
+
+/// Check the screen lines: sorted by y and without line breaks
+pub fn check_screen_lines(lines: &DocLines) -> Result<()> {
+    let mut last_y = f64::MIN;
+    for text in lines.screen_line_texts() {
+        if text.visual_line_y < last_y {
+            bail!(
+                "visual line {} y={} is above the previous line y={}",
+                text.visual_line.line_index,
+                text.visual_line_y,
+                last_y
+            );
+        }
+        last_y = text.visual_line_y;
+        if text.text().contains(['\r', '\n']) {
+            bail!(
+                "visual line {} contains line break: {:?}",
+                text.visual_line.line_index,
+                text.text()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// One step of [replay]. Offsets and indexes are clamped to the
+/// document, so any sequence is valid.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EditOp {
+    Insert { offset: usize, text: String },
+    Delete { offset: usize, len: usize },
+    /// Fold/unfold the folding range of index
+    ToggleFold(usize),
+    /// Scroll the viewport to line
+    Scroll(usize),
+    Resize { width: u16, height: u16 }
+}
+
+impl EditOp {
+    /// Decode the ops from arbitrary bytes, used by fuzz targets
+    pub fn decode(data: &[u8]) -> Vec<EditOp> {
+        const ALPHABET: [&str; 8] =
+            ["a", " ", "\n", "\r\n", "{", "}", "\t", "é"];
+        let mut ops = Vec::new();
+        let mut chunks = data.chunks_exact(4);
+        for chunk in &mut chunks {
+            let arg = u16::from_le_bytes([chunk[1], chunk[2]]) as usize;
+            let extra = chunk[3];
+            let op = match chunk[0] % 5 {
+                0 => EditOp::Insert {
+                    offset: arg,
+                    text:   (0..=(extra % 4))
+                        .map(|x| ALPHABET[(extra as usize + x as usize) % 8])
+                        .collect()
+                },
+                1 => EditOp::Delete {
+                    offset: arg,
+                    len:    extra as usize % 16 + 1
+                },
+                2 => EditOp::ToggleFold(arg),
+                3 => EditOp::Scroll(arg),
+                _ => EditOp::Resize {
+                    width:  100 + arg as u16 % 1000,
+                    height: 100 + extra as u16 * 4
+                }
+            };
+            ops.push(op);
+        }
+        ops
+    }
+}
+
+/// Apply the ops one by one, and check the invariants of lines after
+/// each step. The error contains the index of the failed step.
+pub fn replay(lines: &mut DocLines, ops: &[EditOp]) -> Result<()> {
+    for (index, op) in ops.iter().enumerate() {
+        apply_op(lines, op)
+            .and_then(|_| check_visual_lines(lines))
+            .and_then(|_| check_screen_lines(lines))
+            .map_err(|err| anyhow!("step {index} {op:?}: {err:?}"))?;
+    }
+    Ok(())
+}
+
+fn apply_op(lines: &mut DocLines, op: &EditOp) -> Result<()> {
+    match op {
+        EditOp::Insert { offset, text } => {
+            let offset = char_boundary(lines, *offset);
+            lines.edit_buffer(
+                &[(Selection::caret(offset), text.as_str())],
+                EditType::InsertChars
+            )?;
+        },
+        EditOp::Delete { offset, len } => {
+            let start = char_boundary(lines, *offset);
+            let end = char_boundary(lines, start + len);
+            if start < end {
+                lines.edit_buffer(
+                    &[(Selection::region(start, end), "")],
+                    EditType::Delete
+                )?;
+            }
+        },
+        EditOp::ToggleFold(index) => {
+            let last_line = lines.buffer().last_line() as u32;
+            let ranges: Vec<&FoldingRange> = lines
+                .folding_ranges
+                .0
+                .iter()
+                .filter(|x| x.end.line <= last_line)
+                .collect();
+            if !ranges.is_empty() {
+                let position = ranges[index % ranges.len()].start;
+                let item = fold_at(position.line, position.character);
+                lines.update_folding_ranges(item.into())?;
+            }
+        },
+        EditOp::Scroll(line) => {
+            let line = line % (lines.buffer().last_line() + 1);
+            let y = lines.y_of_origin_line(line)?;
+            let viewport = lines.viewport();
+            lines
+                .update_viewport_by_scroll(viewport.with_origin((viewport.x0, y)));
+        },
+        EditOp::Resize { width, height } => {
+            let viewport = lines.viewport();
+            lines.update_viewport_size(
+                viewport.with_size((*width as f64, *height as f64))
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn char_boundary(lines: &DocLines, offset: usize) -> usize {
+    let text = lines.buffer().text();
+    text.at_or_prev_codepoint_boundary(offset.min(text.len()))
+        .unwrap_or(0)
+}
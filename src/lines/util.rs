@@ -5,6 +5,7 @@ use floem::{
     views::editor::{core::char_buffer::CharBuffer, text::PreeditData}
 };
 use log::error;
+use lsp_types::{InlayHint, InlayHintLabel};
 
 use super::{
     layout::{LineExtraStyle, TextLayout, TextLayoutLine},
@@ -12,9 +13,11 @@ use super::{
 };
 use crate::{
     EditorViewKind,
+    config::BackgroundBlend,
     lines::{
         buffer::{Buffer, rope_text::RopeText},
         line::VisualLine,
+        result_block::BlockHeights,
         screen_lines::{ScreenLines, VisualLineInfo},
         word::WordCursor
     },
@@ -26,6 +29,7 @@ pub fn compute_screen_lines(
     base: Rect,
     vline_infos: Vec<VisualLine>,
     line_height: usize,
+    block_heights: &BlockHeights,
     y0: f64
 ) -> ScreenLines {
     match view_kind {
@@ -35,13 +39,16 @@ pub fn compute_screen_lines(
             // let vline_infos = self.visual_lines(min_val, max_val);
 
             for visual_line in vline_infos {
-                let visual_line_y = visual_line.line_index * line_height;
+                let visual_line_y = (visual_line.line_index * line_height)
+                    as f64
+                    + block_heights.above(visual_line.line_index);
                 let folded_line_y = visual_line_y
-                    - visual_line.origin_folded_line_sub_index * line_height;
+                    - (visual_line.origin_folded_line_sub_index * line_height)
+                        as f64;
 
                 let visual_line_info = VisualLineInfo {
-                    folded_line_y: folded_line_y as f64 - y0,
-                    visual_line_y: visual_line_y as f64 - y0,
+                    folded_line_y: folded_line_y - y0,
+                    visual_line_y: visual_line_y - y0,
                     base,
                     visual_line
                 };
@@ -312,6 +319,31 @@ pub fn preedit_phantom(
     })
 }
 
+/// The texts of the label parts of an inlay hint, a string label is one
+/// part. Spaces are padded to the first and the last part to separate
+/// the hint from the code.
+pub fn inlay_hint_label_parts(hint: &InlayHint) -> Vec<String> {
+    let mut parts = match &hint.label {
+        InlayHintLabel::String(label) => vec![label.clone()],
+        InlayHintLabel::LabelParts(parts) => {
+            parts.iter().map(|x| x.value.clone()).collect()
+        }
+    };
+    let starts_with_colon = parts.first().is_some_and(|x| x.starts_with(':'));
+    let ends_with_colon = parts.last().is_some_and(|x| x.ends_with(':'));
+    if let Some(last) = parts.last_mut() {
+        if starts_with_colon || ends_with_colon {
+            last.push(' ');
+        }
+    }
+    if let Some(first) = parts.first_mut() {
+        if !starts_with_colon {
+            first.insert(0, ' ');
+        }
+    }
+    parts
+}
+
 pub fn push_strip_suffix(line_content_original: &str, rs: &mut String) {
     if let Some(s) = line_content_original.strip_suffix("\r\n") {
         rs.push_str(s);
@@ -324,6 +356,38 @@ pub fn push_strip_suffix(line_content_original: &str, rs: &mut String) {
     }
 }
 
+fn next_display_column(column: usize, c: char, tab_width: usize) -> usize {
+    if c == '\t' {
+        let tab_width = tab_width.max(1);
+        (column / tab_width + 1) * tab_width
+    } else {
+        column + 1
+    }
+}
+
+/// The display columns of `text`, a tab moves to the next tab stop
+pub fn display_columns(text: &str, tab_width: usize) -> usize {
+    text.chars()
+        .fold(0, |column, c| next_display_column(column, c, tab_width))
+}
+
+/// The offset in `text` of the first char at or after the display
+/// column, None if `text` is shorter
+pub fn offset_of_display_column(
+    text: &str,
+    column: usize,
+    tab_width: usize
+) -> Option<usize> {
+    let mut current = 0;
+    for (offset, c) in text.char_indices() {
+        if current >= column {
+            return Some(offset);
+        }
+        current = next_display_column(current, c, tab_width);
+    }
+    None
+}
+
 pub fn apply_layout_styles(layout_line: &mut TextLayoutLine) {
     layout_line.extra_style.clear();
     let layout = &layout_line.text;
@@ -400,6 +464,112 @@ pub fn extra_styles_for_range(
         })
 }
 
+/// Merge the overlapping backgrounds of the same visual line into
+/// segments of composite colors. Underlines and wave lines are kept as
+/// they are.
+pub fn blend_backgrounds(styles: &mut Vec<LineExtraStyle>, blend: BackgroundBlend) {
+    if blend == BackgroundBlend::Stack
+        || styles.iter().filter(|x| x.bg_color.is_some()).count() < 2
+    {
+        return;
+    }
+    let mut backgrounds = Vec::new();
+    for style in styles.iter_mut() {
+        let (Some(bg), Some(width)) = (style.bg_color, style.width) else {
+            continue;
+        };
+        backgrounds.push((style.y, style.height, style.x, style.x + width, bg));
+        style.bg_color = None;
+    }
+    styles.retain(|x| {
+        x.bg_color.is_some() || x.under_line.is_some() || x.wave_line.is_some()
+    });
+
+    // 按视觉行分组，每组按边界切分后合成颜色
+    let mut rows: Vec<(f64, f64)> = backgrounds.iter().map(|x| (x.0, x.1)).collect();
+    rows.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.total_cmp(&b.1)));
+    rows.dedup();
+    for (y, height) in rows {
+        let row: Vec<_> = backgrounds
+            .iter()
+            .filter(|x| x.0 == y && x.1 == height)
+            .collect();
+        let mut xs: Vec<f64> = row.iter().flat_map(|x| [x.2, x.3]).collect();
+        xs.sort_by(f64::total_cmp);
+        xs.dedup();
+        let mut merged: Vec<LineExtraStyle> = Vec::new();
+        for pair in xs.windows(2) {
+            let (x0, x1) = (pair[0], pair[1]);
+            let colors: Vec<Color> = row
+                .iter()
+                .filter(|x| x.2 <= x0 && x1 <= x.3)
+                .map(|x| x.4)
+                .collect();
+            let Some(color) = composite_colors(&colors, blend) else {
+                continue;
+            };
+            match merged.last_mut() {
+                Some(last)
+                    if last.bg_color == Some(color)
+                        && last.x + last.width.unwrap_or_default() == x0 =>
+                {
+                    last.width = Some(x1 - last.x);
+                },
+                _ => merged.push(LineExtraStyle {
+                    x: x0,
+                    y,
+                    width: Some(x1 - x0),
+                    height,
+                    bg_color: Some(color),
+                    under_line: None,
+                    wave_line: None
+                })
+            }
+        }
+        // 背景先于下划线绘制
+        styles.splice(0..0, merged);
+    }
+}
+
+/// The composite of `colors`, the later is on top
+pub fn composite_colors(colors: &[Color], blend: BackgroundBlend) -> Option<Color> {
+    let (first, rest) = colors.split_first()?;
+    Some(match blend {
+        BackgroundBlend::Stack => *colors.last()?,
+        BackgroundBlend::Over => rest.iter().fold(*first, |bottom, top| {
+            let [br, bg, bb, ba] = color_components(bottom);
+            let [tr, tg, tb, ta] = color_components(*top);
+            let alpha = ta + ba * (1.0 - ta);
+            if alpha <= 0.0 {
+                return bottom;
+            }
+            let mix = |t: f32, b: f32| (t * ta + b * ba * (1.0 - ta)) / alpha;
+            color_of_components([mix(tr, br), mix(tg, bg), mix(tb, bb), alpha])
+        }),
+        BackgroundBlend::Mix => {
+            let len = colors.len() as f32;
+            let mut sum = [0.0f32; 4];
+            for color in colors {
+                for (sum, x) in sum.iter_mut().zip(color_components(*color)) {
+                    *sum += x;
+                }
+            }
+            color_of_components(sum.map(|x| x / len))
+        }
+    })
+}
+
+/// rgba in `[0, 1]`
+fn color_components(color: Color) -> [f32; 4] {
+    [color.r, color.g, color.b, color.a].map(|x| x as f32 / 255.0)
+}
+
+fn color_of_components(components: [f32; 4]) -> Color {
+    let [r, g, b, a] =
+        components.map(|x| (x * 255.0).round().clamp(0.0, 255.0) as u8);
+    Color::rgba8(r, g, b, a)
+}
+
 /// Get the previous unmatched character `c` from the `offset` using
 /// `syntax` if applicable
 pub fn syntax_prev_unmatched(
@@ -48,6 +48,21 @@ impl WordBoundary {
     }
 }
 
+/// The word motions of the editor
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WordMotion {
+    /// `w`, the start of the next word
+    Forward,
+    /// `b`, the start of the previous word
+    Backward,
+    /// `e`, the end of the next word
+    EndForward,
+    /// the start of the next subword of camelCase or snake_case
+    SubwordForward,
+    /// the start of the previous subword
+    SubwordBackward
+}
+
 /// A cursor providing utility function to navigate the rope
 /// by word boundaries.
 /// Boundaries can be the start of a word, its end, punctuation etc.
@@ -260,6 +275,60 @@ impl<'a> WordCursor<'a> {
         None
     }
 
+    /// Get the next start of a subword(see [is_subword_start]) or of a
+    /// word, and set the cursor position to the boundary found.
+    ///
+    /// ```rust
+    /// # use doc::lines::word::WordCursor;
+    /// # use lapce_xi_rope::Rope;
+    /// let rope = Rope::from("fooBar_baz");
+    /// let mut cursor = WordCursor::new(&rope, 0);
+    /// assert_eq!(cursor.next_subword_boundary(), Some(3));
+    /// assert_eq!(cursor.next_subword_boundary(), Some(7));
+    /// ```
+    pub fn next_subword_boundary(&mut self) -> Option<usize> {
+        let start = self.inner.pos();
+        let word = self.next_boundary()?;
+        self.inner.set(start);
+        let mut prev = self.inner.next_codepoint()?;
+        while self.inner.pos() < word {
+            let pos = self.inner.pos();
+            let Some(ch) = self.inner.next_codepoint() else {
+                break;
+            };
+            if is_subword_start(prev, ch, self.inner.peek_next_codepoint()) {
+                self.inner.set(pos);
+                return Some(pos);
+            }
+            prev = ch;
+        }
+        self.inner.set(word);
+        Some(word)
+    }
+
+    /// Get the previous start of a subword or of a word, and set the
+    /// cursor position to the boundary found. `mode` is the same as
+    /// [Self::prev_boundary].
+    pub fn prev_subword_boundary(&mut self, mode: Mode) -> Option<usize> {
+        let end = self.inner.pos();
+        let word = self.prev_boundary(mode)?;
+        // 词内位于`end`之前的最后一个子词
+        let mut found = word;
+        let mut prev = self.inner.next_codepoint()?;
+        while self.inner.pos() < end {
+            let pos = self.inner.pos();
+            let Some(ch) = self.inner.next_codepoint() else {
+                break;
+            };
+            if is_subword_start(prev, ch, self.inner.peek_next_codepoint()) {
+                found = pos;
+            }
+            prev = ch;
+        }
+        self.inner.set(found);
+        Some(found)
+    }
+
     /// Get the first matching [`CharClassification::Other`] backward
     /// and set the cursor position to this location . **Example:*
     /// *
@@ -419,6 +488,57 @@ impl<'a> WordCursor<'a> {
         (start, end)
     }
 
+    /// The word around the cursor like [`Self::select_word`], None if
+    /// there is no word character on either side of the cursor
+    pub fn word_at(&mut self) -> Option<(usize, usize)> {
+        let (start, end) = self.select_word();
+        (start < end).then_some((start, end))
+    }
+
+    /// Return the run of characters around the cursor that share the
+    /// [`CharClassification`] of the character under the cursor, so
+    /// whitespaces and punctuations are selected as a whole too. The
+    /// character before the cursor is used at the end of a line.
+    pub fn select_same_class(&mut self) -> (usize, usize) {
+        let initial = self.inner.pos();
+        let class_of = |c: Option<char>| {
+            c.map(get_char_property).filter(|prop| {
+                *prop != CharClassification::Cr && *prop != CharClassification::Lf
+            })
+        };
+        let next = class_of(self.inner.next_codepoint());
+        self.inner.set(initial);
+        let prop = match next {
+            Some(prop) => prop,
+            None => {
+                let prev = class_of(self.inner.prev_codepoint());
+                self.inner.set(initial);
+                match prev {
+                    Some(prop) => prop,
+                    None => return (initial, initial)
+                }
+            }
+        };
+
+        let mut end = initial;
+        while let Some(c) = self.inner.next_codepoint() {
+            if get_char_property(c) != prop {
+                break;
+            }
+            end = self.inner.pos();
+        }
+        self.inner.set(initial);
+        let mut start = initial;
+        while let Some(c) = self.inner.prev_codepoint() {
+            if get_char_property(c) != prop {
+                break;
+            }
+            start = self.inner.pos();
+        }
+        self.inner.set(initial);
+        (start, end)
+    }
+
     /// Return the enclosing brackets of the current position
     ///
     /// **Example**:
@@ -479,6 +599,19 @@ pub fn get_char_property(codepoint: char) -> CharClassification {
     CharClassification::Other
 }
 
+/// Whether `ch` starts a subword of camelCase or snake_case, e.g. `B` of
+/// `fooBar`, `b` of `foo_bar` and `S` of `HTTPServer`
+fn is_subword_start(prev: char, ch: char, next: Option<char>) -> bool {
+    if !ch.is_alphanumeric() {
+        return false;
+    }
+    prev == '_'
+        || (ch.is_uppercase() && (prev.is_lowercase() || prev.is_ascii_digit()))
+        || (ch.is_uppercase()
+            && prev.is_uppercase()
+            && next.is_some_and(|x| x.is_lowercase()))
+}
+
 fn classify_boundary(
     prev: CharClassification,
     next: CharClassification
@@ -721,4 +854,37 @@ mod test {
         let positions = cursor.find_enclosing_pair();
         assert_eq!(positions, None);
     }
+
+    #[test]
+    fn should_get_subword_boundaries() {
+        let text = "fooBar_baz HTTPServer";
+        let rope = Rope::from(text);
+        let mut cursor = WordCursor::new(&rope, 0);
+        let forward: Vec<usize> =
+            std::iter::from_fn(|| cursor.next_subword_boundary()).collect();
+        assert_eq!(forward, vec![3, 7, 11, 15, 21]);
+
+        let mut cursor = WordCursor::new(&rope, text.len());
+        let backward: Vec<usize> =
+            std::iter::from_fn(|| cursor.prev_subword_boundary(Mode::Insert))
+                .collect();
+        assert_eq!(backward, vec![15, 11, 7, 3, 0]);
+    }
+
+    #[test]
+    fn select_same_class_should_select_runs() {
+        let text = "let  a = b::c;\n";
+        let rope = Rope::from(text);
+        let mut cursor = WordCursor::new(&rope, 1);
+        assert_eq!(cursor.select_same_class(), (0, 3));
+
+        let mut cursor = WordCursor::new(&rope, 3);
+        assert_eq!(cursor.select_same_class(), (3, 5));
+
+        let mut cursor = WordCursor::new(&rope, 10);
+        assert_eq!(cursor.select_same_class(), (10, 12));
+
+        let mut cursor = WordCursor::new(&rope, 14);
+        assert_eq!(cursor.select_same_class(), (13, 14));
+    }
 }
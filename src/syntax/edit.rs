@@ -62,6 +62,45 @@ impl SyntaxEdit {
     }
 }
 
+/// The edits of the buffer revisions since the last parse, one edit per
+/// revision
+#[derive(Clone, Default)]
+pub struct SyntaxEdits {
+    edits: Vec<(u64, SyntaxEdit)>
+}
+
+impl SyntaxEdits {
+    /// Record the edit of the revision `rev`, the edits of `rev` and
+    /// later revisions(e.g. undone) are replaced
+    pub fn push(&mut self, rev: u64, edit: SyntaxEdit) {
+        self.edits.retain(|(x, _)| *x < rev);
+        self.edits.push((rev, edit));
+    }
+
+    pub fn clear(&mut self) {
+        self.edits.clear();
+    }
+
+    /// Drop the edits parsed by the tree of revision `rev`
+    pub fn retain_after(&mut self, rev: u64) {
+        self.edits.retain(|(x, _)| *x > rev);
+    }
+
+    /// The edits from revision `from` to `to`, None if some revision
+    /// between them has no edit
+    pub fn between(&self, from: u64, to: u64) -> Option<Vec<SyntaxEdit>> {
+        let edits: Vec<&(u64, SyntaxEdit)> = self
+            .edits
+            .iter()
+            .filter(|(rev, _)| from < *rev && *rev <= to)
+            .collect();
+        // 每个版本一个编辑，且版本连续
+        let contiguous = edits.len() as u64 == to.saturating_sub(from)
+            && edits.iter().zip(from + 1..).all(|((rev, _), x)| *rev == x);
+        contiguous.then(|| edits.into_iter().map(|(_, x)| x.clone()).collect())
+    }
+}
+
 fn point_at_offset(text: &Rope, offset: usize) -> Result<Point> {
     let text = RopeTextRef::new(text);
     let line = text.line_of_offset(offset);
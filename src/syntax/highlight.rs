@@ -140,7 +140,25 @@ pub fn new_highlight_config(
         queries_directory
     );
 
-    match HighlightConfiguration::new(grammar, &query, &injection, "") {
+    let locals = read_grammar_query(
+        queries_directory,
+        query_name,
+        LapceLanguage::LOCALS_QUERIES_FILE_NAME
+    );
+    // 局部变量查询有误时不影响高亮
+    let config = match HighlightConfiguration::new(
+        grammar.clone(),
+        &query,
+        &injection,
+        &locals
+    ) {
+        Err(err) if !locals.is_empty() => {
+            error!("locals of {language_name} {err:?}");
+            HighlightConfiguration::new(grammar, &query, &injection, "")
+        },
+        config => config
+    };
+    match config {
         Ok(x) => Ok(x),
         Err(x) => {
             let str = format!(
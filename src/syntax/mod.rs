@@ -11,12 +11,13 @@ use std::{
     collections::{HashMap, HashSet, VecDeque, hash_map::Entry},
     hash::{Hash, Hasher},
     mem,
+    ops::Range,
     path::Path,
     sync::{Arc, atomic::AtomicUsize}
 };
 
 use ahash::RandomState;
-use anyhow::Result;
+use anyhow::{Result, bail};
 use floem::views::editor::core::util::{
     matching_bracket_general, matching_pair_direction
 };
@@ -29,7 +30,7 @@ use lapce_xi_rope::{
 use log::error;
 use slotmap::{DefaultKey as LayerId, HopSlotMap};
 use thiserror::Error;
-use tree_sitter::{Node, Parser, Point, QueryCursor, Tree};
+use tree_sitter::{Node, Parser, Point, Query, QueryCursor, Tree};
 
 use self::{
     edit::SyntaxEdit,
@@ -38,21 +39,30 @@ use self::{
         HighlightIterLayer, IncludedChildren, LocalScope, get_highlight_config,
         intersect_ranges
     },
+    runnable::RunAnchor,
     util::RopeProvider
 };
 use crate::{
     LineStyle,
     language::{self, LapceLanguage},
     lens::{Lens, LensBuilder},
-    lines::buffer::{Buffer, rope_text::RopeText},
+    lines::buffer::{
+        Buffer,
+        rope_text::{RopeText, RopeTextRef}
+    },
     syntax::highlight::{InjectionLanguageMarker, SCOPES}
 };
 
 pub mod edit;
 pub mod highlight;
+pub mod runnable;
 pub mod util;
 
 const TREE_SITTER_MATCH_LIMIT: u32 = 256;
+/// 超过该字节数的文件只高亮可视区域
+pub const VIEWPORT_HIGHLIGHT_THRESHOLD: usize = 512 * 1024;
+/// 可视区域上下额外高亮的行数
+pub const VIEWPORT_HIGHLIGHT_MARGIN_LINES: usize = 200;
 
 // Uses significant portions Helix's implementation, and on
 // tree-sitter's highlighter implementation
@@ -159,7 +169,87 @@ pub struct BracketParser {
     mode:            BracketParserMode,
     noparsing_token: Vec<char>,
     pub active:      bool,
-    pub limit:       u64
+    pub limit:       u64,
+    /// 跨行的括号对(开括号偏移, 闭括号偏移)，按开括号排序
+    pub pairs:       Vec<(usize, usize)>,
+    /// buffer rev of `bracket_pos`
+    pub rev:         u64,
+    /// 为true时不在编辑时同步计算，由`BracketTask`在后台计算
+    pub background:  bool
+}
+
+/// Bracket pairs computed for the buffer of `rev`
+#[derive(Clone, Debug)]
+pub struct BracketResult {
+    pub rev:         u64,
+    pub bracket_pos: HashMap<usize, Vec<LineStyle>>,
+    pub pairs:       Vec<(usize, usize)>
+}
+
+/// A bracket analysis which can be run in another thread, the result
+/// is merged by `DocLines::set_bracket_result`
+#[derive(Clone)]
+pub struct BracketTask {
+    pub rev: u64,
+    parser:  BracketParser,
+    buffer:  Buffer,
+    syntax:  Option<Syntax>
+}
+
+impl BracketTask {
+    pub fn run(mut self) -> Result<BracketResult> {
+        self.parser.update_code(&self.buffer, self.syntax.as_ref())?;
+        Ok(BracketResult {
+            rev:         self.rev,
+            bracket_pos: self.parser.bracket_pos,
+            pairs:       self.parser.pairs
+        })
+    }
+}
+
+/// A parse of the syntax which can be run in another thread, the result
+/// is set by `DocLines::set_syntax`
+#[derive(Clone)]
+pub struct SyntaxTask {
+    pub rev: u64,
+    text:    Rope,
+    syntax:  Syntax,
+    /// 上次解析后每个版本的编辑，不完整时重新完整解析
+    edits:   Vec<SyntaxEdit>
+}
+
+impl SyntaxTask {
+    pub fn new(
+        rev: u64,
+        text: Rope,
+        syntax: Syntax,
+        edits: Vec<SyntaxEdit>
+    ) -> Self {
+        Self {
+            rev,
+            text,
+            syntax,
+            edits
+        }
+    }
+
+    /// Edit the trees and reparse the changed parts, or parse from
+    /// scratch if some revisions have no edit
+    pub fn run(
+        mut self,
+        grammars_directory: &Path,
+        queries_directory: &Path
+    ) -> Syntax {
+        let edits = (!self.edits.is_empty()).then_some(self.edits.as_slice());
+        self.syntax.parse(
+            self.rev,
+            self.text,
+            edits,
+            grammars_directory,
+            queries_directory
+        );
+        self.syntax
+    }
 }
 
 impl BracketParser {
@@ -177,13 +267,46 @@ impl BracketParser {
                 (']', ASTNode::new_with_type(NodeType::RightBracket, 1))
             ]),
             bracket_pos: HashMap::new(),
+            pairs: Vec::new(),
             mode: BracketParserMode::Parsing,
             noparsing_token: vec!['\'', '"', '`'],
             active,
-            limit
+            limit,
+            rev: 0,
+            background: false
+        }
+    }
+
+    pub fn set_background(&mut self, background: bool) {
+        self.background = background;
+    }
+
+    /// Whether `bracket_pos` is computed for the buffer
+    pub fn is_up_to_date(&self, buffer: &Buffer) -> bool {
+        self.rev == buffer.rev()
+    }
+
+    pub fn task(&self, buffer: &Buffer, syntax: Option<&Syntax>) -> BracketTask {
+        BracketTask {
+            rev:    buffer.rev(),
+            parser: BracketParser::new(String::new(), self.active, self.limit),
+            buffer: buffer.clone(),
+            syntax: syntax.cloned()
         }
     }
 
+    /// Merge the result of a background task, return false if it is
+    /// outdated
+    pub fn merge(&mut self, result: BracketResult, buffer: &Buffer) -> bool {
+        if result.rev != buffer.rev() {
+            return false;
+        }
+        self.bracket_pos = result.bracket_pos;
+        self.pairs = result.pairs;
+        self.rev = result.rev;
+        true
+    }
+
     /*pub fn enable(&self) {
         *(self.active.borrow_mut()) = true;
     }
@@ -202,6 +325,8 @@ impl BracketParser {
             "bracket.color.2".to_string(),
             "bracket.color.3".to_string(),
         ];
+        self.rev = buffer.rev();
+        self.pairs = Vec::new();
         let code = buffer.to_string();
         if self.active
             && code
@@ -221,7 +346,9 @@ impl BracketParser {
                             &mut 0,
                             &mut 0,
                             &mut bracket_pos,
-                            &palette
+                            &palette,
+                            &mut Vec::new(),
+                            &mut self.pairs
                         );
                         self.bracket_pos = bracket_pos;
                     }
@@ -241,8 +368,21 @@ impl BracketParser {
                 if buffer.is_empty() {
                     return Ok(());
                 }
+                // 未闭合的(开括号偏移, 行)
+                let mut open: Vec<(usize, usize)> = Vec::new();
                 for (offset, color, bracket) in pos_vec.into_iter() {
                     let (line, col) = buffer.offset_to_line_col(offset)?;
+                    match bracket.as_deref() {
+                        Some("(" | "[" | "{") => open.push((offset, line)),
+                        Some(_) if color != "bracket.unpaired" => {
+                            if let Some((start, start_line)) = open.pop() {
+                                if start_line != line {
+                                    self.pairs.push((start, offset));
+                                }
+                            }
+                        },
+                        _ => {}
+                    }
                     let line_style = LineStyle {
                         start:    col,
                         end:      col + 1,
@@ -260,6 +400,7 @@ impl BracketParser {
         } else {
             self.bracket_pos = HashMap::new();
         }
+        self.pairs.sort_unstable();
         Ok(())
     }
 
@@ -412,6 +553,8 @@ pub struct LanguageLayer {
     // mode
     // grammar
     pub config:      Arc<HighlightConfiguration>,
+    /// 注入的语言，根层为None，见[Syntax::language]
+    pub language:    Option<LapceLanguage>,
     pub(crate) tree: Option<Tree>,
     pub ranges:      Vec<tree_sitter::Range>,
     pub depth:       usize,
@@ -506,6 +649,7 @@ impl SyntaxLayers {
         let root_layer = LanguageLayer {
             tree: None,
             config,
+            language: None,
             depth: 0,
             ranges: vec![tree_sitter::Range {
                 start_byte:  0,
@@ -567,6 +711,7 @@ impl SyntaxLayers {
             language
                 .map(|x| {
                     get_highlight_config(x, grammars_directory, queries_directory)
+                        .map(|config| (x, config))
                 })
                 .unwrap_or(Err(highlight::HighlightIssue::NotAvailable))
         };
@@ -789,7 +934,7 @@ impl SyntaxLayers {
                             (injection_capture, content_node)
                         {
                             match (injection_callback)(&injection_capture) {
-                                Ok(config) => {
+                                Ok((language, config)) => {
                                     let ranges = intersect_ranges(
                                         &layer.ranges,
                                         &[content_node],
@@ -803,7 +948,7 @@ impl SyntaxLayers {
                                             continue;
                                         }
                                         last_injection_end = content_node.end_byte();
-                                        injections.push((config, ranges));
+                                        injections.push((language, config, ranges));
                                     }
                                 },
                                 Err(err) => {
@@ -820,14 +965,14 @@ impl SyntaxLayers {
                             (lang_name, content_nodes.is_empty())
                         {
                             match (injection_callback)(&lang_name) {
-                                Ok(config) => {
+                                Ok((language, config)) => {
                                     let ranges = intersect_ranges(
                                         &layer.ranges,
                                         &content_nodes,
                                         included_children
                                     );
                                     if !ranges.is_empty() {
-                                        injections.push((config, ranges));
+                                        injections.push((language, config, ranges));
                                     }
                                 },
                                 Err(err) => {
@@ -840,10 +985,11 @@ impl SyntaxLayers {
                     let depth = layer.depth + 1;
                     // TODO: can't inline this since matches borrows
                     // self.layers
-                    for (config, ranges) in injections {
+                    for (language, config, ranges) in injections {
                         let new_layer = LanguageLayer {
                             tree: None,
                             config,
+                            language: Some(language),
                             depth,
                             ranges,
                             _parent: Some(layer_id),
@@ -884,6 +1030,21 @@ impl SyntaxLayers {
         self.layers[self.root].try_tree()
     }
 
+    /// The language of the deepest injected layer containing `offset`,
+    /// None if it's the root language
+    pub fn language_at(&self, offset: usize) -> Option<LapceLanguage> {
+        self.layers
+            .values()
+            .filter(|layer| {
+                layer.depth > 0
+                    && layer.ranges.iter().any(|range| {
+                        range.start_byte <= offset && offset <= range.end_byte
+                    })
+            })
+            .max_by_key(|layer| layer.depth)
+            .and_then(|layer| layer.language)
+    }
+
     /// Iterate over the highlighted regions for a given slice of
     /// source code.
     pub fn highlight_iter<'a>(
@@ -959,6 +1120,37 @@ impl SyntaxLayers {
     }
 }
 
+/// The innermost node at an offset, see [Syntax::token_at]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyntaxToken {
+    /// the node kind, e.g. `identifier`
+    pub kind:      &'static str,
+    /// the highlight name of the offset, e.g. `function.method`
+    pub highlight: Option<String>,
+    pub range:     Range<usize>
+}
+
+/// The captures of the `locals.scm` query
+#[derive(Default)]
+struct LocalCaptures {
+    scopes: Vec<Range<usize>>,
+    defs:   Vec<Range<usize>>,
+    refs:   Vec<Range<usize>>
+}
+
+/// A capture of a custom query, see [Syntax::query]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyntaxCapture {
+    /// the capture name without `@`
+    pub name:          String,
+    pub kind:          &'static str,
+    pub range:         Range<usize>,
+    pub pattern_index: usize
+}
+
+/// 自定义查询最多返回的匹配数，避免过宽的查询卡住界面
+const QUERY_MATCH_LIMIT: u32 = 10_000;
+
 #[derive(Clone)]
 pub struct Syntax {
     pub rev:          u64,
@@ -970,7 +1162,13 @@ pub struct Syntax {
     pub line_height:  usize,
     pub lens_height:  usize,
     pub styles:       Option<Spans<String>>,
-    pub cancel_flag:  Arc<AtomicUsize>
+    /// The byte range covered by `styles`, `None` means the whole text
+    pub styles_range: Option<Range<usize>>,
+    pub cancel_flag:  Arc<AtomicUsize>,
+    /// 可运行项（测试、main函数）的位置，用于放置运行按钮
+    pub run_anchors:  Vec<RunAnchor>,
+    /// tree-sitter的ERROR/MISSING节点范围，语言服务器返回前的粗略诊断
+    pub errors:       Vec<Range<usize>>
 }
 
 impl std::fmt::Debug for Syntax {
@@ -983,6 +1181,9 @@ impl std::fmt::Debug for Syntax {
             .field("line_height", &self.line_height)
             .field("lens_height", &self.lens_height)
             .field("styles", &self.styles)
+            .field("styles_range", &self.styles_range)
+            .field("run_anchors", &self.run_anchors)
+            .field("errors", &self.errors)
             .finish()
     }
 }
@@ -1025,7 +1226,10 @@ impl Syntax {
             lens_height: 0,
             normal_lines: Vec::new(),
             styles: None,
-            cancel_flag: Arc::new(AtomicUsize::new(0))
+            styles_range: None,
+            cancel_flag: Arc::new(AtomicUsize::new(0)),
+            run_anchors: Vec::new(),
+            errors: Vec::new()
         }
     }
 
@@ -1055,40 +1259,23 @@ impl Syntax {
         }
         let tree = layers.try_tree();
 
+        // 大文件只高亮之前的可视区域，其余部分随视口移动再补上
+        let styles_range = if new_text.len() > VIEWPORT_HIGHLIGHT_THRESHOLD {
+            let range = self
+                .styles_range
+                .clone()
+                .unwrap_or(0..VIEWPORT_HIGHLIGHT_THRESHOLD);
+            Some(range.start.min(new_text.len())..range.end.min(new_text.len()))
+        } else {
+            None
+        };
         let styles = if tree.is_some() {
-            let mut current_hl: Option<Highlight> = None;
-            let mut highlights: SpansBuilder<String> =
-                SpansBuilder::new(new_text.len());
-
-            // TODO: Should we be ignoring highlight errors via
-            // flattening them?
-            for highlight in layers
-                .highlight_iter(
-                    &new_text,
-                    Some(0..new_text.len()),
-                    Some(&self.cancel_flag)
-                )
-                .flatten()
-            {
-                match highlight {
-                    HighlightEvent::Source { start, end } => {
-                        if let Some(hl) = current_hl {
-                            if let Some(hl) = SCOPES.get(hl.0) {
-                                highlights.add_span(
-                                    Interval::new(start, end),
-                                    hl.to_string()
-                                );
-                            }
-                        }
-                    },
-                    HighlightEvent::HighlightStart(hl) => {
-                        current_hl = Some(hl);
-                    },
-                    HighlightEvent::HighlightEnd => current_hl = None
-                }
-            }
-
-            Some(highlights.build())
+            Some(Self::highlight_styles(
+                layers,
+                &new_text,
+                styles_range.clone().unwrap_or(0..new_text.len()),
+                &self.cancel_flag
+            ))
         } else {
             None
         };
@@ -1102,6 +1289,21 @@ impl Syntax {
             Vec::new()
         };
 
+        let run_anchors = tree
+            .map(|tree| {
+                runnable::run_anchors(
+                    self.language,
+                    tree,
+                    &new_text,
+                    queries_directory
+                )
+            })
+            .unwrap_or_default();
+
+        let errors = tree
+            .map(|tree| Self::error_ranges(tree, &new_text))
+            .unwrap_or_default();
+
         let lens = Self::lens_from_normal_lines(
             new_text.line_of_offset(new_text.len()) + 1,
             self.line_height,
@@ -1113,9 +1315,122 @@ impl Syntax {
         self.lens = lens;
         self.normal_lines = normal_lines;
         self.styles = styles;
+        self.styles_range = styles_range;
+        self.run_anchors = run_anchors;
+        self.errors = errors;
         self.text = new_text
     }
 
+    /// The ranges of the ERROR and MISSING nodes, the children of an
+    /// ERROR node are skipped. A MISSING node is empty, its range is
+    /// widened to the previous char so that it's visible.
+    fn error_ranges(tree: &Tree, text: &Rope) -> Vec<Range<usize>> {
+        let mut ranges = Vec::new();
+        if !tree.root_node().has_error() {
+            return ranges;
+        }
+        let mut cursor = tree.walk();
+        'walk: loop {
+            let node = cursor.node();
+            let is_error = node.is_error() || node.is_missing();
+            if is_error {
+                let range = node.byte_range();
+                if range.is_empty() {
+                    let start = text.prev_codepoint_offset(range.start);
+                    ranges.push(start.unwrap_or(range.start)..range.end);
+                } else {
+                    ranges.push(range);
+                }
+            } else if node.has_error() && cursor.goto_first_child() {
+                continue;
+            }
+            while !cursor.goto_next_sibling() {
+                if !cursor.goto_parent() {
+                    break 'walk;
+                }
+            }
+        }
+        ranges
+    }
+
+    fn highlight_styles(
+        layers: &SyntaxLayers,
+        text: &Rope,
+        range: Range<usize>,
+        cancel_flag: &AtomicUsize
+    ) -> Spans<String> {
+        let mut current_hl: Option<Highlight> = None;
+        let mut highlights: SpansBuilder<String> = SpansBuilder::new(text.len());
+
+        // TODO: Should we be ignoring highlight errors via
+        // flattening them?
+        for highlight in layers
+            .highlight_iter(text, Some(range), Some(cancel_flag))
+            .flatten()
+        {
+            match highlight {
+                HighlightEvent::Source { start, end } => {
+                    if let Some(hl) = current_hl {
+                        if let Some(hl) = SCOPES.get(hl.0) {
+                            highlights
+                                .add_span(Interval::new(start, end), hl.to_string());
+                        }
+                    }
+                },
+                HighlightEvent::HighlightStart(hl) => {
+                    current_hl = Some(hl);
+                },
+                HighlightEvent::HighlightEnd => current_hl = None
+            }
+        }
+        highlights.build()
+    }
+
+    /// Make sure the origin lines `[start_line, end_line]` are
+    /// highlighted. Only huge files are highlighted by range, the
+    /// highlight queries are re-run with a margin when the lines
+    /// leave the highlighted range.
+    ///
+    /// Return true if the styles are changed.
+    pub fn ensure_lines_highlighted(
+        &mut self,
+        start_line: usize,
+        end_line: usize
+    ) -> Result<bool> {
+        let Some(styles_range) = self.styles_range.clone() else {
+            return Ok(false);
+        };
+        let Some(layers) = &self.layers else {
+            return Ok(false);
+        };
+        if layers.try_tree().is_none() {
+            return Ok(false);
+        }
+        let text = RopeTextRef::new(&self.text);
+        let last_line = text.last_line();
+        let start_line = start_line.min(last_line);
+        let end_line = end_line.min(last_line);
+        let start = text.offset_of_line(start_line)?;
+        let end = text.offset_of_line((end_line + 1).min(last_line + 1))?;
+        if styles_range.start <= start && end <= styles_range.end {
+            return Ok(false);
+        }
+        let start = text.offset_of_line(
+            start_line.saturating_sub(VIEWPORT_HIGHLIGHT_MARGIN_LINES)
+        )?;
+        let end = text.offset_of_line(
+            (end_line + 1 + VIEWPORT_HIGHLIGHT_MARGIN_LINES).min(last_line + 1)
+        )?;
+        self.styles = Some(Self::highlight_styles(
+            layers,
+            &self.text,
+            start..end,
+            &self.cancel_flag
+        ));
+        self.styles_range = Some(start..end);
+        Ok(true)
+    }
+
     pub fn update_lens_height(&mut self, line_height: usize, lens_height: usize) {
         self.lens = Self::lens_from_normal_lines(
             self.text.line_of_offset(self.text.len()) + 1,
@@ -1255,6 +1570,313 @@ impl Syntax {
         Some(offsets)
     }
 
+    /// The byte ranges of the outline nodes, the sticky header tags of
+    /// the language such as functions and impls, in the order of start.
+    /// The flag is true if the node contains other outline nodes.
+    pub fn outline_ranges(&self) -> Option<Vec<(Range<usize>, bool)>> {
+        let tree = self.layers.as_ref()?.try_tree()?;
+        let tags = self.language.sticky_header_tags();
+        let mut ranges: Vec<(Range<usize>, bool)> = Vec::new();
+        // 外层大纲节点的(序号, 结束)
+        let mut parents: Vec<(usize, usize)> = Vec::new();
+        let mut cursor = tree.walk();
+        'walk: loop {
+            let node = cursor.node();
+            if tags.contains(&node.kind()) {
+                while parents
+                    .last()
+                    .is_some_and(|(_, end)| *end <= node.start_byte())
+                {
+                    parents.pop();
+                }
+                if let Some((index, _)) = parents.last() {
+                    ranges[*index].1 = true;
+                }
+                parents.push((ranges.len(), node.end_byte()));
+                ranges.push((node.byte_range(), false));
+            }
+            if cursor.goto_first_child() {
+                continue;
+            }
+            while !cursor.goto_next_sibling() {
+                if !cursor.goto_parent() {
+                    break 'walk;
+                }
+            }
+        }
+        Some(ranges)
+    }
+
+    /// The nearest complete statement or expression around `range`,
+    /// for "evaluate selection". An empty range(the cursor) expands to
+    /// the statement containing it, a selection to the smallest
+    /// expression or statement covering it.
+    pub fn evaluation_range(&self, range: Range<usize>) -> Option<Range<usize>> {
+        let tree = self.layers.as_ref()?.try_tree()?;
+        let root = tree.root_node();
+        let mut node =
+            root.named_descendant_for_byte_range(range.start, range.end)?;
+        // 光标在行尾时，取前一个字符所在的节点
+        if node == root && range.is_empty() && range.start > 0 {
+            node = root
+                .named_descendant_for_byte_range(range.start - 1, range.start)?;
+        }
+        if node == root {
+            return None;
+        }
+        // 选中的正好是一个节点，如标识符
+        if !range.is_empty() && node.byte_range() == range {
+            return Some(range);
+        }
+        let is_statement = |node: &Node| {
+            node.parent().is_some_and(|parent| {
+                parent == root
+                    || parent.kind().contains("block")
+                    || parent.kind().ends_with("body")
+            })
+        };
+        let is_expression = |node: &Node| {
+            let kind = node.kind();
+            kind.contains("expression") || kind.contains("statement")
+        };
+        loop {
+            if is_statement(&node) || (!range.is_empty() && is_expression(&node)) {
+                return Some(node.byte_range());
+            }
+            node = node.parent().filter(|x| *x != root)?;
+        }
+    }
+
+    /// The language governing `offset`, e.g. JavaScript in the
+    /// `<script>` of a Vue file, by the injections of the last parse
+    pub fn language_at(&self, offset: usize) -> LapceLanguage {
+        self.layers
+            .as_ref()
+            .and_then(|layers| layers.language_at(offset))
+            .unwrap_or(self.language)
+    }
+
+    /// The innermost node at `offset` and the highlight name there. At
+    /// the end of a token, the token before is taken.
+    pub fn token_at(&self, offset: usize) -> Option<SyntaxToken> {
+        let tree = self.layers.as_ref()?.try_tree()?;
+        let offset = offset.min(self.text.len());
+        let root = tree.root_node();
+        let mut node = root.descendant_for_byte_range(offset, offset)?;
+        if offset > 0 && (node == root || node.start_byte() == offset) {
+            let prev = root.descendant_for_byte_range(offset - 1, offset)?;
+            let prev_ends = prev.end_byte() == offset && prev.child_count() == 0;
+            if node == root || prev_ends {
+                node = prev;
+            }
+        }
+        if node == root {
+            return None;
+        }
+        let range = node.byte_range();
+        // 在节点末尾时取最后一个字符的高亮
+        let probe = if offset == range.end && offset > range.start {
+            offset - 1
+        } else {
+            offset
+        };
+        let highlight = self.styles.as_ref().and_then(|styles| {
+            styles
+                .iter_chunks(range.clone())
+                .find(|(iv, _)| iv.start <= probe && probe < iv.end)
+                .map(|(_, name)| name.clone())
+        });
+        Some(SyntaxToken {
+            kind: node.kind(),
+            highlight,
+            range
+        })
+    }
+
+    /// Run the query source against the tree of the root language,
+    /// only the matches in `range` are returned, in order. The number
+    /// of matches is limited.
+    pub fn query(
+        &self,
+        range: Range<usize>,
+        query_src: &str
+    ) -> Result<Vec<SyntaxCapture>> {
+        let Some(tree) = self.layers.as_ref().and_then(|x| x.try_tree()) else {
+            bail!("no syntax tree of {}", self.language.name());
+        };
+        let query = Query::new(&tree.language(), query_src)?;
+        let len = self.text.len();
+        let range = range.start.min(len)..range.end.min(len);
+        let names = query.capture_names();
+        let mut cursor = QueryCursor::new();
+        cursor.set_byte_range(range);
+        cursor.set_match_limit(QUERY_MATCH_LIMIT);
+        let mut captures = Vec::new();
+        for query_match in
+            cursor.matches(&query, tree.root_node(), RopeProvider(&self.text))
+        {
+            for capture in query_match.captures {
+                captures.push(SyntaxCapture {
+                    name:          names[capture.index as usize].to_string(),
+                    kind:          capture.node.kind(),
+                    range:         capture.node.byte_range(),
+                    pattern_index: query_match.pattern_index
+                });
+            }
+        }
+        captures.sort_by_key(|x| (x.range.start, x.pattern_index));
+        Ok(captures)
+    }
+
+    /// The definition and all references of the local variable at
+    /// `offset` by the `locals.scm` query, in order. None if it's not a
+    /// local variable.
+    ///
+    /// Only the outermost scope containing `offset` is queried, the
+    /// whole text is queried only if the definition is outside of it.
+    pub fn local_uses(&self, offset: usize) -> Option<Vec<Range<usize>>> {
+        let scope = self
+            .local_captures(offset..offset + 1)?
+            .scopes
+            .into_iter()
+            .max_by_key(|x| x.len());
+        scope
+            .and_then(|scope| self.local_uses_in(offset, scope))
+            .or_else(|| self.local_uses_in(offset, 0..self.text.len()))
+    }
+
+    /// The captures of the `locals.scm` query intersecting `range`
+    fn local_captures(&self, range: Range<usize>) -> Option<LocalCaptures> {
+        let layers = self.layers.as_ref()?;
+        let tree = layers.try_tree()?;
+        let config = &layers.layers[layers.root].config;
+        let def_index = config.local_def_capture_index?;
+        let mut captures = LocalCaptures::default();
+        let mut cursor = QueryCursor::new();
+        cursor.set_match_limit(QUERY_MATCH_LIMIT);
+        cursor.set_byte_range(range);
+        let matches =
+            cursor.matches(&config.query, tree.root_node(), RopeProvider(&self.text));
+        // locals.scm的模式在高亮模式之前
+        for query_match in
+            matches.filter(|x| x.pattern_index < config.highlights_pattern_index)
+        {
+            for capture in query_match.captures {
+                let range = capture.node.byte_range();
+                let index = Some(capture.index);
+                if capture.index == def_index {
+                    captures.defs.push(range);
+                } else if index == config.local_ref_capture_index {
+                    captures.refs.push(range);
+                } else if index == config.local_scope_capture_index {
+                    captures.scopes.push(range);
+                }
+            }
+        }
+        captures.defs.sort_by_key(|x| x.start);
+        let defs = &captures.defs;
+        captures.refs.retain(|x| !defs.contains(x));
+        Some(captures)
+    }
+
+    /// The uses of the local variable at `offset`, resolved by the
+    /// captures in `range`
+    fn local_uses_in(
+        &self,
+        offset: usize,
+        range: Range<usize>
+    ) -> Option<Vec<Range<usize>>> {
+        let LocalCaptures { scopes, defs, refs } = self.local_captures(range)?;
+        let contains = |scope: &Range<usize>, offset: usize| {
+            scope.start <= offset && offset < scope.end
+        };
+        // 包含该位置的最内层作用域
+        let scope_of = |offset: usize| {
+            scopes
+                .iter()
+                .filter(|x| contains(x, offset))
+                .min_by_key(|x| x.len())
+        };
+        let name_of = |range: &Range<usize>| self.text.slice_to_cow(range.clone());
+        // 由内向外的作用域中，在引用之前的最后一个同名定义
+        let resolve = |range: &Range<usize>| {
+            let name = name_of(range);
+            let def_in = |scope: Option<&Range<usize>>| {
+                defs.iter()
+                    .rev()
+                    .find(|x| {
+                        x.start <= range.start
+                            && scope_of(x.start) == scope
+                            && name_of(x) == name
+                    })
+                    .cloned()
+            };
+            scopes
+                .iter()
+                .filter(|x| contains(x, range.start))
+                .sorted_by_key(|x| x.len())
+                .find_map(|scope| def_in(Some(scope)))
+                .or_else(|| def_in(None))
+        };
+
+        let at = |x: &&Range<usize>| x.start <= offset && offset <= x.end;
+        let target = match defs.iter().find(at) {
+            Some(def) => def.clone(),
+            None => resolve(refs.iter().find(at)?)?
+        };
+        let name = name_of(&target);
+        let mut uses: Vec<Range<usize>> = refs
+            .iter()
+            .filter(|x| name_of(x) == name && resolve(x).as_ref() == Some(&target))
+            .cloned()
+            .collect();
+        uses.push(target);
+        uses.sort_by_key(|x| x.start);
+        uses.dedup();
+        Some(uses)
+    }
+
+    /// The parameter names and whether there is a return type of the
+    /// function starting at the line of `offset`, `self` is skipped
+    pub fn function_signature_at(
+        &self,
+        offset: usize
+    ) -> Option<(Vec<String>, bool)> {
+        let tree = self.layers.as_ref()?.try_tree()?;
+        let line = self.text.line_of_offset(offset);
+        let mut node = tree
+            .root_node()
+            .named_descendant_for_byte_range(offset, offset)?;
+        let params = loop {
+            let kind = node.kind();
+            if kind.contains("function") || kind.contains("method") {
+                if let Some(params) = node.child_by_field_name("parameters") {
+                    break params;
+                }
+            }
+            node = node.parent()?;
+        };
+        if node.start_position().row != line {
+            return None;
+        }
+        let mut cursor = params.walk();
+        let names = params
+            .named_children(&mut cursor)
+            .filter(|x| !x.kind().contains("self") && !x.kind().contains("comment"))
+            .filter_map(|x| {
+                let name = ["pattern", "name", "declarator"]
+                    .iter()
+                    .find_map(|field| x.child_by_field_name(field))
+                    .unwrap_or(x);
+                let name = self.text.slice_to_cow(name.byte_range()).to_string();
+                (name != "self").then_some(name)
+            })
+            .collect();
+        let has_return = node.child_by_field_name("return_type").is_some()
+            || node.child_by_field_name("result").is_some();
+        Some((names, has_return))
+    }
+
     pub fn find_enclosing_parentheses(
         &self,
         offset: usize
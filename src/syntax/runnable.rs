@@ -0,0 +1,119 @@
+use std::{cell::RefCell, collections::HashMap, path::Path, sync::Arc};
+
+use lapce_xi_rope::{Rope, RopeDelta, Transformer};
+use log::error;
+use tree_sitter::{Query, QueryCursor, Tree};
+
+use super::util::RopeProvider;
+use crate::language::{LapceLanguage, read_grammar_query};
+
+thread_local! {
+    static RUNNABLE_QUERIES: RefCell<HashMap<LapceLanguage, Option<Arc<Query>>>> = Default::default();
+}
+
+/// 可运行项的类型，对应查询中的捕获名`@run.test`、`@run.bench`、
+/// `@run.main`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RunAnchorKind {
+    Test,
+    Bench,
+    Main
+}
+
+impl RunAnchorKind {
+    fn from_capture(name: &str) -> Option<Self> {
+        match name {
+            "run.test" => Some(Self::Test),
+            "run.bench" => Some(Self::Bench),
+            "run.main" => Some(Self::Main),
+            _ => None
+        }
+    }
+}
+
+/// Where the run/debug lens of a runnable item is placed, found by the
+/// `runnables.scm` query of the language
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunAnchor {
+    /// the start of the captured node
+    pub offset: usize,
+    pub kind:   RunAnchorKind,
+    /// the text captured by `@run.name` of the same match, e.g. the
+    /// name of the test function
+    pub name:   Option<String>
+}
+
+impl RunAnchor {
+    pub fn apply_delta(anchors: &mut Vec<RunAnchor>, delta: &RopeDelta) {
+        let mut transformer = Transformer::new(delta);
+        for anchor in anchors.iter_mut() {
+            anchor.offset = transformer.transform(anchor.offset, false);
+        }
+    }
+}
+
+/// The runnables query of the language, None if the language has no
+/// such query
+fn runnables_query(
+    language: LapceLanguage,
+    tree: &Tree,
+    queries_directory: &Path
+) -> Option<Arc<Query>> {
+    RUNNABLE_QUERIES.with_borrow_mut(|queries| {
+        queries
+            .entry(language)
+            .or_insert_with(|| {
+                let source = read_grammar_query(
+                    queries_directory,
+                    &language.query_name(),
+                    LapceLanguage::RUNNABLES_QUERIES_FILE_NAME
+                );
+                if source.trim().is_empty() {
+                    return None;
+                }
+                match Query::new(&tree.language(), &source) {
+                    Ok(query) => Some(Arc::new(query)),
+                    Err(err) => {
+                        error!("{} {:?}", language.name(), err);
+                        None
+                    }
+                }
+            })
+            .clone()
+    })
+}
+
+/// The anchors of runnable items in the tree, in the order of offset
+pub fn run_anchors(
+    language: LapceLanguage,
+    tree: &Tree,
+    text: &Rope,
+    queries_directory: &Path
+) -> Vec<RunAnchor> {
+    let Some(query) = runnables_query(language, tree, queries_directory) else {
+        return Vec::new();
+    };
+    let names = query.capture_names();
+    let mut cursor = QueryCursor::new();
+    let mut anchors = Vec::new();
+    for query_match in cursor.matches(&query, tree.root_node(), RopeProvider(text))
+    {
+        let mut name = None;
+        let mut found = None;
+        for capture in query_match.captures {
+            let capture_name = names[capture.index as usize];
+            if capture_name == "run.name" {
+                let range = capture.node.byte_range();
+                name = Some(text.slice_to_cow(range).to_string());
+            } else if let Some(kind) = RunAnchorKind::from_capture(capture_name) {
+                found = Some((capture.node.start_byte(), kind));
+            }
+        }
+        if let Some((offset, kind)) = found {
+            anchors.push(RunAnchor { offset, kind, name });
+        }
+    }
+    anchors.sort_by_key(|x| x.offset);
+    anchors.dedup_by_key(|x| x.offset);
+    anchors
+}
@@ -219,4 +219,55 @@ fn test_resolve_delta_compute() -> Result<()> {
         });
     }
     Ok(())
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_resolve_deltas() -> Result<()> {
+    use doc::lines::delta_compute::resolve_deltas_rs;
+    let lines = init_main_2()?;
+    let insert = |offset: usize, base_len: usize, s: &str| RopeDelta {
+        els: vec![DeltaElement::<RopeInfo>::Copy(0, offset),
+                  DeltaElement::<RopeInfo>::Insert(Node::from_leaf(s.to_string())), DeltaElement::<RopeInfo>::Copy(offset, base_len), ],
+        base_len,
+    };
+    // 两次插入合并：未改动的前缀到第一次插入，后缀从第二次插入之后
+    let deltas = [insert(117, 461, "m"), insert(300, 462, "n")];
+    let rs = resolve_deltas_rs(lines.buffer().text(), &deltas)?;
+    let expected = resolve_line_delta(lines.buffer().text(), OffsetDelta {
+        copy_start: Interval::new(0, 117),
+        internal_len: 184,
+        copy_end: Interval::new(299, 461),
+    })?;
+    assert_eq!(rs, expected);
+    assert_eq!(
+        resolve_deltas_rs(lines.buffer().text(), std::iter::empty())?,
+        OriginLinesDelta::default()
+    );
+    Ok(())
+}
+
+#[test]
+fn test_incremental_relayout() -> Result<()> {
+    for offset in [0, 117, 461] {
+        let mut lines = init_main_2()?;
+        let mut cursor = cursor_insert(offset, offset);
+        let mut register = Register::default();
+        let mut response = Vec::new();
+        let edit = EditBuffer::DoEditBuffer {
+            cursor:    &mut cursor,
+            cmd:       &EditCommand::InsertNewLine,
+            count:     1,
+            modal:     false,
+            register:  &mut register,
+            smart_tab: true,
+            response: &mut response,
+        };
+        lines.buffer_edit(edit)?;
+        assert!(lines.check_lines());
+        // 复制的折叠行和视觉行与全量计算的一致
+        let visual_lines = lines.visual_lines.clone();
+        lines.update_lines_new(OriginLinesDelta::default())?;
+        assert_eq!(visual_lines, lines.visual_lines);
+    }
+    Ok(())
+}
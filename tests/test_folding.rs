@@ -37,4 +37,128 @@ fn test_debug() -> Result<()> {
 
 fn init() -> Result<FoldingDisplayItem> {
     Ok(serde_json::from_str(r#"{"position":{"line":1,"character":12},"y":23,"ty":"UnfoldStart"}"#)?)
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_y_of_origin_line() -> Result<()> {
+    custom_utils::logger::logger_stdout_debug();
+    let mut lines = init_main_2()?;
+    let line_height = lines.line_height as f64;
+    assert_eq!(lines.y_of_origin_line(2)?, line_height * 2.0);
+
+    //  2|   if true {...} else {\r\n
+    lines.update_folding_ranges(folded_v1().into())?;
+    assert_eq!(lines.y_of_origin_line(1)?, line_height);
+    assert_eq!(lines.y_of_origin_line(2)?, line_height);
+    assert_eq!(lines.y_of_origin_line(3)?, line_height);
+    assert_eq!(lines.y_of_origin_line(4)?, line_height * 2.0);
+
+    assert_eq!(lines.origin_line_of_y(line_height + 1.0)?, 1);
+    assert_eq!(lines.origin_line_of_y(line_height * 2.0 + 1.0)?, 4);
+    assert_eq!(lines.origin_line_of_y(-1.0)?, 0);
+    Ok(())
+}
+
+#[test]
+fn test_folding_item_at_y() -> Result<()> {
+    custom_utils::logger::logger_stdout_debug();
+    let mut lines = init_main_2()?;
+    let line_height = lines.line_height as f64;
+    lines.update_folding_ranges(folded_v1().into())?;
+
+    let item = lines.folding_item_at_y(line_height + 1.0).unwrap();
+    assert_eq!(item.position.line, 1);
+    assert_eq!(item.ty, FoldingDisplayType::Folded);
+
+    let lane_items = lines.folding_lane_items(16.0);
+    let lane_item = lane_items.get(&1).unwrap();
+    assert!(lane_item.is_folded());
+    assert_eq!(lane_item.rect.width(), 16.0);
+    assert_eq!(lane_item.rect.y0, line_height);
+    Ok(())
+}
+
+#[test]
+fn test_screen_line_texts() -> Result<()> {
+    custom_utils::logger::logger_stdout_debug();
+    let mut lines = init_main_2()?;
+    //  2|   if true {...} else {\r\n
+    lines.update_folding_ranges(folded_v1().into())?;
+    let texts: Vec<_> = lines.screen_line_texts().collect();
+    let folded = texts
+        .iter()
+        .find(|x| x.visual_line.origin_line == 1)
+        .unwrap();
+    assert!(folded.segments.iter().any(|x| x.is_folded()));
+    assert!(folded.text().contains("if true"));
+    assert!(folded.text().contains("else"));
+    assert!(folded.marked_text("\u{1}", "\u{2}").contains('\u{1}'));
+    assert!(texts.iter().all(|x| !x.text().contains('\n')));
+    Ok(())
+}
+
+#[test]
+fn test_auto_fold() -> Result<()> {
+    use doc::{
+        config::AutoFoldConfig,
+        lines::fold::{FoldingRange, FoldingRangeStatus, FoldingRanges}
+    };
+    let ranges = r#"[{"startLine":0,"endLine":2,"kind":"imports"},{"startLine":4,"endLine":20},{"startLine":5,"endLine":10},{"startLine":6,"endLine":8},{"startLine":12,"endLine":14,"kind":"comment"}]"#;
+    let ranges: Vec<lsp_types::FoldingRange> = serde_json::from_str(ranges)?;
+    let mut ranges =
+        FoldingRanges(ranges.into_iter().map(FoldingRange::from_lsp).collect());
+    ranges.auto_fold(&AutoFoldConfig {
+        imports:  true,
+        comments: false,
+        level:    Some(2)
+    });
+    let folded: Vec<bool> = ranges
+        .0
+        .iter()
+        .map(|x| x.status == FoldingRangeStatus::Fold)
+        .collect();
+    assert_eq!(folded, vec![true, false, false, true, false]);
+    Ok(())
+}
+
+#[test]
+fn test_locked_folding_range() -> Result<()> {
+    use doc::lines::fold::{FoldingRange, FoldingRangeStatus, FoldingSource};
+    let mut lines = init_main_2()?;
+    lines.update_folding_ranges(folded_v1().into())?;
+    let range = lines.folding_ranges.0[1].clone();
+    assert_eq!(range.source, FoldingSource::User);
+    assert!(lines.set_folding_range_locked(range.start, true));
+
+    lines.update_folding_ranges(Vec::<FoldingRange>::new().into())?;
+    assert_eq!(lines.folding_ranges.0.len(), 1);
+    assert_eq!(lines.folding_ranges.0[0].status, FoldingRangeStatus::Fold);
+    assert!(lines.folding_ranges.0[0].locked);
+    Ok(())
+}
+
+#[test]
+fn test_reload_keep_next_folding_range() -> Result<()> {
+    use doc::lines::fold::FoldingRange;
+    let mut lines = init_empty()?;
+    lines.init_buffer("fn a() {\n    1\n}\nfn b() {\n    2\n}\n".into())?;
+    let ranges = r#"[{"startLine":0,"endLine":2},{"startLine":3,"endLine":5}]"#;
+    let ranges: Vec<lsp_types::FoldingRange> = serde_json::from_str(ranges)?;
+    let ranges: Vec<FoldingRange> =
+        ranges.into_iter().map(FoldingRange::from_lsp).collect();
+    lines.update_folding_ranges(ranges.into())?;
+
+    // 在两个范围之间插入一行，后面的范围下移
+    lines.reload_buffer(
+        "fn a() {\n    1\n}\n// c\nfn b() {\n    2\n}\n".into(),
+        true
+    )?;
+    let lines_of_ranges: Vec<_> = lines
+        .folding_ranges
+        .0
+        .iter()
+        .map(|x| (x.start.line, x.end.line))
+        .collect();
+    assert_eq!(lines_of_ranges, vec![(0, 2), (4, 6)]);
+    Ok(())
+}
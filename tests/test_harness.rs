@@ -0,0 +1,1355 @@
+use anyhow::Result;
+use doc::config::FoldedCursorPolicy;
+use doc::lines::buffer::rope_text::RopeText;
+use doc::lines::{
+    DIAGNOSTIC_RELATED_DECORATION_KEY,
+    phantom_text::PhantomTextKind,
+    testing::{
+        TestLines, assert_phantom_at, check_visual_lines, diagnostic, fold_at,
+        folding_range, inlay_hint, visual_line_structure
+    }
+};
+use lsp_types::{
+    DiagnosticRelatedInformation, DiagnosticSeverity, DocumentDiagnosticReportKind,
+    FullDocumentDiagnosticReport, Location, Position, Range,
+    UnchangedDocumentDiagnosticReport, Url
+};
+
+#[test]
+fn test_harness() -> Result<()> {
+    let code = "fn main() {\n    let a = 1;\n}\n";
+    let lines = TestLines::new(code)
+        .inlay_hint(inlay_hint(1, 9, ": i32"))
+        .build()?;
+    check_visual_lines(&lines)?;
+    assert_phantom_at(&lines, 1, 9, ": i32 ", |x| {
+        *x == PhantomTextKind::InlayHint
+    });
+    let structure = visual_line_structure(&lines);
+    assert_eq!(structure[1], (1, 0, "    let a: i32  = 1;".to_string()));
+
+    let lines = TestLines::new(code)
+        .folding_range(folding_range(0, 10, 2, 0))
+        .fold(fold_at(0, 10))
+        .build()?;
+    check_visual_lines(&lines)?;
+    assert_eq!(lines.origin_folded_lines.len(), 2);
+    Ok(())
+}
+
+#[test]
+fn test_replay() -> Result<()> {
+    use doc::lines::testing::{EditOp, replay};
+    let code = "fn main() {\n    let a = 1;\n}\n";
+    let mut lines = TestLines::new(code)
+        .folding_range(folding_range(0, 10, 2, 0))
+        .build()?;
+    replay(&mut lines, &[
+        EditOp::ToggleFold(0),
+        EditOp::Insert {
+            offset: 0,
+            text:   "é\n".to_string()
+        },
+        EditOp::ToggleFold(0),
+        EditOp::Delete {
+            offset: 2,
+            len:    100
+        },
+        EditOp::Scroll(3),
+        EditOp::Resize {
+            width:  200,
+            height: 100
+        }
+    ])?;
+    assert_eq!(lines.buffer().text().to_string(), "é");
+    replay(&mut lines, &EditOp::decode(&[0, 1, 0, 7, 1, 0, 0, 2, 3, 5, 0, 0]))?;
+    Ok(())
+}
+
+#[test]
+fn test_monospace_metrics() -> Result<()> {
+    use doc::lines::{
+        layout::{MonospaceMetrics, TextLayout},
+        testing::test_monospace_metrics
+    };
+    use floem::text::{Attrs, AttrsList, Wrap};
+    let metrics = test_monospace_metrics();
+    let attrs_list = || AttrsList::new(Attrs::new());
+    let layout = TextLayout::new_monospace(0, "ab\t中c", attrs_list(), metrics);
+    let glyphs = &layout.line_layout()[0].glyphs;
+    assert_eq!(glyphs.len(), 5);
+    assert_eq!(glyphs[2].x, 16.0);
+    assert_eq!(glyphs[3].x, 64.0);
+    assert_eq!(glyphs[4].x, 80.0);
+    assert_eq!(MonospaceMetrics::columns('中'), 2);
+
+    let mut layout =
+        TextLayout::new_monospace(0, "aaa bbb ccc", attrs_list(), metrics);
+    layout.set_wrap(Wrap::WordOrGlyph);
+    layout.set_size(metrics.char_width * 5.0, f32::MAX);
+    let lines: Vec<_> = layout
+        .line_layout()
+        .iter()
+        .map(|x| (x.glyphs[0].start, x.glyphs.last().unwrap().end))
+        .collect();
+    assert_eq!(lines, vec![(0, 4), (4, 8), (8, 11)]);
+    Ok(())
+}
+
+#[test]
+fn test_shared_shaping_cache() -> Result<()> {
+    use doc::lines::{layout::shaping_cache_stats, testing::test_monospace_metrics};
+    let metrics = test_monospace_metrics();
+    // 两个文档中相同的license头只布局一次
+    let code = "// Copyright test_shared_shaping_cache\n";
+    let _a = TestLines::new(code).monospace(metrics).build()?;
+    let hits = shaping_cache_stats().hits;
+    let _b = TestLines::new(code).monospace(metrics).build()?;
+    let stats = shaping_cache_stats();
+    assert!(stats.hits > hits);
+    assert!(stats.entries > 0);
+    assert!(stats.hit_rate() > 0.0);
+    Ok(())
+}
+
+#[test]
+fn test_smart_home_end() -> Result<()> {
+    use doc::lines::{cursor::CursorAffinity, testing::test_monospace_metrics};
+    use floem::{kurbo::Rect, views::editor::core::mode::Mode};
+    let metrics = test_monospace_metrics();
+    let code = "  aaaa bbbb cccc dddd\n";
+    let lines = TestLines::new(code)
+        .viewport(Rect::new(0.0, 0.0, metrics.char_width as f64 * 12.5, 200.0))
+        .config(|config| {
+            config.smart_home = true;
+            config.wrap_aware_end = true;
+        })
+        .monospace(metrics)
+        .build()?;
+    let mut affinity = CursorAffinity::Backward;
+    assert_eq!(lines.smart_home(&mut affinity, 8)?.0, 2);
+    assert_eq!(affinity, CursorAffinity::Forward);
+    assert_eq!(lines.smart_home(&mut affinity, 2)?.0, 0);
+    assert_eq!(lines.smart_home(&mut affinity, 0)?.0, 2);
+
+    let (vl, ..) = lines.visual_line_of_offset(0, CursorAffinity::Forward)?;
+    let wrap_end = vl.origin_interval.end;
+    assert!(wrap_end < 21);
+    let (offset, _) = lines.smart_end(&mut affinity, 0, Mode::Insert)?;
+    assert_eq!(offset, wrap_end);
+    assert_eq!(affinity, CursorAffinity::Backward);
+    let (offset, _) = lines.smart_end(&mut affinity, offset, Mode::Insert)?;
+    assert_eq!(offset, 21);
+    Ok(())
+}
+
+#[test]
+fn test_move_by_origin_line() -> Result<()> {
+    use doc::lines::{cursor::CursorAffinity, testing::test_monospace_metrics};
+    use floem::{kurbo::Rect, views::editor::core::mode::Mode};
+    let metrics = test_monospace_metrics();
+    let code = "aaaa bbbb cccc dddd\nxy\n";
+    let build = |by_origin_line: bool| {
+        TestLines::new(code)
+            .viewport(Rect::new(0.0, 0.0, metrics.char_width as f64 * 12.5, 200.0))
+            .config(|config| config.move_by_origin_line = by_origin_line)
+            .monospace(metrics)
+            .build()
+    };
+    let forward = CursorAffinity::Forward;
+    let lines = build(false)?;
+    // 默认按视觉行移动，先到换行后的视觉行
+    let (offset, _, affinity) = lines.move_down(0, forward, None, Mode::Insert, 1)?;
+    let (vl, ..) = lines.visual_line_of_offset(offset, affinity)?;
+    assert_eq!((vl.origin_line, vl.origin_folded_line_sub_index), (0, 1));
+    let (offset, ..) = lines.move_down_visual_line(0, forward, None, Mode::Insert, 2)?;
+    assert_eq!(offset, 20);
+    let (offset, ..) = lines.move_down_origin_line(0, forward, None, Mode::Insert, 1)?;
+    assert_eq!(offset, 20);
+
+    let lines = build(true)?;
+    let (offset, ..) = lines.move_down(0, forward, None, Mode::Insert, 1)?;
+    assert_eq!(offset, 20);
+    // 目标列在两种移动之间保持
+    let (offset, horiz, affinity) =
+        lines.move_up(21, forward, None, Mode::Insert, 1)?;
+    assert_eq!(offset, 1);
+    let (offset, ..) =
+        lines.move_down_visual_line(offset, affinity, Some(horiz), Mode::Insert, 1)?;
+    let (vl, ..) = lines.visual_line_of_offset(offset, CursorAffinity::Forward)?;
+    assert_eq!((vl.origin_line, vl.origin_folded_line_sub_index), (0, 1));
+    let (offset, ..) = lines.move_down(1, affinity, Some(horiz), Mode::Insert, 1)?;
+    assert_eq!(offset, 21);
+    // 超出文档时停在最后一行
+    let (offset, ..) = lines.move_down(1, affinity, None, Mode::Insert, 5)?;
+    assert_eq!(offset, 23);
+    Ok(())
+}
+
+#[test]
+fn test_screen_line_parity() -> Result<()> {
+    use doc::lines::testing::test_monospace_metrics;
+    use floem::kurbo::Rect;
+    let metrics = test_monospace_metrics();
+    let code = "aaaa bbbb cccc dddd\nxy\nz\n";
+    let mut lines = TestLines::new(code)
+        .viewport(Rect::new(0.0, 0.0, metrics.char_width as f64 * 12.5, 200.0))
+        .monospace(metrics)
+        .build()?;
+    let parity = |lines: &doc::lines::DocLines| -> Vec<(bool, bool, bool)> {
+        lines
+            .screen_lines()
+            .visual_lines
+            .iter()
+            .map(|x| {
+                (
+                    x.is_odd_visual_line(),
+                    x.is_odd_origin_line(),
+                    x.is_first_of_folded_line()
+                )
+            })
+            .collect()
+    };
+    // 第一行换行为两个视觉行
+    assert_eq!(parity(&lines), [
+        (false, false, true),
+        (true, false, false),
+        (false, true, true),
+        (true, false, true),
+        (false, true, true)
+    ]);
+    // 滚动后奇偶不变
+    let line_height = lines.screen_lines().line_height;
+    lines.update_viewport_by_scroll(Rect::new(
+        0.0,
+        line_height,
+        metrics.char_width as f64 * 12.5,
+        200.0 + line_height
+    ));
+    assert_eq!(parity(&lines)[0], (true, false, false));
+    Ok(())
+}
+
+#[test]
+fn test_cursor_column() -> Result<()> {
+    use doc::lines::{
+        cursor::{Cursor, CursorAffinity, CursorMode},
+        selection::Selection,
+        testing::test_monospace_metrics
+    };
+    let metrics = test_monospace_metrics();
+    let char_width = metrics.char_width as f64;
+    let cursor = |offset: usize| {
+        Cursor::new(CursorMode::Insert(Selection::caret(offset)), None, None)
+    };
+    let lines = TestLines::new("\tlet a = 1;\n")
+        .monospace(metrics)
+        .build()?;
+    assert!(lines.cursor_column_rect(&cursor(0))?.is_none());
+
+    let lines = TestLines::new("\tlet a = 1;\n")
+        .config(|x| x.cursor_column = true)
+        .inlay_hint(inlay_hint(0, 5, ": i32"))
+        .monospace(metrics)
+        .build()?;
+    let viewport = lines.viewport();
+    // 制表符的宽度
+    let tab = lines.cursor_column_rect(&cursor(0))?.unwrap();
+    assert_eq!((tab.y0, tab.y1), (viewport.y0, viewport.y1));
+    assert!(tab.width() > char_width);
+    let l = lines.cursor_column_rect(&cursor(1))?.unwrap();
+    assert_eq!(l.x0, tab.x1);
+    assert_eq!(l.width(), char_width);
+    // 幻影文本之后
+    let eq = lines.cursor_column_rect(&cursor(7))?.unwrap();
+    assert!(eq.x0 > l.x0 + 6.0 * char_width);
+    // 按光标的affinity位于幻影文本的前后
+    let mut at_hint = cursor(5);
+    at_hint.affinity = CursorAffinity::Backward;
+    let before = lines.cursor_column_rect(&at_hint)?.unwrap();
+    at_hint.affinity = CursorAffinity::Forward;
+    let after = lines.cursor_column_rect(&at_hint)?.unwrap();
+    assert_eq!(after.x0, before.x0 + 5.0 * char_width);
+    Ok(())
+}
+
+#[test]
+fn test_word_motion_phantom() -> Result<()> {
+    use doc::lines::cursor::CursorAffinity;
+    use floem::views::editor::core::mode::Mode;
+    let code = "let a = foo(1);\n";
+    let lines = TestLines::new(code)
+        .inlay_hint(inlay_hint(0, 5, ": i32"))
+        .inlay_hint(inlay_hint(0, 12, "x:"))
+        .build()?;
+    assert!(lines.has_phantom_at(5)?);
+    assert!(!lines.has_phantom_at(4)?);
+
+    let mut affinity = CursorAffinity::Backward;
+    assert_eq!(lines.move_words_forward(&mut affinity, 8, 2)?, 12);
+    assert_eq!(affinity, CursorAffinity::Forward);
+    affinity = CursorAffinity::Backward;
+    assert_eq!(lines.move_words_backward(&mut affinity, 13, 1, Mode::Normal)?, 12);
+    assert_eq!(affinity, CursorAffinity::Forward);
+
+    assert_eq!(lines.move_word_ends_forward(&mut affinity, 0, 2, true)?, 5);
+    assert_eq!(affinity, CursorAffinity::Backward);
+    assert_eq!(lines.move_word_ends_forward(&mut affinity, 0, 2, false)?, 4);
+    assert_eq!(affinity, CursorAffinity::Forward);
+    Ok(())
+}
+
+#[test]
+fn test_subword_motion_phantom() -> Result<()> {
+    use doc::lines::{cursor::CursorAffinity, word::WordMotion};
+    use floem::views::editor::core::mode::Mode;
+    let code = "let a = foo(myValue);\n";
+    let lines = TestLines::new(code)
+        .inlay_hint(inlay_hint(0, 12, "x:"))
+        .build()?;
+
+    let mut affinity = CursorAffinity::Backward;
+    let motion = WordMotion::SubwordBackward;
+    assert_eq!(lines.move_by_word(&mut affinity, 19, motion, 1, Mode::Insert)?, 14);
+    assert_eq!(affinity, CursorAffinity::Forward);
+    affinity = CursorAffinity::Backward;
+    assert_eq!(lines.move_by_word(&mut affinity, 14, motion, 1, Mode::Insert)?, 12);
+    // 在参数提示之后
+    assert_eq!(affinity, CursorAffinity::Forward);
+
+    let motion = WordMotion::SubwordForward;
+    assert_eq!(lines.move_by_word(&mut affinity, 12, motion, 2, Mode::Insert)?, 19);
+    Ok(())
+}
+
+#[test]
+fn test_normalize_cursor() -> Result<()> {
+    use doc::lines::{
+        cursor::{Cursor, CursorAffinity, CursorMode},
+        selection::Selection,
+        testing::test_monospace_metrics
+    };
+    use floem::kurbo::Rect;
+    let metrics = test_monospace_metrics();
+    let code = "aaaa bbbb cccc dddd\nlet a = 1;\n";
+    let lines = TestLines::new(code)
+        .viewport(Rect::new(0.0, 0.0, metrics.char_width as f64 * 12.5, 200.0))
+        .inlay_hint(inlay_hint(1, 5, ": i32"))
+        .monospace(metrics)
+        .build()?;
+    let (vl, ..) = lines.visual_line_of_offset(0, CursorAffinity::Forward)?;
+    let wrap_end = vl.origin_interval.end;
+    assert!(lines.is_wrap_boundary(wrap_end)?);
+    assert!(!lines.is_wrap_boundary(2)?);
+    let backward = CursorAffinity::Backward;
+    assert_eq!(lines.affinity_of_offset(wrap_end, backward)?, backward);
+    assert_eq!(lines.affinity_of_offset(2, backward)?, CursorAffinity::Forward);
+    assert_eq!(lines.affinity_of_offset(25, backward)?, backward);
+
+    let mut cursor =
+        Cursor::new(CursorMode::Insert(Selection::caret(0)), None, None);
+    cursor.affinity = backward;
+    lines.normalize_cursor(&mut cursor)?;
+    assert_eq!(cursor.affinity, CursorAffinity::Forward);
+    let mut cursor = Cursor::new(CursorMode::Normal(wrap_end), None, None);
+    cursor.affinity = backward;
+    lines.normalize_cursor(&mut cursor)?;
+    assert_eq!(cursor.affinity, CursorAffinity::Forward);
+    Ok(())
+}
+
+#[test]
+fn test_diff_ribbons() -> Result<()> {
+    use std::sync::{Arc, atomic::AtomicU64};
+
+    use doc::lines::{
+        buffer::diff::rope_diff,
+        diff::{DiffRibbonKind, diff_ribbons}
+    };
+    use lapce_xi_rope::Rope;
+    let (left_code, right_code) = ("a\nb\nc\nd\n", "a\nx\ny\nc\nd\n");
+    let left = TestLines::new(left_code).build()?;
+    let right = TestLines::new(right_code).build()?;
+    let changes = rope_diff(
+        Rope::from(left_code),
+        Rope::from(right_code),
+        0,
+        Arc::new(AtomicU64::new(0)),
+        None
+    )
+    .unwrap();
+    let ribbons =
+        diff_ribbons(&changes, left.screen_lines(), right.screen_lines());
+    assert_eq!(ribbons.len(), 1);
+    let ribbon = ribbons[0];
+    let top = left.screen_lines().origin_line_top(0).unwrap();
+    let line_height = left.screen_lines().line_height;
+    assert_eq!(ribbon.kind, DiffRibbonKind::Changed);
+    assert_eq!(ribbon.left_top, top + line_height);
+    assert_eq!(ribbon.left_bottom, top + line_height * 2.0);
+    assert_eq!(ribbon.right_top, top + line_height);
+    assert_eq!(ribbon.right_bottom, top + line_height * 3.0);
+    let [upper, lower] = ribbon.control_points(0.0, 10.0);
+    assert_eq!(upper[1].x, 5.0);
+    assert_eq!(lower[3].y, ribbon.right_bottom);
+    Ok(())
+}
+
+#[test]
+fn test_blame_layer() -> Result<()> {
+    use doc::lines::{
+        blame::{BlameInfo, LineBlame},
+        testing::{EditOp, replay}
+    };
+    let code = "a\nb\nc\n";
+    let mut lines = TestLines::new(code).build()?;
+    assert_eq!(lines.line_blame(0)?, None);
+    let info = |commit: &str| BlameInfo {
+        commit: commit.to_string(),
+        author: "someone".to_string(),
+        time:   100
+    };
+    lines.set_blame(vec![Some(info("1")), Some(info("2")), None])?;
+    let committed = |commit: &str| LineBlame::Committed(info(commit).into());
+    assert_eq!(lines.line_blame(1)?, Some(committed("2")));
+    assert_eq!(lines.line_blame(2)?, Some(LineBlame::Modified));
+
+    // 在第0行前插入新行，原有行下移
+    replay(&mut lines, &[EditOp::Insert {
+        offset: 0,
+        text:   "x\n".to_string()
+    }])?;
+    assert_eq!(lines.line_blame(0)?, Some(LineBlame::Modified));
+    assert_eq!(lines.line_blame(1)?, Some(committed("1")));
+    assert_eq!(lines.line_blame(2)?, Some(committed("2")));
+
+    replay(&mut lines, &[EditOp::Insert {
+        offset: 5,
+        text:   "y".to_string()
+    }])?;
+    assert_eq!(lines.line_blame(1)?, Some(committed("1")));
+    assert_eq!(lines.line_blame(2)?, Some(LineBlame::Modified));
+    assert_eq!(info("1").age(160), 60);
+    Ok(())
+}
+
+#[test]
+fn test_gutter_texts() -> Result<()> {
+    use doc::lines::{
+        gutter_text::GutterText,
+        testing::{EditOp, replay}
+    };
+    let code = "a\nb\nc\n";
+    let mut lines = TestLines::new(code).build()?;
+    let text = |x: &str| GutterText {
+        text:  x.to_string(),
+        color: None
+    };
+    lines.set_gutter_texts(vec![(0, text("10%")), (1, text("20%")), (2, text("30%"))])?;
+    assert_eq!(lines.line_gutter_text(1)?, Some(&text("20%")));
+    assert_eq!(lines.line_gutter_text(3)?, None);
+
+    // 新插入的行没有文本
+    replay(&mut lines, &[EditOp::Insert {
+        offset: 0,
+        text:   "x\n".to_string()
+    }])?;
+    assert_eq!(lines.line_gutter_text(0)?, None);
+    assert_eq!(lines.line_gutter_text(1)?, Some(&text("10%")));
+    assert_eq!(lines.line_gutter_text(2)?, Some(&text("20%")));
+
+    // 删除"b\n"，第2行的文本随行删除
+    replay(&mut lines, &[EditOp::Delete { offset: 4, len: 2 }])?;
+    assert_eq!(lines.buffer().text().to_string(), "x\na\nc\n");
+    assert_eq!(lines.line_gutter_text(2)?, Some(&text("30%")));
+
+    let screen = lines.screen_line_gutter_texts()?;
+    assert_eq!(
+        screen
+            .iter()
+            .map(|(x, text)| (x.visual_line.origin_line, text.text.as_str()))
+            .collect::<Vec<_>>(),
+        vec![(1, "10%"), (2, "30%")]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_gutter_diagnostics() -> Result<()> {
+    let code = "let a = 1;\nlet b = 2;\nlet c = 3;\n";
+    let lines = TestLines::new(code)
+        .diagnostic(diagnostic(0, 4, 5, DiagnosticSeverity::WARNING, "w"))
+        .diagnostic(diagnostic(2, 4, 5, DiagnosticSeverity::WARNING, "w"))
+        .diagnostic(diagnostic(2, 8, 9, DiagnosticSeverity::ERROR, "e"))
+        .build()?;
+    let gutter: Vec<_> = lines
+        .gutter_diagnostics()
+        .iter()
+        .map(|x| (x.origin_line, x.severity, x.count))
+        .collect();
+    assert_eq!(gutter, vec![
+        (0, DiagnosticSeverity::WARNING, 1),
+        (2, DiagnosticSeverity::ERROR, 2)
+    ]);
+    Ok(())
+}
+
+#[test]
+fn test_diagnostic_related_information() -> Result<()> {
+    let uri = Url::parse("file:///main.rs")?;
+    let other = Url::parse("file:///lib.rs")?;
+    let related = |uri: &Url, line: u32| DiagnosticRelatedInformation {
+        location: Location {
+            uri:   uri.clone(),
+            range: Range::new(Position::new(line, 4), Position::new(line, 5))
+        },
+        message:  "moved here".to_string()
+    };
+    let mut diag = diagnostic(2, 8, 9, DiagnosticSeverity::ERROR, "borrowed");
+    diag.related_information = Some(vec![related(&uri, 0), related(&other, 3)]);
+    let code = "let a = 1;\nlet b = a;\nlet c = a;\n";
+    let mut lines = TestLines::new(code).diagnostic(diag).build()?;
+
+    assert_eq!(lines.related_information_at(30).len(), 2);
+    assert!(lines.related_information_at(4).is_empty());
+
+    let infos = lines.show_related_information(30, &uri)?;
+    assert_eq!(infos.len(), 2);
+    let layer = lines
+        .decorations()
+        .get(DIAGNOSTIC_RELATED_DECORATION_KEY)
+        .expect("related ranges highlighted");
+    let ranges: Vec<_> = layer.iter().map(|(iv, _)| (iv.start, iv.end)).collect();
+    assert_eq!(ranges, vec![(4, 5)]);
+
+    lines.clear_related_information()?;
+    assert!(
+        lines
+            .decorations()
+            .get(DIAGNOSTIC_RELATED_DECORATION_KEY)
+            .is_none()
+    );
+    Ok(())
+}
+
+#[test]
+fn test_pull_diagnostics() -> Result<()> {
+    let code = "let a = 1;\nlet b = a;\n";
+    let mut lines = TestLines::new(code).build()?;
+    let rev = lines.buffer().rev();
+    let full = |id: &str| {
+        DocumentDiagnosticReportKind::Full(FullDocumentDiagnosticReport {
+            result_id: Some(id.to_string()),
+            items:     vec![diagnostic(1, 8, 9, DiagnosticSeverity::ERROR, "e")]
+        })
+    };
+    // 过期的结果被丢弃
+    assert!(!lines.set_pull_diagnostics(rev + 1, full("0"))?);
+    assert!(lines.diagnostic_result_id().is_none());
+
+    assert!(lines.set_pull_diagnostics(rev, full("1"))?);
+    assert_eq!(lines.diagnostic_result_id(), Some("1"));
+    assert_eq!(lines.diagnostics_at(19).len(), 1);
+
+    let unchanged =
+        DocumentDiagnosticReportKind::Unchanged(UnchangedDocumentDiagnosticReport {
+            result_id: "2".to_string()
+        });
+    assert!(!lines.set_pull_diagnostics(rev, unchanged)?);
+    assert_eq!(lines.diagnostic_result_id(), Some("2"));
+    assert_eq!(lines.diagnostics_at(19).len(), 1);
+    Ok(())
+}
+
+#[test]
+fn test_folded_cursor_policy() -> Result<()> {
+    use doc::lines::{
+        cursor::{Cursor, CursorMode},
+        selection::Selection
+    };
+    // 折叠范围 `{` 10 到 `}` 27
+    let code = "fn main() {\n    let a = 1;\n}\n";
+    let caret = |offset| {
+        Cursor::new(CursorMode::Insert(Selection::caret(offset)), None, None)
+    };
+    let build = |policy| {
+        TestLines::new(code)
+            .config(|x| x.folded_cursor = policy)
+            .folding_range(folding_range(0, 10, 2, 0))
+            .fold(fold_at(0, 10))
+            .build()
+    };
+
+    let mut lines = build(FoldedCursorPolicy::Skip)?;
+    let mut cursor = caret(15);
+    assert!(lines.resolve_folded_cursor(&mut cursor, 9)?);
+    assert_eq!(cursor.offset(), 27);
+    let mut cursor = caret(15);
+    assert!(lines.resolve_folded_cursor(&mut cursor, 28)?);
+    assert_eq!(cursor.offset(), 10);
+    let mut cursor = caret(5);
+    assert!(!lines.resolve_folded_cursor(&mut cursor, 4)?);
+
+    let mut lines = build(FoldedCursorPolicy::Placeholder)?;
+    let mut cursor = caret(15);
+    assert!(lines.resolve_folded_cursor(&mut cursor, 9)?);
+    assert_eq!(cursor.offset(), 10);
+
+    let mut lines = build(FoldedCursorPolicy::Unfold)?;
+    let mut cursor = caret(15);
+    assert!(lines.resolve_folded_cursor(&mut cursor, 9)?);
+    assert_eq!(cursor.offset(), 15);
+    assert!(!lines.folding_ranges.0[0].status.is_folded());
+    check_visual_lines(&lines)?;
+    Ok(())
+}
+
+#[test]
+fn test_reveal_offset() -> Result<()> {
+    use doc::lines::{
+        cursor::{Cursor, CursorMode},
+        selection::Selection
+    };
+    use lsp_types::Position;
+    let code = "fn main() {\n    if a {\n        b();\n    }\n    c();\n}\n";
+    let mut lines = TestLines::new(code)
+        .folding_range(folding_range(0, 10, 5, 0))
+        .folding_range(folding_range(1, 9, 3, 4))
+        .fold(fold_at(1, 9))
+        .fold(fold_at(0, 10))
+        .build()?;
+    let folded = |lines: &doc::lines::DocLines| -> Vec<bool> {
+        lines
+            .folding_ranges
+            .0
+            .iter()
+            .map(|x| x.status.is_folded())
+            .collect()
+    };
+    assert_eq!(folded(&lines), vec![true, true]);
+
+    // `c();`只在外层折叠中
+    let mut cursor = Cursor::new(CursorMode::Insert(Selection::caret(0)), None, None);
+    let unfolded = lines.goto_offset(&mut cursor, 46)?;
+    assert_eq!(unfolded, vec![Position::new(0, 10)]);
+    assert_eq!(cursor.offset(), 46);
+    assert_eq!(folded(&lines), vec![false, true]);
+    check_visual_lines(&lines)?;
+
+    lines.refold(&unfolded)?;
+    assert_eq!(folded(&lines), vec![true, true]);
+
+    let unfolded = lines.reveal_offset(30)?;
+    assert_eq!(unfolded.len(), 2);
+    assert_eq!(folded(&lines), vec![false, false]);
+    assert!(lines.reveal_offset(30)?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_follow_tail() -> Result<()> {
+    use doc::lines::{edit::EditType, selection::Selection};
+    use floem::{kurbo::Rect, reactive::SignalGet};
+    let code = "log\n".repeat(10);
+    let mut lines = TestLines::new(&code)
+        .viewport(Rect::new(0.0, 0.0, 300.0, 100.0))
+        .build()?;
+    let line_height = lines.line_height as f64;
+    let height = lines.signal_viewport().get_untracked().height();
+    let bottom = |lines: &doc::lines::DocLines| {
+        lines.signal_viewport().get_untracked().y1
+            >= (lines.buffer().num_lines() as f64 * line_height) - 1.0
+    };
+    lines.set_follow_tail(true);
+    assert!(!lines.is_following_tail());
+
+    // 滚动到底部后开始跟随
+    let y0 = 11.0 * line_height - height;
+    lines.update_viewport_by_scroll(Rect::new(0.0, y0, 300.0, y0 + height));
+    assert!(lines.is_following_tail());
+    let end = lines.buffer().len();
+    lines.edit_buffer(&[(Selection::caret(end), "a\nb\n")], EditType::InsertChars)?;
+    assert!(bottom(&lines));
+
+    // 手动滚动离开底部后停止跟随
+    lines.update_viewport_by_scroll(Rect::new(0.0, 0.0, 300.0, height));
+    assert!(!lines.is_following_tail());
+    let end = lines.buffer().len();
+    lines.edit_buffer(&[(Selection::caret(end), "c\n")], EditType::InsertChars)?;
+    assert_eq!(lines.signal_viewport().get_untracked().y0, 0.0);
+    Ok(())
+}
+
+#[test]
+fn test_append() -> Result<()> {
+    let mut lines = TestLines::new("fn a() {}\nlet b").build()?;
+    assert!(lines.append("")?.is_none());
+    lines.append(" = 1;\nlet c = 2;\n")?;
+    lines.append("log")?;
+    check_visual_lines(&lines)?;
+    let expected = TestLines::new("fn a() {}\nlet b = 1;\nlet c = 2;\nlog").build()?;
+    assert_eq!(
+        lines.buffer().text().to_string(),
+        expected.buffer().text().to_string()
+    );
+    assert_eq!(visual_line_structure(&lines), visual_line_structure(&expected));
+    Ok(())
+}
+
+#[test]
+fn test_read_only() -> Result<()> {
+    use doc::lines::{ReadOnlyError, edit::EditType, selection::Selection};
+    let code = "fn main() {\n    let a = 1;\n}\n";
+    let mut lines = TestLines::new(code)
+        .folding_range(folding_range(0, 10, 2, 0))
+        .build()?;
+    lines.set_read_only(true);
+    let err = lines
+        .edit_buffer(&[(Selection::caret(0), "x")], EditType::InsertChars)
+        .unwrap_err();
+    assert_eq!(err.downcast_ref::<ReadOnlyError>(), Some(&ReadOnlyError));
+    assert!(lines.append("x").is_err());
+    assert_eq!(lines.buffer().text().to_string(), code);
+
+    // 折叠不受影响
+    lines.update_folding_ranges(fold_at(0, 10).into())?;
+    assert!(lines.folding_ranges.0[0].status.is_folded());
+    check_visual_lines(&lines)?;
+
+    lines.set_read_only(false);
+    lines.edit_buffer(&[(Selection::caret(0), "x")], EditType::InsertChars)?;
+    assert!(lines.buffer().text().to_string().starts_with("xfn"));
+    Ok(())
+}
+
+#[test]
+fn test_line_metrics() -> Result<()> {
+    let code = "short\n\tlong line here\n";
+    let lines = TestLines::new(code)
+        .config(|x| x.long_line_column = Some(10))
+        .build()?;
+    let metrics = lines.line_metrics(1)?;
+    assert_eq!(metrics.chars, 15);
+    // tab展开到第8列
+    assert_eq!(metrics.columns, 22);
+    assert!(metrics.width > lines.line_metrics(0)?.width);
+    assert_eq!(lines.line_metrics(0)?.columns, 5);
+    assert_eq!(lines.long_lines()?, vec![1]);
+    check_visual_lines(&lines)?;
+
+    assert_eq!(doc::lines::util::offset_of_display_column("\tab", 8, 8), Some(1));
+    assert_eq!(doc::lines::util::offset_of_display_column("ab", 2, 8), None);
+    Ok(())
+}
+
+#[test]
+fn test_metrics_sink() -> Result<()> {
+    use std::{cell::RefCell, rc::Rc, time::Duration};
+
+    use doc::lines::{
+        edit::EditType,
+        metrics::{MetricsSink, Phase},
+        selection::Selection
+    };
+    use lsp_types::{Position, Range, TextEdit};
+
+    #[derive(Default)]
+    struct Recorder(RefCell<Vec<Phase>>);
+
+    impl MetricsSink for Recorder {
+        fn record(&self, phase: Phase, _elapsed: Duration) {
+            self.0.borrow_mut().push(phase);
+        }
+    }
+
+    let mut lines = TestLines::new("fn main() {}\n").build()?;
+    let recorder = Rc::new(Recorder::default());
+    lines.set_metrics_sink(Some(recorder.clone()));
+    lines.edit_buffer(&[(Selection::caret(0), "x")], EditType::InsertChars)?;
+    let phases = recorder.0.borrow().clone();
+    for phase in [Phase::Phantom, Phase::Layout, Phase::ScreenLines] {
+        assert!(phases.contains(&phase), "{phase:?}");
+    }
+    assert!(!phases.contains(&Phase::Diff));
+
+    // 重叠的编辑在diff前就被拒绝
+    recorder.0.borrow_mut().clear();
+    let edit = |start, end| TextEdit {
+        range:    Range::new(Position::new(0, start), Position::new(0, end)),
+        new_text: String::new()
+    };
+    assert!(
+        lines
+            .preview_text_edits(&[edit(0, 3), edit(2, 4)], None)
+            .is_err()
+    );
+    assert!(recorder.0.borrow().is_empty());
+    lines.preview_text_edits(&[edit(0, 1)], None)?;
+    assert_eq!(*recorder.0.borrow(), vec![Phase::Diff]);
+
+    lines.set_metrics_sink(None);
+    recorder.0.borrow_mut().clear();
+    lines.edit_buffer(&[(Selection::caret(0), "y")], EditType::InsertChars)?;
+    assert!(recorder.0.borrow().is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_inlay_hint_parts() -> Result<()> {
+    use doc::lines::ClickResult;
+    use floem::kurbo::Point;
+    use lsp_types::{InlayHintLabel, InlayHintLabelPart, InlayHintLabelPartTooltip};
+
+    let uri = Url::parse("file:///a.rs")?;
+    let part = |value: &str, location: Option<Location>| InlayHintLabelPart {
+        value: value.to_string(),
+        tooltip: location
+            .as_ref()
+            .map(|_| InlayHintLabelPartTooltip::String("struct Vec".to_string())),
+        location,
+        command: None
+    };
+    let mut hint = inlay_hint(1, 9, "");
+    hint.label = InlayHintLabel::LabelParts(vec![
+        part(": ", None),
+        part("Vec", Some(Location::new(uri, Range::default()))),
+        part("<i32>", None)
+    ]);
+    let code = "fn main() {\n    let a = vec![1];\n}\n";
+    let mut lines = TestLines::new(code).inlay_hint(hint).build()?;
+    check_visual_lines(&lines)?;
+    assert_eq!(
+        visual_line_structure(&lines)[1].2,
+        "    let a: Vec<i32>  = vec![1];"
+    );
+    let underlined = |lines: &doc::lines::DocLines| {
+        lines
+            .folded_line_of_origin_line(1)
+            .unwrap()
+            .text_layout
+            .phantom_text
+            .iter_phantom_text()
+            .filter(|x| x.under_line.is_some())
+            .map(|x| x.text.clone())
+            .collect::<Vec<_>>()
+    };
+
+    // "Vec"在第11列
+    let x = lines.text_layout_of_visual_line(1)?.text.hit_position(12).point.x;
+    let hover = lines.hover_inlay_hint(Point::new(x, 30.0))?.unwrap();
+    assert_eq!(hover.part, 1);
+    assert!(hover.is_clickable());
+    assert!(hover.tooltip.is_some());
+    assert_eq!(underlined(&lines), vec!["Vec".to_string()]);
+    check_visual_lines(&lines)?;
+    assert!(matches!(
+        lines.result_of_left_click(Point::new(x, 30.0))?,
+        ClickResult::MatchHint(_)
+    ));
+
+    // 不可点击的部分
+    let x = lines.text_layout_of_visual_line(1)?.text.hit_position(15).point.x;
+    let hover = lines.hover_inlay_hint(Point::new(x, 30.0))?.unwrap();
+    assert_eq!(hover.part, 2);
+    assert!(underlined(&lines).is_empty());
+
+    let x = lines.text_layout_of_visual_line(1)?.text.hit_position(5).point.x;
+    assert!(lines.hover_inlay_hint(Point::new(x, 30.0))?.is_none());
+    Ok(())
+}
+
+#[test]
+fn test_toggle_inlay_hints() -> Result<()> {
+    use doc::lines::{edit::EditType, selection::Selection};
+
+    let code = "fn main() {\n    let a = 1;\n}\n";
+    let mut lines = TestLines::new(code)
+        .inlay_hint(inlay_hint(1, 9, ": i32"))
+        .build()?;
+    let with_hints = visual_line_structure(&lines);
+    lines.set_inlay_hints_visible(false)?;
+    check_visual_lines(&lines)?;
+    let without_hints = visual_line_structure(&lines);
+    assert_eq!(without_hints[1].2, "    let a = 1;");
+
+    // 直接交换缓存的布局
+    lines.set_inlay_hints_visible(true)?;
+    assert_eq!(visual_line_structure(&lines), with_hints);
+    lines.set_inlay_hints_visible(false)?;
+    assert_eq!(visual_line_structure(&lines), without_hints);
+
+    // 编辑后缓存失效
+    lines.edit_buffer(&[(Selection::caret(0), "\n")], EditType::InsertNewline)?;
+    lines.set_inlay_hints_visible(true)?;
+    check_visual_lines(&lines)?;
+    assert_eq!(visual_line_structure(&lines)[2].2, "    let a: i32  = 1;");
+    Ok(())
+}
+
+#[test]
+fn test_fold_to_outline() -> Result<()> {
+    use lsp_types::{DocumentSymbol, SymbolKind};
+
+    #[allow(deprecated)]
+    let symbol = |kind, start: u32, end: u32, children: Vec<DocumentSymbol>| {
+        let range = Range::new(Position::new(start, 0), Position::new(end, 1));
+        DocumentSymbol {
+            name: String::new(),
+            detail: None,
+            kind,
+            tags: None,
+            deprecated: None,
+            range,
+            selection_range: range,
+            children: Some(children)
+        }
+    };
+    let code = concat!(
+        "impl A {\n    fn a() {\n        1\n    }\n}\n",
+        "fn b(\n    x: i32\n) {\n    2\n}\n"
+    );
+    let mut lines = TestLines::new(code)
+        .folding_range(folding_range(0, 8, 4, 0))
+        .folding_range(folding_range(1, 12, 3, 4))
+        .folding_range(folding_range(5, 5, 7, 0))
+        .folding_range(folding_range(7, 3, 9, 0))
+        .build()?;
+    let method = symbol(SymbolKind::METHOD, 1, 3, vec![]);
+    let symbols = [
+        symbol(SymbolKind::OBJECT, 0, 4, vec![method]),
+        symbol(SymbolKind::FUNCTION, 5, 9, vec![])
+    ];
+    lines.fold_to_outline_by_symbols(&symbols)?;
+    let folded: Vec<bool> = lines
+        .folding_ranges
+        .0
+        .iter()
+        .map(|x| x.status.is_folded())
+        .collect();
+    // 参数列表不折叠，签名可见
+    assert_eq!(folded, vec![false, true, false, true]);
+    check_visual_lines(&lines)?;
+    assert_eq!(lines.origin_folded_lines.len(), 7);
+    Ok(())
+}
+
+#[test]
+fn test_phantom_provider() -> Result<()> {
+    use std::{cell::Cell, rc::Rc};
+
+    use doc::lines::{
+        buffer::Buffer,
+        phantom_provider::PhantomProvider,
+        phantom_text::PhantomText,
+        testing::phantom_final_col
+    };
+
+    #[derive(Default)]
+    struct ReplResult {
+        calls: Cell<usize>,
+        value: Cell<i32>
+    }
+
+    impl PhantomProvider for ReplResult {
+        fn phantom_texts(&self, buffer: &Buffer, line: usize) -> Vec<PhantomText> {
+            self.calls.set(self.calls.get() + 1);
+            if line != 1 {
+                return Vec::new();
+            }
+            let col = buffer.line_content(line).unwrap().trim_end().len();
+            vec![PhantomText {
+                kind: PhantomTextKind::InlayHint,
+                col,
+                text: format!(" = {}", self.value.get()),
+                ..Default::default()
+            }]
+        }
+    }
+
+    let code = "fn main() {\n    let a = 1;\n}\n";
+    let mut lines = TestLines::new(code).build()?;
+    let provider = Rc::new(ReplResult::default());
+    lines.register_phantom_provider("repl", provider.clone())?;
+    check_visual_lines(&lines)?;
+    assert_phantom_at(&lines, 1, 14, " = 0", |x| *x == PhantomTextKind::InlayHint);
+
+    // 缓存未失效时不再调用
+    let calls = provider.calls.get();
+    lines.set_inlay_hints_visible(false)?;
+    lines.set_inlay_hints_visible(true)?;
+    assert_eq!(provider.calls.get(), calls);
+
+    provider.value.set(1);
+    assert!(lines.invalidate_phantom_provider("repl")?);
+    assert_phantom_at(&lines, 1, 14, " = 1", |x| *x == PhantomTextKind::InlayHint);
+    assert!(!lines.invalidate_phantom_provider("other")?);
+
+    assert!(lines.remove_phantom_provider("repl")?);
+    assert!(phantom_final_col(&lines, 1, |x| *x == PhantomTextKind::InlayHint)?.is_none());
+    Ok(())
+}
+
+#[test]
+fn test_rect_of_range() -> Result<()> {
+    let code = "fn main() {\n    let a = 1;\n    let a = 1;\n}\n";
+    let lines = TestLines::new(code)
+        .inlay_hint(inlay_hint(1, 9, ": i32"))
+        .build()?;
+    let rects = lines.rect_of_range(0..16)?;
+    assert_eq!(rects.len(), 2);
+    assert_eq!(rects[0].y1, rects[1].y0);
+    assert_eq!(rects[0].y1 - rects[0].y0, lines.screen_lines().line_height);
+    assert!(rects[1].width() > 0.0);
+
+    // "let a = 1"，第1行包含内嵌提示，第2行没有
+    let hinted = lines.rect_of_range(16..25)?;
+    let plain = lines.rect_of_range(31..40)?;
+    assert_eq!((hinted.len(), plain.len()), (1, 1));
+    assert_eq!(hinted[0].x0, plain[0].x0);
+    assert!(hinted[0].width() > plain[0].width());
+    assert_eq!(plain[0].y0 - hinted[0].y0, lines.screen_lines().line_height);
+
+    let empty = lines.rect_of_range(20..20)?;
+    assert_eq!(empty[0].width(), 0.0);
+    Ok(())
+}
+
+#[test]
+fn test_debug_position() -> Result<()> {
+    use doc::lines::{
+        DEBUG_FRAME_DECORATION_KEY, DEBUG_LINE_DECORATION_KEY,
+        testing::{EditOp, phantom_final_col, replay}
+    };
+    let code = "fn a() {\n    b();\n}\nfn b() {\n    let x = 1;\n}\n";
+    let mut lines = TestLines::new(code).build()?;
+    lines.set_debug_position(Some(4), &[1])?;
+    lines.set_debug_inline_values(vec![(4, "x = 1".to_string())])?;
+    let ranges = |lines: &doc::lines::DocLines, key: &str| {
+        lines
+            .decorations()
+            .get(key)
+            .map(|x| x.iter().map(|(iv, _)| (iv.start, iv.end)).collect::<Vec<_>>())
+            .unwrap_or_default()
+    };
+    assert_eq!(ranges(&lines, DEBUG_LINE_DECORATION_KEY), vec![(29, 43)]);
+    assert_eq!(ranges(&lines, DEBUG_FRAME_DECORATION_KEY), vec![(9, 17)]);
+    assert_eq!(
+        phantom_final_col(&lines, 4, |x| *x == PhantomTextKind::InlayHint)?,
+        Some((14, "  x = 1".to_string()))
+    );
+
+    // 在文件开头插入一行，高亮和变量值随行移动
+    replay(&mut lines, &[EditOp::Insert {
+        offset: 0,
+        text:   "//\n".to_string()
+    }])?;
+    assert_eq!(ranges(&lines, DEBUG_LINE_DECORATION_KEY), vec![(32, 46)]);
+    assert!(phantom_final_col(&lines, 5, |x| *x == PhantomTextKind::InlayHint)?.is_some());
+    assert!(phantom_final_col(&lines, 4, |x| *x == PhantomTextKind::InlayHint)?.is_none());
+
+    lines.debug_resume()?;
+    assert!(ranges(&lines, DEBUG_LINE_DECORATION_KEY).is_empty());
+    assert!(phantom_final_col(&lines, 5, |x| *x == PhantomTextKind::InlayHint)?.is_none());
+    check_visual_lines(&lines)?;
+    Ok(())
+}
+
+#[test]
+fn test_result_blocks() -> Result<()> {
+    use doc::lines::{
+        result_block::ResultLine,
+        testing::{EditOp, replay}
+    };
+    let output = |text: &str| ResultLine {
+        text: text.to_string(),
+        fg:   None,
+        bg:   None
+    };
+    let code = "a = 1\nprint(a)\nb = 2\n";
+    let mut lines = TestLines::new(code).build()?;
+    let line_height = lines.screen_lines().line_height;
+    let id = lines.add_result_block(1, vec![output("1"), output("done")], true)?;
+    let other = lines.add_result_block(1, vec![output("x")], false)?;
+
+    let y_of_line = |lines: &doc::lines::DocLines, line: usize| {
+        lines
+            .screen_lines()
+            .visual_lines
+            .iter()
+            .find(|x| x.visual_line.origin_line == line)
+            .map(|x| x.visual_line_y)
+            .unwrap()
+    };
+    let blocks = lines.screen_result_blocks()?;
+    assert_eq!(blocks.len(), 2);
+    assert_eq!(blocks[0].0, y_of_line(&lines, 1) + line_height);
+    assert_eq!(blocks[0].1.id, id);
+    assert_eq!(blocks[1].0, blocks[0].0 + line_height * 2.0);
+    // 块下方的行下移
+    assert_eq!(y_of_line(&lines, 2), blocks[1].0 + line_height);
+
+    assert!(lines.toggle_result_block(id));
+    assert!(!lines.toggle_result_block(other));
+    let blocks = lines.screen_result_blocks()?;
+    assert_eq!(blocks[0].1.visible_lines(), &[output("1")]);
+    assert_eq!(blocks[1].0, blocks[0].0 + line_height);
+    assert_eq!(y_of_line(&lines, 2), blocks[1].0 + line_height);
+
+    // 在行首插入不影响锚点所在的行
+    replay(&mut lines, &[EditOp::Insert {
+        offset: 6,
+        text:   "x\n".to_string()
+    }])?;
+    let blocks = lines.screen_result_blocks()?;
+    assert_eq!(blocks[0].0, y_of_line(&lines, 2) + line_height);
+
+    assert!(lines.remove_result_block(other));
+    assert_eq!(lines.screen_result_blocks()?.len(), 1);
+    assert_eq!(y_of_line(&lines, 3), y_of_line(&lines, 2) + line_height * 2.0);
+
+    lines.clear_result_blocks();
+    assert_eq!(y_of_line(&lines, 3), y_of_line(&lines, 2) + line_height);
+    Ok(())
+}
+
+#[test]
+fn test_peek_view() -> Result<()> {
+    use doc::lines::testing::{EditOp, replay};
+    use floem::kurbo::Rect;
+    let code = "fn a() {}\nfn b() {\n    1\n}\nfn c() {}\n";
+    let mut lines = TestLines::new(code).build()?;
+    let line_height = lines.screen_lines().line_height;
+    let id = lines.add_peek_view(1, 3)?;
+    assert_eq!(lines.peek_view_lines(id), Some((1, 3)));
+
+    let viewport = Rect::new(0.0, 0.0, 300.0, line_height * 10.0);
+    let screen_lines = lines.peek_screen_lines(id, viewport)?.unwrap();
+    let origin_lines: Vec<usize> = screen_lines
+        .visual_lines
+        .iter()
+        .map(|x| x.visual_line.origin_line)
+        .collect();
+    assert_eq!(origin_lines, [1, 2, 3]);
+    assert_eq!(screen_lines.visual_lines[0].visual_line_y, 0.0);
+    // 与主视图共用视觉行
+    assert_eq!(
+        screen_lines.visual_lines[1].visual_line,
+        lines.visual_lines[2]
+    );
+    // 滚动后只有范围内的行
+    let scrolled = viewport.with_origin((0.0, line_height * 2.0));
+    let screen_lines = lines.peek_screen_lines(id, scrolled)?.unwrap();
+    assert_eq!(screen_lines.visual_lines.len(), 1);
+    assert_eq!(screen_lines.visual_lines[0].visual_line.origin_line, 3);
+
+    // 在范围之前插入行，范围随文本移动；范围内插入行，范围变大
+    replay(&mut lines, &[
+        EditOp::Insert {
+            offset: 10,
+            text:   "x\n".to_string()
+        },
+        EditOp::Insert {
+            offset: 21,
+            text:   "    2\n".to_string()
+        }
+    ])?;
+    assert_eq!(lines.peek_view_lines(id), Some((2, 5)));
+    let screen_lines = lines.peek_screen_lines(id, viewport)?.unwrap();
+    assert_eq!(screen_lines.visual_lines.len(), 4);
+
+    assert!(lines.remove_peek_view(id));
+    assert!(lines.peek_screen_lines(id, viewport)?.is_none());
+    Ok(())
+}
+
+#[test]
+fn test_bracket_guides() -> Result<()> {
+    let code = "fn a() {\n    if b {\n        c(\n            1);\n    }\n}\n";
+    let lines = TestLines::new(code).build()?;
+    // 光标在if的块内
+    let cursor = code.find("c(").unwrap();
+    let guides: Vec<(usize, Vec<(usize, bool)>)> = lines
+        .screen_bracket_guides(cursor)?
+        .into_iter()
+        .map(|(info, guides)| {
+            let guides = guides.iter().map(|x| (x.column, x.active)).collect();
+            (info.visual_line.origin_line, guides)
+        })
+        .collect();
+    assert_eq!(guides[0], (0, vec![]));
+    assert_eq!(guides[1], (1, vec![(0, false)]));
+    assert_eq!(guides[2], (2, vec![(0, false), (4, true)]));
+    // c(...)的括号对之间没有行
+    assert_eq!(guides[3], (3, vec![(0, false), (4, true)]));
+    assert_eq!(guides[4], (4, vec![(0, false)]));
+    assert_eq!(guides[5], (5, vec![]));
+    Ok(())
+}
+
+#[test]
+fn test_phantom_kind_enabled() -> Result<()> {
+    use doc::lines::phantom_text::PhantomKindTag;
+    let code = "fn main() {\n    let a = 1;\n    let b = 2;\n    let c = 3;\n}\n";
+    let mut lines = TestLines::new(code)
+        .inlay_hint(inlay_hint(1, 9, ": i32"))
+        .inlay_hint(inlay_hint(3, 9, ": i32"))
+        .build()?;
+    assert!(lines.origin_lines[1].phantom.has_kind(PhantomKindTag::InlayHint));
+
+    lines.set_phantom_kind_enabled(PhantomKindTag::InlayHint, false)?;
+    assert!(!lines.is_phantom_kind_enabled(PhantomKindTag::InlayHint));
+    assert!(!lines.origin_lines[1].phantom.has_kind(PhantomKindTag::InlayHint));
+    // 只记录在有该种类的行上
+    let hidden: Vec<usize> = lines
+        .origin_lines
+        .iter()
+        .filter(|x| x.phantom.has_hidden_kind(PhantomKindTag::InlayHint))
+        .map(|x| x.line_index)
+        .collect();
+    assert_eq!(hidden, vec![1, 3]);
+    check_visual_lines(&lines)?;
+
+    lines.set_phantom_kind_enabled(PhantomKindTag::InlayHint, true)?;
+    check_visual_lines(&lines)?;
+    assert_phantom_at(&lines, 1, 9, ": i32 ", |x| {
+        *x == PhantomTextKind::InlayHint
+    });
+    assert_phantom_at(&lines, 3, 9, ": i32 ", |x| {
+        *x == PhantomTextKind::InlayHint
+    });
+    Ok(())
+}
+
+#[test]
+fn test_error_lens_truncate() -> Result<()> {
+    use doc::lines::{
+        ClickResult, error_lens::ELLIPSIS, testing::test_monospace_metrics
+    };
+    use floem::kurbo::Point;
+    let metrics = test_monospace_metrics();
+    let message = format!("{}\nhelp: remove it", "unused variable ".repeat(10));
+    let mut lines = TestLines::new("let a = 1;\nlet b = 2;\n")
+        .config(|x| x.error_lens_truncate = true)
+        .diagnostic(diagnostic(0, 4, 5, DiagnosticSeverity::WARNING, &message))
+        .monospace(metrics)
+        .build()?;
+    let lens = lines.screen_error_lens();
+    assert_eq!(lens.len(), 1);
+    assert_eq!(lens[0].line, 0);
+    assert!(lens[0].truncated);
+    assert!(lens[0].lines[0].text.starts_with("unused variable"));
+    assert!(lens[0].lines[0].text.ends_with(ELLIPSIS));
+
+    // 点击展开完整消息，再次点击收起
+    let point = Point::new(lens[0].x + 1.0, lens[0].y + 1.0);
+    let ClickResult::MatchDiagnostic(diag) = lines.result_of_left_click(point)?
+    else {
+        panic!("the error lens is not clicked");
+    };
+    assert_eq!(diag.message, message);
+    let lens = lines.screen_error_lens();
+    assert!(lens[0].expanded && !lens[0].truncated);
+    assert_eq!(lens[0].lines.len(), 2);
+    lines.result_of_left_click(point)?;
+    assert!(!lines.screen_error_lens()[0].expanded);
+    Ok(())
+}
+
+#[test]
+fn test_error_lens_more() -> Result<()> {
+    use doc::lines::{error_lens::SEPARATOR, testing::test_monospace_metrics};
+    use floem::kurbo::Point;
+    let metrics = test_monospace_metrics();
+    let mut lines = TestLines::new("let a = b + c;\n")
+        .config(|x| x.error_lens_max_count = 2)
+        .monospace(metrics)
+        .diagnostic(diagnostic(0, 12, 13, DiagnosticSeverity::WARNING, "unused c"))
+        .diagnostic(diagnostic(0, 8, 9, DiagnosticSeverity::ERROR, "b not found"))
+        .diagnostic(diagnostic(0, 4, 5, DiagnosticSeverity::WARNING, "unused a"))
+        .diagnostic(diagnostic(0, 4, 5, DiagnosticSeverity::WARNING, "unused a"))
+        .build()?;
+    let lens = lines.screen_error_lens();
+    assert_eq!(lens.len(), 1);
+    // 按严重程度、列排序，重复的合并
+    assert_eq!(lens[0].severity, DiagnosticSeverity::ERROR);
+    assert_eq!(lens[0].count, 3);
+    assert_eq!(
+        lens[0].lines[0].text,
+        format!("b not found{SEPARATOR}unused a +1 more")
+    );
+
+    let point = Point::new(lens[0].x + 1.0, lens[0].y + 1.0);
+    let messages: Vec<String> = lines
+        .hover_error_lens(point)
+        .into_iter()
+        .map(|x| x.message)
+        .collect();
+    assert_eq!(messages, ["b not found", "unused a", "unused c"]);
+    assert!(lines.hover_error_lens(Point::new(1.0, 1.0)).is_empty());
+    // 只在消息的宽度内
+    assert!(lens[0].width > 0.0);
+    let after = Point::new(lens[0].x + lens[0].width + 1.0, point.y);
+    assert!(lines.hover_error_lens(after).is_empty());
+
+    lines.result_of_left_click(point)?;
+    assert_eq!(lines.screen_error_lens()[0].lines.len(), 3);
+    Ok(())
+}
+
+#[test]
+fn test_lazy_layout() -> Result<()> {
+    use floem::kurbo::Rect;
+    let code = "let a = 1;\n".repeat(400);
+    let mut lines = TestLines::new(code)
+        .config(|x| x.lazy_layout = true)
+        .viewport(Rect::new(0.0, 0.0, 600.0, 230.0))
+        .build()?;
+    let estimated = |lines: &doc::lines::DocLines, line: usize| -> Result<bool> {
+        Ok(lines.folded_line_of_origin_line(line)?.text_layout.text.is_estimated())
+    };
+    assert!(!estimated(&lines, 0)?);
+    assert!(estimated(&lines, 300)?);
+    // 估算的行也有视觉行，滚动条的高度不变
+    assert_eq!(lines.visual_lines.len(), 401);
+
+    lines.update_viewport_by_scroll(Rect::new(0.0, 6900.0, 600.0, 7130.0));
+    assert!(!estimated(&lines, 300)?);
+    check_visual_lines(&lines)?;
+
+    assert!(lines.ensure_lines_laid_out(390, 399)?);
+    assert!(!estimated(&lines, 395)?);
+    assert!(!lines.ensure_lines_laid_out(390, 399)?);
+    Ok(())
+}
+
+#[test]
+fn test_degenerate_screen_lines() -> Result<()> {
+    use doc::lines::cursor::CursorMode;
+    use floem::kurbo::{Point, Rect};
+    let mode = CursorMode::Normal(0);
+    // 空文档只有一行
+    let lines = TestLines::new("").build()?;
+    assert_eq!(lines.screen_lines().visual_lines.len(), 1);
+    assert!(lines.screen_lines().visual_line_of_y(1000.0).is_some());
+    assert_eq!(lines.buffer_offset_of_click(&mode, Point::new(50.0, 1000.0))?.0, 0);
+
+    // 所有行都被折叠为一行
+    let code = "fn main() {\n    let a = 1;\n}";
+    let mut lines = TestLines::new(code)
+        .folding_range(folding_range(0, 10, 2, 0))
+        .fold(fold_at(0, 10))
+        .build()?;
+    check_visual_lines(&lines)?;
+    assert_eq!(lines.visual_lines.len(), 1);
+    assert_eq!(lines.last_visual_line().map(|x| x.origin_line), Some(0));
+
+    // 视口完全超出文档
+    lines.update_viewport_by_scroll(Rect::new(0.0, 2300.0, 600.0, 2840.0));
+    assert!(lines.screen_lines().is_empty());
+    assert!(lines.screen_lines().visual_line_of_y(2400.0).is_none());
+    assert!(lines.screen_lines().line_interval().is_err());
+    let (offset, _) = lines.buffer_offset_of_click(&mode, Point::new(0.0, 2400.0))?;
+    assert_eq!(offset, 0);
+    assert!(lines.hover_error_lens(Point::new(0.0, 2400.0)).is_empty());
+    Ok(())
+}
@@ -188,6 +188,7 @@ fn test_buffer_edit() -> Result<()> {
     let deltas = lines.do_edit_buffer(
         &mut cursor,
         &EditCommand::InsertNewLine,
+        1,
         false,
         &mut register,
         true
@@ -232,3 +233,1424 @@ fn cursor_normal() -> Cursor {
     let mode = CursorMode::Normal(183);
     Cursor::new(mode, None, None)
 }
+
+#[test]
+fn test_batch_offset_position() -> Result<()> {
+    use doc::lines::{RopeTextPosition, buffer::rope_text::RopeTextVal};
+    let text = RopeTextVal::new("ab😀c\nfn a() {}\r\nend".into());
+    let offsets = [15, 0, 7, 2, 6, 8, 22, 20];
+    let positions = text.offsets_to_positions(&offsets)?;
+    for (offset, position) in offsets.iter().zip(positions.iter()) {
+        assert_eq!(text.offset_to_position(*offset)?, *position);
+    }
+    let rs = text.positions_to_offsets(&positions)?;
+    assert_eq!(rs, offsets);
+    Ok(())
+}
+
+#[test]
+fn test_utf16_column_cache() -> Result<()> {
+    use doc::lines::{
+        RopeTextPosition, buffer::Buffer, buffer::rope_text::RopeTextVal,
+        edit::EditType
+    };
+    let mut buffer = Buffer::new("ab😀c\nfn a() {}\nend");
+    let position = buffer.offset_to_position(6)?;
+    assert_eq!(position, Position::new(0, 4));
+    assert_eq!(buffer.offset_of_position(&position)?, 6);
+
+    buffer.edit([(Selection::caret(0), "😀\n")], EditType::InsertChars);
+    let text = RopeTextVal::new(buffer.text().clone());
+    for offset in 0..buffer.len() {
+        let position = buffer.offset_to_position(offset)?;
+        assert_eq!(position, text.offset_to_position(offset)?);
+        assert_eq!(
+            buffer.offset_of_position(&position)?,
+            text.offset_of_position(&position)?
+        );
+    }
+    // 字符中间、超出行尾的列与不缓存时一致
+    for position in [Position::new(1, 3), Position::new(1, 20), Position::new(3, 9)] {
+        assert_eq!(
+            buffer.offset_of_position(&position)?,
+            text.offset_of_position(&position)?
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn test_occurrence_count() -> Result<()> {
+    use doc::lines::{buffer::Buffer, edit::EditType, occurrence::Occurrences};
+    let mut buffer = Buffer::new("let a = 1;\nlet b = a;\nlet ab = a + b;\n");
+    let mut occurrences = Occurrences::word("a", buffer.text())?;
+    assert_eq!(occurrences.matches().len(), 3);
+    let count = occurrences.count(4);
+    assert_eq!(count.to_string(), "1 of 3");
+    assert_eq!(occurrences.count(0).current, None);
+
+    let (_, delta, _) = buffer
+        .edit([(Selection::caret(11), "a;\n")], EditType::InsertChars)
+        .unwrap();
+    occurrences.apply_delta(&delta);
+    assert!(occurrences.is_dirty());
+    occurrences.refresh(buffer.text());
+    assert_eq!(occurrences.count(4).to_string(), "1 of 4");
+    assert_eq!(occurrences.count(11).to_string(), "2 of 4");
+    let expected = Occurrences::word("a", buffer.text())?;
+    assert_eq!(occurrences.matches(), expected.matches());
+    Ok(())
+}
+
+#[test]
+fn test_reload_minimal_delta() -> Result<()> {
+    use doc::lines::buffer::Buffer;
+    let mut buffer = Buffer::new("fn a() {}\nlet 😀 = 1;\nend\n");
+    let (_, delta, inval_lines) =
+        buffer.reload("fn a() {}\nlet 😁 = 2;\nend\n".into(), true);
+    let (iv, new_len) = delta.summary();
+    assert_eq!(iv, Interval::new(14, 22));
+    assert_eq!(new_len, 8);
+    assert_eq!(inval_lines.start_line, 1);
+    assert_eq!(inval_lines.inval_count, 0);
+    assert_eq!(buffer.to_string(), "fn a() {}\nlet 😁 = 2;\nend\n");
+    Ok(())
+}
+
+#[test]
+fn test_caret_infos() -> Result<()> {
+    custom_utils::logger::logger_stdout_debug();
+    let lines = init_main_2()?;
+    let mut selection = Selection::new();
+    selection.add_region(doc::lines::selection::SelRegion::new(0, 3, None));
+    selection.add_region(doc::lines::selection::SelRegion::caret(20));
+    let cursor = Cursor::new(CursorMode::Insert(selection), None, None);
+    let infos = lines.caret_infos(&cursor);
+    assert_eq!(infos.len(), 2);
+    assert_eq!(infos[0].offset, 3);
+    assert_eq!(infos[0].region, (0, 3));
+    assert!(infos[0].is_visible());
+    assert_eq!(infos[1].offset, 20);
+    assert_eq!(infos[1].visual_line.origin_line, 1);
+    let rect = infos[1].rect.unwrap();
+    assert_eq!(rect.height(), lines.line_height as f64);
+    assert_eq!(rect.width(), 0.0);
+    Ok(())
+}
+
+#[test]
+fn test_ime_preedit_segments() -> Result<()> {
+    use doc::lines::ime::{ImePreedit, PreeditClause, PreeditSegment};
+    use crate::lines_util::check_line_final_col;
+    let mut lines = init_main_2()?;
+    // fn main() {|
+    let preedit = ImePreedit::new(11, vec![
+        PreeditSegment::new("日本", PreeditClause::Converted),
+        PreeditSegment::new("ご\nを", PreeditClause::Target),
+    ])
+    .with_cursor(6);
+    lines.set_ime_preedit(preedit)?;
+    let line = &lines.origin_folded_lines[0];
+    check_line_final_col(&line.text_layout.phantom_text, "fn main() {日本ご↵を ");
+    assert!(lines.ime_cursor_rect()?.is_some());
+    assert_eq!(lines.ime_preedit().unwrap().display_cursor(), Some(6));
+
+    lines.clear_ime_preedit()?;
+    let line = &lines.origin_folded_lines[0];
+    check_line_final_col(&line.text_layout.phantom_text, "fn main() { ");
+    assert!(lines.ime_cursor_rect()?.is_none());
+    Ok(())
+}
+
+#[test]
+fn test_caret_style() -> Result<()> {
+    use doc::lines::cursor::CaretStyle;
+    custom_utils::logger::logger_stdout_debug();
+    let lines = init_main_2()?;
+    let cursor = Cursor::new(CursorMode::Normal(0), None, None);
+    let info = &lines.caret_infos(&cursor)[0];
+    assert_eq!(info.style, CaretStyle::Block);
+    let block = info.shape_rect.unwrap();
+    assert!(block.width() > 0.0);
+    assert_eq!(block.height(), lines.line_height as f64);
+
+    let info = &lines.caret_infos(&cursor_insert(0, 0))[0];
+    assert_eq!(info.style, CaretStyle::Bar);
+    assert_eq!(info.shape_rect.unwrap().x0, block.x0);
+    Ok(())
+}
+
+#[test]
+fn test_snippet_session() -> Result<()> {
+    use doc::lines::{LINKED_DECORATION_KEY, edit::EditType};
+    custom_utils::logger::logger_stdout_debug();
+    let mut lines = init_main_2()?;
+    let start = lines.buffer().len();
+    lines.edit_buffer(
+        &[(Selection::caret(start), "let x = x;")],
+        EditType::InsertChars
+    )?;
+    // let |x| = |x|;
+    let selection = lines
+        .start_snippet_session(vec![
+            vec![
+                Interval::new(start + 4, start + 5),
+                Interval::new(start + 8, start + 9),
+            ],
+            vec![Interval::new(start + 10, start + 10)],
+        ])?
+        .unwrap();
+    assert_eq!(selection.len(), 2);
+    assert!(lines.decorations().get(LINKED_DECORATION_KEY).is_some());
+
+    lines.edit_buffer(
+        &[(Selection::caret(start + 5), "yz")],
+        EditType::InsertChars
+    )?;
+    assert_eq!(
+        lines.buffer().text().slice_to_cow(start..lines.buffer().len()),
+        "let xyz = xyz;"
+    );
+    let groups = lines.linked_ranges().unwrap().groups().to_vec();
+    assert_eq!(groups[0][1], Interval::new(start + 10, start + 13));
+
+    let selection = lines.next_snippet_placeholder()?.unwrap();
+    assert_eq!(selection.min_offset(), start + 14);
+    assert!(lines.next_snippet_placeholder()?.is_none());
+    assert!(lines.linked_ranges().is_none());
+    assert!(lines.decorations().get(LINKED_DECORATION_KEY).is_none());
+    Ok(())
+}
+
+#[test]
+fn test_rename_session() -> Result<()> {
+    use doc::lines::{LINKED_DECORATION_KEY, edit::EditType};
+    use lsp_types::Range;
+    let code = "let a = 1;\nlet b = a;\n";
+    let mut lines = init_empty()?;
+    lines.init_buffer(code.into())?;
+    let ranges = vec![
+        Range::new(Position::new(0, 4), Position::new(0, 5)),
+        Range::new(Position::new(1, 8), Position::new(1, 9)),
+    ];
+    let start = |lines: &mut doc::lines::DocLines| -> Result<()> {
+        let selection = lines.start_rename_session(ranges.clone())?.unwrap();
+        assert_eq!(selection.len(), 2);
+        assert_eq!(lines.rename_session().unwrap().original, "a");
+        Ok(())
+    };
+    start(&mut lines)?;
+    lines.edit_buffer(&[(Selection::caret(5), "x")], EditType::InsertChars)?;
+    lines.edit_buffer(&[(Selection::caret(6), "y")], EditType::Other)?;
+    assert_eq!(lines.buffer().text().to_string(), "let axy = 1;\nlet b = axy;\n");
+
+    // 取消时撤销整个会话
+    let mut cursor = Cursor::new(CursorMode::Insert(Selection::caret(7)), None, None);
+    let mut register = Register::default();
+    lines.cancel_rename_session(&mut cursor, &mut register)?;
+    assert_eq!(lines.buffer().text().to_string(), code);
+    assert!(lines.rename_session().is_none());
+    assert!(lines.decorations().get(LINKED_DECORATION_KEY).is_none());
+
+    start(&mut lines)?;
+    lines.edit_buffer(&[(Selection::caret(5), "z")], EditType::InsertChars)?;
+    lines.commit_rename_session()?;
+    assert!(lines.linked_ranges().is_none());
+    assert_eq!(lines.buffer().text().to_string(), "let az = 1;\nlet b = az;\n");
+    Ok(())
+}
+
+#[test]
+fn test_decoration_spans() -> Result<()> {
+    use doc::lines::{decoration::Decoration, edit::EditType};
+    use floem::peniko::Color;
+    let mut lines = init_empty()?;
+    lines.init_buffer("let a = b + c;\n".into())?;
+    let style = Decoration {
+        fg_color: Some(Color::rgb8(255, 0, 0)),
+        under_line: Some(Color::rgb8(0, 255, 0)),
+        ..Decoration::default()
+    };
+    let mut builder = SpansBuilder::new(lines.buffer().len());
+    builder.add_span(Interval::new(8, 9), style);
+    lines.set_decoration_spans("coverage", builder.build())?;
+    // 长度与文本不一致
+    assert!(lines.set_decoration_spans("x", SpansBuilder::new(1).build()).is_err());
+
+    let color_of_col = |lines: &doc::lines::DocLines, col: usize| -> Result<_> {
+        let text = &lines.folded_line_of_origin_line(0)?.text_layout.text;
+        Ok(text.line().attrs_list().get_span(col).color_opt)
+    };
+    assert_ne!(color_of_col(&lines, 8)?, color_of_col(&lines, 4)?);
+    assert!(!lines.folded_line_of_origin_line(0)?.text_layout.extra_style.is_empty());
+
+    // 编辑后随文本移动
+    lines.edit_buffer(&[(Selection::caret(0), "  ")], EditType::InsertChars)?;
+    let spans = lines.decorations().get("coverage").unwrap();
+    let moved: Vec<Interval> = spans.iter().map(|(iv, _)| iv).collect();
+    assert_eq!(moved, [Interval::new(10, 11)]);
+    assert_ne!(color_of_col(&lines, 10)?, color_of_col(&lines, 8)?);
+    Ok(())
+}
+
+#[test]
+fn test_search() -> Result<()> {
+    use doc::lines::{SEARCH_DECORATION_KEY, edit::EditType, search::SearchQuery};
+    use floem::reactive::SignalGet;
+    let mut lines = init_empty()?;
+    lines.init_buffer("let foo = Foo::new();\nfoobar(foo);\n".into())?;
+    lines.set_search(Some(SearchQuery::literal("foo")))?;
+    let starts = |lines: &doc::lines::DocLines| -> Vec<usize> {
+        let matches = lines.signal_search_matches().get_untracked();
+        matches.iter().map(|x| x.start).collect()
+    };
+    assert_eq!(starts(&lines), [4, 22, 29]);
+    assert!(lines.decorations().get(SEARCH_DECORATION_KEY).is_some());
+
+    // 全词、忽略大小写
+    lines.set_search(Some(SearchQuery {
+        pattern: "foo".to_string(),
+        whole_word: true,
+        ..SearchQuery::default()
+    }))?;
+    assert_eq!(starts(&lines), [4, 10, 29]);
+    let count = lines.search_count(11).map(|x| x.to_string());
+    assert_eq!(count.as_deref(), Some("2 of 3"));
+    // 无效的正则不改变查找
+    let invalid = SearchQuery {
+        pattern: "(".to_string(),
+        regex: true,
+        ..SearchQuery::default()
+    };
+    assert!(lines.set_search(Some(invalid)).is_err());
+    assert_eq!(starts(&lines), [4, 10, 29]);
+
+    // 以光标为起点，首尾循环
+    assert_eq!(lines.next_match(5).map(|x| x.start), Some(10));
+    assert_eq!(lines.next_match(30).map(|x| x.start), Some(4));
+    assert_eq!(lines.prev_match(13).map(|x| x.start), Some(4));
+    assert_eq!(lines.prev_match(4).map(|x| x.start), Some(29));
+
+    // 编辑后更新匹配项
+    lines.edit_buffer(&[(Selection::caret(0), "foo ")], EditType::InsertChars)?;
+    assert_eq!(starts(&lines), [0, 8, 14, 33]);
+
+    lines.set_search(None)?;
+    assert!(starts(&lines).is_empty());
+    assert!(lines.decorations().get(SEARCH_DECORATION_KEY).is_none());
+    Ok(())
+}
+
+#[test]
+fn test_replace() -> Result<()> {
+    use doc::lines::search::SearchQuery;
+    let mut lines = init_empty()?;
+    lines.init_buffer("foo(1);\nfoo(22);\nfoo(3);\n".into())?;
+    lines.set_search(Some(SearchQuery {
+        pattern: r"foo\((\d+)\)".to_string(),
+        regex: true,
+        case_sensitive: true,
+        ..SearchQuery::default()
+    }))?;
+    let mut register = Register::default();
+    let mut cursor = cursor_insert(2, 2);
+
+    // 替换光标后的匹配，并选中下一个匹配
+    let rs = lines.replace_next(&mut cursor, "bar($1, 0)")?;
+    assert_eq!(rs.len(), 1);
+    assert_eq!(rs[0].2.start_line, 1);
+    assert_eq!(
+        lines.buffer().text().to_string(),
+        "foo(1);\nbar(22, 0);\nfoo(3);\n"
+    );
+    assert_eq!(
+        cursor.mode(),
+        &CursorMode::Insert(Selection::region(20, 26))
+    );
+
+    // 全部替换只有一个撤销步骤
+    lines.replace_all(&mut cursor, "baz($1)")?;
+    assert_eq!(
+        lines.buffer().text().to_string(),
+        "baz(1);\nbar(22, 0);\nbaz(3);\n"
+    );
+    assert!(lines.search().is_some_and(|x| x.matches().is_empty()));
+    assert!(lines.replace_all(&mut cursor, "baz")?.is_empty());
+    lines.do_edit_buffer(
+        &mut cursor,
+        &EditCommand::Undo,
+        1,
+        false,
+        &mut register,
+        false
+    )?;
+    assert_eq!(
+        lines.buffer().text().to_string(),
+        "foo(1);\nbar(22, 0);\nfoo(3);\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_search_scope() -> Result<()> {
+    use doc::lines::{edit::EditType, search::SearchQuery, selection::SelRegion};
+    use floem::reactive::SignalGet;
+    let mut lines = init_empty()?;
+    lines.init_buffer("a a\na a\na a\n".into())?;
+    lines.set_search(Some(SearchQuery::literal("a")))?;
+    let starts = |lines: &doc::lines::DocLines| -> Vec<usize> {
+        let matches = lines.signal_search_matches().get_untracked();
+        matches.iter().map(|x| x.start).collect()
+    };
+    assert_eq!(starts(&lines), [0, 2, 4, 6, 8, 10]);
+
+    // 第一行和第三行的选区，光标被忽略
+    let mut selection = Selection::new();
+    selection.add_region(SelRegion::new(0, 3, None));
+    selection.add_region(SelRegion::caret(5));
+    selection.add_region(SelRegion::new(9, 11, None));
+    lines.set_search_scope(Some(&selection))?;
+    assert_eq!(starts(&lines), [0, 2, 10]);
+    assert_eq!(lines.next_match(3).map(|x| x.start), Some(10));
+    assert_eq!(lines.prev_match(0).map(|x| x.start), Some(10));
+    let count = lines.search_count(10).map(|x| x.to_string());
+    assert_eq!(count.as_deref(), Some("3 of 3"));
+
+    // 范围随编辑移动，更换查找条件后保留范围
+    lines.edit_buffer(&[(Selection::caret(0), "a")], EditType::InsertChars)?;
+    assert_eq!(starts(&lines), [0, 1, 3, 11]);
+    lines.set_search(Some(SearchQuery::literal("a a")))?;
+    assert_eq!(starts(&lines), [1]);
+
+    // 全部替换只替换范围内的匹配
+    lines.set_search(Some(SearchQuery::literal("a")))?;
+    let mut cursor = cursor_insert(0, 0);
+    lines.replace_all(&mut cursor, "b")?;
+    assert_eq!(lines.buffer().text().to_string(), "bb b\na a\na b\n");
+
+    lines.set_search_scope(None)?;
+    assert_eq!(starts(&lines), [5, 7, 9]);
+    Ok(())
+}
+
+#[test]
+fn test_add_selection_to_next_match() -> Result<()> {
+    let mut lines = init_empty()?;
+    lines.init_buffer("foo bar\nfoo(foo)\n".into())?;
+    let regions = |cursor: &Cursor| -> Vec<(usize, usize)> {
+        match cursor.mode() {
+            CursorMode::Insert(selection) => {
+                selection.regions().iter().map(|x| (x.min(), x.max())).collect()
+            },
+            _ => Vec::new()
+        }
+    };
+    // 光标先扩展为所在的单词
+    let mut cursor = cursor_insert(9, 9);
+    let found = lines.add_selection_to_next_match(&mut cursor);
+    assert_eq!(found, Some(Interval::new(8, 11)));
+    assert_eq!(regions(&cursor), [(8, 11)]);
+
+    let found = lines.add_selection_to_next_match(&mut cursor);
+    assert_eq!(found, Some(Interval::new(12, 15)));
+    assert_eq!(regions(&cursor), [(8, 11), (12, 15)]);
+    // 回到开头继续查找
+    let found = lines.add_selection_to_next_match(&mut cursor);
+    assert_eq!(found, Some(Interval::new(0, 3)));
+    assert_eq!(regions(&cursor), [(0, 3), (8, 11), (12, 15)]);
+    // 全部选中后不再变化
+    assert_eq!(lines.add_selection_to_next_match(&mut cursor), None);
+    assert_eq!(regions(&cursor).len(), 3);
+
+    // 两侧都不是单词
+    let mut cursor = cursor_insert(16, 16);
+    assert_eq!(lines.add_selection_to_next_match(&mut cursor), None);
+
+    // 由光标扩展的单词按整词查找，选中的文本则不限
+    lines.init_buffer("foo foobar foo\n".into())?;
+    let mut cursor = cursor_insert(1, 1);
+    lines.add_selection_to_next_match(&mut cursor);
+    let found = lines.add_selection_to_next_match(&mut cursor);
+    assert_eq!(found, Some(Interval::new(11, 14)));
+    let mut cursor = cursor_insert(0, 3);
+    let found = lines.add_selection_to_next_match(&mut cursor);
+    assert_eq!(found, Some(Interval::new(4, 7)));
+    Ok(())
+}
+
+#[test]
+fn test_flash_range() -> Result<()> {
+    use std::time::Duration;
+
+    use doc::lines::{FLASH_DECORATION_KEY, decoration::Decoration, edit::EditType};
+    use floem::peniko::Color;
+    let mut lines = init_empty()?;
+    lines.init_buffer("let a = b + c;\n".into())?;
+    let style = Decoration {
+        fg_color:   None,
+        bg_color:   Some(Color::rgba8(0, 0, 255, 64)),
+        under_line: None,
+        wave_line:  None
+    };
+    lines.flash_range(Interval::new(8, 13), style, Duration::from_millis(500))?;
+    assert!(lines.decorations().get(FLASH_DECORATION_KEY).is_some());
+
+    lines.edit_buffer(&[(Selection::caret(0), "  ")], EditType::InsertChars)?;
+    assert_eq!(lines.flashes()[0].range, Interval::new(10, 15));
+
+    assert!(!lines.tick_flashes(Duration::from_millis(300))?);
+    assert!(lines.tick_flashes(Duration::from_millis(300))?);
+    assert!(lines.flashes().is_empty());
+    assert!(lines.decorations().get(FLASH_DECORATION_KEY).is_none());
+    Ok(())
+}
+
+#[test]
+fn test_whitespace_only_edit() -> Result<()> {
+    use doc::lines::edit::EditType;
+    let mut lines = init_empty()?;
+    lines.init_buffer("let a = 1;\nlet b = 2;\n".into())?;
+    lines.edit_buffer(&[(Selection::caret(0), "    ")], EditType::InsertChars)?;
+    assert!(lines.buffer().is_last_edit_whitespace_only());
+
+    // 删除换行也只改动了空白
+    lines.edit_buffer(&[(Selection::region(14, 15), " ")], EditType::Other)?;
+    assert_eq!(lines.buffer().text().to_string(), "    let a = 1; let b = 2;\n");
+    assert!(lines.buffer().is_last_edit_whitespace_only());
+
+    lines.edit_buffer(&[(Selection::region(12, 13), "2")], EditType::Other)?;
+    assert!(!lines.buffer().is_last_edit_whitespace_only());
+
+    lines.edit_buffer(&[(Selection::region(0, 4), "")], EditType::Delete)?;
+    assert!(lines.buffer().is_last_edit_whitespace_only());
+    Ok(())
+}
+
+#[test]
+fn test_enter_between_brackets() -> Result<()> {
+    let mut lines = init_empty()?;
+    lines.init_buffer("fn a() {}\n".into())?;
+    let indent = lines.buffer().indent_unit().to_string();
+    let mut cursor = cursor_insert(8, 8);
+    let mut register = Register::default();
+    let deltas = lines.do_edit_buffer(
+        &mut cursor,
+        &EditCommand::InsertNewLine,
+        1,
+        false,
+        &mut register,
+        true
+    )?;
+    // 一次编辑
+    assert_eq!(deltas.len(), 1);
+    assert_eq!(
+        lines.buffer().text().to_string(),
+        format!("fn a() {{\n{indent}\n}}\n")
+    );
+    assert_eq!(cursor.offset(), 9 + indent.len());
+
+    let mut config = lines.config.clone();
+    config.enter_between_brackets.disabled = true;
+    lines.update_config(config)?;
+    lines.init_buffer("fn a() {}\n".into())?;
+    let mut cursor = cursor_insert(8, 8);
+    lines.do_edit_buffer(
+        &mut cursor,
+        &EditCommand::InsertNewLine,
+        1,
+        false,
+        &mut register,
+        true
+    )?;
+    assert_eq!(
+        lines.buffer().text().to_string(),
+        format!("fn a() {{\n{indent}}}\n")
+    );
+    Ok(())
+}
+
+#[test]
+fn test_smart_backspace() -> Result<()> {
+    let mut lines = init_empty()?;
+    let mut register = Register::default();
+    let mut backspace = |lines: &mut doc::lines::DocLines, offset: usize| {
+        let mut cursor = cursor_insert(offset, offset);
+        lines.do_edit_buffer(
+            &mut cursor,
+            &EditCommand::DeleteBackward,
+            1,
+            false,
+            &mut register,
+            true
+        )
+    };
+    lines.init_buffer("a {\n        x\n}\n".into())?;
+    backspace(&mut lines, 12)?;
+    assert_eq!(lines.buffer().text().to_string(), "a {\n    x\n}\n");
+
+    lines.init_buffer("f()\n".into())?;
+    backspace(&mut lines, 2)?;
+    assert_eq!(lines.buffer().text().to_string(), "f\n");
+
+    let mut config = lines.config.clone();
+    config.backspace.indent = false;
+    config.backspace.pairs = false;
+    lines.update_config(config)?;
+    lines.init_buffer("f()\n".into())?;
+    backspace(&mut lines, 2)?;
+    assert_eq!(lines.buffer().text().to_string(), "f)\n");
+    lines.init_buffer("a {\n        x\n}\n".into())?;
+    backspace(&mut lines, 12)?;
+    assert_eq!(lines.buffer().text().to_string(), "a {\n       x\n}\n");
+    Ok(())
+}
+
+#[test]
+fn test_auto_surround() -> Result<()> {
+    use doc::lines::{
+        cursor::{Cursor, CursorMode},
+        selection::SelRegion
+    };
+    let mut lines = init_empty()?;
+    lines.init_buffer("let a = b + c;\n".into())?;
+    let mut selection = Selection::new();
+    selection.add_region(SelRegion::new(8, 9, None));
+    selection.add_region(SelRegion::new(12, 13, None));
+    let mut cursor = Cursor::new(CursorMode::Insert(selection), None, None);
+    lines.do_insert_buffer(&mut cursor, "(")?;
+    assert_eq!(lines.buffer().text().to_string(), "let a = (b) + (c);\n");
+    // 选区仍是原文本
+    let CursorMode::Insert(selection) = cursor.mode() else {
+        panic!("{:?}", cursor.mode());
+    };
+    let regions: Vec<_> =
+        selection.regions().iter().map(|x| (x.min(), x.max())).collect();
+    assert_eq!(regions, vec![(9, 10), (15, 16)]);
+
+    // 整行选区，括号各占一行
+    lines.init_buffer("fn a() {\n    b();\n    c();\n}\n".into())?;
+    let mut cursor = cursor_insert(9, 27);
+    lines.do_insert_buffer(&mut cursor, "{")?;
+    assert_eq!(
+        lines.buffer().text().to_string(),
+        "fn a() {\n    {\n    b();\n    c();\n    }\n}\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_linked_ranges() -> Result<()> {
+    use doc::lines::edit::EditType;
+    custom_utils::logger::logger_stdout_debug();
+    let mut lines = init_main_2()?;
+    let start = lines.buffer().len();
+    lines.edit_buffer(
+        &[(Selection::caret(start), "<div></div>")],
+        EditType::InsertChars
+    )?;
+    let line = lines.buffer().last_line() as u32;
+    let col = lines.buffer().offset_to_line_col(start)?.1 as u32;
+    lines.set_linked_ranges(
+        vec![
+            lsp_types::Range::new(
+                Position::new(line, col + 1),
+                Position::new(line, col + 4)
+            ),
+            lsp_types::Range::new(
+                Position::new(line, col + 7),
+                Position::new(line, col + 10)
+            ),
+        ],
+        Some(r"[a-z]+")
+    )?;
+    lines.edit_buffer(
+        &[(Selection::region(start + 1, start + 4), "span")],
+        EditType::InsertChars
+    )?;
+    let content = || lines.buffer().text().slice_to_cow(start..lines.buffer().len());
+    assert_eq!(content(), "<span></span>");
+
+    // break the word pattern
+    lines.edit_buffer(
+        &[(Selection::caret(start + 5), " ")],
+        EditType::InsertChars
+    )?;
+    assert!(lines.linked_ranges().is_none());
+    Ok(())
+}
+
+#[test]
+fn test_copy_mode() -> Result<()> {
+    use doc::lines::clipboard::CopyMode;
+    custom_utils::logger::logger_stdout_debug();
+    let mut lines = init_main_2()?;
+    //  2|   if true {...} else {\n
+    lines.update_folding_ranges(folded_v1().into())?;
+    let start = lines.buffer().offset_of_line(1)?;
+    let end = lines.buffer().offset_of_line(7)?;
+
+    let underlying = lines.copy_text(start, end, CopyMode::Underlying)?;
+    assert!(underlying.contains("println"));
+
+    let visible = lines.copy_text(start, end, CopyMode::Visible)?;
+    assert!(visible.starts_with("    if true {...} else {\n"));
+    assert!(!visible.contains(": A"));
+
+    let with_hints = lines.copy_text(start, end, CopyMode::WithInlayHints)?;
+    assert!(with_hints.contains("let a: A  = A;"));
+    Ok(())
+}
+
+#[test]
+fn test_block_paste() -> Result<()> {
+    use floem::views::editor::core::{mode::VisualMode, register::RegisterData};
+    custom_utils::logger::logger_stdout_debug();
+    let mut lines = init_empty()?;
+    lines.init_buffer("abc\nd\n".into())?;
+    let mut cursor = Cursor::new(CursorMode::Normal(0), None, None);
+    let data = RegisterData {
+        content: "xy\nzw\nuv\n".to_string(),
+        mode:    VisualMode::Blockwise
+    };
+    lines.do_paste_buffer(&mut cursor, &data)?;
+    assert_eq!(lines.buffer().text().to_string(), "axybc\ndzw\n uv");
+    assert_eq!(cursor.offset(), 1);
+    Ok(())
+}
+
+#[test]
+fn test_typed_register() -> Result<()> {
+    use floem::views::editor::core::{mode::VisualMode, register::RegisterData};
+    custom_utils::logger::logger_stdout_debug();
+    let mut lines = init_empty()?;
+    lines.init_buffer("ab\ncd".into())?;
+    let mut register = Register::default();
+    let mut cursor = Cursor::new(CursorMode::Normal(0), None, None);
+    lines.do_edit_buffer(
+        &mut cursor,
+        &EditCommand::DeleteForward,
+        1,
+        true,
+        &mut register,
+        false
+    )?;
+    assert_eq!(register.unnamed.content, "a");
+    assert!(matches!(register.unnamed.mode, VisualMode::Normal));
+
+    // linewise paste below the last line without newline
+    let mut cursor = Cursor::new(CursorMode::Normal(2), None, None);
+    let data = RegisterData {
+        content: "xy\n".to_string(),
+        mode:    VisualMode::Linewise
+    };
+    lines.do_paste_buffer(&mut cursor, &data)?;
+    assert_eq!(lines.buffer().text().to_string(), "b\ncd\nxy");
+    assert_eq!(cursor.offset(), 5);
+    Ok(())
+}
+
+#[test]
+fn test_edit_count() -> Result<()> {
+    custom_utils::logger::logger_stdout_debug();
+    let mut lines = init_empty()?;
+    lines.init_buffer("abc\nd".into())?;
+    let mut register = Register::default();
+    let mut cursor = Cursor::new(CursorMode::Normal(0), None, None);
+    // 2x
+    lines.do_edit_buffer(
+        &mut cursor,
+        &EditCommand::DeleteForward,
+        2,
+        true,
+        &mut register,
+        false
+    )?;
+    assert_eq!(lines.buffer().text().to_string(), "c\nd");
+    assert_eq!(register.unnamed.content, "ab");
+    lines.do_edit_buffer(
+        &mut cursor,
+        &EditCommand::Undo,
+        1,
+        true,
+        &mut register,
+        false
+    )?;
+    assert_eq!(lines.buffer().text().to_string(), "abc\nd");
+    Ok(())
+}
+
+#[test]
+fn test_repeat_last_change() -> Result<()> {
+    custom_utils::logger::logger_stdout_debug();
+    let mut lines = init_empty()?;
+    lines.init_buffer("abc\nabc".into())?;
+    let mut register = Register::default();
+    let mut cursor = Cursor::new(CursorMode::Normal(0), None, None);
+    lines.do_edit_buffer(
+        &mut cursor,
+        &EditCommand::DeleteForward,
+        2,
+        true,
+        &mut register,
+        false
+    )?;
+    let mut cursor = Cursor::new(CursorMode::Normal(2), None, None);
+    lines.repeat_last_change(&mut cursor, &mut register)?;
+    assert_eq!(lines.buffer().text().to_string(), "c\nc");
+
+    // i xy <esc>
+    let mut cursor = Cursor::new(CursorMode::Normal(0), None, None);
+    for cmd in [EditCommand::InsertMode, EditCommand::NormalMode] {
+        lines.do_edit_buffer(&mut cursor, &cmd, 1, true, &mut register, false)?;
+        if cursor.is_insert() {
+            lines.do_insert_buffer(&mut cursor, "xy")?;
+        }
+    }
+    assert_eq!(lines.last_change().steps().len(), 3);
+    let mut cursor = Cursor::new(CursorMode::Normal(4), None, None);
+    lines.repeat_last_change(&mut cursor, &mut register)?;
+    assert_eq!(lines.buffer().text().to_string(), "xyc\nxyc");
+    assert!(!cursor.is_insert());
+    lines.do_edit_buffer(
+        &mut cursor,
+        &EditCommand::Undo,
+        1,
+        true,
+        &mut register,
+        false
+    )?;
+    assert_eq!(lines.buffer().text().to_string(), "xyc\nc");
+    Ok(())
+}
+
+#[test]
+fn test_sentence_paragraph_motion() -> Result<()> {
+    use doc::lines::buffer::rope_text::RopeTextVal;
+    // starts: 0 "One", 5 "Two", 14 "three", 21 "Four", 27 blank line, 30 "Five"
+    let text = RopeTextVal::new("One. Two (x.) three!\nFour?\n\n  Five.".into());
+    assert_eq!(text.move_n_sentences_forward(0, 1)?, 5);
+    assert_eq!(text.move_n_sentences_forward(0, 2)?, 14);
+    assert_eq!(text.move_n_sentences_forward(21, 1)?, 27);
+    assert_eq!(text.move_n_sentences_forward(27, 1)?, 30);
+    assert_eq!(text.move_n_sentences_forward(30, 1)?, 35);
+    assert_eq!(text.move_n_sentences_backward(30, 1)?, 27);
+    assert_eq!(text.move_n_sentences_backward(30, 3)?, 14);
+    assert_eq!(text.move_n_sentences_backward(3, 1)?, 0);
+
+    assert_eq!(text.select_sentence(7, false)?, (5, 13));
+    assert_eq!(text.select_sentence(7, true)?, (5, 14));
+    assert_eq!(text.select_paragraph(7, false)?, (0, 27));
+    assert_eq!(text.select_paragraph(7, true)?, (0, 28));
+    assert_eq!(text.select_paragraph(30, true)?, (27, 35));
+    Ok(())
+}
+
+#[test]
+fn test_diff_options() -> Result<()> {
+    use doc::lines::{
+        buffer::diff::{DiffLines, DiffOptions, rope_diff},
+        diff::DiffInfo
+    };
+    use lapce_xi_rope::Rope;
+    let left = Rope::from("fn a() {\n  x\n}\n");
+    let right = Rope::from("FN a()  {  \n    x\n}\n");
+    let changes = rope_diff(
+        left.clone(),
+        right.clone(),
+        0,
+        std::sync::Arc::new(std::sync::atomic::AtomicU64::new(0)),
+        None
+    )
+    .unwrap();
+    let mut info = DiffInfo {
+        is_right: true,
+        changes,
+        options: DiffOptions::default()
+    };
+    assert!(info.changes.iter().any(|x| !matches!(x, DiffLines::Both(_))));
+
+    let options = DiffOptions {
+        trim_trailing: true,
+        ..Default::default()
+    };
+    assert!(info.set_options(options, &left, &right, None));
+    assert!(info.changes.iter().any(|x| !matches!(x, DiffLines::Both(_))));
+    assert!(!info.set_options(options, &left, &right, None));
+
+    let options = DiffOptions {
+        ignore_whitespace: true,
+        ignore_case: true,
+        ..Default::default()
+    };
+    assert!(info.set_options(options, &left, &right, None));
+    assert_eq!(info.options, options);
+    assert!(info.changes.iter().all(|x| matches!(x, DiffLines::Both(_))));
+    Ok(())
+}
+
+#[test]
+fn test_preview_text_edits() -> Result<()> {
+    use doc::lines::buffer::diff::DiffLines;
+    use lsp_types::{Range, TextEdit};
+    let mut lines = init_empty()?;
+    lines.init_buffer("fn a() {\n    b();\n}\n".into())?;
+    let edit = |start: (u32, u32), end: (u32, u32), text: &str| TextEdit {
+        range:    Range::new(
+            Position::new(start.0, start.1),
+            Position::new(end.0, end.1)
+        ),
+        new_text: text.to_string()
+    };
+    let preview = lines.preview_text_edits(
+        &[
+            edit((1, 4), (1, 5), "c"),
+            edit((0, 3), (0, 4), "main"),
+            edit((2, 1), (2, 1), "\n")
+        ],
+        None
+    )?;
+    assert_eq!(preview.text.to_string(), "fn main() {\n    c();\n}\n\n");
+    assert_eq!(lines.buffer().text().to_string(), "fn a() {\n    b();\n}\n");
+    assert!(preview.diff.is_right);
+    assert!(
+        preview
+            .diff
+            .changes
+            .iter()
+            .any(|x| matches!(x, DiffLines::Right(_)))
+    );
+
+    assert!(
+        lines
+            .preview_text_edits(
+                &[edit((1, 4), (1, 6), "x"), edit((1, 5), (1, 7), "y")],
+                None
+            )
+            .is_err()
+    );
+    Ok(())
+}
+
+#[test]
+fn test_diff_sections_wrap() -> Result<()> {
+    use doc::lines::{
+        buffer::diff::{DiffBothInfo, DiffLines},
+        diff::diff_sections
+    };
+    use floem::views::editor::view::DiffSectionKind;
+    // 左侧第1行被替换为右侧两行，右侧第1行换行为3个视觉行
+    let changes = vec![
+        DiffLines::Both(DiffBothInfo {
+            left:  0..1,
+            right: 0..1,
+            skip:  None
+        }),
+        DiffLines::Left(1..2),
+        DiffLines::Right(1..3),
+        DiffLines::Both(DiffBothInfo {
+            left:  2..3,
+            right: 3..4,
+            skip:  None
+        })
+    ];
+    let left_wraps = |line: usize| if line == 2 { 2 } else { 1 };
+    let right_wraps = |line: usize| if line == 1 { 3 } else { 1 };
+
+    let left = diff_sections(&changes, false, left_wraps, right_wraps);
+    let rows: Vec<_> = left.iter().map(|x| (x.y_idx, x.height)).collect();
+    assert_eq!(rows, vec![(1, 1), (2, 3)]);
+    assert!(matches!(left[0].kind, DiffSectionKind::Removed));
+    assert!(matches!(left[1].kind, DiffSectionKind::NoCode));
+
+    let right = diff_sections(&changes, true, left_wraps, right_wraps);
+    let rows: Vec<_> = right.iter().map(|x| (x.y_idx, x.height)).collect();
+    assert_eq!(rows, vec![(1, 4), (6, 1)]);
+    assert!(matches!(right[0].kind, DiffSectionKind::Added));
+    assert!(matches!(right[1].kind, DiffSectionKind::NoCode));
+    Ok(())
+}
+
+#[test]
+fn test_run_anchor_delta() {
+    use doc::syntax::runnable::{RunAnchor, RunAnchorKind};
+    use lapce_xi_rope::{Rope, RopeDelta};
+    let text = Rope::from("#[test]\nfn a() {}\nfn main() {}\n");
+    let mut anchors = vec![
+        RunAnchor {
+            offset: 0,
+            kind:   RunAnchorKind::Test,
+            name:   Some("a".to_string())
+        },
+        RunAnchor {
+            offset: 18,
+            kind:   RunAnchorKind::Main,
+            name:   None
+        }
+    ];
+    let delta =
+        RopeDelta::simple_edit(Interval::new(8, 8), Rope::from("\n"), text.len());
+    RunAnchor::apply_delta(&mut anchors, &delta);
+    assert_eq!(anchors[0].offset, 0);
+    assert_eq!(anchors[1].offset, 19);
+}
+
+#[test]
+fn test_blend_backgrounds() {
+    use doc::{
+        config::BackgroundBlend,
+        lines::{layout::LineExtraStyle, util::blend_backgrounds}
+    };
+    use floem::peniko::Color;
+    let style = |x: f64, width: f64, bg: Color| LineExtraStyle {
+        x,
+        y: 0.0,
+        width: Some(width),
+        height: 10.0,
+        bg_color: Some(bg),
+        under_line: None,
+        wave_line: None
+    };
+    let red = Color::rgba8(255, 0, 0, 255);
+    let blue = Color::rgba8(0, 0, 255, 128);
+    let mut styles = vec![style(0.0, 20.0, red), style(10.0, 20.0, blue)];
+    blend_backgrounds(&mut styles, BackgroundBlend::Stack);
+    assert_eq!(styles.len(), 2);
+
+    blend_backgrounds(&mut styles, BackgroundBlend::Over);
+    let segments: Vec<_> = styles
+        .iter()
+        .map(|x| (x.x, x.width.unwrap(), x.bg_color.unwrap()))
+        .collect();
+    assert_eq!(segments, vec![
+        (0.0, 10.0, red),
+        (10.0, 10.0, Color::rgba8(127, 0, 128, 255)),
+        (20.0, 10.0, blue)
+    ]);
+}
+
+#[test]
+fn test_edit_listener() -> Result<()> {
+    use std::{cell::RefCell, rc::Rc};
+
+    use doc::lines::{buffer::EditEvent, edit::EditType};
+    let mut lines = init_empty()?;
+    lines.init_buffer("a\nb\nc\n".into())?;
+    let events: Rc<RefCell<Vec<EditEvent>>> = Default::default();
+    let id = {
+        let events = events.clone();
+        lines.add_edit_listener(move |x| events.borrow_mut().push(x.clone()))
+    };
+    let rev = lines.buffer().rev();
+    lines.edit_buffer(&[(Selection::caret(2), "x\ny")], EditType::InsertChars)?;
+    {
+        let events = events.borrow();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].old_rev, rev);
+        assert_eq!(events[0].new_rev, lines.buffer().rev());
+        assert_eq!(events[0].edit_type, EditType::InsertChars);
+        assert_eq!(events[0].affected_lines, 1..3);
+        let old = lapce_xi_rope::Rope::from("a\nb\nc\n");
+        assert_eq!(events[0].delta.apply(&old).to_string(), "a\nx\nyb\nc\n");
+    }
+
+    let mut cursor = cursor_insert(5, 5);
+    let mut register = Register::default();
+    lines.do_edit_buffer(&mut cursor, &EditCommand::Undo, 1, false, &mut register, true)?;
+    assert_eq!(events.borrow().len(), 2);
+    assert_eq!(events.borrow()[1].edit_type, EditType::Undo);
+
+    assert!(lines.remove_edit_listener(id));
+    lines.edit_buffer(&[(Selection::caret(0), "z")], EditType::InsertChars)?;
+    assert_eq!(events.borrow().len(), 2);
+    Ok(())
+}
+
+#[test]
+fn test_edit_validator() -> Result<()> {
+    use std::rc::Rc;
+
+    use doc::lines::{EditRejectedError, buffer::EditVerdict, edit::EditType};
+    use lapce_xi_rope::{Rope, RopeDelta};
+    let mut lines = init_empty()?;
+    lines.init_buffer("// header\nlet a = 1;\n".into())?;
+    // 第一行只读
+    let id = lines.add_edit_validator(Rc::new(|_: &Rope, delta: &RopeDelta, _: EditType| {
+        let (iv, _) = delta.summary();
+        if iv.start < 10 {
+            EditVerdict::Reject("read-only header".to_string())
+        } else {
+            EditVerdict::Accept
+        }
+    }));
+    let mut cursor = cursor_insert(3, 3);
+    let err = lines.do_insert_buffer(&mut cursor, "x").unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<EditRejectedError>().map(|x| x.reason.as_str()),
+        Some("read-only header")
+    );
+    assert_eq!(lines.buffer().text().to_string(), "// header\nlet a = 1;\n");
+
+    let mut cursor = cursor_insert(14, 14);
+    lines.do_insert_buffer(&mut cursor, "b")?;
+    assert_eq!(lines.buffer().text().to_string(), "// header\nlet ba = 1;\n");
+
+    // 重新加载不经过校验
+    let rev = lines.buffer().rev();
+    lines.reload_buffer("// Header\nlet ba = 1;\n".into(), true)?;
+    assert_eq!(lines.buffer().text().to_string(), "// Header\nlet ba = 1;\n");
+    assert!(lines.buffer().rev() > rev);
+    lines.reload_buffer("// header\nlet ba = 1;\n".into(), true)?;
+
+    assert!(lines.remove_edit_validator(id));
+    let mut cursor = cursor_insert(3, 3);
+    lines.do_insert_buffer(&mut cursor, "x")?;
+    assert_eq!(lines.buffer().text().to_string(), "// xheader\nlet ba = 1;\n");
+    Ok(())
+}
+
+#[test]
+fn test_edit_validator_partly_rejected() -> Result<()> {
+    use std::rc::Rc;
+
+    use doc::lines::{buffer::EditVerdict, edit::EditType};
+    use lapce_xi_rope::{Rope, RopeDelta};
+    let mut lines = init_empty()?;
+    lines.init_buffer("fn a\n".into())?;
+    // 接受插入的"("，拒绝之后自动闭合的")"
+    lines.add_edit_validator(Rc::new(|text: &Rope, _: &RopeDelta, _: EditType| {
+        if text.to_string().contains('(') {
+            EditVerdict::Reject("no pair".to_string())
+        } else {
+            EditVerdict::Accept
+        }
+    }));
+    let mut cursor = cursor_insert(4, 4);
+    let rs = lines.do_insert_buffer(&mut cursor, "(")?;
+    assert_eq!(rs.len(), 1);
+    assert_eq!(lines.buffer().text().to_string(), "fn a(\n");
+    assert_eq!(lines.take_edit_rejection().as_deref(), Some("no pair"));
+    assert_eq!(lines.take_edit_rejection(), None);
+    Ok(())
+}
+
+#[test]
+fn test_buffer_rejection_scope() -> Result<()> {
+    use std::rc::Rc;
+
+    use doc::lines::{
+        buffer::{Buffer, EditVerdict},
+        edit::EditType
+    };
+    use lapce_xi_rope::{Rope, RopeDelta};
+    let mut buffer = Buffer::new("abc\n");
+    buffer.add_validator(Rc::new(|_: &Rope, delta: &RopeDelta, _: EditType| {
+        if delta.summary().0.start == 0 {
+            EditVerdict::Reject("first column".to_string())
+        } else {
+            EditVerdict::Accept
+        }
+    }));
+    assert!(
+        buffer
+            .edit([(Selection::caret(0), "x")], EditType::InsertChars)
+            .is_none()
+    );
+    // 事务外被拒绝不影响之后的编辑
+    assert!(
+        buffer
+            .edit([(Selection::caret(1), "y")], EditType::InsertChars)
+            .is_some()
+    );
+    assert_eq!(buffer.to_string(), "aybc\n");
+    assert_eq!(buffer.take_rejection(), None);
+
+    buffer.start_transaction();
+    assert!(
+        buffer
+            .edit([(Selection::caret(0), "x")], EditType::InsertChars)
+            .is_none()
+    );
+    assert!(
+        buffer
+            .edit([(Selection::caret(1), "y")], EditType::InsertChars)
+            .is_none()
+    );
+    assert_eq!(buffer.end_transaction().as_deref(), Some("first column"));
+    assert!(
+        buffer
+            .edit([(Selection::caret(1), "z")], EditType::InsertChars)
+            .is_some()
+    );
+    assert_eq!(buffer.to_string(), "azybc\n");
+    Ok(())
+}
+
+#[test]
+fn test_evaluation_range() -> Result<()> {
+    let mut lines = init_empty()?;
+    lines.init_buffer("x = 1\n  print(x)  \n\n".into())?;
+    // 没有语法树时，光标所在的行
+    assert_eq!(lines.evaluation_range(9..9)?, Some((8..16, "print(x)".to_string())));
+    assert_eq!(lines.evaluation_range(0..2)?, Some((0..1, "x".to_string())));
+    assert_eq!(lines.evaluation_range(19..19)?, None);
+    Ok(())
+}
+
+#[test]
+fn test_syntax_error_toggle() -> Result<()> {
+    use doc::lines::{SYNTAX_ERROR_DECORATION_KEY, decoration::Decoration};
+    let mut lines = init_empty()?;
+    lines.init_buffer("fn a( {\n".into())?;
+    let mut config = lines.config.clone();
+    config.syntax_errors = true;
+    lines.update_config(config)?;
+    // 没有语法树时不产生错误
+    assert!(lines.decorations().get(SYNTAX_ERROR_DECORATION_KEY).is_none());
+
+    let style = Decoration {
+        fg_color:   None,
+        bg_color:   None,
+        under_line: None,
+        wave_line:  Some(lines.config.diagnostic_error)
+    };
+    lines.set_decorations(SYNTAX_ERROR_DECORATION_KEY, [(Interval::new(4, 5), style)])?;
+    let mut config = lines.config.clone();
+    config.syntax_errors = false;
+    lines.update_config(config)?;
+    assert!(lines.decorations().get(SYNTAX_ERROR_DECORATION_KEY).is_none());
+    Ok(())
+}
+
+#[test]
+fn test_syntax_query_without_tree() -> Result<()> {
+    let mut lines = init_empty()?;
+    lines.init_buffer("let a = 1;\n".into())?;
+    // 纯文本没有语法树
+    assert!(lines.syntax_token_at(4).is_none());
+    assert!(lines.syntax_query(0..10, "(identifier) @name").is_err());
+    Ok(())
+}
+
+#[test]
+fn test_syntax_task_without_grammar() -> Result<()> {
+    let mut lines = init_empty()?;
+    lines.init_buffer("let a = 1;\n".into())?;
+    let mut cursor = cursor_insert(4, 4);
+    lines.do_insert_buffer(&mut cursor, "b")?;
+    lines.trigger_syntax_change()?;
+    // 没有语法时不需要解析
+    assert!(lines.syntax_task().is_none());
+    Ok(())
+}
+
+#[test]
+fn test_syntax_edits_between() {
+    use doc::syntax::edit::{SyntaxEdit, SyntaxEdits};
+    let mut edits = SyntaxEdits::default();
+    for rev in 2..=4 {
+        edits.push(rev, SyntaxEdit::new(Vec::new()));
+    }
+    // 上次解析在版本1，之后每个版本都有编辑
+    assert_eq!(edits.between(1, 4).map(|x| x.len()), Some(3));
+    assert_eq!(edits.between(2, 4).map(|x| x.len()), Some(2));
+    // 版本1的编辑缺失
+    assert!(edits.between(0, 4).is_none());
+    // 版本5没有记录(如初始化内容)
+    assert!(edits.between(1, 5).is_none());
+
+    // 撤销后重新编辑，替换版本3及之后的编辑
+    edits.push(3, SyntaxEdit::new(Vec::new()));
+    assert_eq!(edits.between(1, 3).map(|x| x.len()), Some(2));
+    assert!(edits.between(1, 4).is_none());
+
+    edits.retain_after(2);
+    assert!(edits.between(1, 3).is_none());
+    assert_eq!(edits.between(2, 3).map(|x| x.len()), Some(1));
+}
+
+#[test]
+fn test_stale_syntax_styles() -> Result<()> {
+    use std::path::Path;
+
+    use doc::syntax::Syntax;
+    let mut lines = init_empty()?;
+    lines.init_buffer("let a = 1;\n".into())?;
+    let rev = lines.buffer().rev();
+    let mut syntax = Syntax::plaintext(Path::new(""), Path::new(""));
+    let mut styles = SpansBuilder::new(lines.buffer().len());
+    styles.add_span(Interval::new(4, 5), "variable".to_string());
+    syntax.styles = Some(styles.build());
+    syntax.rev = rev;
+
+    let mut cursor = cursor_insert(0, 0);
+    lines.do_insert_buffer(&mut cursor, "xy")?;
+    // 旧版本的高亮随编辑移动
+    assert!(lines.set_syntax_with_rev(syntax.clone(), rev)?);
+    let spans: Vec<(Interval, String)> = lines
+        .syntax
+        .styles
+        .as_ref()
+        .unwrap()
+        .iter()
+        .map(|(iv, x)| (iv, x.clone()))
+        .collect();
+    assert_eq!(spans, vec![(Interval::new(6, 7), "variable".to_string())]);
+    // 比当前更旧的结果被丢弃
+    assert!(!lines.set_syntax_with_rev(syntax, rev - 1)?);
+    Ok(())
+}
+
+#[test]
+fn test_local_uses_without_tree() -> Result<()> {
+    use doc::lines::LOCAL_USES_DECORATION_KEY;
+    let mut lines = init_empty()?;
+    lines.init_buffer("let a = 1;\nlet b = a;\n".into())?;
+    // 没有语法树时不是局部变量
+    assert_eq!(lines.highlight_local_uses(4)?, 0);
+    assert!(lines.decorations().get(LOCAL_USES_DECORATION_KEY).is_none());
+    Ok(())
+}
+
+#[test]
+fn test_local_uses_bg() -> Result<()> {
+    use floem::peniko::Color;
+    let lines = init_empty()?;
+    let mut config = lines.config.clone();
+    assert_eq!(config.local_uses_bg("a"), config.inlay_hint_bg);
+    config.local_uses_bg = vec![Color::RED, Color::GREEN, Color::BLUE];
+    // 同一个变量的颜色固定
+    assert_eq!(config.local_uses_bg("count"), config.local_uses_bg("count"));
+    assert!(config.local_uses_bg.contains(&config.local_uses_bg("count")));
+    Ok(())
+}
+
+#[test]
+fn test_language_at_without_injections() -> Result<()> {
+    let mut lines = init_empty()?;
+    lines.init_buffer("fn a() {}\n  let b = 1;\n".into())?;
+    // 没有注入时都是文档的语言
+    let language = lines.syntax.language;
+    assert_eq!(lines.language_at(4), language);
+    assert_eq!(lines.language_at(100), language);
+    assert_eq!(lines.language_of_line(1)?, language);
+    Ok(())
+}
+
+#[test]
+fn test_continue_comments() -> Result<()> {
+    let mut lines = init_empty()?;
+    let mut register = Register::default();
+    lines.init_buffer("    /// abc\n".into())?;
+    // 默认关闭
+    let mut cursor = cursor_insert(11, 11);
+    lines.do_edit_buffer(&mut cursor, &EditCommand::InsertNewLine, 1, false, &mut register, true)?;
+    assert_eq!(lines.buffer().text().to_string(), "    /// abc\n    \n");
+
+    let mut config = lines.config.clone();
+    config.continue_comments.enabled = true;
+    lines.update_config(config)?;
+    lines.init_buffer("    /// abc\n".into())?;
+    let mut cursor = cursor_insert(11, 11);
+    lines.do_edit_buffer(&mut cursor, &EditCommand::InsertNewLine, 1, false, &mut register, true)?;
+    assert_eq!(lines.buffer().text().to_string(), "    /// abc\n    /// \n");
+    assert_eq!(cursor.offset(), 20);
+
+    lines.init_buffer("/** abc\n".into())?;
+    let mut cursor = cursor_insert(7, 7);
+    lines.do_edit_buffer(&mut cursor, &EditCommand::InsertNewLine, 1, false, &mut register, true)?;
+    assert_eq!(lines.buffer().text().to_string(), "/** abc\n * \n");
+
+    lines.init_buffer("/*\n * abc\n".into())?;
+    let mut cursor = cursor_insert(9, 9);
+    lines.do_edit_buffer(&mut cursor, &EditCommand::InsertNewLine, 1, false, &mut register, true)?;
+    assert_eq!(lines.buffer().text().to_string(), "/*\n * abc\n * \n");
+
+    // 解引用不是注释
+    lines.init_buffer("{\n    *count += 1;\n".into())?;
+    let mut cursor = cursor_insert(18, 18);
+    lines.do_edit_buffer(&mut cursor, &EditCommand::InsertNewLine, 1, false, &mut register, true)?;
+    assert_eq!(lines.buffer().text().to_string(), "{\n    *count += 1;\n    \n");
+    Ok(())
+}
+
+#[test]
+fn test_doc_comment_template() -> Result<()> {
+    let mut lines = init_empty()?;
+    lines.init_buffer("    ///\n    #[inline]\n    pub fn a(&mut self, x: u8, mut y: Vec<(u8, u8)>) -> u8 {}\n".into())?;
+    let template = lines.doc_comment_template(5)?.unwrap();
+    assert_eq!(template.range, 0..7);
+    assert_eq!(
+        template.text,
+        "    /// \n    ///\n    /// # Arguments\n    ///\n    /// * `x` -\n    /// * `y` -\n    ///\n    /// # Returns"
+    );
+    assert_eq!(template.caret, 8);
+
+    // 已有内容的注释行
+    lines.init_buffer("/// abc\nfn a() {}\n".into())?;
+    assert!(lines.doc_comment_template(3)?.is_none());
+    // 下面不是函数
+    lines.init_buffer("///\nlet a = b(1);\n".into())?;
+    assert!(lines.doc_comment_template(3)?.is_none());
+    Ok(())
+}
+
+#[test]
+fn test_error_lens_markdown() {
+    use doc::lines::error_lens::{ErrorLensLine, format_message, single_line, truncate};
+    let message = "**mismatched types**: expected `u8`, found [`String`](https://doc.rust-lang.org)\n\n```rust\nlet a: u8 = s;\n```\nsee snake_case and a * b";
+    let lines = format_message(message);
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[0], ErrorLensLine {
+        text: "mismatched types: expected u8, found String".to_string(),
+        code: vec![27..29, 37..43]
+    });
+    assert_eq!(lines[1].code, vec![0..14]);
+    assert_eq!(lines[2].text, "see snake_case and a * b");
+    assert_eq!(
+        format_message("# Error\n> quoted _text_   here")
+            .into_iter()
+            .map(|x| x.text)
+            .collect::<Vec<_>>(),
+        vec!["Error", "quoted text here"]
+    );
+
+    let line = single_line(message);
+    assert_eq!(
+        line.text,
+        "mismatched types: expected u8, found String let a: u8 = s; see snake_case and a * b"
+    );
+    assert_eq!(line.code, vec![27..29, 37..43, 44..58]);
+
+    // 截断时代码片段随之截断
+    let line = single_line("see `snake_case`");
+    let glyphs: Vec<(usize, f64)> = (1..=line.text.len()).map(|x| (x, x as f64)).collect();
+    let truncated = truncate(&line, &glyphs, 10.0, 1.0).unwrap();
+    assert_eq!(truncated.text, "see snake…");
+    assert_eq!(truncated.code, vec![4..9]);
+    assert!(truncate(&line, &glyphs, 20.0, 1.0).is_none());
+}
+
+#[test]
+fn test_inline_completion_filter() -> Result<()> {
+    use lapce_xi_rope::{Rope, RopeDelta};
+    let mut lines = init_empty()?;
+    lines.init_buffer("let \n".into())?;
+    lines.completion_pos = (0, 4);
+    let candidates = vec!["abc".to_string(), "xyz".to_string(), "abd".to_string()];
+    lines.set_inline_completions(candidates.clone(), 0, 4)?;
+    lines.previous_inline_completion()?;
+    assert_eq!(lines.inline_completion_index(), Some((2, 3)));
+
+    // 只保留以输入内容开头的候选，当前候选不变
+    let delta = RopeDelta::simple_edit(Interval::new(4, 4), Rope::from("a"), 5);
+    lines.update_inline_completion(&delta)?;
+    assert_eq!(lines.inline_completion_index(), Some((1, 2)));
+    assert_eq!(lines.inline_completion.as_ref().map(|x| x.0.as_str()), Some("bd"));
+
+    // 没有匹配的候选
+    lines.set_inline_completions(candidates, 0, 4)?;
+    let delta = RopeDelta::simple_edit(Interval::new(4, 4), Rope::from("q"), 5);
+    lines.update_inline_completion(&delta)?;
+    assert!(lines.inline_completion.is_none());
+    Ok(())
+}